@@ -7,6 +7,24 @@ pub struct Cli {
 
 	#[clap(flatten)]
 	pub run: RunCmd,
+
+	#[clap(flatten)]
+	pub oidc: crate::oidc::OidcParams,
+
+	#[clap(flatten)]
+	pub indexer: crate::indexer::IndexerParams,
+
+	#[clap(flatten)]
+	pub metrics: crate::metrics::MetricsParams,
+
+	/// Seal blocks on demand instead of with Aura and GRANDPA, for development and tests.
+	///
+	/// `instant` seals and finalizes a block as soon as a transaction enters the pool, `manual`
+	/// only when asked to. Both accept the `engine_createBlock` and `engine_finalizeBlock` RPC
+	/// methods, which are unsafe and so not served to external RPC clients unless
+	/// `--rpc-methods unsafe` is given.
+	#[clap(long, arg_enum)]
+	pub sealing: Option<crate::service::Sealing>,
 }
 
 #[derive(Debug, clap::Subcommand)]
@@ -39,4 +57,8 @@ pub enum Subcommand {
 	/// The custom benchmark subcommand benchmarking runtime pallets.
 	#[clap(name = "benchmark", about = "Benchmark runtime pallets.")]
 	Benchmark(frame_benchmarking_cli::BenchmarkCmd),
+
+	/// Student identity tooling for registrars and operators.
+	#[clap(subcommand)]
+	Studentid(crate::studentid::StudentidSubcommand),
 }
@@ -0,0 +1,365 @@
+//! An index of studentid activity for dashboards.
+//!
+//! The node only keeps state, so questions like "registrations per day" or "logins per
+//! service" can't be answered from it. When enabled, the indexer follows finalized blocks,
+//! decodes the `pallet_studentid` and `pallet_poe` events in them and writes them to an SQLite
+//! database, which the `studentidIndex_*` RPC methods query.
+//!
+//! Only finalized blocks are indexed, so forks never reach the index. Finalized blocks can
+//! still go away when the chain is reverted with the `revert` subcommand; the indexer notices
+//! that the hashes it kept no longer match the chain and drops the blocks from there on.
+//!
+//! Events are read from the state of their block, which a pruning node only keeps for recent
+//! blocks. The indexer starts from the oldest block whose state is kept and records the blocks
+//! it had to skip, as it does blocks whose events this runtime's types no longer decode; see
+//! `studentidIndex_skipped`. Run the node with `--pruning archive` to index the whole chain.
+
+pub mod rpc;
+mod store;
+
+pub use store::{
+	Count, CountBy, CountQuery, EventQuery, Index, IndexedBlock, IndexedEvent, Skipped, Status,
+};
+
+use std::{path::PathBuf, sync::Arc};
+
+use codec::{Decode, Encode};
+use frame_support::storage::StorageValue;
+use futures::StreamExt;
+use node_template_runtime::{opaque::Block, AccountId, Call, Event, Runtime, UncheckedExtrinsic};
+use pallet_studentid::Data;
+use sc_client_api::{Backend, BlockBackend, BlockchainEvents, StorageProvider};
+use sp_blockchain::HeaderBackend;
+use sp_core::{crypto::Ss58Codec, storage::StorageKey, H256};
+use sp_runtime::generic::BlockId;
+
+const LOG_TARGET: &str = "studentid-indexer";
+
+/// How many blocks to index between progress reports while catching up.
+const REPORT_EVERY: u32 = 10_000;
+
+type EventRecord = frame_system::EventRecord<Event, H256>;
+
+/// Command line parameters of the indexer.
+#[derive(Debug, Clone, clap::Parser)]
+pub struct IndexerParams {
+	/// Index the studentid and proof-of-existence events of finalized blocks and serve them
+	/// over the `studentidIndex_*` RPC methods.
+	#[clap(long = "studentid-index")]
+	pub studentid_index: bool,
+
+	/// Where to keep the index. Implies `--studentid-index`.
+	///
+	/// Defaults to `studentid-index.sqlite` in the chain's database directory.
+	#[clap(long = "studentid-index-path", value_name = "PATH", parse(from_os_str))]
+	pub studentid_index_path: Option<PathBuf>,
+}
+
+impl IndexerParams {
+	/// The indexer configuration, or `None` if the indexer is not enabled.
+	pub fn config(&self) -> Option<Config> {
+		if !self.studentid_index && self.studentid_index_path.is_none() {
+			return None
+		}
+		Some(Config { path: self.studentid_index_path.clone() })
+	}
+}
+
+/// Configuration of the indexer.
+#[derive(Debug, Clone)]
+pub struct Config {
+	/// The database file, if not the default one.
+	pub path: Option<PathBuf>,
+}
+
+/// Follow finalized blocks, indexing them into `index`.
+///
+/// Returns the future doing so, to be spawned by the task manager.
+pub async fn run<B, C>(client: Arc<C>, index: Arc<Index>)
+where
+	B: Backend<Block>,
+	C: BlockchainEvents<Block>
+		+ BlockBackend<Block>
+		+ HeaderBackend<Block>
+		+ StorageProvider<Block, B>
+		+ Send
+		+ Sync
+		+ 'static,
+{
+	let mut finality = client.finality_notification_stream();
+	loop {
+		if let Err(e) = catch_up(&*client, &index) {
+			log::warn!(target: LOG_TARGET, "Indexing failed: {}", e);
+		}
+		if finality.next().await.is_none() {
+			return
+		}
+	}
+}
+
+/// Index the finalized blocks the index doesn't have yet, after dropping the ones that are no
+/// longer part of the chain.
+fn catch_up<B, C>(client: &C, index: &Index) -> Result<(), String>
+where
+	B: Backend<Block>,
+	C: BlockBackend<Block> + HeaderBackend<Block> + StorageProvider<Block, B>,
+{
+	let finalized = client.info().finalized_number;
+	let indexed = index.status()?.block;
+	let mut last = indexed;
+	while let Some(number) = last {
+		if number <= finalized && index.hash(number)? == client_hash(client, number)? {
+			break
+		}
+		last = index.previous(number)?;
+	}
+	if last != indexed {
+		log::info!(target: LOG_TARGET, "Dropping reverted blocks after {:?}", last);
+		index.revert_to(last)?;
+	}
+
+	let next = last.map_or(0, |number| number + 1);
+	if next > finalized {
+		return Ok(())
+	}
+	let first = first_with_state(client, next, finalized)?;
+	if first > next {
+		log::warn!(
+			target: LOG_TARGET,
+			"The state of blocks #{} to #{} is pruned, so their events are not indexed. Run the \
+			 node with --pruning archive to index them.",
+			next,
+			first - 1,
+		);
+		index.skip(next, first - 1, "state pruned")?;
+	}
+	for number in first..=finalized {
+		let hash = client_hash(client, number)?.ok_or("Finalized block without a hash.")?;
+		index.insert(&read_block(client, number, hash)?)?;
+		if number > first && (number - first) % REPORT_EVERY == 0 {
+			log::info!(target: LOG_TARGET, "Indexed up to #{} of #{}", number, finalized);
+		}
+	}
+	Ok(())
+}
+
+/// The first block from `next` to `finalized` whose state is still kept. States are pruned
+/// oldest first, so the blocks after it have theirs too.
+fn first_with_state<B, C>(client: &C, next: u32, finalized: u32) -> Result<u32, String>
+where
+	B: Backend<Block>,
+	C: HeaderBackend<Block> + StorageProvider<Block, B>,
+{
+	let has_state = |number| -> Result<bool, String> {
+		let hash = client_hash(client, number)?.ok_or("Finalized block without a hash.")?;
+		let key = StorageKey(frame_system::Number::<Runtime>::hashed_key().to_vec());
+		Ok(client.storage(&BlockId::Hash(hash), &key).is_ok())
+	};
+	if has_state(next)? {
+		return Ok(next)
+	}
+	if !has_state(finalized)? {
+		return Err(format!("The state of the finalized block #{} is not available.", finalized))
+	}
+	let (mut pruned, mut kept) = (next, finalized);
+	while kept - pruned > 1 {
+		let middle = pruned + (kept - pruned) / 2;
+		if has_state(middle)? {
+			kept = middle;
+		} else {
+			pruned = middle;
+		}
+	}
+	Ok(kept)
+}
+
+fn client_hash<C: HeaderBackend<Block>>(client: &C, number: u32) -> Result<Option<H256>, String> {
+	client
+		.hash(number)
+		.map_err(|e| format!("Unable to read block #{}: {}", number, e))
+}
+
+/// Decode the events of block `number`.
+fn read_block<B, C>(client: &C, number: u32, hash: H256) -> Result<IndexedBlock, String>
+where
+	B: Backend<Block>,
+	C: BlockBackend<Block> + StorageProvider<Block, B>,
+{
+	let at = BlockId::Hash(hash);
+	let read = |key: Vec<u8>| {
+		client
+			.storage(&at, &StorageKey(key))
+			.map_err(|e| format!("Unable to read the state of #{}: {}", number, e))
+	};
+	let timestamp = read(pallet_timestamp::Now::<Runtime>::hashed_key().to_vec())?
+		.and_then(|data| u64::decode(&mut &data.0[..]).ok())
+		.unwrap_or_default();
+	let mut undecodable = None;
+	let records = match read(frame_system::Events::<Runtime>::hashed_key().to_vec())? {
+		Some(data) => Vec::<EventRecord>::decode(&mut &data.0[..]).unwrap_or_else(|e| {
+			log::warn!(target: LOG_TARGET, "Undecodable events in #{}: {}", number, e);
+			undecodable = Some(format!("undecodable events: {}", e));
+			Vec::new()
+		}),
+		None => Vec::new(),
+	};
+	let extrinsics = client
+		.block_body(&at)
+		.map_err(|e| format!("Unable to read block #{}: {}", number, e))?
+		.unwrap_or_default()
+		.iter()
+		.map(|xt| UncheckedExtrinsic::decode(&mut &xt.encode()[..]).ok())
+		.collect::<Vec<_>>();
+
+	let events = records
+		.iter()
+		.enumerate()
+		.filter_map(|(i, record)| {
+			let extrinsic = match &record.phase {
+				frame_system::Phase::ApplyExtrinsic(extrinsic) => Some(*extrinsic),
+				_ => None,
+			};
+			let call = extrinsic
+				.and_then(|extrinsic| extrinsics.get(extrinsic as usize))
+				.and_then(|xt| xt.as_ref())
+				.map(|xt| &xt.function);
+			let mut event = describe(&record.event, call)?;
+			event.block = number;
+			event.timestamp = timestamp;
+			event.index = i as u32;
+			event.extrinsic = extrinsic;
+			Some(event)
+		})
+		.collect();
+	Ok(IndexedBlock { number, hash, timestamp, events, undecodable })
+}
+
+/// The name of the variant `event` is, from its debug form.
+fn event_name(event: &impl std::fmt::Debug) -> (String, String) {
+	let data = format!("{:?}", event);
+	let name = data.chars().take_while(|c| c.is_ascii_alphanumeric() || *c == '_').collect();
+	(name, data)
+}
+
+/// The service `call` is about.
+pub(crate) fn call_service(call: &Call) -> Option<Vec<u8>> {
+	use pallet_studentid::Call as Identity;
+	match call {
+		Call::Identity(Identity::check_web3access_sel18 { service, .. }) |
+		Call::Identity(Identity::set_accessservice_sel17 { service, .. }) |
+		Call::Identity(Identity::set_disclosure { service, .. }) => Some(service.clone()),
+		Call::Identity(Identity::login_siws { message, .. }) => Some(message.domain.clone()),
+		_ => None,
+	}
+}
+
+/// `event` as an index entry, if it is a studentid or proof-of-existence event. `call` is the
+/// call of the extrinsic that emitted it.
+fn describe(event: &Event, call: Option<&Call>) -> Option<IndexedEvent> {
+	use pallet_poe::Event as Poe;
+	use pallet_studentid::Event as Identity;
+
+	let (pallet, name, data, account, email, service): (_, _, _, Option<&AccountId>, _, _) =
+		match event {
+			Event::Identity(e) => {
+				let (name, data) = event_name(e);
+				let (account, email, service) = match e {
+					Identity::UserRegistered { who } |
+					Identity::UserExists { who } |
+					Identity::UserInvalid { who } |
+					Identity::UserRegisterfailed { who } |
+					Identity::UserWeb3registered { who } |
+					Identity::UserWeb3registerfailed { who } |
+					Identity::UserLoginsuccess { who, .. } |
+					Identity::UserLoginfailed { who } |
+					Identity::UserDoesnotexist { who } |
+					Identity::UserWeb3loginsuccess { who, .. } |
+					Identity::UserWeb3loginfailed { who } |
+					Identity::UserWeb3doesnotexist { who } |
+					Identity::PasskeyRegistered { who, .. } |
+					Identity::PasskeyRemoved { who, .. } |
+					Identity::StudentRouted { who, .. } => (None, Some(who), None),
+					Identity::DisclosureSet { who, service: Data::Raw(service) } =>
+						(Some(who), None, Some(service.to_vec())),
+					Identity::IdentitySet { who } |
+					Identity::IdentityCleared { who, .. } |
+					Identity::IdentityClearPending { who } |
+					Identity::DisclosureSet { who, .. } |
+					Identity::BlobNoted { who, .. } |
+					Identity::BlobUnnoted { who, .. } |
+					Identity::EthAccountLinked { who, .. } |
+					Identity::EthAccountUnlinked { who, .. } |
+					Identity::IdentityKilled { who, .. } |
+					Identity::StudentStatusChanged { who, .. } |
+					Identity::RenewalSkipped { who } |
+					Identity::SponsorPotFunded { who, .. } |
+					Identity::EnrollmentClaimed { who, .. } => (Some(who), None, None),
+					Identity::JudgementGiven { target: who, .. } |
+					Identity::JudgementRevoked { target: who, .. } |
+					Identity::SubIdentityAdded { sub: who, .. } |
+					Identity::SubIdentityRemoved { sub: who, .. } |
+					Identity::SubIdentityRevoked { sub: who, .. } |
+					Identity::CredentialIssued { owner: who, .. } |
+					Identity::CredentialRevoked { owner: who, .. } => (Some(who), None, None),
+					_ => (None, None, None),
+				};
+				("Identity", name, data, account, email, service)
+			},
+			Event::PoeModule(e) => {
+				let (name, data) = event_name(e);
+				let account = match e {
+					Poe::ClaimCreated(who, _) |
+					Poe::ClaimRevoked(who, _) |
+					Poe::AttributedClaimCreated(who, ..) => Some(who),
+					_ => None,
+				};
+				("PoeModule", name, data, account, None, None)
+			},
+			_ => return None,
+		};
+
+	Some(IndexedEvent {
+		block: 0,
+		timestamp: 0,
+		index: 0,
+		extrinsic: None,
+		pallet: pallet.into(),
+		name,
+		account: account.map(|a| a.to_ss58check()),
+		email: email.map(|e| String::from_utf8_lossy(e).into_owned()),
+		service: service
+			.or_else(|| call.and_then(call_service))
+			.map(|s| String::from_utf8_lossy(&s).into_owned()),
+		data,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn events_are_described() {
+		let login = Event::Identity(pallet_studentid::Event::UserWeb3loginsuccess {
+			who: b"dara@rupp.edu.kh".to_vec(),
+			blocksession: vec![1],
+		});
+		let call = Call::Identity(pallet_studentid::Call::check_web3access_sel18 {
+			service: b"moodle".to_vec(),
+		});
+		let event = describe(&login, Some(&call)).unwrap();
+		assert_eq!(event.pallet, "Identity");
+		assert_eq!(event.name, "UserWeb3loginsuccess");
+		assert_eq!(event.email.as_deref(), Some("dara@rupp.edu.kh"));
+		assert_eq!(event.service.as_deref(), Some("moodle"));
+
+		let who = AccountId::new([1; 32]);
+		let set = Event::Identity(pallet_studentid::Event::IdentitySet { who: who.clone() });
+		let event = describe(&set, None).unwrap();
+		assert_eq!(event.account, Some(who.to_ss58check()));
+		assert_eq!(event.service, None);
+
+		let other = Event::System(frame_system::Event::CodeUpdated);
+		assert_eq!(describe(&other, None), None);
+	}
+}
@@ -0,0 +1,66 @@
+//! RPC methods querying the studentid index.
+
+use std::sync::Arc;
+
+use jsonrpc_core::{Error as RpcError, ErrorCode, Result};
+use jsonrpc_derive::rpc;
+
+use super::{Count, CountQuery, EventQuery, Index, IndexedEvent, Skipped, Status};
+
+/// Error code of failed index queries.
+const INDEX_ERROR: i64 = 1;
+
+#[rpc]
+pub trait IndexerApi {
+	/// The last block in the index.
+	#[rpc(name = "studentidIndex_status")]
+	fn status(&self) -> Result<Status>;
+
+	/// The number of events per day or per service, e.g. registrations per day or logins per
+	/// service.
+	#[rpc(name = "studentidIndex_counts")]
+	fn counts(&self, query: CountQuery) -> Result<Vec<Count>>;
+
+	/// The most recent events matching `query`, newest first.
+	#[rpc(name = "studentidIndex_events")]
+	fn events(&self, query: EventQuery) -> Result<Vec<IndexedEvent>>;
+
+	/// The blocks whose events are missing from the index, because their state was pruned
+	/// before they were indexed or their events did not decode.
+	#[rpc(name = "studentidIndex_skipped")]
+	fn skipped(&self) -> Result<Vec<Skipped>>;
+}
+
+/// Implements [`IndexerApi`] on top of an [`Index`].
+pub struct Indexer {
+	index: Arc<Index>,
+}
+
+impl Indexer {
+	/// Create a new `Indexer` answering from `index`.
+	pub fn new(index: Arc<Index>) -> Self {
+		Self { index }
+	}
+}
+
+fn index_error(message: String) -> RpcError {
+	RpcError { code: ErrorCode::ServerError(INDEX_ERROR), message, data: None }
+}
+
+impl IndexerApi for Indexer {
+	fn status(&self) -> Result<Status> {
+		self.index.status().map_err(index_error)
+	}
+
+	fn counts(&self, query: CountQuery) -> Result<Vec<Count>> {
+		self.index.counts(&query).map_err(index_error)
+	}
+
+	fn events(&self, query: EventQuery) -> Result<Vec<IndexedEvent>> {
+		self.index.events(&query).map_err(index_error)
+	}
+
+	fn skipped(&self) -> Result<Vec<Skipped>> {
+		self.index.skipped().map_err(index_error)
+	}
+}
@@ -0,0 +1,538 @@
+//! The SQLite database the indexer writes to and the RPC reads from.
+
+use std::{path::Path, sync::Mutex};
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use sp_core::H256;
+
+/// The most events `events` returns at once.
+pub const MAX_EVENTS: u32 = 1000;
+
+const SCHEMA: &str = "
+	CREATE TABLE IF NOT EXISTS blocks (
+		number INTEGER PRIMARY KEY,
+		hash BLOB NOT NULL,
+		timestamp INTEGER NOT NULL
+	);
+	CREATE TABLE IF NOT EXISTS events (
+		block INTEGER NOT NULL,
+		idx INTEGER NOT NULL,
+		extrinsic INTEGER,
+		pallet TEXT NOT NULL,
+		name TEXT NOT NULL,
+		account TEXT,
+		email TEXT,
+		service TEXT,
+		data TEXT NOT NULL,
+		PRIMARY KEY (block, idx)
+	);
+	CREATE INDEX IF NOT EXISTS events_by_name ON events (name, block);
+	CREATE INDEX IF NOT EXISTS events_by_account ON events (account, block);
+	CREATE INDEX IF NOT EXISTS events_by_email ON events (email, block);
+	CREATE TABLE IF NOT EXISTS skipped (
+		first INTEGER PRIMARY KEY,
+		last INTEGER NOT NULL,
+		reason TEXT NOT NULL
+	);
+";
+
+/// An indexed event.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexedEvent {
+	/// The number of the block the event was emitted in.
+	pub block: u32,
+	/// The timestamp of that block, in milliseconds.
+	pub timestamp: u64,
+	/// The position of the event in the block.
+	pub index: u32,
+	/// The extrinsic that emitted the event, if any.
+	pub extrinsic: Option<u32>,
+	/// The name of the pallet in the runtime.
+	pub pallet: String,
+	/// The name of the event.
+	pub name: String,
+	/// The SS58 address of the account the event is about.
+	pub account: Option<String>,
+	/// The email the event is about.
+	pub email: Option<String>,
+	/// The service the event is about.
+	pub service: Option<String>,
+	/// The event as a whole, in debug form.
+	pub data: String,
+}
+
+/// A finalized block and its events.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexedBlock {
+	/// The block number.
+	pub number: u32,
+	/// The block hash.
+	pub hash: H256,
+	/// The block timestamp, in milliseconds.
+	pub timestamp: u64,
+	/// The studentid and proof-of-existence events of the block.
+	pub events: Vec<IndexedEvent>,
+	/// Why the events of the block could not be decoded, if they could not.
+	pub undecodable: Option<String>,
+}
+
+/// Blocks whose events are missing from the index.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Skipped {
+	/// The first skipped block.
+	pub first: u32,
+	/// The last skipped block.
+	pub last: u32,
+	/// Why they were skipped, e.g. their state was pruned or their events did not decode.
+	pub reason: String,
+}
+
+/// How far the index got.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+	/// The last indexed block, `None` before the first one.
+	pub block: Option<u32>,
+	/// Its hash.
+	pub hash: Option<H256>,
+	/// The number of blocks up to it whose events are missing, see [`Index::skipped`].
+	pub skipped: u64,
+}
+
+/// What to group event counts by.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum CountBy {
+	/// The UTC day of the block timestamp, as `YYYY-MM-DD`.
+	Day,
+	/// The service the event is about.
+	Service,
+}
+
+/// Which events to count.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CountQuery {
+	/// What to group the counts by.
+	pub by: CountBy,
+	/// Only count events with this name, e.g. `UserLoginsuccess`.
+	#[serde(default)]
+	pub name: Option<String>,
+	/// Only count events in blocks from this timestamp on, in milliseconds.
+	#[serde(default)]
+	pub from: Option<u64>,
+	/// Only count events in blocks before this timestamp, in milliseconds.
+	#[serde(default)]
+	pub to: Option<u64>,
+}
+
+/// The number of events with a name in a group.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Count {
+	/// The day or the service, empty for events without a service.
+	pub key: String,
+	/// The name of the events.
+	pub name: String,
+	/// How many there are.
+	pub count: u64,
+}
+
+/// Which events to list.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct EventQuery {
+	/// Only events with this name.
+	pub name: Option<String>,
+	/// Only events about the account with this SS58 address.
+	pub account: Option<String>,
+	/// Only events about this email.
+	pub email: Option<String>,
+	/// Only events about this service.
+	pub service: Option<String>,
+	/// Only events in blocks before this one, to page through older events.
+	pub before: Option<u32>,
+	/// The most events to return, up to [`MAX_EVENTS`].
+	pub limit: Option<u32>,
+}
+
+/// The index database.
+pub struct Index {
+	conn: Mutex<Connection>,
+}
+
+fn sql_error(e: rusqlite::Error) -> String {
+	format!("Studentid index error: {}", e)
+}
+
+fn timestamp(ms: u64) -> i64 {
+	i64::try_from(ms).unwrap_or(i64::MAX)
+}
+
+impl Index {
+	/// Open the index at `path`, creating it if needed.
+	pub fn open(path: &Path) -> Result<Self, String> {
+		Self::new(Connection::open(path).map_err(sql_error)?)
+	}
+
+	/// An index kept in memory.
+	#[cfg(test)]
+	pub fn open_in_memory() -> Result<Self, String> {
+		Self::new(Connection::open_in_memory().map_err(sql_error)?)
+	}
+
+	fn new(conn: Connection) -> Result<Self, String> {
+		conn.execute_batch(SCHEMA).map_err(sql_error)?;
+		Ok(Self { conn: Mutex::new(conn) })
+	}
+
+	fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+		self.conn.lock().expect("index connection poisoned")
+	}
+
+	/// The last indexed block.
+	pub fn status(&self) -> Result<Status, String> {
+		let last = self
+			.conn()
+			.query_row("SELECT number, hash FROM blocks ORDER BY number DESC LIMIT 1", [], |row| {
+				Ok((row.get::<_, u32>(0)?, row.get::<_, Vec<u8>>(1)?))
+			})
+			.optional()
+			.map_err(sql_error)?;
+		let skipped = self
+			.conn()
+			.query_row("SELECT COALESCE(SUM(last - first + 1), 0) FROM skipped", [], |row| {
+				row.get(0)
+			})
+			.map_err(sql_error)?;
+		Ok(Status {
+			block: last.as_ref().map(|(number, _)| *number),
+			hash: last.map(|(_, hash)| H256::from_slice(&hash)),
+			skipped,
+		})
+	}
+
+	/// The last indexed block before block `number`.
+	pub fn previous(&self, number: u32) -> Result<Option<u32>, String> {
+		self.conn()
+			.query_row("SELECT MAX(number) FROM blocks WHERE number < ?1", [number], |row| {
+				row.get(0)
+			})
+			.map_err(sql_error)
+	}
+
+	/// The hash indexed for block `number`.
+	pub fn hash(&self, number: u32) -> Result<Option<H256>, String> {
+		self.conn()
+			.query_row("SELECT hash FROM blocks WHERE number = ?1", [number], |row| {
+				row.get::<_, Vec<u8>>(0)
+			})
+			.optional()
+			.map(|hash| hash.map(|hash| H256::from_slice(&hash)))
+			.map_err(sql_error)
+	}
+
+	/// Add a block and its events.
+	pub fn insert(&self, block: &IndexedBlock) -> Result<(), String> {
+		let mut conn = self.conn();
+		let tx = conn.transaction().map_err(sql_error)?;
+		tx.execute(
+			"INSERT INTO blocks (number, hash, timestamp) VALUES (?1, ?2, ?3)",
+			params![block.number, block.hash.as_bytes(), timestamp(block.timestamp)],
+		)
+		.map_err(sql_error)?;
+		for e in &block.events {
+			tx.execute(
+				"INSERT INTO events
+					(block, idx, extrinsic, pallet, name, account, email, service, data)
+					VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+				params![
+					block.number,
+					e.index,
+					e.extrinsic,
+					e.pallet,
+					e.name,
+					e.account,
+					e.email,
+					e.service,
+					e.data
+				],
+			)
+			.map_err(sql_error)?;
+		}
+		if let Some(reason) = &block.undecodable {
+			tx.execute(
+				"INSERT INTO skipped (first, last, reason) VALUES (?1, ?1, ?2)",
+				params![block.number, reason],
+			)
+			.map_err(sql_error)?;
+		}
+		tx.commit().map_err(sql_error)
+	}
+
+	/// Note that the blocks `first` to `last` are not indexed, for `reason`.
+	pub fn skip(&self, first: u32, last: u32, reason: &str) -> Result<(), String> {
+		self.conn()
+			.execute(
+				"INSERT INTO skipped (first, last, reason) VALUES (?1, ?2, ?3)",
+				params![first, last, reason],
+			)
+			.map(|_| ())
+			.map_err(sql_error)
+	}
+
+	/// The blocks whose events are missing from the index, oldest first.
+	pub fn skipped(&self) -> Result<Vec<Skipped>, String> {
+		let conn = self.conn();
+		let mut statement = conn
+			.prepare("SELECT first, last, reason FROM skipped ORDER BY first")
+			.map_err(sql_error)?;
+		let skipped = statement
+			.query_map([], |row| {
+				Ok(Skipped { first: row.get(0)?, last: row.get(1)?, reason: row.get(2)? })
+			})
+			.map_err(sql_error)?
+			.collect::<Result<_, _>>()
+			.map_err(sql_error);
+		skipped
+	}
+
+	/// Forget the blocks after `number` and their events, or all of them if `number` is `None`.
+	pub fn revert_to(&self, number: Option<u32>) -> Result<(), String> {
+		let mut conn = self.conn();
+		let tx = conn.transaction().map_err(sql_error)?;
+		tx.execute("DELETE FROM events WHERE ?1 IS NULL OR block > ?1", [number])
+			.map_err(sql_error)?;
+		tx.execute("DELETE FROM blocks WHERE ?1 IS NULL OR number > ?1", [number])
+			.map_err(sql_error)?;
+		tx.execute("DELETE FROM skipped WHERE ?1 IS NULL OR first > ?1", [number])
+			.map_err(sql_error)?;
+		tx.execute("UPDATE skipped SET last = ?1 WHERE last > ?1", [number])
+			.map_err(sql_error)?;
+		tx.commit().map_err(sql_error)
+	}
+
+	/// Count events, grouped by day or service.
+	pub fn counts(&self, query: &CountQuery) -> Result<Vec<Count>, String> {
+		let key = match query.by {
+			CountBy::Day => "date(b.timestamp / 1000, 'unixepoch')",
+			CountBy::Service => "COALESCE(e.service, '')",
+		};
+		let sql = format!(
+			"SELECT {} AS key, e.name, COUNT(*) FROM events e JOIN blocks b ON b.number = e.block
+				WHERE (?1 IS NULL OR e.name = ?1)
+				AND (?2 IS NULL OR b.timestamp >= ?2)
+				AND (?3 IS NULL OR b.timestamp < ?3)
+				GROUP BY key, e.name ORDER BY key, e.name",
+			key
+		);
+		let conn = self.conn();
+		let mut statement = conn.prepare(&sql).map_err(sql_error)?;
+		let counts = statement
+			.query_map(
+				params![query.name, query.from.map(timestamp), query.to.map(timestamp)],
+				|row| Ok(Count { key: row.get(0)?, name: row.get(1)?, count: row.get(2)? }),
+			)
+			.map_err(sql_error)?
+			.collect::<Result<_, _>>()
+			.map_err(sql_error);
+		counts
+	}
+
+	/// The most recent events matching `query`, newest first.
+	pub fn events(&self, query: &EventQuery) -> Result<Vec<IndexedEvent>, String> {
+		let conn = self.conn();
+		let mut statement = conn
+			.prepare(
+				"SELECT e.block, b.timestamp, e.idx, e.extrinsic, e.pallet, e.name, e.account,
+					e.email, e.service, e.data
+					FROM events e JOIN blocks b ON b.number = e.block
+					WHERE (?1 IS NULL OR e.name = ?1)
+					AND (?2 IS NULL OR e.account = ?2)
+					AND (?3 IS NULL OR e.email = ?3)
+					AND (?4 IS NULL OR e.service = ?4)
+					AND (?5 IS NULL OR e.block < ?5)
+					ORDER BY e.block DESC, e.idx DESC LIMIT ?6",
+			)
+			.map_err(sql_error)?;
+		let limit = query.limit.unwrap_or(MAX_EVENTS).min(MAX_EVENTS);
+		let events = statement
+			.query_map(
+				params![query.name, query.account, query.email, query.service, query.before, limit],
+				event_from_row,
+			)
+			.map_err(sql_error)?
+			.collect::<Result<_, _>>()
+			.map_err(sql_error);
+		events
+	}
+}
+
+fn event_from_row(row: &Row) -> rusqlite::Result<IndexedEvent> {
+	Ok(IndexedEvent {
+		block: row.get(0)?,
+		timestamp: row.get::<_, i64>(1)? as u64,
+		index: row.get(2)?,
+		extrinsic: row.get(3)?,
+		pallet: row.get(4)?,
+		name: row.get(5)?,
+		account: row.get(6)?,
+		email: row.get(7)?,
+		service: row.get(8)?,
+		data: row.get(9)?,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const DAY: u64 = 24 * 60 * 60 * 1000;
+
+	fn event(block: u32, index: u32, name: &str, service: Option<&str>) -> IndexedEvent {
+		IndexedEvent {
+			block,
+			timestamp: 0,
+			index,
+			extrinsic: Some(1),
+			pallet: "Identity".into(),
+			name: name.into(),
+			account: None,
+			email: Some("dara@rupp.edu.kh".into()),
+			service: service.map(Into::into),
+			data: format!("{} {{ .. }}", name),
+		}
+	}
+
+	fn block(number: u32, timestamp: u64, events: Vec<IndexedEvent>) -> IndexedBlock {
+		let events = events.into_iter().map(|e| IndexedEvent { timestamp, ..e }).collect();
+		IndexedBlock {
+			number,
+			hash: H256::repeat_byte(number as u8),
+			timestamp,
+			events,
+			undecodable: None,
+		}
+	}
+
+	fn index() -> Index {
+		let index = Index::open_in_memory().unwrap();
+		index.insert(&block(1, DAY, vec![event(1, 0, "UserRegistered", None)])).unwrap();
+		index
+			.insert(&block(
+				2,
+				DAY + 1,
+				vec![
+					event(2, 0, "UserLoginsuccess", None),
+					event(2, 1, "UserWeb3loginsuccess", Some("moodle")),
+				],
+			))
+			.unwrap();
+		index
+			.insert(&block(3, 2 * DAY, vec![event(3, 0, "UserWeb3loginsuccess", Some("moodle"))]))
+			.unwrap();
+		index
+	}
+
+	#[test]
+	fn events_are_counted_per_day_and_service() {
+		let index = index();
+		let by_day = index
+			.counts(&CountQuery { by: CountBy::Day, name: None, from: None, to: None })
+			.unwrap();
+		assert_eq!(
+			by_day.iter().map(|c| (&c.key[..], &c.name[..], c.count)).collect::<Vec<_>>(),
+			vec![
+				("1970-01-02", "UserLoginsuccess", 1),
+				("1970-01-02", "UserRegistered", 1),
+				("1970-01-02", "UserWeb3loginsuccess", 1),
+				("1970-01-03", "UserWeb3loginsuccess", 1),
+			]
+		);
+
+		let logins = CountQuery {
+			by: CountBy::Service,
+			name: Some("UserWeb3loginsuccess".into()),
+			from: Some(DAY + 1),
+			to: None,
+		};
+		assert_eq!(
+			index.counts(&logins).unwrap(),
+			vec![Count { key: "moodle".into(), name: "UserWeb3loginsuccess".into(), count: 2 }]
+		);
+	}
+
+	#[test]
+	fn events_are_listed_newest_first() {
+		let index = index();
+		let events = index
+			.events(&EventQuery { service: Some("moodle".into()), ..Default::default() })
+			.unwrap();
+		assert_eq!(
+			events.iter().map(|e| (e.block, e.index)).collect::<Vec<_>>(),
+			vec![(3, 0), (2, 1)]
+		);
+		assert_eq!(events[0].timestamp, 2 * DAY);
+
+		let page = EventQuery { before: Some(3), limit: Some(1), ..Default::default() };
+		assert_eq!(
+			index.events(&page).unwrap()[0],
+			IndexedEvent {
+				timestamp: DAY + 1,
+				..event(2, 1, "UserWeb3loginsuccess", Some("moodle"))
+			}
+		);
+	}
+
+	#[test]
+	fn reverted_blocks_are_forgotten() {
+		let index = index();
+		assert_eq!(
+			index.status().unwrap(),
+			Status { block: Some(3), hash: Some(H256::repeat_byte(3)), skipped: 0 }
+		);
+		index.revert_to(Some(1)).unwrap();
+		assert_eq!(index.status().unwrap().block, Some(1));
+		assert_eq!(index.hash(2).unwrap(), None);
+		assert_eq!(index.events(&EventQuery::default()).unwrap().len(), 1);
+		index.insert(&block(2, DAY, vec![])).unwrap();
+		assert_eq!(index.status().unwrap().block, Some(2));
+
+		index.revert_to(None).unwrap();
+		assert_eq!(index.status().unwrap(), Status { block: None, hash: None, skipped: 0 });
+	}
+
+	#[test]
+	fn skipped_blocks_are_recorded() {
+		let index = Index::open_in_memory().unwrap();
+		index.skip(0, 9, "state pruned").unwrap();
+		index.insert(&block(10, DAY, vec![])).unwrap();
+		let undecodable =
+			IndexedBlock { undecodable: Some("bad events".into()), ..block(11, DAY, vec![]) };
+		index.insert(&undecodable).unwrap();
+		index.insert(&block(12, DAY, vec![])).unwrap();
+		index.skip(13, 20, "state pruned").unwrap();
+		index.insert(&block(21, DAY, vec![])).unwrap();
+
+		assert_eq!(index.status().unwrap().skipped, 19);
+		assert_eq!(index.previous(21).unwrap(), Some(12));
+		assert_eq!(index.previous(10).unwrap(), None);
+		let skipped = |first, last, reason: &str| Skipped { first, last, reason: reason.into() };
+		assert_eq!(
+			index.skipped().unwrap(),
+			vec![
+				skipped(0, 9, "state pruned"),
+				skipped(11, 11, "bad events"),
+				skipped(13, 20, "state pruned")
+			]
+		);
+
+		index.revert_to(Some(15)).unwrap();
+		assert_eq!(index.skipped().unwrap().last(), Some(&skipped(13, 15, "state pruned")));
+		index.revert_to(Some(10)).unwrap();
+		assert_eq!(index.skipped().unwrap(), vec![skipped(0, 9, "state pruned")]);
+	}
+}
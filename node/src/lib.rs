@@ -1,3 +1,7 @@
 pub mod chain_spec;
+pub mod indexer;
+pub mod metrics;
+pub mod oidc;
 pub mod rpc;
 pub mod service;
+pub mod studentid;
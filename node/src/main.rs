@@ -6,7 +6,11 @@ mod chain_spec;
 mod service;
 mod cli;
 mod command;
+mod indexer;
+mod metrics;
+mod oidc;
 mod rpc;
+mod studentid;
 
 fn main() -> sc_cli::Result<()> {
 	command::run()
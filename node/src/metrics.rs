@@ -0,0 +1,368 @@
+//! Prometheus metrics of studentid activity.
+//!
+//! Substrate only exports generic metrics, which say nothing about students signing in. These
+//! are registered on the node's Prometheus registry next to them:
+//!
+//! - `studentid_registered_identities`, `studentid_linked_accounts` and
+//!   `studentid_active_tokens`: sampled from the state of the best block every
+//!   [`SAMPLE_INTERVAL`], by a task of its own as it walks all tokens.
+//! - `studentid_logins_total` and `studentid_failed_logins_total`, by login method (and
+//!   service, for successful logins): counted from the `ExtrinsicSuccess` and
+//!   `ExtrinsicFailed` events of the login calls in new best blocks. Anybody can name a
+//!   service, so only the ones given with `--studentid-metrics-service` get a label of their
+//!   own; logins to the others are counted under `other`.
+//! - `studentid_referral_redemptions_total`: counted the same way from the calls linking an
+//!   account with a referral.
+//!
+//! Blocks that are imported as best and then retracted are counted all the same, so the
+//! counters may run slightly ahead of the canonical chain.
+
+use std::{sync::Arc, time::Duration};
+
+use codec::{Decode, Encode};
+use frame_support::storage::{StoragePrefixedMap, StorageValue};
+use futures::StreamExt;
+use node_template_runtime::{opaque::Block, Call, Event, Runtime, UncheckedExtrinsic};
+use pallet_studentid::{EmailId, StudentidOf, TokenInfoOf, Tokens};
+use sc_client_api::{Backend, BlockBackend, BlockchainEvents, StorageProvider};
+use sp_blockchain::HeaderBackend;
+use sp_core::{storage::StorageKey, H256};
+use sp_runtime::generic::BlockId;
+use substrate_prometheus_endpoint::{
+	register, Counter, CounterVec, Gauge, Opts, PrometheusError, Registry, U64,
+};
+
+const LOG_TARGET: &str = "studentid-metrics";
+
+/// How often the state metrics are sampled.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The service label of logins to services that are not given on the command line.
+const OTHER_SERVICE: &str = "other";
+
+type EventRecord = frame_system::EventRecord<Event, H256>;
+
+/// Command line parameters of the studentid metrics.
+#[derive(Debug, Clone, clap::Parser)]
+pub struct MetricsParams {
+	/// A service whose logins `studentid_logins_total` counts under its own label. Can be
+	/// given more than once.
+	#[clap(long = "studentid-metrics-service", value_name = "SERVICE")]
+	pub studentid_metrics_services: Vec<String>,
+}
+
+impl MetricsParams {
+	/// The metrics configuration.
+	pub fn config(&self) -> Config {
+		Config { services: self.studentid_metrics_services.clone() }
+	}
+}
+
+/// Configuration of the studentid metrics.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+	/// The services that get a label of their own.
+	pub services: Vec<String>,
+}
+
+/// The studentid metrics.
+#[derive(Clone)]
+pub struct Metrics {
+	services: Vec<String>,
+	registered_identities: Gauge<U64>,
+	linked_accounts: Gauge<U64>,
+	active_tokens: Gauge<U64>,
+	logins: CounterVec<U64>,
+	failed_logins: CounterVec<U64>,
+	referral_redemptions: Counter<U64>,
+}
+
+impl Metrics {
+	/// Register the metrics on `registry`.
+	pub fn register(registry: &Registry, config: Config) -> Result<Self, PrometheusError> {
+		Ok(Self {
+			services: config.services,
+			registered_identities: register(
+				Gauge::new(
+					"studentid_registered_identities",
+					"Number of registered student identities",
+				)?,
+				registry,
+			)?,
+			linked_accounts: register(
+				Gauge::new("studentid_linked_accounts", "Number of accounts linked to an email")?,
+				registry,
+			)?,
+			active_tokens: register(
+				Gauge::new("studentid_active_tokens", "Number of access tokens not yet expired")?,
+				registry,
+			)?,
+			logins: register(
+				CounterVec::new(
+					Opts::new("studentid_logins_total", "Number of successful logins"),
+					&["method", "service"],
+				)?,
+				registry,
+			)?,
+			failed_logins: register(
+				CounterVec::new(
+					Opts::new("studentid_failed_logins_total", "Number of failed logins"),
+					&["method"],
+				)?,
+				registry,
+			)?,
+			referral_redemptions: register(
+				Counter::new(
+					"studentid_referral_redemptions_total",
+					"Number of accounts linked with a referral",
+				)?,
+				registry,
+			)?,
+		})
+	}
+}
+
+/// What happened in a block, as far as the counters are concerned.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Tally {
+	logins: Vec<(&'static str, String)>,
+	failed_logins: Vec<&'static str>,
+	referral_redemptions: u64,
+}
+
+/// The login method of `call`, if it is a login.
+fn login_method(call: &Call) -> Option<&'static str> {
+	use pallet_studentid::Call as Identity;
+	match call {
+		Call::Identity(Identity::login_access_sel12 { .. }) => Some("password"),
+		Call::Identity(Identity::login_web3_sel16 { .. }) => Some("web3"),
+		Call::Identity(Identity::check_web3access_sel18 { .. }) => Some("web3_access"),
+		Call::Identity(Identity::login_siws { .. }) => Some("siws"),
+		Call::Identity(Identity::login_passkey { .. }) => Some("passkey"),
+		_ => None,
+	}
+}
+
+fn is_referral_redemption(call: &Call) -> bool {
+	use pallet_studentid::Call as Identity;
+	matches!(
+		call,
+		Call::Identity(Identity::create_web3link_sel15 { .. }) |
+			Call::Identity(Identity::create_web3link_sel { .. }) |
+			Call::Identity(Identity::create_weblink_sel { .. })
+	)
+}
+
+/// The service label of `call`: empty for calls without a service, the service if it is one of
+/// `services` and `other` otherwise.
+fn service_label(call: &Call, services: &[String]) -> String {
+	match crate::indexer::call_service(call) {
+		None => String::new(),
+		Some(service) => services
+			.iter()
+			.find(|known| known.as_bytes() == service.as_slice())
+			.map_or_else(|| OTHER_SERVICE.into(), Clone::clone),
+	}
+}
+
+/// Count the logins and referral redemptions among the outcomes of `calls`, the calls of the
+/// block's extrinsics. Logins to `services` are told apart.
+fn tally(records: &[EventRecord], calls: &[Option<Call>], services: &[String]) -> Tally {
+	let mut tally = Tally::default();
+	for record in records {
+		let (index, succeeded) = match (&record.phase, &record.event) {
+			(
+				frame_system::Phase::ApplyExtrinsic(index),
+				Event::System(frame_system::Event::ExtrinsicSuccess { .. }),
+			) => (index, true),
+			(
+				frame_system::Phase::ApplyExtrinsic(index),
+				Event::System(frame_system::Event::ExtrinsicFailed { .. }),
+			) => (index, false),
+			_ => continue,
+		};
+		let call = match calls.get(*index as usize) {
+			Some(Some(call)) => call,
+			_ => continue,
+		};
+		match (login_method(call), succeeded) {
+			(Some(method), true) => tally.logins.push((method, service_label(call, services))),
+			(Some(method), false) => tally.failed_logins.push(method),
+			(None, true) if is_referral_redemption(call) => tally.referral_redemptions += 1,
+			_ => (),
+		}
+	}
+	tally
+}
+
+/// Follow new best blocks, updating the counters of `metrics`.
+///
+/// Returns the future doing so, to be spawned by the task manager.
+pub async fn run<B, C>(client: Arc<C>, metrics: Metrics)
+where
+	B: Backend<Block>,
+	C: BlockchainEvents<Block>
+		+ BlockBackend<Block>
+		+ StorageProvider<Block, B>
+		+ Send
+		+ Sync
+		+ 'static,
+{
+	let mut imports = client.import_notification_stream();
+	while let Some(notification) = imports.next().await {
+		if !notification.is_new_best {
+			continue
+		}
+		if let Err(e) = count(&*client, &metrics, notification.hash) {
+			log::debug!(target: LOG_TARGET, "Counting failed: {}", e);
+		}
+	}
+}
+
+/// Update the gauges of `metrics` from the best block every [`SAMPLE_INTERVAL`].
+///
+/// Returns the future doing so, to be spawned by the task manager apart from [`run`], so that
+/// walking all tokens does not hold up counting new blocks.
+pub async fn sample_periodically<B, C>(client: Arc<C>, metrics: Metrics)
+where
+	B: Backend<Block>,
+	C: HeaderBackend<Block> + StorageProvider<Block, B> + Send + Sync + 'static,
+{
+	loop {
+		if let Err(e) = sample(&*client, &metrics, client.info().best_hash) {
+			log::debug!(target: LOG_TARGET, "Sampling failed: {}", e);
+		}
+		futures_timer::Delay::new(SAMPLE_INTERVAL).await;
+	}
+}
+
+/// Update the counters with the events of block `hash`.
+fn count<B, C>(client: &C, metrics: &Metrics, hash: H256) -> Result<(), String>
+where
+	B: Backend<Block>,
+	C: BlockBackend<Block> + StorageProvider<Block, B>,
+{
+	let at = BlockId::Hash(hash);
+	let records = client
+		.storage(&at, &StorageKey(frame_system::Events::<Runtime>::hashed_key().to_vec()))
+		.map_err(|e| e.to_string())?
+		.map(|data| Vec::<EventRecord>::decode(&mut &data.0[..]))
+		.transpose()
+		.map_err(|e| e.to_string())?
+		.unwrap_or_default();
+	let calls: Vec<_> = client
+		.block_body(&at)
+		.map_err(|e| e.to_string())?
+		.unwrap_or_default()
+		.iter()
+		.map(|xt| UncheckedExtrinsic::decode(&mut &xt.encode()[..]).ok().map(|xt| xt.function))
+		.collect();
+
+	let tally = tally(&records, &calls, &metrics.services);
+	for (method, service) in &tally.logins {
+		metrics.logins.with_label_values(&[*method, service.as_str()]).inc();
+	}
+	for method in &tally.failed_logins {
+		metrics.failed_logins.with_label_values(&[*method]).inc();
+	}
+	metrics.referral_redemptions.inc_by(tally.referral_redemptions);
+	Ok(())
+}
+
+/// Update the gauges from the state of block `hash`.
+fn sample<B, C>(client: &C, metrics: &Metrics, hash: H256) -> Result<(), String>
+where
+	B: Backend<Block>,
+	C: HeaderBackend<Block> + StorageProvider<Block, B>,
+{
+	let at = BlockId::Hash(hash);
+	let number = client.number(hash).map_err(|e| e.to_string())?.ok_or("Unknown block hash")?;
+	let keys = |prefix: [u8; 32]| {
+		client
+			.storage_keys(&at, &StorageKey(prefix.to_vec()))
+			.map_err(|e| e.to_string())
+	};
+	let active_tokens = client
+		.storage_pairs(&at, &StorageKey(Tokens::<Runtime>::final_prefix().to_vec()))
+		.map_err(|e| e.to_string())?
+		.into_iter()
+		.filter_map(|(_, value)| TokenInfoOf::<Runtime>::decode(&mut &value.0[..]).ok())
+		.filter(|token| token.expires > number)
+		.count();
+
+	metrics
+		.registered_identities
+		.set(keys(StudentidOf::<Runtime>::final_prefix())?.len() as u64);
+	metrics
+		.linked_accounts
+		.set(keys(EmailId::<Runtime>::final_prefix())?.len() as u64);
+	metrics.active_tokens.set(active_tokens as u64);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use frame_support::weights::DispatchInfo;
+	use node_template_runtime::AccountId;
+	use sp_runtime::DispatchError;
+
+	fn record(index: u32, event: frame_system::Event<Runtime>) -> EventRecord {
+		EventRecord {
+			phase: frame_system::Phase::ApplyExtrinsic(index),
+			event: Event::System(event),
+			topics: vec![],
+		}
+	}
+
+	#[test]
+	fn login_outcomes_are_tallied() {
+		use pallet_studentid::Call as Identity;
+
+		let calls = vec![
+			None,
+			Some(Call::Identity(Identity::check_web3access_sel18 { service: b"moodle".to_vec() })),
+			Some(Call::Identity(Identity::login_access_sel12 {
+				email: b"dara@rupp.edu.kh".to_vec(),
+				password: b"wrong".to_vec(),
+			})),
+			Some(Call::Identity(Identity::create_web3link_sel15 {
+				email: b"dara@rupp.edu.kh".to_vec(),
+				idtolink: AccountId::new([1; 32]),
+				referal: b"code".to_vec(),
+			})),
+			Some(Call::Identity(Identity::check_web3access_sel18 { service: b"spam1".to_vec() })),
+			Some(Call::Identity(Identity::login_access_sel12 {
+				email: b"dara@rupp.edu.kh".to_vec(),
+				password: b"hello123".to_vec(),
+			})),
+		];
+		let success =
+			|| frame_system::Event::ExtrinsicSuccess { dispatch_info: DispatchInfo::default() };
+		let failure = || frame_system::Event::ExtrinsicFailed {
+			dispatch_error: DispatchError::BadOrigin,
+			dispatch_info: DispatchInfo::default(),
+		};
+		let records = vec![
+			record(0, success()),
+			record(1, success()),
+			record(2, failure()),
+			record(3, success()),
+			record(4, success()),
+			record(5, success()),
+		];
+
+		assert_eq!(
+			tally(&records, &calls, &["moodle".into()]),
+			Tally {
+				logins: vec![
+					("web3_access", "moodle".into()),
+					("web3_access", "other".into()),
+					("password", String::new()),
+				],
+				failed_logins: vec!["password"],
+				referral_redemptions: 1,
+			}
+		);
+	}
+}
@@ -0,0 +1,899 @@
+//! An OpenID Connect provider backed by chain state.
+//!
+//! Partner sites sign students in with the authorization code flow. The provider is a thin
+//! layer over the chain: a student is only authenticated if they hold an access token in
+//! `pallet_studentid`'s `Tokens` map and have given the site (the OIDC `client_id`, i.e. the
+//! service name) access to their identity. Both are checked through the runtime API against
+//! the finalized state when a code is issued and again when it is redeemed.
+//!
+//! Codes, bearer tokens and the signatures already used are only kept in memory. A restart
+//! drops them, so to keep a signature from being used again after one, authorization requests
+//! signed before the provider started are refused.
+//!
+//! Sites must be registered as clients in the file given with `--oidc-clients`, with their
+//! secret and redirect URIs. ID tokens are signed with RS256 using the RSA key given with
+//! `--oidc-rsa-key`, or with EdDSA using an Ed25519 key kept in the node's keystore for clients
+//! asking for it.
+//!
+//! The endpoints are:
+//!
+//! - `GET /.well-known/openid-configuration`: discovery document.
+//! - `GET /jwks`: the public signing keys.
+//! - `POST /authorize`: takes the usual authorization request parameters as a form, with
+//!   `login_hint` set to the hex encoded access token ID, `iat` to the Unix time the request
+//!   was signed at and `signature` to the hex encoded sr25519 signature of the token owner over
+//!   [`authorization_message`]. A signature is only accepted once and for
+//!   [`MAX_AUTHORIZATION_AGE`], and not if it was signed before the provider started, allowing
+//!   for [`MAX_CLOCK_SKEW`]. Redirects to `redirect_uri` with a one-time code.
+//! - `POST /token`: redeems a code for an ID token and a bearer token. Clients authenticate with
+//!   `client_secret_basic` or `client_secret_post`. PKCE (`S256`) is checked when the
+//!   authorization request had a `code_challenge`.
+//! - `GET /userinfo`: the claims the student disclosed to the site, for the scopes it was
+//!   granted: `name` with `profile` and `email` with `email`.
+
+use std::{
+	collections::HashMap,
+	convert::Infallible,
+	net::SocketAddr,
+	path::{Path, PathBuf},
+	sync::{Arc, Mutex},
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use hyper::{
+	header,
+	service::{make_service_fn, service_fn},
+	Body, Method, Request, Response, Server, StatusCode,
+};
+use node_template_runtime::{opaque::Block, AccountId, MaxAdditionalFields};
+use pallet_studentid_rpc::{Data, StudentidRuntimeApi};
+use rand::Rng;
+use ring::signature::{KeyPair, RsaKeyPair};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::HeaderBackend;
+use sp_core::{
+	crypto::{CryptoTypePublicPair, KeyTypeId, Ss58Codec},
+	ed25519,
+	hashing::{blake2_256, sha2_256},
+	sr25519, Pair,
+};
+use sp_keystore::{SyncCryptoStore, SyncCryptoStorePtr};
+use sp_runtime::{generic::BlockId, traits::NumberFor};
+
+/// Key type of the ID token signing key in the keystore.
+pub const KEY_TYPE: KeyTypeId = KeyTypeId(*b"oidc");
+
+/// How long an authorization code can be redeemed for.
+const CODE_LIFETIME: Duration = Duration::from_secs(60);
+
+/// How long ID tokens and bearer tokens are valid for.
+const TOKEN_LIFETIME: Duration = Duration::from_secs(600);
+
+/// How long after it was signed an authorization request is accepted for.
+pub const MAX_AUTHORIZATION_AGE: Duration = Duration::from_secs(300);
+
+/// How far in the future the signing time of an authorization request may be, to allow for
+/// clock skew.
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+/// The scopes clients may ask for.
+const SUPPORTED_SCOPES: [&str; 3] = ["openid", "profile", "email"];
+
+/// Largest request body accepted by the authorization and token endpoints.
+const MAX_BODY_LEN: u64 = 8 * 1024;
+
+/// Command line parameters of the OpenID Connect provider.
+#[derive(Debug, Clone, clap::Parser)]
+pub struct OidcParams {
+	/// Start the OpenID Connect provider on the given port.
+	#[clap(long = "oidc-port", value_name = "PORT")]
+	pub oidc_port: Option<u16>,
+
+	/// Listen for OpenID Connect requests on all interfaces instead of only on localhost.
+	///
+	/// The provider should normally be put behind a reverse proxy terminating TLS instead.
+	#[clap(long = "oidc-external")]
+	pub oidc_external: bool,
+
+	/// The issuer URL advertised in the discovery document and ID tokens.
+	///
+	/// Defaults to `http://localhost:<oidc-port>`.
+	#[clap(long = "oidc-issuer", value_name = "URL")]
+	pub oidc_issuer: Option<String>,
+
+	/// JSON file listing the clients allowed to sign students in.
+	///
+	/// It holds an array of objects with the `client_id`, `client_secret` and `redirect_uris`
+	/// of each client, and optionally its `id_token_signed_response_alg`, `RS256` (the
+	/// default) or `EdDSA`. Required with `--oidc-port`.
+	#[clap(long = "oidc-clients", value_name = "PATH")]
+	pub oidc_clients: Option<PathBuf>,
+
+	/// PKCS #8 RSA private key, PEM or DER encoded, to sign RS256 ID tokens with.
+	///
+	/// Required with `--oidc-port`.
+	#[clap(long = "oidc-rsa-key", value_name = "PATH")]
+	pub oidc_rsa_key: Option<PathBuf>,
+}
+
+impl OidcParams {
+	/// The provider configuration, or `None` if the provider is not enabled.
+	pub fn config(&self) -> Result<Option<Config>, String> {
+		let port = match self.oidc_port {
+			Some(port) => port,
+			None => return Ok(None),
+		};
+		let ip = if self.oidc_external { [0, 0, 0, 0] } else { [127, 0, 0, 1] };
+		let issuer = self
+			.oidc_issuer
+			.clone()
+			.unwrap_or_else(|| format!("http://localhost:{}", port))
+			.trim_end_matches('/')
+			.to_string();
+
+		let clients_path =
+			self.oidc_clients.as_ref().ok_or("`--oidc-clients` is required with `--oidc-port`")?;
+		let clients = read_clients(clients_path)?;
+		let rsa_key_path =
+			self.oidc_rsa_key.as_ref().ok_or("`--oidc-rsa-key` is required with `--oidc-port`")?;
+		let rsa_key = read_pkcs8(rsa_key_path)?;
+
+		Ok(Some(Config { addr: SocketAddr::from((ip, port)), issuer, clients, rsa_key }))
+	}
+}
+
+/// Configuration of the OpenID Connect provider.
+#[derive(Debug, Clone)]
+pub struct Config {
+	/// Address to listen on.
+	pub addr: SocketAddr,
+	/// Issuer URL, without a trailing slash.
+	pub issuer: String,
+	/// The registered clients, by client ID.
+	pub clients: HashMap<String, Client>,
+	/// DER encoded PKCS #8 RSA private key signing RS256 ID tokens.
+	pub rsa_key: Vec<u8>,
+}
+
+/// A site registered to sign students in.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Client {
+	/// The client ID, which is also the service name students give access to.
+	pub client_id: String,
+	/// The secret the client authenticates to the token endpoint with.
+	pub client_secret: String,
+	/// The URIs authorization responses may be redirected to.
+	pub redirect_uris: Vec<String>,
+	/// The algorithm ID tokens for the client are signed with.
+	#[serde(default)]
+	pub id_token_signed_response_alg: Alg,
+}
+
+/// An ID token signing algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Alg {
+	RS256,
+	EdDSA,
+}
+
+impl Default for Alg {
+	fn default() -> Self {
+		Alg::RS256
+	}
+}
+
+impl Alg {
+	fn name(self) -> &'static str {
+		match self {
+			Alg::RS256 => "RS256",
+			Alg::EdDSA => "EdDSA",
+		}
+	}
+}
+
+/// The message the owner of access token `token_id` signs at Unix time `issued_at` to sign in
+/// to `client_id`.
+///
+/// The client ID, redirect URI and nonce are prefixed with their length, so no other values
+/// give the same message.
+pub fn authorization_message(
+	client_id: &str,
+	redirect_uri: &str,
+	nonce: &str,
+	issued_at: u64,
+	token_id: &[u8],
+) -> String {
+	let field = |value: &str| format!("{}:{}", value.len(), value);
+	format!(
+		"studentid-oidc:{}:{}:{}:{}:0x{}",
+		field(client_id),
+		field(redirect_uri),
+		field(nonce),
+		issued_at,
+		hex::encode(token_id)
+	)
+}
+
+/// Read the registered clients from the JSON file at `path`.
+fn read_clients(path: &Path) -> Result<HashMap<String, Client>, String> {
+	let file = std::fs::read(path)
+		.map_err(|e| format!("Unable to read OIDC clients from {}: {}", path.display(), e))?;
+	let clients: Vec<Client> = serde_json::from_slice(&file)
+		.map_err(|e| format!("Invalid OIDC clients file {}: {}", path.display(), e))?;
+	Ok(clients.into_iter().map(|client| (client.client_id.clone(), client)).collect())
+}
+
+/// Read a PKCS #8 private key from `path`, decoding it from PEM if needed.
+fn read_pkcs8(path: &Path) -> Result<Vec<u8>, String> {
+	let file = std::fs::read(path)
+		.map_err(|e| format!("Unable to read OIDC signing key from {}: {}", path.display(), e))?;
+	match std::str::from_utf8(&file) {
+		Ok(pem) if pem.contains("-----BEGIN") => {
+			let body: String = pem
+				.lines()
+				.filter(|line| !line.starts_with("-----"))
+				.flat_map(str::chars)
+				.filter(|c| !c.is_whitespace())
+				.collect();
+			base64::decode(&body)
+				.map_err(|e| format!("Invalid PEM in OIDC signing key {}: {}", path.display(), e))
+		},
+		_ => Ok(file),
+	}
+}
+
+/// Start the provider on the tokio runtime of the calling task.
+///
+/// Returns the future serving requests, to be spawned by the task manager.
+pub fn start<C>(
+	config: Config,
+	client: Arc<C>,
+	keystore: SyncCryptoStorePtr,
+) -> Result<impl std::future::Future<Output = ()>, String>
+where
+	C: ProvideRuntimeApi<Block> + HeaderBackend<Block> + Send + Sync + 'static,
+	C::Api: StudentidRuntimeApi<Block, AccountId, MaxAdditionalFields>,
+{
+	let signer = Signer::new(keystore, &config.rsa_key)?;
+	let provider = Arc::new(Provider {
+		client,
+		issuer: config.issuer,
+		clients: config.clients,
+		signer,
+		started: unix_time(),
+		state: Default::default(),
+	});
+
+	let make_service = make_service_fn(move |_| {
+		let provider = provider.clone();
+		async move {
+			Ok::<_, Infallible>(service_fn(move |request| {
+				let provider = provider.clone();
+				async move { Ok::<_, Infallible>(provider.handle(request).await) }
+			}))
+		}
+	});
+
+	let server = Server::try_bind(&config.addr)
+		.map_err(|e| format!("Unable to bind OIDC provider to {}: {}", config.addr, e))?
+		.serve(make_service);
+	log::info!("OpenID Connect provider listening on {}", config.addr);
+
+	Ok(async move {
+		if let Err(e) = server.await {
+			log::error!("OpenID Connect provider failed: {}", e);
+		}
+	})
+}
+
+/// The ID token signing keys: the RSA key and the Ed25519 key held in the keystore.
+struct Signer {
+	keystore: SyncCryptoStorePtr,
+	public: ed25519::Public,
+	rsa: RsaKeyPair,
+	rng: ring::rand::SystemRandom,
+}
+
+impl Signer {
+	/// Use the RSA key `rsa_key` and the first `oidc` key in the keystore, generating one if
+	/// there is none.
+	fn new(keystore: SyncCryptoStorePtr, rsa_key: &[u8]) -> Result<Self, String> {
+		let rsa = RsaKeyPair::from_pkcs8(rsa_key)
+			.map_err(|e| format!("Invalid OIDC RSA signing key: {}", e))?;
+		let public = match SyncCryptoStore::ed25519_public_keys(&*keystore, KEY_TYPE).pop() {
+			Some(public) => public,
+			None => SyncCryptoStore::ed25519_generate_new(&*keystore, KEY_TYPE, None)
+				.map_err(|e| format!("Unable to generate OIDC signing key: {}", e))?,
+		};
+		Ok(Self { keystore, public, rsa, rng: ring::rand::SystemRandom::new() })
+	}
+
+	fn kid(&self, alg: Alg) -> String {
+		let public = match alg {
+			Alg::RS256 => self.rsa.public_key().as_ref(),
+			Alg::EdDSA => self.public.as_ref(),
+		};
+		hex::encode(&blake2_256(public)[..8])
+	}
+
+	fn jwks(&self) -> Value {
+		let rsa = self.rsa.public_key();
+		json!({ "keys": [
+			{
+				"kty": "RSA",
+				"use": "sig",
+				"alg": "RS256",
+				"kid": self.kid(Alg::RS256),
+				"n": base64url(rsa.modulus().big_endian_without_leading_zero()),
+				"e": base64url(rsa.exponent().big_endian_without_leading_zero()),
+			},
+			{
+				"kty": "OKP",
+				"crv": "Ed25519",
+				"use": "sig",
+				"alg": "EdDSA",
+				"kid": self.kid(Alg::EdDSA),
+				"x": base64url(self.public.as_ref()),
+			},
+		]})
+	}
+
+	/// A compact JWS of `claims`, signed with `alg`.
+	fn sign(&self, alg: Alg, claims: &Value) -> Result<String, String> {
+		let header = json!({ "alg": alg.name(), "typ": "JWT", "kid": self.kid(alg) });
+		let mut jwt = format!(
+			"{}.{}",
+			base64url(header.to_string().as_bytes()),
+			base64url(claims.to_string().as_bytes())
+		);
+		let signature = match alg {
+			Alg::RS256 => {
+				let mut signature = vec![0; self.rsa.public_modulus_len()];
+				let padding = &ring::signature::RSA_PKCS1_SHA256;
+				self.rsa
+					.sign(padding, &self.rng, jwt.as_bytes(), &mut signature)
+					.map_err(|_| "Unable to sign ID token".to_string())?;
+				signature
+			},
+			Alg::EdDSA => SyncCryptoStore::sign_with(
+				&*self.keystore,
+				KEY_TYPE,
+				&CryptoTypePublicPair::from(self.public),
+				jwt.as_bytes(),
+			)
+			.map_err(|e| format!("Unable to sign ID token: {}", e))?
+			.ok_or_else(|| "OIDC signing key is missing from the keystore".to_string())?,
+		};
+		jwt.push('.');
+		jwt.push_str(&base64url(&signature));
+		Ok(jwt)
+	}
+}
+
+/// An authorization code waiting to be redeemed.
+struct PendingCode {
+	account: AccountId,
+	client_id: String,
+	redirect_uri: String,
+	nonce: String,
+	scope: Vec<String>,
+	code_challenge: Option<String>,
+	expires: Instant,
+}
+
+/// A bearer token for the userinfo endpoint.
+struct Session {
+	account: AccountId,
+	client_id: String,
+	scope: Vec<String>,
+	expires: Instant,
+}
+
+#[derive(Default)]
+struct State {
+	codes: HashMap<String, PendingCode>,
+	sessions: HashMap<String, Session>,
+	/// The (access token ID, nonce) pairs of the authorization requests accepted so far, with
+	/// the block their access token expires in.
+	used: HashMap<(Vec<u8>, String), NumberFor<Block>>,
+}
+
+struct Provider<C> {
+	client: Arc<C>,
+	issuer: String,
+	clients: HashMap<String, Client>,
+	signer: Signer,
+	/// The Unix time the provider started at.
+	started: u64,
+	state: Mutex<State>,
+}
+
+/// An OAuth 2.0 error response.
+struct OAuthError {
+	status: StatusCode,
+	error: &'static str,
+	description: String,
+}
+
+fn oauth_error(status: StatusCode, error: &'static str, description: impl Into<String>) -> OAuthError {
+	OAuthError { status, error, description: description.into() }
+}
+
+fn invalid_request(description: impl Into<String>) -> OAuthError {
+	oauth_error(StatusCode::BAD_REQUEST, "invalid_request", description)
+}
+
+fn server_error(description: impl Into<String>) -> OAuthError {
+	oauth_error(StatusCode::INTERNAL_SERVER_ERROR, "server_error", description)
+}
+
+impl<C> Provider<C>
+where
+	C: ProvideRuntimeApi<Block> + HeaderBackend<Block> + Send + Sync + 'static,
+	C::Api: StudentidRuntimeApi<Block, AccountId, MaxAdditionalFields>,
+{
+	async fn handle(&self, request: Request<Body>) -> Response<Body> {
+		let result = match (request.method(), request.uri().path()) {
+			(&Method::GET, "/.well-known/openid-configuration") => Ok(json_response(self.discovery())),
+			(&Method::GET, "/jwks") => Ok(json_response(self.signer.jwks())),
+			// The signature and access token are kept out of the URL, which ends up in logs and
+			// browser history.
+			(&Method::GET, "/authorize") =>
+				Err(invalid_request("Authorization requests must be sent as a form POST")),
+			(&Method::POST, "/authorize") => match read_form(request).await {
+				Ok(form) => self.authorize(&form),
+				Err(e) => Err(e),
+			},
+			(&Method::POST, "/token") => {
+				let credentials = basic_credentials(&request);
+				match read_form(request).await {
+					Ok(form) => self.token(&form, credentials),
+					Err(e) => Err(e),
+				}
+			},
+			(&Method::GET, "/userinfo") | (&Method::POST, "/userinfo") => self.userinfo(&request),
+			_ => Err(oauth_error(StatusCode::NOT_FOUND, "not_found", "Unknown endpoint")),
+		};
+
+		result.unwrap_or_else(|e| {
+			let mut response = json_response(json!({
+				"error": e.error,
+				"error_description": e.description,
+			}));
+			*response.status_mut() = e.status;
+			if e.status == StatusCode::UNAUTHORIZED {
+				let scheme = if e.error == "invalid_client" { "Basic" } else { "Bearer" };
+				response
+					.headers_mut()
+					.insert(header::WWW_AUTHENTICATE, header::HeaderValue::from_static(scheme));
+			}
+			response
+		})
+	}
+
+	fn discovery(&self) -> Value {
+		json!({
+			"issuer": self.issuer,
+			"authorization_endpoint": format!("{}/authorize", self.issuer),
+			"token_endpoint": format!("{}/token", self.issuer),
+			"userinfo_endpoint": format!("{}/userinfo", self.issuer),
+			"jwks_uri": format!("{}/jwks", self.issuer),
+			"response_types_supported": ["code"],
+			"grant_types_supported": ["authorization_code"],
+			"subject_types_supported": ["public"],
+			"id_token_signing_alg_values_supported": ["RS256", "EdDSA"],
+			"scopes_supported": SUPPORTED_SCOPES,
+			"token_endpoint_auth_methods_supported": ["client_secret_basic", "client_secret_post"],
+			"code_challenge_methods_supported": ["S256"],
+			"claims_supported": ["sub", "name", "email"],
+		})
+	}
+
+	/// The last finalized block. Tokens and grants are read from it, so a login in a block that
+	/// is later reverted cannot sign a student in.
+	fn finalized_block(&self) -> BlockId<Block> {
+		BlockId::hash(self.client.info().finalized_hash)
+	}
+
+	fn has_access(&self, account: &AccountId, client_id: &str) -> Result<bool, OAuthError> {
+		self.client
+			.runtime_api()
+			.has_access(&self.finalized_block(), account.clone(), client_id.as_bytes().to_vec())
+			.map_err(|e| server_error(format!("Unable to query service access: {:?}", e)))
+	}
+
+	/// The UTF-8 text behind `data` at `at`, resolving blob references. `None` for empty,
+	/// binary or unresolvable data.
+	fn text(&self, at: &BlockId<Block>, data: &Data) -> Result<Option<String>, OAuthError> {
+		let bytes = match data {
+			Data::Raw(bytes) => bytes.to_vec(),
+			Data::BlakeTwo256(_) => match self
+				.client
+				.runtime_api()
+				.resolve_data(at, data.clone())
+				.map_err(|e| server_error(format!("Unable to resolve data: {:?}", e)))?
+			{
+				Some(bytes) => bytes,
+				None => return Ok(None),
+			},
+			_ => return Ok(None),
+		};
+		Ok(String::from_utf8(bytes).ok().filter(|value| !value.is_empty()))
+	}
+
+	fn authorize(&self, params: &HashMap<String, String>) -> Result<Response<Body>, OAuthError> {
+		let param = |name: &str| params.get(name).map(String::as_str);
+		let required =
+			|name: &str| param(name).ok_or_else(|| invalid_request(format!("Missing `{}`", name)));
+
+		if required("response_type")? != "code" {
+			return Err(oauth_error(
+				StatusCode::BAD_REQUEST,
+				"unsupported_response_type",
+				"Only the authorization code flow is supported",
+			))
+		}
+		// Unknown scopes are ignored, as OpenID Connect requires.
+		let scope: Vec<String> = required("scope")?
+			.split(' ')
+			.filter(|scope| SUPPORTED_SCOPES.contains(scope))
+			.map(String::from)
+			.collect();
+		if !scope.iter().any(|scope| scope == "openid") {
+			return Err(oauth_error(StatusCode::BAD_REQUEST, "invalid_scope", "Missing `openid` scope"))
+		}
+		let client_id = required("client_id")?;
+		let redirect_uri = required("redirect_uri")?;
+		let client = self.clients.get(client_id).ok_or_else(|| {
+			oauth_error(StatusCode::BAD_REQUEST, "unauthorized_client", "Unknown client")
+		})?;
+		if !client.redirect_uris.iter().any(|uri| uri == redirect_uri) {
+			return Err(invalid_request("`redirect_uri` is not registered for the client"))
+		}
+		let nonce = required("nonce")?;
+		let issued_at = required("iat")?
+			.parse::<u64>()
+			.map_err(|_| invalid_request("`iat` must be a Unix time in seconds"))?;
+		let code_challenge = match (param("code_challenge"), param("code_challenge_method")) {
+			(None, _) => None,
+			(Some(challenge), Some("S256")) => Some(challenge.to_string()),
+			_ => return Err(invalid_request("Only the `S256` code challenge method is supported")),
+		};
+		let token_id = from_hex(required("login_hint")?)
+			.ok_or_else(|| invalid_request("`login_hint` must be a hex encoded token ID"))?;
+		let signature = from_hex(required("signature")?)
+			.and_then(|s| sr25519::Signature::try_from(&s[..]).ok())
+			.ok_or_else(|| invalid_request("`signature` must be a hex encoded sr25519 signature"))?;
+
+		let access_denied = |description: &str| {
+			oauth_error(StatusCode::FORBIDDEN, "access_denied", description.to_string())
+		};
+
+		let now = unix_time();
+		if issued_at.saturating_add(MAX_AUTHORIZATION_AGE.as_secs()) < now ||
+			issued_at > now.saturating_add(MAX_CLOCK_SKEW.as_secs())
+		{
+			return Err(access_denied("The authorization request is stale"))
+		}
+		// The signatures used before a restart are forgotten. Any of them was signed at most
+		// `MAX_CLOCK_SKEW` after the provider last stopped, so only later ones are accepted.
+		if issued_at < self.started.saturating_add(MAX_CLOCK_SKEW.as_secs()) {
+			return Err(access_denied("The authorization request predates the provider's start"))
+		}
+
+		let token = self
+			.client
+			.runtime_api()
+			.introspect_token(&self.finalized_block(), token_id.clone())
+			.map_err(|e| server_error(format!("Unable to query access token: {:?}", e)))?
+			.filter(|token| token.active)
+			.ok_or_else(|| access_denied("Unknown or revoked access token"))?;
+		let account = token.owner;
+
+		let message = authorization_message(client_id, redirect_uri, nonce, issued_at, &token_id);
+		let public = sr25519::Public::from_raw(account.clone().into());
+		if !verify_wallet_signature(&signature, message.as_bytes(), &public) {
+			return Err(access_denied("Signature does not match the token owner"))
+		}
+		if !self.has_access(&account, client_id)? {
+			return Err(access_denied("The student has not given this service access"))
+		}
+
+		let code = random_token();
+		let finalized_number = self.client.info().finalized_number;
+		let mut state = self.state.lock().expect("OIDC state lock poisoned");
+		state.used.retain(|_, expires| *expires > finalized_number);
+		if state.used.insert((token_id, nonce.to_string()), token.expires).is_some() {
+			return Err(access_denied("The authorization request was used before"))
+		}
+		let now = Instant::now();
+		state.codes.retain(|_, pending| pending.expires > now);
+		state.codes.insert(
+			code.clone(),
+			PendingCode {
+				account,
+				client_id: client_id.to_string(),
+				redirect_uri: redirect_uri.to_string(),
+				nonce: nonce.to_string(),
+				scope,
+				code_challenge,
+				expires: now + CODE_LIFETIME,
+			},
+		);
+
+		let mut response_params = url::form_urlencoded::Serializer::new(String::new());
+		response_params.append_pair("code", &code);
+		if let Some(s) = param("state") {
+			response_params.append_pair("state", s);
+		}
+		let separator = if redirect_uri.contains('?') { '&' } else { '?' };
+		let location = format!("{}{}{}", redirect_uri, separator, response_params.finish());
+
+		Response::builder()
+			.status(StatusCode::FOUND)
+			.header(header::LOCATION, location)
+			.header(header::CACHE_CONTROL, "no-store")
+			.body(Body::empty())
+			.map_err(|e| server_error(e.to_string()))
+	}
+
+	fn token(
+		&self,
+		form: &HashMap<String, String>,
+		credentials: Option<(String, String)>,
+	) -> Result<Response<Body>, OAuthError> {
+		let param = |name: &str| form.get(name).map(String::as_str);
+		let invalid_grant =
+			|description: &str| oauth_error(StatusCode::BAD_REQUEST, "invalid_grant", description.to_string());
+
+		// `client_secret_basic`, or else `client_secret_post`.
+		let (client_id, client_secret) = match &credentials {
+			Some((id, secret)) => (Some(id.as_str()), Some(secret.as_str())),
+			None => (param("client_id"), param("client_secret")),
+		};
+		let client = client_id
+			.and_then(|id| self.clients.get(id))
+			.filter(|client| {
+				let secret = client_secret.unwrap_or("");
+				ring::constant_time::verify_slices_are_equal(
+					client.client_secret.as_bytes(),
+					secret.as_bytes(),
+				)
+				.is_ok()
+			})
+			.ok_or_else(|| {
+				oauth_error(StatusCode::UNAUTHORIZED, "invalid_client", "Client authentication failed")
+			})?;
+
+		if param("grant_type") != Some("authorization_code") {
+			return Err(oauth_error(
+				StatusCode::BAD_REQUEST,
+				"unsupported_grant_type",
+				"Only `authorization_code` is supported",
+			))
+		}
+		let code = param("code").ok_or_else(|| invalid_request("Missing `code`"))?;
+
+		// Codes are single use, so the code is dropped even if the request turns out invalid.
+		let pending = self
+			.state
+			.lock()
+			.expect("OIDC state lock poisoned")
+			.codes
+			.remove(code)
+			.filter(|pending| pending.expires > Instant::now())
+			.ok_or_else(|| invalid_grant("Unknown or expired code"))?;
+
+		if client.client_id != pending.client_id ||
+			param("redirect_uri") != Some(&pending.redirect_uri)
+		{
+			return Err(invalid_grant("`client_id` or `redirect_uri` does not match the authorization request"))
+		}
+		if let Some(challenge) = &pending.code_challenge {
+			let verifier = param("code_verifier").ok_or_else(|| invalid_grant("Missing `code_verifier`"))?;
+			if &base64url(&sha2_256(verifier.as_bytes())) != challenge {
+				return Err(invalid_grant("`code_verifier` does not match the code challenge"))
+			}
+		}
+
+		// Access may have been revoked since the code was issued.
+		if !self.has_access(&pending.account, &pending.client_id)? {
+			return Err(invalid_grant("The student no longer gives this service access"))
+		}
+
+		let now = unix_time();
+		let claims = json!({
+			"iss": self.issuer,
+			"sub": pending.account.to_ss58check(),
+			"aud": pending.client_id,
+			"iat": now,
+			"auth_time": now,
+			"exp": now + TOKEN_LIFETIME.as_secs(),
+			"nonce": pending.nonce,
+		});
+		let id_token = self
+			.signer
+			.sign(client.id_token_signed_response_alg, &claims)
+			.map_err(server_error)?;
+
+		let scope = pending.scope.join(" ");
+		let access_token = random_token();
+		let mut state = self.state.lock().expect("OIDC state lock poisoned");
+		let now = Instant::now();
+		state.sessions.retain(|_, session| session.expires > now);
+		state.sessions.insert(
+			access_token.clone(),
+			Session {
+				account: pending.account,
+				client_id: pending.client_id,
+				scope: pending.scope,
+				expires: now + TOKEN_LIFETIME,
+			},
+		);
+
+		Ok(json_response(json!({
+			"access_token": access_token,
+			"token_type": "Bearer",
+			"expires_in": TOKEN_LIFETIME.as_secs(),
+			"scope": scope,
+			"id_token": id_token,
+		})))
+	}
+
+	fn userinfo(&self, request: &Request<Body>) -> Result<Response<Body>, OAuthError> {
+		let invalid_token = |description: &str| {
+			oauth_error(StatusCode::UNAUTHORIZED, "invalid_token", description.to_string())
+		};
+
+		let bearer = request
+			.headers()
+			.get(header::AUTHORIZATION)
+			.and_then(|value| value.to_str().ok())
+			.and_then(|value| value.strip_prefix("Bearer "))
+			.ok_or_else(|| invalid_token("Missing bearer token"))?;
+
+		let (account, client_id, scope) = {
+			let state = self.state.lock().expect("OIDC state lock poisoned");
+			let session = state
+				.sessions
+				.get(bearer)
+				.filter(|session| session.expires > Instant::now())
+				.ok_or_else(|| invalid_token("Unknown or expired bearer token"))?;
+			(session.account.clone(), session.client_id.clone(), session.scope.clone())
+		};
+
+		let mut claims = json!({ "sub": account.to_ss58check() });
+
+		// Only the fields the student disclosed to the service are returned, and of those only
+		// the ones the granted scopes cover.
+		let at = self.finalized_block();
+		let info = self
+			.client
+			.runtime_api()
+			.disclosed_identity(&at, account, client_id.into_bytes())
+			.map_err(|e| server_error(format!("Unable to query disclosed identity: {:?}", e)))?
+			.ok_or_else(|| invalid_token("The student no longer gives this service access"))?;
+		for (claim, required_scope, data) in
+			[("name", "profile", &info.display), ("email", "email", &info.email)]
+		{
+			if !scope.iter().any(|scope| scope == required_scope) {
+				continue
+			}
+			if let Some(value) = self.text(&at, data)? {
+				claims[claim] = value.into();
+			}
+		}
+
+		Ok(json_response(claims))
+	}
+}
+
+/// Verify a signature made by a wallet, which may have wrapped the message in `<Bytes>` tags.
+fn verify_wallet_signature(
+	signature: &sr25519::Signature,
+	message: &[u8],
+	public: &sr25519::Public,
+) -> bool {
+	let wrapped = [&b"<Bytes>"[..], message, &b"</Bytes>"[..]].concat();
+	sr25519::Pair::verify(signature, message, public) || sr25519::Pair::verify(signature, &wrapped, public)
+}
+
+fn json_response(body: Value) -> Response<Body> {
+	let mut response = Response::new(Body::from(body.to_string()));
+	let headers = response.headers_mut();
+	headers.insert(header::CONTENT_TYPE, header::HeaderValue::from_static("application/json"));
+	headers.insert(header::CACHE_CONTROL, header::HeaderValue::from_static("no-store"));
+	response
+}
+
+/// The client ID and secret of an `Authorization: Basic` header.
+fn basic_credentials(request: &Request<Body>) -> Option<(String, String)> {
+	let encoded = request
+		.headers()
+		.get(header::AUTHORIZATION)?
+		.to_str()
+		.ok()?
+		.strip_prefix("Basic ")?;
+	let decoded = String::from_utf8(base64::decode(encoded).ok()?).ok()?;
+	let (id, secret) = decoded.split_once(':')?;
+	// Both are form encoded before being joined (RFC 6749, section 2.3.1).
+	let form_decode = |value: &str| {
+		url::form_urlencoded::parse(value.as_bytes()).map(|(key, _)| key).collect::<String>()
+	};
+	Some((form_decode(id), form_decode(secret)))
+}
+
+fn unix_time() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+async fn read_form(request: Request<Body>) -> Result<HashMap<String, String>, OAuthError> {
+	let too_large = request
+		.headers()
+		.get(header::CONTENT_LENGTH)
+		.and_then(|value| value.to_str().ok()?.parse::<u64>().ok())
+		.map_or(true, |len| len > MAX_BODY_LEN);
+	if too_large {
+		return Err(invalid_request("Missing or too large `Content-Length`"))
+	}
+
+	let body = hyper::body::to_bytes(request.into_body())
+		.await
+		.map_err(|e| invalid_request(e.to_string()))?;
+	Ok(url::form_urlencoded::parse(&body).into_owned().collect())
+}
+
+fn random_token() -> String {
+	base64url(&rand::thread_rng().gen::<[u8; 32]>())
+}
+
+fn base64url(data: &[u8]) -> String {
+	base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+	hex::decode(s.strip_prefix("0x").unwrap_or(s)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn pkce_challenge_matches_rfc7636() {
+		// Appendix B of RFC 7636.
+		let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+		assert_eq!(
+			base64url(&sha2_256(verifier.as_bytes())),
+			"E9Melhoa2OwvFrEMTJguCHaoeK1t8URLbxmQh3L2RMw"
+		);
+	}
+
+	#[test]
+	fn authorization_message_fields_are_length_prefixed() {
+		let message =
+			authorization_message("wordpress", "https://a.example/cb", "n", 1_646_136_000, b"session1");
+		assert_eq!(
+			message,
+			"studentid-oidc:9:wordpress:20:https://a.example/cb:1:n:1646136000:0x73657373696f6e31"
+		);
+		assert_ne!(
+			authorization_message("a:b", "c", "n", 0, b"t"),
+			authorization_message("a", "b:c", "n", 0, b"t")
+		);
+	}
+
+	#[test]
+	fn wallet_signatures_may_be_wrapped() {
+		let pair = sr25519::Pair::from_seed(&[1; 32]);
+		let message = authorization_message("wordpress", "https://a.example/cb", "n", 0, b"session1");
+
+		let raw = pair.sign(message.as_bytes());
+		let wrapped = pair.sign(format!("<Bytes>{}</Bytes>", message).as_bytes());
+		assert!(verify_wallet_signature(&raw, message.as_bytes(), &pair.public()));
+		assert!(verify_wallet_signature(&wrapped, message.as_bytes(), &pair.public()));
+		assert!(!verify_wallet_signature(&raw, b"other", &pair.public()));
+	}
+}
@@ -7,12 +7,14 @@
 
 use std::sync::Arc;
 
-use node_template_runtime::{opaque::Block, AccountId, Balance, Index};
+use node_template_runtime::{opaque::Block, AccountId, Balance, Hash, Index, MaxAdditionalFields};
+use sc_consensus_manual_seal::EngineCommand;
 pub use sc_rpc_api::DenyUnsafe;
 use sc_transaction_pool_api::TransactionPool;
 use sp_api::ProvideRuntimeApi;
 use sp_block_builder::BlockBuilder;
 use sp_blockchain::{Error as BlockChainError, HeaderBackend, HeaderMetadata};
+use sp_keystore::SyncCryptoStorePtr;
 
 /// Full client dependencies.
 pub struct FullDeps<C, P> {
@@ -20,8 +22,15 @@ pub struct FullDeps<C, P> {
 	pub client: Arc<C>,
 	/// Transaction pool instance.
 	pub pool: Arc<P>,
+	/// The keystore registrar attestation keys are kept in.
+	pub keystore: SyncCryptoStorePtr,
 	/// Whether to deny unsafe calls
 	pub deny_unsafe: DenyUnsafe,
+	/// The studentid index, if the indexer is enabled.
+	pub index: Option<Arc<crate::indexer::Index>>,
+	/// Where to send the `engine_*` sealing commands, if blocks are sealed on demand. They are
+	/// unsafe, so only served where `deny_unsafe` allows it.
+	pub command_sink: Option<futures::channel::mpsc::Sender<EngineCommand<Hash>>>,
 }
 
 /// Instantiate all full RPC extensions.
@@ -32,19 +41,45 @@ where
 	C: Send + Sync + 'static,
 	C::Api: substrate_frame_rpc_system::AccountNonceApi<Block, AccountId, Index>,
 	C::Api: pallet_transaction_payment_rpc::TransactionPaymentRuntimeApi<Block, Balance>,
+	C::Api: pallet_studentid_rpc::StudentidRuntimeApi<Block, AccountId, MaxAdditionalFields>,
 	C::Api: BlockBuilder<Block>,
 	P: TransactionPool + 'static,
 {
+	use crate::indexer::rpc::{Indexer, IndexerApi};
+	use pallet_studentid_rpc::{
+		did::{Did, DidApi},
+		Studentid, StudentidApi,
+	};
 	use pallet_transaction_payment_rpc::{TransactionPayment, TransactionPaymentApi};
+	use sc_consensus_manual_seal::rpc::{ManualSeal, ManualSealApi};
 	use substrate_frame_rpc_system::{FullSystem, SystemApi};
 
 	let mut io = jsonrpc_core::IoHandler::default();
-	let FullDeps { client, pool, deny_unsafe } = deps;
+	let FullDeps { client, pool, keystore, deny_unsafe, index, command_sink } = deps;
 
 	io.extend_with(SystemApi::to_delegate(FullSystem::new(client.clone(), pool, deny_unsafe)));
 
 	io.extend_with(TransactionPaymentApi::to_delegate(TransactionPayment::new(client.clone())));
 
+	io.extend_with(StudentidApi::to_delegate(Studentid::<_, _, MaxAdditionalFields>::new(
+		client.clone(),
+		keystore,
+		deny_unsafe,
+	)));
+
+	io.extend_with(DidApi::to_delegate(Did::<_, _, AccountId, MaxAdditionalFields>::new(
+		client.clone(),
+	)));
+
+	if let Some(index) = index {
+		io.extend_with(IndexerApi::to_delegate(Indexer::new(index)));
+	}
+
+	// Anybody able to call these could seal and finalize blocks at will.
+	if let Some(command_sink) = command_sink.filter(|_| deny_unsafe.check_if_safe().is_ok()) {
+		io.extend_with(ManualSealApi::to_delegate(ManualSeal::new(command_sink)));
+	}
+
 	// Extend this RPC with a custom API by using the following syntax.
 	// `YourRpcStruct` should have a reference to a client, which is needed
 	// to call into the runtime.
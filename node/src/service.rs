@@ -1,16 +1,33 @@
 //! Service and ServiceFactory implementation. Specialized wrapper over substrate service.
 
-use node_template_runtime::{self, opaque::Block, RuntimeApi};
-use sc_client_api::{BlockBackend, ExecutorProvider};
+use codec::Decode;
+use frame_support::storage::StorageValue;
+use futures::StreamExt;
+use node_template_runtime::{self, opaque::Block, Runtime, RuntimeApi};
+use sc_client_api::{BlockBackend, ExecutorProvider, StorageProvider};
 use sc_consensus_aura::{ImportQueueParams, SlotProportion, StartAuraParams};
+use sc_consensus_manual_seal::{
+	consensus::aura::AuraConsensusDataProvider, EngineCommand, ManualSealParams,
+};
 pub use sc_executor::NativeElseWasmExecutor;
 use sc_finality_grandpa::SharedVoterState;
 use sc_keystore::LocalKeystore;
-use sc_service::{error::Error as ServiceError, Configuration, TaskManager};
+use sc_service::{
+	config::DatabaseSource, error::Error as ServiceError, Configuration, TaskManager,
+};
 use sc_telemetry::{Telemetry, TelemetryWorker};
 use sp_consensus::SlotData;
 use sp_consensus_aura::sr25519::AuthorityPair as AuraPair;
-use std::{sync::Arc, time::Duration};
+use sp_core::storage::StorageKey;
+use sp_runtime::generic::BlockId;
+use std::{
+	path::Path,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc,
+	},
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 // Our native executor instance.
 pub struct ExecutorDispatch;
@@ -37,8 +54,107 @@ type FullClient =
 type FullBackend = sc_service::TFullBackend<Block>;
 type FullSelectChain = sc_consensus::LongestChain<FullBackend, Block>;
 
+/// How blocks are sealed when not by Aura.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ArgEnum)]
+pub enum Sealing {
+	/// Seal and finalize a block as soon as a transaction enters the pool.
+	Instant,
+	/// Seal a block only when asked to with the `engine_createBlock` RPC method.
+	Manual,
+}
+
+/// Timestamps of manually sealed blocks.
+///
+/// The runtime still runs Aura, which requires the slot of every block, derived from its
+/// timestamp, to be greater than the last one. Blocks sealed within the same slot are moved
+/// forward in time to the next slot.
+struct SealTimestamps {
+	last: AtomicU64,
+	slot_duration: u64,
+}
+
+impl SealTimestamps {
+	/// Timestamps following the one of the best block of `client`.
+	fn new(client: &FullClient, slot_duration: Duration) -> Result<Self, ServiceError> {
+		let key = StorageKey(pallet_timestamp::Now::<Runtime>::hashed_key().to_vec());
+		let last = client
+			.storage(&BlockId::Hash(client.chain_info().best_hash), &key)?
+			.and_then(|now| u64::decode(&mut &now.0[..]).ok())
+			.unwrap_or_default();
+		Ok(Self { last: AtomicU64::new(last), slot_duration: slot_duration.as_millis() as u64 })
+	}
+
+	/// The current time, or the start of the slot after the last block if that is later.
+	fn next(&self) -> u64 {
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|now| now.as_millis() as u64)
+			.unwrap_or_default();
+		let next = |last: u64| {
+			let next_slot = (last / self.slot_duration + 1) * self.slot_duration;
+			now.max(next_slot)
+		};
+		let last = self
+			.last
+			.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(next(last)))
+			.unwrap_or_else(|last| last);
+		next(last)
+	}
+}
+
+/// A client that never writes to its database, see [`new_read_only_client`].
+pub struct ReadOnlyClient {
+	pub client: Arc<FullClient>,
+	/// The files of the secondary instance, removed once the client is dropped.
+	_secondary: tempfile::TempDir,
+}
+
+/// The number of columns of the RocksDB database at `path`, as `sc_client_db` laid it out.
+/// RocksDB refuses to open a database without all its columns.
+fn db_columns(path: &Path) -> Result<u32, ServiceError> {
+	let families = rocksdb::DB::list_cf(&rocksdb::Options::default(), path).map_err(|e| {
+		ServiceError::Other(format!("Unable to list the columns of {}: {}", path.display(), e))
+	})?;
+	Ok(families.iter().filter(|name| name.starts_with("col")).count() as u32)
+}
+
+/// A client of the database of `config` that never writes to it, for offline inspection.
+///
+/// The RocksDB database is opened as a secondary instance, which only reads the files of the
+/// primary one and may do so while a node runs on it. Unlike `new_partial`, this starts neither
+/// a transaction pool nor telemetry.
+pub fn new_read_only_client(mut config: Configuration) -> Result<ReadOnlyClient, ServiceError> {
+	let path = match &config.database {
+		DatabaseSource::RocksDb { path, .. } => path.clone(),
+		DatabaseSource::Auto { rocksdb_path, .. } if rocksdb_path.exists() => rocksdb_path.clone(),
+		_ =>
+			return Err(ServiceError::Other(
+				"Only RocksDB databases can be opened read-only.".into(),
+			)),
+	};
+	let secondary = tempfile::Builder::new().prefix("node-template-secondary-").tempdir()?;
+	let mut db_config = kvdb_rocksdb::DatabaseConfig::with_columns(db_columns(&path)?);
+	db_config.create_if_missing = false;
+	db_config.secondary = Some(secondary.path().to_path_buf());
+	let db = kvdb_rocksdb::Database::open(&db_config, &path).map_err(|e| {
+		ServiceError::Other(format!("Unable to open {} read-only: {}", path.display(), e))
+	})?;
+	config.database = DatabaseSource::Custom(sp_database::as_database(db));
+
+	let executor = NativeElseWasmExecutor::<ExecutorDispatch>::new(
+		config.wasm_method,
+		config.default_heap_pages,
+		config.max_runtime_instances,
+		config.runtime_cache_size,
+	);
+	let (client, _, _, _) =
+		sc_service::new_full_parts::<Block, RuntimeApi, _>(&config, None, executor)?;
+	Ok(ReadOnlyClient { client: Arc::new(client), _secondary: secondary })
+}
+
 pub fn new_partial(
 	config: &Configuration,
+	sealing: Option<Sealing>,
 ) -> Result<
 	sc_service::PartialComponents<
 		FullClient,
@@ -113,8 +229,13 @@ pub fn new_partial(
 
 	let slot_duration = sc_consensus_aura::slot_duration(&*client)?.slot_duration();
 
-	let import_queue =
-		sc_consensus_aura::import_queue::<AuraPair, _, _, _, _, _, _>(ImportQueueParams {
+	let import_queue = match sealing {
+		Some(_) => sc_consensus_manual_seal::import_queue(
+			Box::new(client.clone()),
+			&task_manager.spawn_essential_handle(),
+			config.prometheus_registry(),
+		),
+		None => sc_consensus_aura::import_queue::<AuraPair, _, _, _, _, _, _>(ImportQueueParams {
 			block_import: grandpa_block_import.clone(),
 			justification_import: Some(Box::new(grandpa_block_import.clone())),
 			client: client.clone(),
@@ -136,7 +257,8 @@ pub fn new_partial(
 			registry: config.prometheus_registry(),
 			check_for_equivocation: Default::default(),
 			telemetry: telemetry.as_ref().map(|x| x.handle()),
-		})?;
+		})?,
+	};
 
 	Ok(sc_service::PartialComponents {
 		client,
@@ -158,7 +280,18 @@ fn remote_keystore(_url: &String) -> Result<Arc<LocalKeystore>, &'static str> {
 }
 
 /// Builds a new service for a full client.
-pub fn new_full(mut config: Configuration) -> Result<TaskManager, ServiceError> {
+///
+/// If `oidc` is given the OpenID Connect provider is started as well, and if `indexer` is
+/// given the studentid indexer. The studentid metrics are registered with `metrics` when
+/// Prometheus is enabled. If `sealing` is given blocks are sealed on demand instead of by Aura,
+/// and GRANDPA is not started.
+pub fn new_full(
+	mut config: Configuration,
+	oidc: Option<crate::oidc::Config>,
+	indexer: Option<crate::indexer::Config>,
+	metrics: crate::metrics::Config,
+	sealing: Option<Sealing>,
+) -> Result<TaskManager, ServiceError> {
 	let sc_service::PartialComponents {
 		client,
 		backend,
@@ -168,7 +301,7 @@ pub fn new_full(mut config: Configuration) -> Result<TaskManager, ServiceError>
 		select_chain,
 		transaction_pool,
 		other: (block_import, grandpa_link, mut telemetry),
-	} = new_partial(&config)?;
+	} = new_partial(&config, sealing)?;
 
 	if let Some(url) = &config.keystore_remote {
 		match remote_keystore(url) {
@@ -222,13 +355,49 @@ pub fn new_full(mut config: Configuration) -> Result<TaskManager, ServiceError>
 	let enable_grandpa = !config.disable_grandpa;
 	let prometheus_registry = config.prometheus_registry().cloned();
 
+	let index = match indexer {
+		Some(indexer) => {
+			let path = indexer
+				.path
+				.or_else(|| {
+					let db = config.database.path()?.parent()?;
+					Some(db.join("studentid-index.sqlite"))
+				})
+				.ok_or_else(|| {
+					ServiceError::Other(
+						"The studentid index needs --studentid-index-path with this database."
+							.into(),
+					)
+				})?;
+			Some(Arc::new(crate::indexer::Index::open(&path).map_err(ServiceError::Other)?))
+		},
+		None => None,
+	};
+
+	let (command_sink, commands_stream) = match sealing {
+		Some(_) => {
+			let (sink, stream) = futures::channel::mpsc::channel(1024);
+			(Some(sink), Some(stream))
+		},
+		None => (None, None),
+	};
+
 	let rpc_extensions_builder = {
 		let client = client.clone();
 		let pool = transaction_pool.clone();
+		let keystore = keystore_container.sync_keystore();
+		let index = index.clone();
+		let command_sink = command_sink.clone();
 
 		Box::new(move |deny_unsafe, _| {
-			let deps =
-				crate::rpc::FullDeps { client: client.clone(), pool: pool.clone(), deny_unsafe };
+			let deps = crate::rpc::FullDeps {
+				client: client.clone(),
+				pool: pool.clone(),
+				keystore: keystore.clone(),
+				deny_unsafe,
+				index: index.clone(),
+				command_sink: command_sink.clone(),
+			};
 
 			Ok(crate::rpc::create_full(deps))
 		})
@@ -247,6 +416,83 @@ pub fn new_full(mut config: Configuration) -> Result<TaskManager, ServiceError>
 		telemetry: telemetry.as_mut(),
 	})?;
 
+	if let Some(oidc) = oidc {
+		let provider =
+			crate::oidc::start(oidc, client.clone(), keystore_container.sync_keystore())
+				.map_err(ServiceError::Other)?;
+		task_manager.spawn_handle().spawn("oidc-provider", None, provider);
+	}
+
+	if let Some(index) = index {
+		task_manager.spawn_handle().spawn_blocking(
+			"studentid-indexer",
+			None,
+			crate::indexer::run(client.clone(), index),
+		);
+	}
+
+	if let Some(registry) = prometheus_registry.as_ref() {
+		let metrics = crate::metrics::Metrics::register(registry, metrics)?;
+		task_manager.spawn_handle().spawn_blocking(
+			"studentid-metrics",
+			None,
+			crate::metrics::run(client.clone(), metrics.clone()),
+		);
+		task_manager.spawn_handle().spawn_blocking(
+			"studentid-metrics-sampling",
+			None,
+			crate::metrics::sample_periodically(client.clone(), metrics),
+		);
+	}
+
+	if let (Some(sealing), Some(rpc_commands)) = (sealing, commands_stream) {
+		let proposer_factory = sc_basic_authorship::ProposerFactory::new(
+			task_manager.spawn_handle(),
+			client.clone(),
+			transaction_pool.clone(),
+			prometheus_registry.as_ref(),
+			telemetry.as_ref().map(|x| x.handle()),
+		);
+
+		let commands_stream: Box<dyn futures::Stream<Item = _> + Send + Unpin> = match sealing {
+			Sealing::Manual => Box::new(rpc_commands),
+			Sealing::Instant => Box::new(futures::stream::select(
+				rpc_commands,
+				transaction_pool.import_notification_stream().map(|_| EngineCommand::SealNewBlock {
+					create_empty: false,
+					finalize: true,
+					parent_hash: None,
+					sender: None,
+				}),
+			)),
+		};
+
+		let slot_duration = sc_consensus_aura::slot_duration(&*client)?.slot_duration();
+		let timestamps = Arc::new(SealTimestamps::new(&client, slot_duration)?);
+
+		let manual_seal = sc_consensus_manual_seal::run_manual_seal(ManualSealParams {
+			block_import: client.clone(),
+			env: proposer_factory,
+			client: client.clone(),
+			pool: transaction_pool,
+			commands_stream,
+			select_chain,
+			consensus_data_provider: Some(Box::new(AuraConsensusDataProvider::new(client.clone()))),
+			create_inherent_data_providers: move |_, ()| {
+				let timestamp = timestamps.next();
+				async move { Ok(sp_timestamp::InherentDataProvider::new(timestamp.into())) }
+			},
+		});
+
+		// like Aura, sealing is essential: if it fails we take down the service with it.
+		task_manager
+			.spawn_essential_handle()
+			.spawn_blocking("manual-seal", Some("block-authoring"), manual_seal);
+
+		network_starter.start_network();
+		return Ok(task_manager)
+	}
+
 	if role.is_authority() {
 		let proposer_factory = sc_basic_authorship::ProposerFactory::new(
 			task_manager.spawn_handle(),
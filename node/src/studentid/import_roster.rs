@@ -0,0 +1,526 @@
+//! Bulk import of enrollment rosters.
+//!
+//! Reads one student per row (email, display name, program and term), rejects rows that do
+//! not fit the pallet's limits and normalizes the rest. The students of each term make up one
+//! Merkle roster (see `studentid_roster`) whose root the registrar publishes; students claim
+//! their enrollment with the proofs written by `--proofs`. Only the roots go on-chain, either
+//! through `publish_roster` extrinsics signed with the registrar's key from the keystore or a
+//! raw chain spec patch for new networks.
+
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	fs,
+	path::{Path, PathBuf},
+};
+
+use codec::Encode;
+use frame_system_rpc_runtime_api::AccountNonceApi;
+use node_template_runtime::{
+	AccountId, BlockNumber, Call, ChargeTransactionPaymentOrFeeless, MaxEmailsize, Runtime,
+	SignedExtra, UncheckedExtrinsic,
+};
+use pallet_studentid::{normalize_domain, RegistrarIndex, Roster};
+use sc_cli::{CliConfiguration, DatabaseParams, KeystoreParams, SharedParams};
+use sc_service::{Configuration, PartialComponents};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sp_api::{Core, ProvideRuntimeApi};
+use sp_blockchain::HeaderBackend;
+use sp_core::{
+	crypto::{key_types::ACCOUNT, CryptoTypePublicPair, Ss58Codec},
+	sr25519, Get,
+};
+use sp_keystore::SyncCryptoStore;
+use sp_runtime::{
+	generic::{BlockId, Era, SignedPayload},
+	MultiSignature,
+};
+use studentid_roster::{Leaf, Status, Term};
+
+use crate::service;
+
+/// The longest display name or program that fits a `Data::Raw` field.
+const MAX_FIELD_LEN: usize = 32;
+
+/// The number of blocks the signed extrinsics stay valid for from the best block. A power of
+/// two within the runtime's `BlockHashCount`, so the hash of the block they are anchored at is
+/// still known when they expire.
+const MORTALITY: u64 = 2048;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum RosterFormat {
+	Csv,
+	Json,
+}
+
+/// The `studentid import-roster` command.
+#[derive(Debug, clap::Parser)]
+pub struct ImportRosterCmd {
+	/// The CSV or JSON file with the students: `email`, `display_name`, `program` and `term`
+	/// columns or keys.
+	#[clap(long, parse(from_os_str), value_name = "FILE")]
+	pub input: PathBuf,
+
+	/// The format of the input file. Guessed from its extension if left out.
+	#[clap(long, arg_enum)]
+	pub format: Option<RosterFormat>,
+
+	/// The index of the registrar publishing the rosters.
+	#[clap(long, value_name = "INDEX")]
+	pub registrar_index: RegistrarIndex,
+
+	/// The block the statuses students claim from the rosters lapse at. Required to publish the
+	/// rosters.
+	#[clap(long, value_name = "BLOCK")]
+	pub valid_until: Option<BlockNumber>,
+
+	/// Only validate the rows and report the rejected ones.
+	#[clap(long)]
+	pub dry_run: bool,
+
+	/// Write the Merkle proof every student claims their enrollment with to this JSON file.
+	#[clap(long, parse(from_os_str), value_name = "FILE")]
+	pub proofs: Option<PathBuf>,
+
+	/// Write one signed `publish_roster` extrinsic per term to this file, hex encoded, one per
+	/// line, for `author_submitExtrinsic`. They must be submitted within 2048 blocks of the
+	/// best block of the local database.
+	#[clap(long, parse(from_os_str), value_name = "FILE", requires = "registrar")]
+	pub extrinsics: Option<PathBuf>,
+
+	/// The SS58 address of the registrar account signing the extrinsics. Its sr25519 key must
+	/// be in the keystore under the `acco` key type.
+	#[clap(long, value_name = "SS58")]
+	pub registrar: Option<String>,
+
+	/// The nonce of the first extrinsic. Read from the local database at the best block if
+	/// left out.
+	#[clap(long)]
+	pub nonce: Option<u32>,
+
+	/// Write the roster roots as raw genesis storage to this chain spec patch, for new
+	/// networks.
+	#[clap(long, parse(from_os_str), value_name = "FILE")]
+	pub chain_spec_patch: Option<PathBuf>,
+
+	#[allow(missing_docs)]
+	#[clap(flatten)]
+	pub shared_params: SharedParams,
+
+	#[allow(missing_docs)]
+	#[clap(flatten)]
+	pub keystore_params: KeystoreParams,
+
+	#[allow(missing_docs)]
+	#[clap(flatten)]
+	pub database_params: DatabaseParams,
+}
+
+/// A row as read from the input file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Row {
+	pub email: String,
+	pub display_name: String,
+	pub program: String,
+	pub term: Value,
+}
+
+/// A validated and normalized row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Student {
+	pub email: String,
+	pub display_name: String,
+	pub program: String,
+	pub term: Term,
+}
+
+impl Student {
+	fn leaf(&self) -> Leaf {
+		Leaf {
+			email_hash: sp_core::hashing::blake2_256(self.email.as_bytes()),
+			status: Status::Enrolled,
+			term: self.term,
+		}
+	}
+}
+
+/// A rejected row, by its 1-based row number in the input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejected {
+	pub row: usize,
+	pub reason: String,
+}
+
+/// Parse `input` into rows.
+pub fn parse_rows(input: &str, format: RosterFormat) -> Result<Vec<Row>, String> {
+	match format {
+		RosterFormat::Json =>
+			serde_json::from_str(input).map_err(|e| format!("Invalid JSON roster: {}", e)),
+		RosterFormat::Csv => csv::ReaderBuilder::new()
+			.trim(csv::Trim::All)
+			.from_reader(input.as_bytes())
+			.deserialize()
+			.collect::<Result<_, _>>()
+			.map_err(|e| format!("Invalid CSV roster: {}", e)),
+	}
+}
+
+/// `email` with surrounding whitespace removed and the domain normalized like
+/// `pallet_studentid` does when routing registrations.
+pub fn normalize_email(email: &str) -> Result<String, String> {
+	let email = email.trim();
+	let (local, domain) = email.rsplit_once('@').ok_or("the email has no @")?;
+	if local.is_empty() || local.chars().any(char::is_whitespace) {
+		return Err("the email has an invalid local part".into())
+	}
+	let domain = String::from_utf8(normalize_domain(domain.as_bytes()))
+		.map_err(|_| "the email domain is not valid UTF-8")?;
+	if !domain.contains('.') || domain.starts_with('.') || domain.contains("..") {
+		return Err("the email has an invalid domain".into())
+	}
+	let email = format!("{}@{}", local, domain);
+	if email.len() > MaxEmailsize::get() as usize {
+		return Err(format!("the email is longer than {} bytes", MaxEmailsize::get()))
+	}
+	Ok(email)
+}
+
+fn validate_row(row: &Row) -> Result<Student, String> {
+	let email = normalize_email(&row.email)?;
+	let display_name = row.display_name.trim().to_string();
+	if display_name.is_empty() {
+		return Err("the display name is empty".into())
+	}
+	if display_name.len() > MAX_FIELD_LEN {
+		return Err(format!("the display name is longer than {} bytes", MAX_FIELD_LEN))
+	}
+	let program = row.program.trim().to_string();
+	if program.len() > MAX_FIELD_LEN {
+		return Err(format!("the program is longer than {} bytes", MAX_FIELD_LEN))
+	}
+	let term = match &row.term {
+		Value::Number(n) => n.as_u64().and_then(|n| Term::try_from(n).ok()),
+		Value::String(s) => s.trim().parse().ok(),
+		_ => None,
+	}
+	.ok_or("the term is not a number")?;
+	Ok(Student { email, display_name, program, term })
+}
+
+/// Validate and normalize `rows`, rejecting invalid rows and repeated students of a term.
+pub fn validate(rows: &[Row]) -> (Vec<Student>, Vec<Rejected>) {
+	let mut seen = BTreeSet::new();
+	let mut students = Vec::new();
+	let mut rejected = Vec::new();
+	for (i, row) in rows.iter().enumerate() {
+		let result = validate_row(row).and_then(|student| {
+			if seen.insert((student.term, student.email.clone())) {
+				Ok(student)
+			} else {
+				Err(format!("{} is listed twice for term {}", student.email, student.term))
+			}
+		});
+		match result {
+			Ok(student) => students.push(student),
+			Err(reason) => rejected.push(Rejected { row: i + 1, reason }),
+		}
+	}
+	(students, rejected)
+}
+
+/// The students of each term, in input order.
+pub fn by_term(students: &[Student]) -> BTreeMap<Term, Vec<&Student>> {
+	let mut terms = BTreeMap::<_, Vec<_>>::new();
+	for student in students {
+		terms.entry(student.term).or_default().push(student);
+	}
+	terms
+}
+
+/// The roster root of each term.
+pub fn roots(terms: &BTreeMap<Term, Vec<&Student>>) -> BTreeMap<Term, [u8; 32]> {
+	terms
+		.iter()
+		.map(|(term, students)| {
+			let leaves: Vec<_> = students.iter().map(|s| s.leaf()).collect();
+			(*term, studentid_roster::root(&leaves))
+		})
+		.collect()
+}
+
+/// The proofs file: every student with the proof of their leaf.
+pub fn proofs(terms: &BTreeMap<Term, Vec<&Student>>) -> Value {
+	let entries = terms.values().flat_map(|students| {
+		let leaves: Vec<_> = students.iter().map(|s| s.leaf()).collect();
+		students
+			.iter()
+			.enumerate()
+			.map(move |(i, student)| {
+				let proof =
+					studentid_roster::proof(&leaves, i).expect("index within the leaves; qed");
+				json!({
+					"email": student.email,
+					"displayName": student.display_name,
+					"program": student.program,
+					"term": student.term,
+					"status": "Enrolled",
+					"proof": proof.iter().map(|node| format!("0x{}", hex::encode(node))).collect::<Vec<_>>(),
+				})
+			})
+			.collect::<Vec<_>>()
+	});
+	Value::Array(entries.collect())
+}
+
+/// A chain spec patch setting the rosters of the registrar in genesis storage.
+pub fn chain_spec_patch(
+	registrar: RegistrarIndex,
+	roots: &BTreeMap<Term, [u8; 32]>,
+	valid_until: BlockNumber,
+) -> Value {
+	let top: serde_json::Map<_, _> = roots
+		.iter()
+		.map(|(term, root)| {
+			let key = pallet_studentid::Rosters::<Runtime>::hashed_key_for(registrar, term);
+			let roster = Roster { root: *root, valid_until };
+			(
+				format!("0x{}", hex::encode(key)),
+				Value::String(format!("0x{}", hex::encode(roster.encode()))),
+			)
+		})
+		.collect();
+	json!({ "genesis": { "raw": { "top": top } } })
+}
+
+impl ImportRosterCmd {
+	/// Run the command.
+	pub fn run(&self, config: Configuration) -> sc_cli::Result<()> {
+		let input = fs::read_to_string(&self.input)?;
+		let rows = parse_rows(&input, self.format()?)?;
+		let (students, rejected) = validate(&rows);
+		let terms = by_term(&students);
+
+		println!("{} of {} rows accepted", students.len(), rows.len());
+		for (term, students) in &terms {
+			println!("  term {}: {} students", term, students.len());
+		}
+		for r in &rejected {
+			println!("rejected row {}: {}", r.row, r.reason);
+		}
+		if self.dry_run || students.is_empty() {
+			return Ok(())
+		}
+
+		let roots = roots(&terms);
+		if let Some(path) = &self.proofs {
+			write_json(path, &proofs(&terms))?;
+		}
+		if let Some(path) = &self.chain_spec_patch {
+			let patch = chain_spec_patch(self.registrar_index, &roots, self.valid_until()?);
+			write_json(path, &patch)?;
+		}
+		if let Some(path) = &self.extrinsics {
+			let extrinsics = self.sign(config, &roots)?;
+			let lines: Vec<_> =
+				extrinsics.iter().map(|x| format!("0x{}", hex::encode(x))).collect();
+			fs::write(path, lines.join("\n") + "\n")?;
+			println!("wrote {} extrinsics to {}", lines.len(), path.display());
+		}
+		Ok(())
+	}
+
+	fn format(&self) -> Result<RosterFormat, String> {
+		if let Some(format) = self.format {
+			return Ok(format)
+		}
+		match self.input.extension().and_then(|e| e.to_str()) {
+			Some(e) if e.eq_ignore_ascii_case("csv") => Ok(RosterFormat::Csv),
+			Some(e) if e.eq_ignore_ascii_case("json") => Ok(RosterFormat::Json),
+			_ => Err("Unable to tell the roster format from the file name; pass --format.".into()),
+		}
+	}
+
+	fn valid_until(&self) -> Result<BlockNumber, String> {
+		self.valid_until.ok_or_else(|| "--valid-until is required to publish rosters".into())
+	}
+
+	/// One `publish_roster` extrinsic per term, signed by the registrar with consecutive nonces.
+	fn sign(
+		&self,
+		config: Configuration,
+		roots: &BTreeMap<Term, [u8; 32]>,
+	) -> sc_cli::Result<Vec<Vec<u8>>> {
+		let address =
+			self.registrar.as_ref().ok_or("--registrar is required to sign extrinsics")?;
+		let public = sr25519::Public::from_ss58check(address)
+			.map_err(|e| format!("Invalid registrar address: {:?}", e))?;
+		let account: AccountId = public.into();
+		let valid_until = self.valid_until()?;
+
+		let PartialComponents { client, keystore_container, .. } = service::new_partial(&config, None)?;
+		let keystore = keystore_container.sync_keystore();
+		let info = client.info();
+		let at = BlockId::Hash(info.best_hash);
+		let era = Era::mortal(MORTALITY, info.best_number.into());
+		let birth = era.birth(info.best_number.into()) as BlockNumber;
+		let birth_hash = client
+			.hash(birth)
+			.ok()
+			.flatten()
+			.ok_or_else(|| format!("Unable to read the hash of block {}", birth))?;
+		let api = client.runtime_api();
+		let version = api
+			.version(&at)
+			.map_err(|e| format!("Unable to read the runtime version: {}", e))?;
+		let mut nonce = match self.nonce {
+			Some(nonce) => nonce,
+			None => api
+				.account_nonce(&at, account.clone())
+				.map_err(|e| format!("Unable to read the registrar nonce: {}", e))?,
+		};
+
+		let mut extrinsics = Vec::new();
+		for (term, root) in roots {
+			let call = Call::Identity(pallet_studentid::Call::publish_roster {
+				reg_index: self.registrar_index,
+				term: *term,
+				root: *root,
+				valid_until,
+			});
+			let extra: SignedExtra = (
+				frame_system::CheckNonZeroSender::new(),
+				frame_system::CheckSpecVersion::new(),
+				frame_system::CheckTxVersion::new(),
+				frame_system::CheckGenesis::new(),
+				frame_system::CheckEra::from(era),
+				frame_system::CheckNonce::from(nonce),
+				frame_system::CheckWeight::new(),
+				ChargeTransactionPaymentOrFeeless::from(0),
+			);
+			let payload = SignedPayload::from_raw(
+				call.clone(),
+				extra.clone(),
+				(
+					(),
+					version.spec_version,
+					version.transaction_version,
+					info.genesis_hash,
+					birth_hash,
+					(),
+					(),
+					(),
+				),
+			);
+			let signature = payload
+				.using_encoded(|payload| {
+					SyncCryptoStore::sign_with(
+						&*keystore,
+						ACCOUNT,
+						&CryptoTypePublicPair::from(public),
+						payload,
+					)
+				})
+				.map_err(|e| format!("Unable to sign extrinsic: {}", e))?
+				.ok_or("The registrar key is not in the keystore.")?;
+			let signature = sr25519::Signature::try_from(&signature[..])
+				.map_err(|_| "The keystore returned an invalid signature.")?;
+
+			let extrinsic = UncheckedExtrinsic::new_signed(
+				call,
+				account.clone().into(),
+				MultiSignature::Sr25519(signature),
+				extra,
+			);
+			extrinsics.push(extrinsic.encode());
+			nonce += 1;
+		}
+		Ok(extrinsics)
+	}
+}
+
+fn write_json(path: &Path, value: &Value) -> sc_cli::Result<()> {
+	fs::write(path, serde_json::to_string_pretty(value).map_err(|e| e.to_string())?)?;
+	println!("wrote {}", path.display());
+	Ok(())
+}
+
+impl CliConfiguration for ImportRosterCmd {
+	fn shared_params(&self) -> &SharedParams {
+		&self.shared_params
+	}
+
+	fn keystore_params(&self) -> Option<&KeystoreParams> {
+		Some(&self.keystore_params)
+	}
+
+	fn database_params(&self) -> Option<&DatabaseParams> {
+		Some(&self.database_params)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const CSV: &str = "email,display_name,program,term
+ dara@RUPP.edu.kh. , Dara , CS , 2024
+sok@rupp.edu.kh,Sok,,2024
+dara@rupp.edu.kh,Dara again,CS,2024
+nobody,No One,CS,2024
+vy@rupp.edu.kh,,CS,2024
+vy@rupp.edu.kh,Vy,CS,next
+vy@rupp.edu.kh,Vy,CS,2025
+";
+
+	#[test]
+	fn rows_are_validated_and_normalized() {
+		let rows = parse_rows(CSV, RosterFormat::Csv).unwrap();
+		let (students, rejected) = validate(&rows);
+		assert_eq!(
+			students[0],
+			Student {
+				email: "dara@rupp.edu.kh".into(),
+				display_name: "Dara".into(),
+				program: "CS".into(),
+				term: 2024,
+			}
+		);
+		assert_eq!(students.len(), 3);
+		assert_eq!(rejected.iter().map(|r| r.row).collect::<Vec<_>>(), vec![3, 4, 5, 6]);
+		assert_eq!(rejected[0].reason, "dara@rupp.edu.kh is listed twice for term 2024");
+	}
+
+	#[test]
+	fn json_rows_match_csv_rows() {
+		let json = r#"[
+			{"email": "sok@rupp.edu.kh", "display_name": "Sok", "program": "", "term": 2024},
+			{"email": "vy@rupp.edu.kh", "display_name": "Vy", "program": "CS", "term": "2025"}
+		]"#;
+		let (students, rejected) = validate(&parse_rows(json, RosterFormat::Json).unwrap());
+		assert!(rejected.is_empty());
+		assert_eq!(students.iter().map(|s| s.term).collect::<Vec<_>>(), vec![2024, 2025]);
+	}
+
+	#[test]
+	fn proofs_verify_against_the_published_roots() {
+		let (students, _) = validate(&parse_rows(CSV, RosterFormat::Csv).unwrap());
+		let terms = by_term(&students);
+		let roots = roots(&terms);
+		assert_eq!(roots.len(), 2);
+		for (term, students) in &terms {
+			let leaves: Vec<_> = students.iter().map(|s| s.leaf()).collect();
+			for (i, leaf) in leaves.iter().enumerate() {
+				let proof = studentid_roster::proof(&leaves, i).unwrap();
+				assert!(studentid_roster::verify(&roots[term], leaf, &proof));
+			}
+		}
+		assert_eq!(proofs(&terms).as_array().unwrap().len(), 3);
+	}
+
+	#[test]
+	fn extrinsics_expire_while_their_birth_block_is_known() {
+		assert!(MORTALITY.is_power_of_two());
+		assert!(MORTALITY <= node_template_runtime::BlockHashCount::get() as u64);
+	}
+}
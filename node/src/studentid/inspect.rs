@@ -0,0 +1,535 @@
+//! Offline inspection of the student records in the local database.
+//!
+//! Reads `StudentidOf`, `EmailId` and `Tokens` at a block straight from the client's storage,
+//! decodes them with the pallet's types and prints what matches the filters. The database is
+//! opened read-only, see `service::new_read_only_client`, so this can be run against a copy of a
+//! support ticket's node or next to a running one. Tokens that do not decode, e.g. ones a
+//! storage migration has not reached, are skipped and reported.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use codec::Decode;
+use frame_support::storage::StoragePrefixedMap;
+use node_template_runtime::{
+	opaque::Block, AccountId, Balance, BlockNumber, MaxAdditionalFields, Runtime,
+};
+use pallet_studentid::{
+	Data, Email, EmailId, RegistrationSel, StudentidOf, TokenId, TokenInfoOf, Tokens,
+};
+use sc_cli::{BlockNumberOrHash, CliConfiguration, DatabaseParams, PruningParams, SharedParams};
+use sc_client_api::StorageProvider;
+use sc_service::Configuration;
+use serde_json::{json, Value};
+use sp_blockchain::HeaderBackend;
+use sp_core::{crypto::Ss58Codec, hashing::blake2_256, storage::StorageKey};
+use sp_runtime::generic::BlockId;
+
+use crate::service;
+
+type Registration = RegistrationSel<Balance, AccountId, MaxAdditionalFields>;
+
+/// The `studentid inspect` command.
+#[derive(Debug, clap::Parser)]
+pub struct InspectCmd {
+	/// The block to inspect the state at, by number or hash. Defaults to the best block.
+	#[clap(long, value_name = "HASH or NUMBER")]
+	pub at: Option<BlockNumberOrHash>,
+
+	/// Only show the records of this account.
+	#[clap(long, value_name = "SS58")]
+	pub account: Option<String>,
+
+	/// Only show the records of the email with this Blake2-256 hash.
+	#[clap(long, value_name = "HEX")]
+	pub email_hash: Option<String>,
+
+	/// Only show this access token and the records of its owner.
+	#[clap(long, value_name = "TOKEN")]
+	pub token: Option<String>,
+
+	/// Only print the summary statistics.
+	#[clap(long)]
+	pub summary: bool,
+
+	/// Print JSON instead of human readable output.
+	#[clap(long)]
+	pub json: bool,
+
+	#[allow(missing_docs)]
+	#[clap(flatten)]
+	pub shared_params: SharedParams,
+
+	#[allow(missing_docs)]
+	#[clap(flatten)]
+	pub pruning_params: PruningParams,
+
+	#[allow(missing_docs)]
+	#[clap(flatten)]
+	pub database_params: DatabaseParams,
+}
+
+/// A registration in `StudentidOf`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Student {
+	pub email: Vec<u8>,
+	pub account: AccountId,
+	pub deposit: Balance,
+	pub display: Data,
+}
+
+/// An access token in `Tokens`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+	pub id: Vec<u8>,
+	pub owner: AccountId,
+	pub data: Data,
+	pub issued: BlockNumber,
+	pub expires: BlockNumber,
+}
+
+/// The student records at a block.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Records {
+	pub number: BlockNumber,
+	pub students: Vec<Student>,
+	/// The `EmailId` entries: the email each account is linked to.
+	pub links: BTreeMap<AccountId, Vec<u8>>,
+	pub tokens: Vec<Token>,
+	/// The storage keys of the `Tokens` entries that do not decode.
+	pub undecodable_tokens: Vec<Vec<u8>>,
+}
+
+/// What to narrow the records down to. Filters combine: a record must match all of them.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+	pub account: Option<AccountId>,
+	pub email_hash: Option<[u8; 32]>,
+	pub token: Option<Vec<u8>>,
+}
+
+impl Filter {
+	fn is_empty(&self) -> bool {
+		self.account.is_none() && self.email_hash.is_none() && self.token.is_none()
+	}
+
+	fn matches(&self, account: &AccountId, email: Option<&[u8]>, records: &Records) -> bool {
+		let email = email.or_else(|| {
+			records.links.get(account).map(|e| &e[..]).or_else(|| {
+				records.students.iter().find(|s| &s.account == account).map(|s| &s.email[..])
+			})
+		});
+		self.account.as_ref().map_or(true, |a| a == account) &&
+			self.email_hash.map_or(true, |h| email.map_or(false, |e| blake2_256(e) == h)) &&
+			self.token.as_ref().map_or(true, |id| {
+				records.tokens.iter().any(|t| &t.id == id && &t.owner == account)
+			})
+	}
+
+	/// The records that match the filter.
+	pub fn apply(&self, records: &Records) -> Records {
+		if self.is_empty() {
+			return records.clone()
+		}
+		Records {
+			number: records.number,
+			students: records
+				.students
+				.iter()
+				.filter(|s| self.matches(&s.account, Some(&s.email), records))
+				.cloned()
+				.collect(),
+			links: records
+				.links
+				.iter()
+				.filter(|(account, email)| self.matches(account, Some(email), records))
+				.map(|(account, email)| (account.clone(), email.clone()))
+				.collect(),
+			tokens: records
+				.tokens
+				.iter()
+				.filter(|t| self.token.as_ref().map_or(true, |id| &t.id == id))
+				.filter(|t| self.matches(&t.owner, None, records))
+				.cloned()
+				.collect(),
+			// Whom these belong to is unknown, so they are always reported.
+			undecodable_tokens: records.undecodable_tokens.clone(),
+		}
+	}
+}
+
+/// Summary statistics of a set of records.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+	pub students: usize,
+	pub linked_accounts: usize,
+	/// `EmailId` entries whose email has no registration.
+	pub dangling_links: usize,
+	pub tokens: usize,
+	pub active_tokens: usize,
+	pub undecodable_tokens: usize,
+	pub students_by_domain: BTreeMap<String, usize>,
+}
+
+impl Summary {
+	pub fn of(records: &Records) -> Self {
+		let registered: BTreeSet<_> = records.students.iter().map(|s| &s.email).collect();
+		let mut students_by_domain = BTreeMap::new();
+		for student in &records.students {
+			let domain = pallet_studentid::email_domain(&student.email).unwrap_or_default();
+			let domain = String::from_utf8_lossy(&domain).into_owned();
+			*students_by_domain.entry(domain).or_default() += 1;
+		}
+		let active_tokens = records.tokens.iter().filter(|t| t.expires > records.number).count();
+		Summary {
+			students: records.students.len(),
+			linked_accounts: records.links.len(),
+			dangling_links: records.links.values().filter(|e| !registered.contains(e)).count(),
+			tokens: records.tokens.len(),
+			active_tokens,
+			undecodable_tokens: records.undecodable_tokens.len(),
+			students_by_domain,
+		}
+	}
+
+	fn to_json(&self) -> Value {
+		json!({
+			"students": self.students,
+			"linkedAccounts": self.linked_accounts,
+			"danglingLinks": self.dangling_links,
+			"tokens": self.tokens,
+			"activeTokens": self.active_tokens,
+			"undecodableTokens": self.undecodable_tokens,
+			"studentsByDomain": self.students_by_domain,
+		})
+	}
+}
+
+/// The key a `Twox64Concat` map entry was inserted under, from its full storage key.
+fn decode_key<K: Decode>(prefix: &[u8; 32], key: &StorageKey) -> Option<K> {
+	let mut rest = key.0.strip_prefix(&prefix[..])?.get(8..)?;
+	K::decode(&mut rest).ok()
+}
+
+fn data_to_json(data: &Data) -> Value {
+	match data {
+		Data::None => Value::Null,
+		Data::Raw(raw) => Value::String(String::from_utf8_lossy(raw).into_owned()),
+		Data::BlakeTwo256(h) | Data::Sha256(h) | Data::Keccak256(h) | Data::ShaThree256(h) =>
+			Value::String(format!("0x{}", hex::encode(h))),
+	}
+}
+
+fn text(data: &Data) -> String {
+	match data_to_json(data) {
+		Value::String(s) => s,
+		_ => "-".into(),
+	}
+}
+
+impl Records {
+	fn to_json(&self) -> Value {
+		json!({
+			"block": self.number,
+			"students": self.students.iter().map(|s| json!({
+				"email": String::from_utf8_lossy(&s.email),
+				"emailHash": format!("0x{}", hex::encode(blake2_256(&s.email))),
+				"account": s.account.to_ss58check(),
+				"linked": self.links.get(&s.account) == Some(&s.email),
+				"deposit": s.deposit.to_string(),
+				"display": data_to_json(&s.display),
+			})).collect::<Vec<_>>(),
+			"links": self.links.iter().map(|(account, email)| json!({
+				"account": account.to_ss58check(),
+				"email": String::from_utf8_lossy(email),
+			})).collect::<Vec<_>>(),
+			"tokens": self.tokens.iter().map(|t| json!({
+				"token": String::from_utf8_lossy(&t.id),
+				"owner": t.owner.to_ss58check(),
+				"data": data_to_json(&t.data),
+				"issued": t.issued,
+				"expires": t.expires,
+				"active": t.expires > self.number,
+			})).collect::<Vec<_>>(),
+			"undecodableTokens": self.undecodable_tokens.iter()
+				.map(|key| format!("0x{}", hex::encode(key)))
+				.collect::<Vec<_>>(),
+		})
+	}
+
+	fn print(&self) {
+		println!("block #{}", self.number);
+		for s in &self.students {
+			let linked =
+				if self.links.get(&s.account) == Some(&s.email) { "" } else { " (unlinked)" };
+			println!(
+				"student {} [0x{}]\n  account {}{}\n  display {}\n  deposit {}",
+				String::from_utf8_lossy(&s.email),
+				hex::encode(blake2_256(&s.email)),
+				s.account.to_ss58check(),
+				linked,
+				text(&s.display),
+				s.deposit,
+			);
+		}
+		for (account, email) in &self.links {
+			println!("link {} -> {}", account.to_ss58check(), String::from_utf8_lossy(email));
+		}
+		for t in &self.tokens {
+			println!(
+				"token {}\n  owner {}\n  data {}\n  issued #{}, expires #{}{}",
+				String::from_utf8_lossy(&t.id),
+				t.owner.to_ss58check(),
+				text(&t.data),
+				t.issued,
+				t.expires,
+				if t.expires > self.number { "" } else { " (expired)" },
+			);
+		}
+		for key in &self.undecodable_tokens {
+			println!("undecodable token 0x{}", hex::encode(key));
+		}
+	}
+}
+
+impl Summary {
+	fn print(&self) {
+		println!("students:        {}", self.students);
+		println!("linked accounts: {} ({} dangling)", self.linked_accounts, self.dangling_links);
+		println!("tokens:          {} ({} active)", self.tokens, self.active_tokens);
+		if self.undecodable_tokens > 0 {
+			println!("undecodable:     {} tokens", self.undecodable_tokens);
+		}
+		for (domain, count) in &self.students_by_domain {
+			println!("  {}: {}", domain, count);
+		}
+	}
+}
+
+impl InspectCmd {
+	/// Run the command.
+	pub fn run(&self, config: Configuration) -> sc_cli::Result<()> {
+		let filter = self.filter()?;
+		let read_only = service::new_read_only_client(config)?;
+		let client = &*read_only.client;
+		let at = match &self.at {
+			Some(at) => at.parse::<Block>()?,
+			None => BlockId::Hash(client.info().best_hash),
+		};
+		let records = filter.apply(&read_records(client, &at)?);
+		let summary = Summary::of(&records);
+
+		match (self.json, self.summary) {
+			(true, true) => println!("{}", summary.to_json()),
+			(true, false) => {
+				let mut output = records.to_json();
+				output["summary"] = summary.to_json();
+				println!("{}", serde_json::to_string_pretty(&output).map_err(|e| e.to_string())?);
+			},
+			(false, true) => summary.print(),
+			(false, false) => {
+				records.print();
+				summary.print();
+			},
+		}
+		Ok(())
+	}
+
+	fn filter(&self) -> Result<Filter, String> {
+		let account = self
+			.account
+			.as_ref()
+			.map(|a| AccountId::from_ss58check(a).map_err(|e| format!("Invalid account: {:?}", e)))
+			.transpose()?;
+		let email_hash = self
+			.email_hash
+			.as_ref()
+			.map(|h| {
+				let bytes = hex::decode(h.trim_start_matches("0x")).map_err(|e| e.to_string())?;
+				<[u8; 32]>::try_from(bytes)
+					.map_err(|_| "The email hash must be 32 bytes.".to_string())
+			})
+			.transpose()?;
+		let token = self.token.as_ref().map(|t| t.as_bytes().to_vec());
+		Ok(Filter { account, email_hash, token })
+	}
+}
+
+/// Read and decode the student records at `at`.
+fn read_records<B, C>(client: &C, at: &BlockId<Block>) -> sc_cli::Result<Records>
+where
+	B: sc_client_api::Backend<Block>,
+	C: StorageProvider<Block, B> + HeaderBackend<Block>,
+{
+	let hash = match at {
+		BlockId::Hash(hash) => *hash,
+		BlockId::Number(number) => client.hash(*number)?.ok_or("Unknown block number.")?,
+	};
+	let number = client.number(hash)?.ok_or("Unknown block hash.")?;
+	let at = BlockId::Hash(hash);
+	let pairs = |prefix: [u8; 32]| {
+		client
+			.storage_pairs(&at, &StorageKey(prefix.to_vec()))
+			.map(|pairs| pairs.into_iter().map(move |(k, v)| (prefix, k, v.0)))
+			.map_err(|e| format!("Unable to read storage: {}", e))
+	};
+
+	let mut records = Records { number, ..Default::default() };
+	for (prefix, key, value) in pairs(StudentidOf::<Runtime>::final_prefix())? {
+		let email: Email<Runtime> =
+			decode_key(&prefix, &key).ok_or("Undecodable StudentidOf key")?;
+		let registration = Registration::decode(&mut &value[..])
+			.map_err(|e| format!("Undecodable StudentidOf entry: {}", e))?;
+		records.students.push(Student {
+			email: email.into_inner(),
+			account: registration.accountId,
+			deposit: registration.deposit,
+			display: registration.info.display,
+		});
+	}
+	for (prefix, key, value) in pairs(EmailId::<Runtime>::final_prefix())? {
+		let account: AccountId = decode_key(&prefix, &key).ok_or("Undecodable EmailId key")?;
+		let email = Email::<Runtime>::decode(&mut &value[..])
+			.map_err(|e| format!("Undecodable EmailId entry: {}", e))?;
+		records.links.insert(account, email.into_inner());
+	}
+	for (prefix, key, value) in pairs(Tokens::<Runtime>::final_prefix())? {
+		match decode_token(&prefix, &key, &value) {
+			Some(token) => records.tokens.push(token),
+			None => records.undecodable_tokens.push(key.0),
+		}
+	}
+	Ok(records)
+}
+
+/// The access token stored under `key`, if both the key and the `value` decode.
+fn decode_token(prefix: &[u8; 32], key: &StorageKey, value: &[u8]) -> Option<Token> {
+	let id: TokenId<Runtime> = decode_key(prefix, key)?;
+	let info = TokenInfoOf::<Runtime>::decode(&mut &value[..]).ok()?;
+	Some(Token {
+		id: id.into_inner(),
+		owner: info.owner,
+		data: info.data,
+		issued: info.issued,
+		expires: info.expires,
+	})
+}
+
+impl CliConfiguration for InspectCmd {
+	fn shared_params(&self) -> &SharedParams {
+		&self.shared_params
+	}
+
+	fn pruning_params(&self) -> Option<&PruningParams> {
+		Some(&self.pruning_params)
+	}
+
+	fn database_params(&self) -> Option<&DatabaseParams> {
+		Some(&self.database_params)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use codec::Encode;
+
+	fn student(email: &str, account: u8) -> Student {
+		Student {
+			email: email.as_bytes().to_vec(),
+			account: AccountId::new([account; 32]),
+			deposit: 10,
+			display: Data::None,
+		}
+	}
+
+	fn records() -> Records {
+		let token = |id: &str, owner: u8, expires| Token {
+			id: id.as_bytes().to_vec(),
+			owner: AccountId::new([owner; 32]),
+			data: Data::None,
+			issued: 1,
+			expires,
+		};
+		Records {
+			number: 10,
+			students: vec![student("dara@rupp.edu.kh", 1), student("sok@itc.edu.kh", 2)],
+			links: [
+				(AccountId::new([1; 32]), b"dara@rupp.edu.kh".to_vec()),
+				(AccountId::new([3; 32]), b"gone@rupp.edu.kh".to_vec()),
+			]
+			.into_iter()
+			.collect(),
+			tokens: vec![token("t1", 1, 20), token("t2", 1, 5), token("t3", 2, 20)],
+			undecodable_tokens: vec![b"old".to_vec()],
+		}
+	}
+
+	#[test]
+	fn map_keys_are_decoded_after_the_prefix() {
+		let prefix = [7; 32];
+		let account = AccountId::new([1; 32]);
+		let mut key = prefix.to_vec();
+		key.extend(sp_core::hashing::twox_64(&account.encode()));
+		key.extend(account.encode());
+		assert_eq!(decode_key::<AccountId>(&prefix, &StorageKey(key)), Some(account));
+		assert_eq!(decode_key::<AccountId>(&[8; 32], &StorageKey(prefix.to_vec())), None);
+	}
+
+	#[test]
+	fn undecodable_tokens_are_skipped() {
+		let prefix = [7; 32];
+		let id: TokenId<Runtime> = b"t1".to_vec().try_into().unwrap();
+		let mut key = prefix.to_vec();
+		key.extend(sp_core::hashing::twox_64(&id.encode()));
+		key.extend(id.encode());
+		let key = StorageKey(key);
+		let owner = AccountId::new([1; 32]);
+
+		// A token stored before `issued` and `expires` were added.
+		let old = (b"metadata".to_vec(), owner.clone(), Data::None).encode();
+		assert_eq!(decode_token(&prefix, &key, &old), None);
+
+		let mut current = old.clone();
+		current.extend((1 as BlockNumber, 5 as BlockNumber).encode());
+		assert_eq!(
+			decode_token(&prefix, &key, &current),
+			Some(Token { id: b"t1".to_vec(), owner, data: Data::None, issued: 1, expires: 5 })
+		);
+	}
+
+	#[test]
+	fn filters_narrow_down_the_records() {
+		let records = records();
+		assert_eq!(Filter::default().apply(&records), records);
+
+		let by_account = Filter { account: Some(AccountId::new([1; 32])), ..Default::default() };
+		let filtered = by_account.apply(&records);
+		assert_eq!(filtered.students, vec![student("dara@rupp.edu.kh", 1)]);
+		assert_eq!(filtered.links.len(), 1);
+		assert_eq!(filtered.tokens.len(), 2);
+
+		let by_email =
+			Filter { email_hash: Some(blake2_256(b"sok@itc.edu.kh")), ..Default::default() };
+		let filtered = by_email.apply(&records);
+		assert_eq!(filtered.students, vec![student("sok@itc.edu.kh", 2)]);
+		assert!(filtered.links.is_empty());
+		assert_eq!(filtered.tokens.iter().map(|t| &t.id[..]).collect::<Vec<_>>(), vec![b"t3"]);
+
+		let by_token = Filter { token: Some(b"t2".to_vec()), ..Default::default() };
+		let filtered = by_token.apply(&records);
+		assert_eq!(filtered.students, vec![student("dara@rupp.edu.kh", 1)]);
+		assert_eq!(filtered.tokens.iter().map(|t| &t.id[..]).collect::<Vec<_>>(), vec![b"t2"]);
+	}
+
+	#[test]
+	fn summary_counts_records() {
+		let summary = Summary::of(&records());
+		assert_eq!(summary.students, 2);
+		assert_eq!(summary.linked_accounts, 2);
+		assert_eq!(summary.dangling_links, 1);
+		assert_eq!(summary.tokens, 3);
+		assert_eq!(summary.active_tokens, 2);
+		assert_eq!(summary.undecodable_tokens, 1);
+		assert_eq!(summary.students_by_domain.get("itc.edu.kh"), Some(&1));
+	}
+}
@@ -0,0 +1,16 @@
+//! `studentid` subcommands for registrars and operators.
+
+mod import_roster;
+mod inspect;
+
+pub use import_roster::ImportRosterCmd;
+pub use inspect::InspectCmd;
+
+#[derive(Debug, clap::Subcommand)]
+pub enum StudentidSubcommand {
+	/// Build the enrollment rosters of a cohort from a CSV or JSON file of students.
+	ImportRoster(ImportRosterCmd),
+
+	/// Decode and print the student records in the local database at a block.
+	Inspect(InspectCmd),
+}
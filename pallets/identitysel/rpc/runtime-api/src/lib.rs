@@ -0,0 +1,97 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2019-2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Runtime API definition for the studentid pallet.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::Codec;
+use frame_support::traits::Get;
+use sp_std::prelude::*;
+
+use sp_runtime::traits::NumberFor;
+
+pub use pallet_studentid::{
+	attestation::{self, Snapshot}, CredentialIndex, CredentialKind, Data, DidDetails, IdentityField,
+	IdentityFields, IdentityInfoSel, IssuedCredential, JudgedIdentity, Judgement, RegistrarIndex,
+	RegistrarInfo, StudentState, StudentStatus, TokenStatus,
+};
+
+sp_api::decl_runtime_apis! {
+	pub trait StudentidApi<AccountId, MaxAdditionalFields> where
+		AccountId: Codec,
+		MaxAdditionalFields: Get<u32>,
+	{
+		/// The identity of `who` as seen by `service`, restricted to the fields `who` disclosed
+		/// to it. `None` if `service` has no access to `who`.
+		fn disclosed_identity(
+			who: AccountId,
+			service: Vec<u8>,
+		) -> Option<IdentityInfoSel<MaxAdditionalFields>>;
+
+		/// The full value behind `data`, looking `Data::BlakeTwo256` up in the blob store.
+		fn resolve_data(data: Data) -> Option<Vec<u8>>;
+
+		/// The owner of the access token `token_id`, if it exists and is still allowed.
+		fn token_owner(token_id: Vec<u8>) -> Option<AccountId>;
+
+		/// Whether `who` has given `service` access.
+		fn has_access(who: AccountId, service: Vec<u8>) -> bool;
+
+		/// The state of the access token `token_id`, `None` if there is no such token.
+		fn introspect_token(token_id: Vec<u8>) -> Option<TokenStatus<AccountId, NumberFor<Block>>>;
+
+		/// The challenge the next passkey assertion for the student with `email` must sign.
+		fn passkey_challenge(email: Vec<u8>) -> [u8; 32];
+
+		/// The registrar attestation keys and unexpired revocations, for verifying ID card
+		/// attestations offline.
+		fn attestation_snapshot() -> Snapshot;
+
+		/// The registrar `index`, `None` if there is no such registrar.
+		fn registrar(index: RegistrarIndex) -> Option<RegistrarInfo<AccountId>>;
+
+		/// The hash of the identity of `who` that registrars judge, `None` if `who` has no
+		/// identity.
+		fn identity_hash(who: AccountId) -> Option<[u8; 32]>;
+
+		/// The identity of `who` with the judgement of the registrar `index`. `None` if the
+		/// registrar has not judged the current identity of `who`.
+		fn judged_identity(
+			who: AccountId,
+			index: RegistrarIndex,
+		) -> Option<JudgedIdentity<AccountId, MaxAdditionalFields>>;
+
+		/// What the `did:selendra` DID document of `who` is derived from, `None` if `who` has
+		/// no identity.
+		fn did_details(who: AccountId) -> Option<DidDetails<AccountId>>;
+
+		/// The SS58 prefix of the chain, which `did:selendra` DIDs encode accounts with.
+		fn ss58_prefix() -> u16;
+
+		/// The status of `who` and how long it holds, `Expired` once it ran past its validity.
+		/// `None` if no registrar set a status for `who`.
+		fn student_state(who: AccountId) -> Option<StudentState<NumberFor<Block>>>;
+
+		/// The status of `who`, `Unverified` if no status was set. Only students whose status
+		/// grants access are active students. `None` if `who` has no identity.
+		fn student_status(who: AccountId) -> Option<StudentStatus>;
+
+		/// The diplomas, certificates and badges issued to `who`, revoked ones included.
+		fn credentials_of(who: AccountId) -> Vec<(CredentialIndex, IssuedCredential<AccountId>)>;
+	}
+}
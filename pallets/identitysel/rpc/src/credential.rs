@@ -0,0 +1,575 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2019-2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! W3C Verifiable Credentials of identities judged by a registrar.
+//!
+//! Credentials are issued as JWTs, following the JWT encoding of the Verifiable Credentials
+//! data model: the [`VerifiableCredential`] is the `vc` claim, next to `iss`, `sub` and `nbf`,
+//! and the registrar signs the JWS signing input with its sr25519 attestation key, under the
+//! [`JWT_ALGORITHM`] algorithm and the `substrate` signing context. The signature covers the
+//! bytes as they are sent, so no canonicalization is involved. The terms of the credential are
+//! defined by [`studentid_context`], which is embedded in every credential rather than
+//! referenced by URL, so verifiers do not need to fetch it.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use codec::Encode;
+use frame_support::traits::Get;
+use jsonrpc_core::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sp_core::{
+	crypto::Ss58Codec, hashing::blake2_256, hexdisplay::HexDisplay, sr25519, Pair, H160, H256,
+};
+
+use crate::{
+	did::{account_of_did, did},
+	Data, DataValue, JudgedIdentity, Judgement, RegistrarIndex,
+};
+
+/// The base context of W3C Verifiable Credentials.
+pub const CREDENTIALS_CONTEXT: &str = "https://www.w3.org/2018/credentials/v1";
+
+/// The vocabulary the terms of student identity credentials are defined in.
+pub const VOCABULARY: &str = "https://id.selendra.org/credentials#";
+
+/// The type of student identity credentials.
+pub const CREDENTIAL_TYPE: &str = "StudentIdentityCredential";
+
+/// The JWS algorithm of credentials: an sr25519 signature of the signing input.
+pub const JWT_ALGORITHM: &str = "Sr25519";
+
+/// The JSON-LD context defining the terms of student identity credentials.
+pub fn studentid_context() -> Value {
+	json!({
+		"@version": 1.1,
+		"schema": "https://schema.org/",
+		"sid": VOCABULARY,
+		CREDENTIAL_TYPE: "sid:StudentIdentityCredential",
+		"name": "schema:name",
+		"legalName": "schema:legalName",
+		"email": "schema:email",
+		"url": { "@id": "schema:url", "@type": "@id" },
+		"pgpFingerprint": "sid:pgpFingerprint",
+		"profile": { "@id": "sid:profile", "@type": "@json" },
+		"judgement": {
+			"@id": "sid:judgement",
+			"@context": {
+				"registrar": "sid:registrar",
+				"result": "sid:result",
+				"identityHash": "sid:identityHash",
+			},
+		},
+	})
+}
+
+/// A positive registrar judgement. Credentials are only issued for these.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum JudgementResult {
+	/// See [`Judgement::KnownGood`].
+	KnownGood,
+	/// See [`Judgement::Reasonable`].
+	Reasonable,
+}
+
+impl JudgementResult {
+	/// The result for `judgement`, `None` if it is not positive.
+	pub fn from_judgement(judgement: Judgement) -> Option<Self> {
+		match judgement {
+			Judgement::KnownGood => Some(JudgementResult::KnownGood),
+			Judgement::Reasonable => Some(JudgementResult::Reasonable),
+			_ => None,
+		}
+	}
+
+	/// The judgement this result stands for.
+	pub fn judgement(self) -> Judgement {
+		match self {
+			JudgementResult::KnownGood => Judgement::KnownGood,
+			JudgementResult::Reasonable => Judgement::Reasonable,
+		}
+	}
+}
+
+/// The registrar judgement a credential states.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialJudgement {
+	/// The index of the registrar.
+	pub registrar: RegistrarIndex,
+	/// The judgement.
+	pub result: JudgementResult,
+	/// The Blake2 hash of the judged identity.
+	pub identity_hash: H256,
+}
+
+/// The claims of a credential, taken from the judged identity.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Claims {
+	/// Display name.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub name: Option<String>,
+	/// Legal name.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub legal_name: Option<String>,
+	/// Email address.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub email: Option<String>,
+	/// Website.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub url: Option<String>,
+	/// PGP fingerprint.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub pgp_fingerprint: Option<H160>,
+	/// Additional profile fields, by key.
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub profile: BTreeMap<String, String>,
+}
+
+impl Claims {
+	/// The claims of `info`, turning each value into a `DataValue` with `resolve`. Values only
+	/// known by their hash are left out.
+	pub fn new<MaxAdditionalFields: Get<u32>>(
+		info: &crate::IdentityInfoSel<MaxAdditionalFields>,
+		mut resolve: impl FnMut(&Data) -> Result<DataValue>,
+	) -> Result<Self> {
+		let mut claim = |data: &Data| -> Result<Option<String>> {
+			Ok(match resolve(data)? {
+				DataValue::Text(text) => Some(text),
+				DataValue::Raw(bytes) => Some(format!("0x{}", HexDisplay::from(&bytes.0))),
+				_ => None,
+			})
+		};
+
+		let mut profile = BTreeMap::new();
+		for (key, value) in info.additional.iter() {
+			if let (Some(key), Some(value)) = (claim(key)?, claim(value)?) {
+				profile.insert(key, value);
+			}
+		}
+
+		Ok(Claims {
+			name: claim(&info.display)?,
+			legal_name: claim(&info.legal)?,
+			email: claim(&info.email)?,
+			url: claim(&info.web)?,
+			pgp_fingerprint: info.pgp_fingerprint.map(H160::from),
+			profile,
+		})
+	}
+}
+
+/// The subject of a credential: the student, the claims about them and the judgement.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialSubject {
+	/// The DID of the student.
+	pub id: String,
+	/// The claims about the student.
+	#[serde(flatten)]
+	pub claims: Claims,
+	/// The judgement of the registrar.
+	pub judgement: CredentialJudgement,
+}
+
+/// A student identity credential, as a W3C Verifiable Credential.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifiableCredential {
+	/// [`CREDENTIALS_CONTEXT`] and [`studentid_context`].
+	#[serde(rename = "@context")]
+	pub context: Vec<Value>,
+	/// `VerifiableCredential` and [`CREDENTIAL_TYPE`].
+	#[serde(rename = "type")]
+	pub types: Vec<String>,
+	/// The DID of the registrar.
+	pub issuer: String,
+	/// When the credential was issued.
+	pub issuance_date: String,
+	/// The student and what the registrar states about them.
+	pub credential_subject: CredentialSubject,
+}
+
+impl VerifiableCredential {
+	/// The credential for the identity of `who` as `judged` by the registrar `index`, issued at
+	/// `issued` on the chain with the SS58 prefix `ss58_prefix`. `None` if the judgement is not
+	/// positive.
+	pub fn new<AccountId: Ss58Codec, MaxAdditionalFields: Get<u32>>(
+		who: &AccountId,
+		index: RegistrarIndex,
+		judged: &JudgedIdentity<AccountId, MaxAdditionalFields>,
+		resolve: impl FnMut(&Data) -> Result<DataValue>,
+		issued: DateTime<Utc>,
+		ss58_prefix: u16,
+	) -> Result<Option<Self>> {
+		let result = match JudgementResult::from_judgement(judged.judgement) {
+			Some(result) => result,
+			None => return Ok(None),
+		};
+
+		Ok(Some(VerifiableCredential {
+			context: vec![CREDENTIALS_CONTEXT.into(), studentid_context()],
+			types: vec!["VerifiableCredential".into(), CREDENTIAL_TYPE.into()],
+			issuer: did(&judged.registrar, ss58_prefix),
+			issuance_date: issued.to_rfc3339_opts(SecondsFormat::Secs, true),
+			credential_subject: CredentialSubject {
+				id: did(who, ss58_prefix),
+				claims: Claims::new(&judged.info, resolve)?,
+				judgement: CredentialJudgement {
+					registrar: index,
+					result,
+					identity_hash: blake2_256(&judged.info.encode()).into(),
+				},
+			},
+		}))
+	}
+
+	/// The account of the student, `None` if it is not of the chain with the SS58 prefix
+	/// `ss58_prefix`.
+	pub fn subject<AccountId: Ss58Codec>(&self, ss58_prefix: u16) -> Option<AccountId> {
+		account_of_did(&self.credential_subject.id, ss58_prefix)
+	}
+
+	/// When the credential was issued.
+	pub fn issued(&self) -> Option<DateTime<Utc>> {
+		DateTime::parse_from_rfc3339(&self.issuance_date).ok().map(|date| date.with_timezone(&Utc))
+	}
+
+	/// The registrar's attestation key, relative to the registrar's DID.
+	pub fn key_id(&self) -> String {
+		format!("{}#attestation", self.issuer)
+	}
+}
+
+/// The JOSE header of a credential JWT.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct JwtHeader {
+	/// [`JWT_ALGORITHM`].
+	pub alg: String,
+	/// Always `JWT`.
+	pub typ: String,
+	/// [`VerifiableCredential::key_id`].
+	pub kid: String,
+}
+
+/// The claims of a credential JWT.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct JwtClaims {
+	/// The DID of the registrar.
+	pub iss: String,
+	/// The DID of the student.
+	pub sub: String,
+	/// When the credential was issued, in seconds since the Unix epoch.
+	pub nbf: i64,
+	/// The credential.
+	pub vc: VerifiableCredential,
+}
+
+/// A credential encoded as a JWT signed by the registrar.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CredentialJwt {
+	/// The credential.
+	pub credential: VerifiableCredential,
+	signing_input: String,
+	signature: Vec<u8>,
+}
+
+fn base64url(bytes: &[u8]) -> String {
+	base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn from_base64url<T: serde::de::DeserializeOwned>(
+	part: &str,
+) -> std::result::Result<T, CredentialError> {
+	let json = base64::decode_config(part, base64::URL_SAFE_NO_PAD)
+		.map_err(|_| CredentialError::Malformed)?;
+	serde_json::from_slice(&json).map_err(|_| CredentialError::Malformed)
+}
+
+impl CredentialJwt {
+	/// The JWS signing input of `credential`, which the registrar signs.
+	pub fn signing_input(
+		credential: &VerifiableCredential,
+	) -> std::result::Result<String, CredentialError> {
+		let issued = credential.issued().ok_or(CredentialError::Malformed)?;
+		let header = JwtHeader {
+			alg: JWT_ALGORITHM.into(),
+			typ: "JWT".into(),
+			kid: credential.key_id(),
+		};
+		let claims = JwtClaims {
+			iss: credential.issuer.clone(),
+			sub: credential.credential_subject.id.clone(),
+			nbf: issued.timestamp(),
+			vc: credential.clone(),
+		};
+		let header = serde_json::to_vec(&header).map_err(|_| CredentialError::Malformed)?;
+		let claims = serde_json::to_vec(&claims).map_err(|_| CredentialError::Malformed)?;
+		Ok(format!("{}.{}", base64url(&header), base64url(&claims)))
+	}
+
+	/// The JWT of `credential` with the registrar's `signature` of `signing_input`.
+	pub fn new(
+		credential: VerifiableCredential,
+		signing_input: String,
+		signature: Vec<u8>,
+	) -> Self {
+		CredentialJwt { credential, signing_input, signature }
+	}
+
+	/// Parse a compact JWT, checking that its header and registered claims agree with the
+	/// credential it carries.
+	pub fn parse(jwt: &str) -> std::result::Result<Self, CredentialError> {
+		let (signing_input, signature) = jwt.rsplit_once('.').ok_or(CredentialError::Malformed)?;
+		let (header, claims) = signing_input.split_once('.').ok_or(CredentialError::Malformed)?;
+		let header: JwtHeader = from_base64url(header)?;
+		let claims: JwtClaims = from_base64url(claims)?;
+		let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD)
+			.map_err(|_| CredentialError::Malformed)?;
+
+		let credential = claims.vc;
+		let issued = credential.issued().ok_or(CredentialError::Malformed)?;
+		if header.alg != JWT_ALGORITHM ||
+			header.typ != "JWT" ||
+			header.kid != credential.key_id() ||
+			claims.iss != credential.issuer ||
+			claims.sub != credential.credential_subject.id ||
+			claims.nbf != issued.timestamp()
+		{
+			return Err(CredentialError::Malformed)
+		}
+		Ok(CredentialJwt { credential, signing_input: signing_input.into(), signature })
+	}
+
+	/// Check the credential against `expected`, the credential rendered from chain state for the
+	/// same student, registrar and issuance date, and the signature against the registrar's
+	/// attestation `key`.
+	pub fn verify(
+		&self,
+		expected: &VerifiableCredential,
+		key: &[u8; 32],
+	) -> std::result::Result<(), CredentialError> {
+		if self.credential.issuer != expected.issuer {
+			return Err(CredentialError::WrongIssuer)
+		}
+		if self.credential.credential_subject.judgement != expected.credential_subject.judgement {
+			return Err(CredentialError::JudgementChanged)
+		}
+		if &self.credential != expected {
+			return Err(CredentialError::ClaimsMismatch)
+		}
+
+		let signature = <[u8; 64]>::try_from(&self.signature[..])
+			.map_err(|_| CredentialError::BadSignature)?;
+		if !sr25519::Pair::verify(
+			&sr25519::Signature::from_raw(signature),
+			self.signing_input.as_bytes(),
+			&sr25519::Public::from_raw(*key),
+		) {
+			return Err(CredentialError::BadSignature)
+		}
+		Ok(())
+	}
+}
+
+impl std::fmt::Display for CredentialJwt {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{}.{}", self.signing_input, base64url(&self.signature))
+	}
+}
+/// Why a credential was rejected.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum CredentialError {
+	/// The credential is not a student identity credential.
+	Malformed,
+	/// The registrar is no longer registered or has no attestation key.
+	UnknownRegistrar,
+	/// The issuer is not the registrar.
+	WrongIssuer,
+	/// The registrar revoked its judgement or the student changed the judged identity.
+	JudgementRevoked,
+	/// The registrar has given another judgement since.
+	JudgementChanged,
+	/// The claims differ from the judged identity.
+	ClaimsMismatch,
+	/// The proof is not a signature of the registrar's attestation key.
+	BadSignature,
+}
+
+/// The result of verifying a credential against chain state.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialVerification<BlockHash> {
+	/// Whether the credential is valid.
+	pub verified: bool,
+	/// Why the credential was rejected.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub error: Option<CredentialError>,
+	/// The finalized block the credential was checked against.
+	pub at: BlockHash,
+}
+
+impl<BlockHash> CredentialVerification<BlockHash> {
+	/// The verification `result` as of block `at`.
+	pub fn new(result: std::result::Result<(), CredentialError>, at: BlockHash) -> Self {
+		CredentialVerification { verified: result.is_ok(), error: result.err(), at }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use frame_support::traits::ConstU32;
+	use sp_core::crypto::AccountId32;
+
+	fn raw(bytes: &[u8]) -> Data {
+		Data::Raw(bytes.to_vec().try_into().unwrap())
+	}
+
+	fn judged(judgement: Judgement) -> JudgedIdentity<AccountId32, ConstU32<2>> {
+		JudgedIdentity {
+			registrar: AccountId32::new([3; 32]),
+			attestation_key: Some(sr25519::Pair::from_seed(&[8; 32]).public().0),
+			judgement,
+			info: crate::IdentityInfoSel {
+				additional: vec![(raw(b"program"), raw(b"Physics"))].try_into().unwrap(),
+				display: raw(b"Alice"),
+				legal: Data::None,
+				web: Data::None,
+				referalhash: Data::None,
+				email: raw(b"a@b.com"),
+				pgp_fingerprint: None,
+				account: Data::None,
+				accesstoken: Data::None,
+				passwordhash: Data::None,
+			},
+		}
+	}
+
+	fn credential(judged: &JudgedIdentity<AccountId32, ConstU32<2>>) -> VerifiableCredential {
+		let issued = DateTime::parse_from_rfc3339("2022-03-01T12:00:00Z").unwrap().with_timezone(&Utc);
+		let who = AccountId32::new([1; 32]);
+		VerifiableCredential::new(&who, 0, judged, |data| Ok(data.into()), issued, 42)
+			.unwrap()
+			.unwrap()
+	}
+
+	fn signed(judged: &JudgedIdentity<AccountId32, ConstU32<2>>) -> CredentialJwt {
+		let credential = credential(judged);
+		let input = CredentialJwt::signing_input(&credential).unwrap();
+		let signature = sr25519::Pair::from_seed(&[8; 32]).sign(input.as_bytes()).0.to_vec();
+		CredentialJwt::new(credential, input, signature)
+	}
+
+	#[test]
+	fn renders_jwt_vc() {
+		let jwt = signed(&judged(Judgement::KnownGood)).to_string();
+		let parts: Vec<_> = jwt.split('.').collect();
+		assert_eq!(parts.len(), 3);
+
+		let header: Value = from_base64url(parts[0]).unwrap();
+		let kid = format!("{}#attestation", did(&AccountId32::new([3; 32]), 42));
+		assert_eq!(header, json!({ "alg": JWT_ALGORITHM, "typ": "JWT", "kid": kid }));
+
+		let claims: Value = from_base64url(parts[1]).unwrap();
+		assert_eq!(claims["iss"], did(&AccountId32::new([3; 32]), 42));
+		assert_eq!(claims["sub"], did(&AccountId32::new([1; 32]), 42));
+		assert_eq!(claims["nbf"], 1_646_136_000);
+		let vc = &claims["vc"];
+		assert_eq!(vc["@context"], json!([CREDENTIALS_CONTEXT, studentid_context()]));
+		assert_eq!(vc["type"][1], CREDENTIAL_TYPE);
+		assert_eq!(vc["issuanceDate"], "2022-03-01T12:00:00Z");
+		assert_eq!(vc["credentialSubject"]["name"], "Alice");
+		assert_eq!(vc["credentialSubject"]["profile"]["program"], "Physics");
+		assert_eq!(vc["credentialSubject"]["judgement"]["result"], "knownGood");
+		assert!(vc["credentialSubject"].get("legalName").is_none());
+		assert!(vc.get("proof").is_none());
+
+		let parsed = CredentialJwt::parse(&jwt).unwrap();
+		assert_eq!(parsed, signed(&judged(Judgement::KnownGood)));
+		assert_eq!(parsed.credential.subject::<AccountId32>(42), Some(AccountId32::new([1; 32])));
+	}
+
+	#[test]
+	fn rejects_inconsistent_jwts() {
+		let jwt = signed(&judged(Judgement::KnownGood)).to_string();
+		assert_eq!(CredentialJwt::parse("not a jwt"), Err(CredentialError::Malformed));
+		assert_eq!(CredentialJwt::parse(&jwt.replace('.', "")), Err(CredentialError::Malformed));
+
+		let encode = |value: &Value| base64url(&serde_json::to_vec(value).unwrap());
+		let parts: Vec<_> = jwt.split('.').collect();
+		let mut claims: Value = from_base64url(parts[1]).unwrap();
+		claims["sub"] = did(&AccountId32::new([2; 32]), 42).into();
+		let forged = format!("{}.{}.{}", parts[0], encode(&claims), parts[2]);
+		assert_eq!(CredentialJwt::parse(&forged), Err(CredentialError::Malformed));
+
+		let mut header: Value = from_base64url(parts[0]).unwrap();
+		header["alg"] = "none".into();
+		let forged = format!("{}.{}.{}", encode(&header), parts[1], parts[2]);
+		assert_eq!(CredentialJwt::parse(&forged), Err(CredentialError::Malformed));
+	}
+
+	#[test]
+	fn only_positive_judgements_are_issued() {
+		let erroneous = judged(Judgement::Erroneous);
+		let issued = Utc::now();
+		let who = AccountId32::new([1; 32]);
+		let render = |data: &Data| Ok(data.into());
+		assert_eq!(VerifiableCredential::new(&who, 0, &erroneous, render, issued, 42), Ok(None));
+	}
+
+	#[test]
+	fn verifies_against_chain_state() {
+		let identity = judged(Judgement::KnownGood);
+		let key = identity.attestation_key.unwrap();
+		let signed = signed(&identity);
+		assert_eq!(signed.verify(&credential(&identity), &key), Ok(()));
+
+		assert_eq!(
+			signed.verify(&credential(&identity), &[9; 32]),
+			Err(CredentialError::BadSignature)
+		);
+		assert_eq!(
+			signed.verify(&credential(&judged(Judgement::Reasonable)), &key),
+			Err(CredentialError::JudgementChanged)
+		);
+
+		let mut other_registrar = judged(Judgement::KnownGood);
+		other_registrar.registrar = AccountId32::new([4; 32]);
+		assert_eq!(
+			signed.verify(&credential(&other_registrar), &key),
+			Err(CredentialError::WrongIssuer)
+		);
+
+		let mut tampered = signed.clone();
+		tampered.credential.credential_subject.claims.name = Some("Mallory".into());
+		assert_eq!(
+			tampered.verify(&credential(&identity), &key),
+			Err(CredentialError::ClaimsMismatch)
+		);
+
+		let mut resigned = credential(&identity);
+		resigned.credential_subject.claims.name = Some("Mallory".into());
+		let input = CredentialJwt::signing_input(&resigned).unwrap();
+		let forged = CredentialJwt::new(credential(&identity), input, signed.signature.clone());
+		assert_eq!(forged.verify(&credential(&identity), &key), Err(CredentialError::BadSignature));
+	}
+}
@@ -0,0 +1,408 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2019-2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The `did:selendra` DID method.
+//!
+//! `did:selendra:<ss58>` identifies the student identity of an account, rendered with the SS58
+//! prefix of the chain. Its DID document is derived from the studentid pallet state:
+//!
+//! - the controller is the account the registration is linked to,
+//! - the account and its linked Ethereum address authenticate as the DID,
+//! - the sub-accounts are listed, but in no verification relationship, as `add_sub` does not
+//!   need the consent of the sub-account,
+//! - the services are the services the account granted access to.
+//!
+//! Accounts may be sr25519, ed25519 or ecdsa keys, which cannot be told apart from the account
+//! ID, so they are given as CAIP-10 blockchain account IDs rather than public keys.
+
+use std::{marker::PhantomData, sync::Arc};
+
+use codec::Codec;
+use jsonrpc_core::Result;
+use jsonrpc_derive::rpc;
+use serde::{Deserialize, Serialize};
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::HeaderBackend;
+use sp_core::{
+	crypto::{Ss58AddressFormat, Ss58Codec},
+	hexdisplay::HexDisplay,
+};
+use sp_runtime::{generic::BlockId, traits::Block as BlockT};
+
+use crate::{runtime_error, DidDetails, StudentidRuntimeApi};
+
+/// The context of DID documents.
+pub const DID_CONTEXT: &str = "https://www.w3.org/ns/did/v1";
+
+/// The context defining `BlockchainVerificationMethod2021`.
+pub const BLOCKCHAIN_CONTEXT: &str = "https://w3id.org/security/suites/blockchain-2021/v1";
+
+/// The context defining `EcdsaSecp256k1RecoveryMethod2020`.
+pub const SECP256K1_RECOVERY_CONTEXT: &str =
+	"https://w3id.org/security/suites/secp256k1recovery-2020/v2";
+
+/// The context defining the service types of `did:selendra`.
+pub const SELENDRA_DID_CONTEXT: &str = "https://id.selendra.org/did/v1";
+
+/// The context of DID resolution results.
+pub const RESOLUTION_CONTEXT: &str = "https://w3id.org/did-resolution/v1";
+
+/// The media type of resolved DID documents.
+pub const CONTENT_TYPE: &str = "application/did+ld+json";
+
+/// The `did:selendra` DID of `account`, on a chain with the SS58 prefix `ss58_prefix`.
+pub fn did<AccountId: Ss58Codec>(account: &AccountId, ss58_prefix: u16) -> String {
+	let address = account.to_ss58check_with_version(Ss58AddressFormat::custom(ss58_prefix));
+	format!("did:selendra:{}", address)
+}
+
+/// The account the `did:selendra` DID `did` refers to. `None` if it is not rendered with the
+/// SS58 prefix `ss58_prefix` of this chain.
+pub fn account_of_did<AccountId: Ss58Codec>(did: &str, ss58_prefix: u16) -> Option<AccountId> {
+	let (account, format) =
+		AccountId::from_ss58check_with_version(did.strip_prefix("did:selendra:")?).ok()?;
+	(u16::from(format) == ss58_prefix).then(|| account)
+}
+
+/// The CAIP-2 chain ID of the chain with the genesis hash `genesis_hash`.
+pub fn chain_id(genesis_hash: &[u8]) -> String {
+	format!("polkadot:{}", HexDisplay::from(&&genesis_hash[..16]))
+}
+
+/// A verification method of a DID document.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct VerificationMethod {
+	/// The DID URL of the method.
+	pub id: String,
+	/// `BlockchainVerificationMethod2021` or `EcdsaSecp256k1RecoveryMethod2020`.
+	#[serde(rename = "type")]
+	pub method_type: String,
+	/// The DID controlling the method.
+	pub controller: String,
+	/// The CAIP-10 account ID, for accounts of this chain.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub blockchain_account_id: Option<String>,
+	/// The address, for Ethereum addresses. These are not bound to any Ethereum chain.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub ethereum_address: Option<String>,
+}
+
+impl VerificationMethod {
+	/// `account` of the chain `chain`, under `fragment` of `did`.
+	fn account<AccountId: Ss58Codec>(
+		did: &str,
+		fragment: &str,
+		account: &AccountId,
+		chain: &str,
+		ss58_prefix: u16,
+	) -> Self {
+		let address = account.to_ss58check_with_version(Ss58AddressFormat::custom(ss58_prefix));
+		VerificationMethod {
+			id: format!("{}#{}", did, fragment),
+			method_type: "BlockchainVerificationMethod2021".into(),
+			controller: did.into(),
+			blockchain_account_id: Some(format!("{}:{}", chain, address)),
+			ethereum_address: None,
+		}
+	}
+}
+
+/// A service of a DID document.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Service {
+	/// The DID URL of the service.
+	pub id: String,
+	/// Always `StudentidService`.
+	#[serde(rename = "type")]
+	pub service_type: String,
+	/// The service, if it is named by a URL, or else a `urn:selendra:service:` URN of its name.
+	pub service_endpoint: String,
+}
+
+/// A `did:selendra` DID document.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DidDocument {
+	/// The JSON-LD contexts.
+	#[serde(rename = "@context")]
+	pub context: Vec<String>,
+	/// The DID.
+	pub id: String,
+	/// The DID of the account the registration is linked to.
+	pub controller: String,
+	/// The account, its linked Ethereum address and its sub-accounts.
+	pub verification_method: Vec<VerificationMethod>,
+	/// The account and its linked Ethereum address.
+	pub authentication: Vec<String>,
+	/// The account.
+	pub assertion_method: Vec<String>,
+	/// The services the account granted access to.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub service: Vec<Service>,
+}
+
+impl DidDocument {
+	/// The DID document of `who`, derived from `details`, on the chain with the SS58 prefix
+	/// `ss58_prefix` and the genesis hash `genesis_hash`.
+	pub fn new<AccountId: Ss58Codec>(
+		who: &AccountId,
+		details: &DidDetails<AccountId>,
+		ss58_prefix: u16,
+		genesis_hash: &[u8],
+	) -> Self {
+		let id = did(who, ss58_prefix);
+		let chain = chain_id(genesis_hash);
+
+		let mut verification_method =
+			vec![VerificationMethod::account(&id, "account", who, &chain, ss58_prefix)];
+		if let Some(address) = details.eth_account {
+			verification_method.push(VerificationMethod {
+				id: format!("{}#eth", id),
+				method_type: "EcdsaSecp256k1RecoveryMethod2020".into(),
+				controller: id.clone(),
+				blockchain_account_id: None,
+				ethereum_address: Some(format!("0x{}", HexDisplay::from(&address.0))),
+			});
+		}
+		let authentication = verification_method.iter().map(|method| method.id.clone()).collect();
+		for sub in &details.sub_accounts {
+			let fragment = format!("sub-{}", sub.to_ss58check_with_version(ss58_prefix.into()));
+			verification_method.push(VerificationMethod::account(
+				&id,
+				&fragment,
+				sub,
+				&chain,
+				ss58_prefix,
+			));
+		}
+
+		let service = details
+			.services
+			.iter()
+			.enumerate()
+			.map(|(i, name)| {
+				let name = String::from_utf8_lossy(name);
+				Service {
+					id: format!("{}#service-{}", id, i + 1),
+					service_type: "StudentidService".into(),
+					service_endpoint: if name.contains("://") {
+						name.into_owned()
+					} else {
+						format!("urn:selendra:service:{}", name)
+					},
+				}
+			})
+			.collect();
+
+		DidDocument {
+			context: vec![
+				DID_CONTEXT.into(),
+				BLOCKCHAIN_CONTEXT.into(),
+				SECP256K1_RECOVERY_CONTEXT.into(),
+				SELENDRA_DID_CONTEXT.into(),
+			],
+			controller: did(&details.controller, ss58_prefix),
+			authentication,
+			assertion_method: vec![format!("{}#account", id)],
+			verification_method,
+			service,
+			id,
+		}
+	}
+}
+
+/// The metadata of a DID resolution.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolutionMetadata {
+	/// The media type of the DID document, if it was resolved.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub content_type: Option<String>,
+	/// `invalidDid` or `notFound` if the DID could not be resolved.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub error: Option<String>,
+}
+
+/// The metadata of a resolved DID document.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentMetadata {}
+
+/// The result of resolving a DID, as defined by the DID resolution specification.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DidResolution {
+	/// The JSON-LD context.
+	#[serde(rename = "@context")]
+	pub context: String,
+	/// How resolution went.
+	pub did_resolution_metadata: ResolutionMetadata,
+	/// The DID document, if the DID was resolved.
+	pub did_document: Option<DidDocument>,
+	/// Metadata about the DID document.
+	pub did_document_metadata: DocumentMetadata,
+}
+
+impl DidResolution {
+	/// The successful resolution to `document`.
+	pub fn resolved(document: DidDocument) -> Self {
+		DidResolution {
+			context: RESOLUTION_CONTEXT.into(),
+			did_resolution_metadata: ResolutionMetadata {
+				content_type: Some(CONTENT_TYPE.into()),
+				error: None,
+			},
+			did_document: Some(document),
+			did_document_metadata: Default::default(),
+		}
+	}
+
+	/// A failed resolution.
+	pub fn failed(error: &str) -> Self {
+		DidResolution {
+			context: RESOLUTION_CONTEXT.into(),
+			did_resolution_metadata: ResolutionMetadata {
+				content_type: None,
+				error: Some(error.into()),
+			},
+			did_document: None,
+			did_document_metadata: Default::default(),
+		}
+	}
+}
+
+#[rpc]
+pub trait DidApi<BlockHash> {
+	/// Resolve the `did:selendra` DID `did`.
+	#[rpc(name = "did_resolve")]
+	fn resolve(&self, did: String, at: Option<BlockHash>) -> Result<DidResolution>;
+}
+
+/// Implements the [`DidApi`] RPC trait for resolving `did:selendra` DIDs.
+pub struct Did<C, B, A, M> {
+	client: Arc<C>,
+	_marker: PhantomData<(B, A, M)>,
+}
+
+impl<C, B, A, M> Did<C, B, A, M> {
+	/// Create new `Did` with the given reference to the client.
+	pub fn new(client: Arc<C>) -> Self {
+		Self { client, _marker: Default::default() }
+	}
+}
+
+impl<C, Block, AccountId, MaxAdditionalFields> DidApi<<Block as BlockT>::Hash>
+	for Did<C, Block, AccountId, MaxAdditionalFields>
+where
+	Block: BlockT,
+	C: Send + Sync + 'static + ProvideRuntimeApi<Block> + HeaderBackend<Block>,
+	C::Api: StudentidRuntimeApi<Block, AccountId, MaxAdditionalFields>,
+	AccountId: Codec + Clone + Ss58Codec + Send + Sync + 'static,
+	MaxAdditionalFields: frame_support::traits::Get<u32> + Send + Sync + 'static,
+{
+	fn resolve(&self, did: String, at: Option<<Block as BlockT>::Hash>) -> Result<DidResolution> {
+		let api = self.client.runtime_api();
+		let info = self.client.info();
+		let at = BlockId::hash(at.unwrap_or(info.best_hash));
+		let ss58_prefix = api
+			.ss58_prefix(&at)
+			.map_err(|e| runtime_error("Unable to query SS58 prefix.", e))?;
+
+		let who: AccountId = match account_of_did(&did, ss58_prefix) {
+			Some(who) => who,
+			None => return Ok(DidResolution::failed("invalidDid")),
+		};
+		let details = api
+			.did_details(&at, who.clone())
+			.map_err(|e| runtime_error("Unable to query DID details.", e))?;
+
+		Ok(match details {
+			Some(details) => DidResolution::resolved(DidDocument::new(
+				&who,
+				&details,
+				ss58_prefix,
+				info.genesis_hash.as_ref(),
+			)),
+			None => DidResolution::failed("notFound"),
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::{crypto::AccountId32, H160};
+
+	#[test]
+	fn derives_documents_from_chain_state() {
+		let who = AccountId32::new([1; 32]);
+		let details = DidDetails {
+			controller: AccountId32::new([2; 32]),
+			sub_accounts: vec![AccountId32::new([3; 32])],
+			eth_account: Some(H160::repeat_byte(0xab)),
+			services: vec![b"wordpress".to_vec(), b"https://lms.example.edu".to_vec()],
+		};
+		let document = DidDocument::new(&who, &details, 42, &[0x12; 32]);
+		let id = did(&who, 42);
+		let sub = details.sub_accounts[0].to_ss58check_with_version(42u16.into());
+
+		assert_eq!(account_of_did::<AccountId32>(&document.id, 42), Some(who.clone()));
+		assert_eq!(document.controller, did(&details.controller, 42));
+		assert_eq!(document.authentication, vec![format!("{}#account", id), format!("{}#eth", id)]);
+		assert_eq!(document.assertion_method, vec![format!("{}#account", id)]);
+		assert_eq!(document.verification_method[2].id, format!("{}#sub-{}", id, sub));
+
+		assert_eq!(document.verification_method[0].method_type, "BlockchainVerificationMethod2021");
+		assert_eq!(
+			document.verification_method[0].blockchain_account_id,
+			Some(format!("polkadot:12121212121212121212121212121212:{}", who.to_ss58check()))
+		);
+		assert_eq!(
+			document.verification_method[1].ethereum_address.as_deref(),
+			Some("0xabababababababababababababababababababab")
+		);
+		assert_eq!(document.verification_method[1].blockchain_account_id, None);
+
+		let endpoints: Vec<_> = document.service.iter().map(|s| s.service_endpoint.as_str()).collect();
+		assert_eq!(endpoints, vec!["urn:selendra:service:wordpress", "https://lms.example.edu"]);
+	}
+
+	#[test]
+	fn dids_of_other_chains_are_invalid() {
+		let who = AccountId32::new([1; 32]);
+		assert_eq!(account_of_did::<AccountId32>(&did(&who, 42), 42), Some(who.clone()));
+		assert_eq!(account_of_did::<AccountId32>(&did(&who, 0), 42), None);
+		assert_eq!(account_of_did::<AccountId32>(&did(&who, 2), 42), None);
+	}
+
+	#[test]
+	fn resolution_results_follow_the_spec() {
+		let json = serde_json::to_value(DidResolution::failed("notFound")).unwrap();
+		assert_eq!(
+			json,
+			serde_json::json!({
+				"@context": RESOLUTION_CONTEXT,
+				"didResolutionMetadata": { "error": "notFound" },
+				"didDocument": null,
+				"didDocumentMetadata": {},
+			})
+		);
+		assert_eq!(account_of_did::<AccountId32>("did:example:123", 42), None);
+	}
+}
@@ -0,0 +1,543 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2019-2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! RPC interface for the studentid pallet.
+
+use std::{marker::PhantomData, sync::Arc};
+
+use chrono::Utc;
+use codec::{Codec, Encode};
+use frame_support::traits::Get;
+use jsonrpc_core::{Error as RpcError, ErrorCode, Result};
+use jsonrpc_derive::rpc;
+use sc_rpc_api::DenyUnsafe;
+use serde::{Deserialize, Serialize};
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::HeaderBackend;
+use sp_core::{
+	crypto::{CryptoTypePublicPair, Ss58Codec},
+	sr25519, Bytes, H160, H256,
+};
+use sp_keystore::{SyncCryptoStore, SyncCryptoStorePtr};
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, NumberFor},
+};
+
+pub use pallet_studentid_rpc_runtime_api::{
+	attestation, Data, DidDetails, IdentityField, IdentityFields, IdentityInfoSel, JudgedIdentity, Judgement,
+	RegistrarIndex, RegistrarInfo, StudentidApi as StudentidRuntimeApi, TokenStatus,
+};
+
+pub mod credential;
+pub mod did;
+
+use credential::{CredentialError, CredentialJwt, CredentialVerification, VerifiableCredential};
+
+/// A `Data` value as returned over RPC. Raw data that is valid UTF-8 is returned as text.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum DataValue {
+	/// No data.
+	None,
+	/// Raw UTF-8 data.
+	Text(String),
+	/// Raw data that is not UTF-8.
+	Raw(Bytes),
+	/// Blake2 hash of the data.
+	BlakeTwo256(H256),
+	/// SHA2-256 hash of the data.
+	Sha256(H256),
+	/// Keccak-256 hash of the data.
+	Keccak256(H256),
+	/// SHA3-256 hash of the data.
+	ShaThree256(H256),
+}
+
+impl DataValue {
+	/// Render a full value, as text if it is valid UTF-8.
+	pub fn from_bytes(bytes: Vec<u8>) -> Self {
+		match String::from_utf8(bytes) {
+			Ok(text) => DataValue::Text(text),
+			Err(e) => DataValue::Raw(e.into_bytes().into()),
+		}
+	}
+}
+
+impl From<&Data> for DataValue {
+	fn from(data: &Data) -> Self {
+		match data {
+			Data::None => DataValue::None,
+			Data::Raw(x) => DataValue::from_bytes(x.to_vec()),
+			Data::BlakeTwo256(h) => DataValue::BlakeTwo256(h.into()),
+			Data::Sha256(h) => DataValue::Sha256(h.into()),
+			Data::Keccak256(h) => DataValue::Keccak256(h.into()),
+			Data::ShaThree256(h) => DataValue::ShaThree256(h.into()),
+		}
+	}
+}
+
+/// The part of a student identity a service is allowed to see.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DisclosedIdentity {
+	/// Display name.
+	pub display: DataValue,
+	/// Legal name.
+	pub legal: DataValue,
+	/// Website.
+	pub web: DataValue,
+	/// Email address.
+	pub email: DataValue,
+	/// PGP fingerprint.
+	pub pgp_fingerprint: Option<H160>,
+	/// Additional profile fields as (key, value) pairs.
+	pub additional: Vec<(DataValue, DataValue)>,
+}
+
+impl DisclosedIdentity {
+	/// Render `info`, turning each value into a `DataValue` with `resolve`.
+	pub fn new<MaxAdditionalFields: Get<u32>>(
+		info: IdentityInfoSel<MaxAdditionalFields>,
+		mut resolve: impl FnMut(&Data) -> Result<DataValue>,
+	) -> Result<Self> {
+		Ok(DisclosedIdentity {
+			display: resolve(&info.display)?,
+			legal: resolve(&info.legal)?,
+			web: resolve(&info.web)?,
+			email: resolve(&info.email)?,
+			pgp_fingerprint: info.pgp_fingerprint.map(H160::from),
+			additional: info
+				.additional
+				.iter()
+				.map(|(k, v)| Ok((resolve(k)?, resolve(v)?)))
+				.collect::<Result<_>>()?,
+		})
+	}
+}
+
+/// The answer to a token introspection request, modelled on RFC 7662.
+///
+/// Inactive and unknown tokens only report `active: false`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenIntrospection<AccountId, BlockNumber, BlockHash> {
+	/// Whether the token is allowed and has not expired.
+	pub active: bool,
+	/// The account the token was issued to.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub owner: Option<AccountId>,
+	/// The service the token was issued for.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub service: Option<String>,
+	/// Space separated identity fields the owner discloses to the service.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub scope: Option<String>,
+	/// The block the token was issued in.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub issued_at: Option<BlockNumber>,
+	/// The first block the token is no longer valid in.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub expires_at: Option<BlockNumber>,
+	/// The finalized block the answer was read from.
+	pub at: BlockHash,
+}
+
+impl<AccountId, BlockNumber, BlockHash> TokenIntrospection<AccountId, BlockNumber, BlockHash> {
+	/// Render `status` as read at block `at`.
+	pub fn new(status: Option<TokenStatus<AccountId, BlockNumber>>, at: BlockHash) -> Self {
+		match status {
+			Some(status) if status.active => TokenIntrospection {
+				active: true,
+				owner: Some(status.owner),
+				service: Some(String::from_utf8_lossy(&status.service).into_owned()),
+				scope: Some(scope(status.scopes)),
+				issued_at: Some(status.issued),
+				expires_at: Some(status.expires),
+				at,
+			},
+			_ => TokenIntrospection {
+				active: false,
+				owner: None,
+				service: None,
+				scope: None,
+				issued_at: None,
+				expires_at: None,
+				at,
+			},
+		}
+	}
+}
+
+/// The names of `fields`, separated by spaces.
+fn scope(fields: IdentityFields) -> String {
+	const NAMES: [(IdentityField, &str); 10] = [
+		(IdentityField::Display, "display"),
+		(IdentityField::Legal, "legal"),
+		(IdentityField::Web, "web"),
+		(IdentityField::Riot, "riot"),
+		(IdentityField::Email, "email"),
+		(IdentityField::PgpFingerprint, "pgp_fingerprint"),
+		(IdentityField::Image, "image"),
+		(IdentityField::Twitter, "twitter"),
+		(IdentityField::Profile, "profile"),
+		(IdentityField::Account, "account"),
+	];
+	NAMES
+		.iter()
+		.filter(|(field, _)| fields.contains(*field))
+		.map(|(_, name)| *name)
+		.collect::<Vec<_>>()
+		.join(" ")
+}
+
+#[rpc]
+pub trait StudentidApi<BlockHash, BlockNumber, AccountId> {
+	/// The identity of `who` as seen by `service`. `None` if `service` has no access to `who`.
+	#[rpc(name = "studentid_disclosedIdentity")]
+	fn disclosed_identity(
+		&self,
+		who: AccountId,
+		service: Bytes,
+		at: Option<BlockHash>,
+	) -> Result<Option<DisclosedIdentity>>;
+
+	/// The content of the blob stored under `hash`.
+	#[rpc(name = "studentid_blob")]
+	fn blob(&self, hash: H256, at: Option<BlockHash>) -> Result<Option<Bytes>>;
+
+	/// The challenge the next passkey assertion for the student with `email` must sign.
+	#[rpc(name = "studentid_passkeyChallenge")]
+	fn passkey_challenge(&self, email: Bytes, at: Option<BlockHash>) -> Result<H256>;
+
+	/// Whether the access token `token_id` is active, and who and what it was issued for.
+	///
+	/// Always answered from the last finalized block, so services don't act on state that
+	/// may be reverted.
+	#[rpc(name = "studentid_introspectToken")]
+	fn introspect_token(
+		&self,
+		token_id: Bytes,
+	) -> Result<TokenIntrospection<AccountId, BlockNumber, BlockHash>>;
+
+	/// The SCALE encoded `Snapshot` of registrar attestation keys and revoked attestations,
+	/// which offline verifiers check student ID card attestations against.
+	///
+	/// Always answered from the last finalized block.
+	#[rpc(name = "studentid_attestationSnapshot")]
+	fn attestation_snapshot(&self) -> Result<Bytes>;
+
+	/// The identity of `who` as judged by the registrar `registrar`, as a W3C Verifiable
+	/// Credential in a JWT signed with the registrar's attestation key.
+	///
+	/// Only works on nodes holding the attestation key in their keystore, under the key type
+	/// `attn`, and is unsafe as anyone able to call it can have the node sign credentials.
+	/// Rendered from the last finalized block.
+	#[rpc(name = "studentid_exportCredential")]
+	fn export_credential(
+		&self,
+		who: AccountId,
+		registrar: RegistrarIndex,
+	) -> Result<String>;
+
+	/// Check a credential exported with `studentid_exportCredential` against the last finalized
+	/// block: the registrar must still be registered and its judgement must not have been
+	/// revoked or changed.
+	#[rpc(name = "studentid_verifyCredential")]
+	fn verify_credential(&self, jwt: String) -> Result<CredentialVerification<BlockHash>>;
+}
+
+/// Error type of this RPC api.
+pub enum Error {
+	/// The call to runtime failed.
+	RuntimeError,
+	/// No credential can be issued.
+	CredentialUnavailable,
+}
+
+impl From<Error> for i64 {
+	fn from(e: Error) -> i64 {
+		match e {
+			Error::RuntimeError => 1,
+			Error::CredentialUnavailable => 2,
+		}
+	}
+}
+
+/// Implements the [`StudentidApi`] RPC trait for interacting with the studentid pallet.
+pub struct Studentid<C, B, M> {
+	client: Arc<C>,
+	keystore: SyncCryptoStorePtr,
+	deny_unsafe: DenyUnsafe,
+	_marker: PhantomData<(B, M)>,
+}
+
+impl<C, B, M> Studentid<C, B, M> {
+	/// Create new `Studentid` with the given reference to the client, signing credentials with
+	/// the keys in `keystore` unless `deny_unsafe` denies it.
+	pub fn new(client: Arc<C>, keystore: SyncCryptoStorePtr, deny_unsafe: DenyUnsafe) -> Self {
+		Self { client, keystore, deny_unsafe, _marker: Default::default() }
+	}
+}
+
+fn runtime_error(message: &str, e: impl std::fmt::Debug) -> RpcError {
+	RpcError {
+		code: ErrorCode::ServerError(Error::RuntimeError.into()),
+		message: message.into(),
+		data: Some(format!("{:?}", e).into()),
+	}
+}
+
+/// Render `data` as of block `at`, returning values kept in the blob store in full.
+fn resolve<Block, AccountId, MaxAdditionalFields>(
+	api: &impl StudentidRuntimeApi<Block, AccountId, MaxAdditionalFields>,
+	at: &BlockId<Block>,
+	data: &Data,
+) -> Result<DataValue>
+where
+	Block: BlockT,
+	AccountId: Codec,
+	MaxAdditionalFields: Get<u32>,
+{
+	match data {
+		Data::BlakeTwo256(_) => Ok(api
+			.resolve_data(at, data.clone())
+			.map_err(|e| runtime_error("Unable to resolve data.", e))?
+			.map(DataValue::from_bytes)
+			.unwrap_or_else(|| data.into())),
+		_ => Ok(data.into()),
+	}
+}
+
+fn credential_unavailable(message: &str) -> RpcError {
+	RpcError {
+		code: ErrorCode::ServerError(Error::CredentialUnavailable.into()),
+		message: message.into(),
+		data: None,
+	}
+}
+
+impl<C, Block, AccountId, MaxAdditionalFields>
+	StudentidApi<<Block as BlockT>::Hash, NumberFor<Block>, AccountId>
+	for Studentid<C, Block, MaxAdditionalFields>
+where
+	Block: BlockT,
+	C: Send + Sync + 'static + ProvideRuntimeApi<Block> + HeaderBackend<Block>,
+	C::Api: StudentidRuntimeApi<Block, AccountId, MaxAdditionalFields>,
+	AccountId: Codec + Clone + Ss58Codec,
+	MaxAdditionalFields: Get<u32> + Send + Sync + 'static,
+{
+	fn disclosed_identity(
+		&self,
+		who: AccountId,
+		service: Bytes,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<Option<DisclosedIdentity>> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+
+		let info = api
+			.disclosed_identity(&at, who, service.to_vec())
+			.map_err(|e| runtime_error("Unable to query disclosed identity.", e))?;
+
+		info.map(|info| DisclosedIdentity::new(info, |data| resolve(&*api, &at, data)))
+			.transpose()
+	}
+
+	fn blob(
+		&self,
+		hash: H256,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<Option<Bytes>> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+
+		let blob = api
+			.resolve_data(&at, Data::BlakeTwo256(hash.into()))
+			.map_err(|e| runtime_error("Unable to query blob.", e))?;
+
+		Ok(blob.map(Into::into))
+	}
+
+	fn passkey_challenge(&self, email: Bytes, at: Option<<Block as BlockT>::Hash>) -> Result<H256> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+
+		api.passkey_challenge(&at, email.to_vec())
+			.map(Into::into)
+			.map_err(|e| runtime_error("Unable to query passkey challenge.", e))
+	}
+
+	fn introspect_token(
+		&self,
+		token_id: Bytes,
+	) -> Result<TokenIntrospection<AccountId, NumberFor<Block>, <Block as BlockT>::Hash>> {
+		let api = self.client.runtime_api();
+		let finalized = self.client.info().finalized_hash;
+
+		let status = api
+			.introspect_token(&BlockId::hash(finalized), token_id.to_vec())
+			.map_err(|e| runtime_error("Unable to introspect token.", e))?;
+
+		Ok(TokenIntrospection::new(status, finalized))
+	}
+
+	fn attestation_snapshot(&self) -> Result<Bytes> {
+		let api = self.client.runtime_api();
+		let finalized = self.client.info().finalized_hash;
+
+		api.attestation_snapshot(&BlockId::hash(finalized))
+			.map(|snapshot| snapshot.encode().into())
+			.map_err(|e| runtime_error("Unable to take attestation snapshot.", e))
+	}
+
+	fn export_credential(
+		&self,
+		who: AccountId,
+		registrar: RegistrarIndex,
+	) -> Result<String> {
+		self.deny_unsafe.check_if_safe()?;
+
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(self.client.info().finalized_hash);
+		let ss58_prefix = api
+			.ss58_prefix(&at)
+			.map_err(|e| runtime_error("Unable to query SS58 prefix.", e))?;
+
+		let judged = api
+			.judged_identity(&at, who.clone(), registrar)
+			.map_err(|e| runtime_error("Unable to query judged identity.", e))?
+			.ok_or_else(|| credential_unavailable("The registrar has not judged the identity."))?;
+		let key = judged
+			.attestation_key
+			.ok_or_else(|| credential_unavailable("The registrar has no attestation key."))?;
+
+		let render = |data: &Data| resolve(&*api, &at, data);
+		let credential =
+			VerifiableCredential::new(&who, registrar, &judged, render, Utc::now(), ss58_prefix)?
+				.ok_or_else(|| {
+					credential_unavailable("The registrar judged the identity negatively.")
+				})?;
+
+		let signing_input = CredentialJwt::signing_input(&credential)
+			.map_err(|e| runtime_error("Unable to encode credential.", e))?;
+		let signature = SyncCryptoStore::sign_with(
+			&*self.keystore,
+			attestation::KEY_TYPE,
+			&CryptoTypePublicPair::from(sr25519::Public::from_raw(key)),
+			signing_input.as_bytes(),
+		)
+		.map_err(|e| runtime_error("Unable to sign credential.", e))?
+		.ok_or_else(|| credential_unavailable("The attestation key is not in the keystore."))?;
+		Ok(CredentialJwt::new(credential, signing_input, signature).to_string())
+	}
+
+	fn verify_credential(
+		&self,
+		jwt: String,
+	) -> Result<CredentialVerification<<Block as BlockT>::Hash>> {
+		let api = self.client.runtime_api();
+		let finalized = self.client.info().finalized_hash;
+		let at = BlockId::hash(finalized);
+		let reject = |error| Ok(CredentialVerification::new(Err(error), finalized));
+		let ss58_prefix = api
+			.ss58_prefix(&at)
+			.map_err(|e| runtime_error("Unable to query SS58 prefix.", e))?;
+
+		let jwt = match CredentialJwt::parse(&jwt) {
+			Ok(jwt) => jwt,
+			Err(error) => return reject(error),
+		};
+		let credential = &jwt.credential;
+		let subject = credential.subject::<AccountId>(ss58_prefix);
+		let (who, issued) = match (subject, credential.issued()) {
+			(Some(who), Some(issued)) => (who, issued),
+			_ => return reject(CredentialError::Malformed),
+		};
+		let index = credential.credential_subject.judgement.registrar;
+
+		let key = match api
+			.registrar(&at, index)
+			.map_err(|e| runtime_error("Unable to query registrar.", e))?
+		{
+			Some(RegistrarInfo { attestation_key: Some(key), .. }) => key,
+			_ => return reject(CredentialError::UnknownRegistrar),
+		};
+		let judged = match api
+			.judged_identity(&at, who.clone(), index)
+			.map_err(|e| runtime_error("Unable to query judged identity.", e))?
+		{
+			Some(judged) => judged,
+			None => return reject(CredentialError::JudgementRevoked),
+		};
+
+		let render = |data: &Data| resolve(&*api, &at, data);
+		let expected =
+			match VerifiableCredential::new(&who, index, &judged, render, issued, ss58_prefix)? {
+				Some(expected) => expected,
+				None => return reject(CredentialError::JudgementChanged),
+			};
+
+		Ok(CredentialVerification::new(jwt.verify(&expected, &key), finalized))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn data_value_renders_text_and_hashes() {
+		let text = Data::Raw(b"Alice".to_vec().try_into().unwrap());
+		assert_eq!(DataValue::from(&text), DataValue::Text("Alice".into()));
+
+		let binary = Data::Raw(vec![0xff, 0xfe].try_into().unwrap());
+		assert_eq!(DataValue::from(&binary), DataValue::Raw(vec![0xff, 0xfe].into()));
+
+		let hashed = Data::BlakeTwo256([1; 32]);
+		assert!(matches!(DataValue::from(&hashed), DataValue::BlakeTwo256(_)));
+		assert_eq!(
+			serde_json::to_string(&DataValue::from(&text)).unwrap(),
+			r#"{"text":"Alice"}"#
+		);
+	}
+
+	#[test]
+	fn introspection_hides_inactive_tokens() {
+		let status = TokenStatus {
+			active: true,
+			owner: 10u64,
+			service: b"wordpress".to_vec(),
+			scopes: IdentityFields::new(IdentityField::Display | IdentityField::Email),
+			issued: 5u32,
+			expires: 105,
+		};
+		let at = H256::repeat_byte(1);
+
+		let active = TokenIntrospection::new(Some(status.clone()), at);
+		assert_eq!(active.scope.as_deref(), Some("display email"));
+		assert_eq!(active.service.as_deref(), Some("wordpress"));
+		assert_eq!((active.owner, active.issued_at, active.expires_at), (Some(10), Some(5), Some(105)));
+
+		let inactive = TokenIntrospection::new(Some(TokenStatus { active: false, ..status }), at);
+		assert_eq!(inactive, TokenIntrospection::new(None, at));
+		assert_eq!(
+			serde_json::to_string(&inactive).unwrap(),
+			format!(r#"{{"active":false,"at":"0x{}"}}"#, "01".repeat(32))
+		);
+	}
+}
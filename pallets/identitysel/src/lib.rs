@@ -23,25 +23,47 @@
 
 #![cfg_attr(not(feature = "std"), no_std)]
 
+pub mod migrations;
 #[cfg(test)]
 mod tests;
+mod traits;
 mod types;
+mod webauthn;
 
-use codec::{Decode, Encode, MaxEncodedLen};
+pub use studentid_attestation as attestation;
+pub use studentid_roster as roster;
+pub use studentid_siws as siws;
 
-use sp_io::hashing::{sha2_256, blake2_256};
+use codec::{Decode, Encode, MaxEncodedLen};
 
-use frame_support::traits::{Currency, OnUnbalanced, ReservableCurrency};
-use sp_runtime::traits::{AppendZerosInput, Zero, IdentifyAccount };
+use sp_io::hashing::{sha2_256, blake2_128, blake2_256, keccak_256};
+use sp_core::H160;
+
+use frame_support::{
+	dispatch::{DispatchError, DispatchResult},
+	ensure,
+	traits::{
+		BalanceStatus, Currency, ExistenceRequirement, Get, OnUnbalanced, ReservableCurrency,
+		UnixTime,
+	},
+	weights::{
+		constants::{WEIGHT_PER_MICROS, WEIGHT_PER_MILLIS, WEIGHT_PER_NANOS},
+		Weight,
+	},
+	PalletId,
+};
+use sp_runtime::traits::{AccountIdConversion, AppendZerosInput, Saturating, Zero, IdentifyAccount };
 use sp_runtime::RuntimeDebug;
 
 use sp_std::prelude::*;
 use scale_info::TypeInfo;
 
 pub use pallet::*;
+pub use traits::{EnsureRegistrar, EnsureStudent, StudentIdentityProvider};
 pub use types::{
-	Data, IdentityField, IdentityFields,  IdentityInfoSel,  RegistrarIndex, 
-	 RegistrationSel
+	email_domain, normalize_domain, normalize_email, profile_keys, CredentialIndex, CredentialKind,
+	Data, IdentityField, IdentityFields, IdentityInfoSel, Institution, IssuedCredential, InstitutionIndex, Judgement, RegistrarIndex, RegistrarInfo,
+	RegistrationSel, Roster, SponsorshipTerms, StudentState, StudentStatus
 };
 
 type BalanceOf<T> =
@@ -50,19 +72,115 @@ type NegativeImbalanceOf<T> = <<T as Config>::Currency as Currency<
 	<T as frame_system::Config>::AccountId,
 >>::NegativeImbalance;
 
+/// The most used Sign-In with Substrate nonces of an account looked at for expiry on a login.
+const MAX_SIWS_NONCES_PRUNED: u64 = 8;
+
+/// Weight of recovering the public key of a secp256k1 signature with the host function, about
+/// 50 µs.
+const ECRECOVER_WEIGHT: Weight = 50 * WEIGHT_PER_MICROS;
+
+/// Weight of verifying an sr25519 signature with the host function, about 50 µs.
+const SR25519_VERIFY_WEIGHT: Weight = 50 * WEIGHT_PER_MICROS;
+
+/// Weight of verifying a P-256 ECDSA signature, which has no host function and runs in wasm,
+/// about 5 ms.
+const P256_VERIFY_WEIGHT: Weight = 5 * WEIGHT_PER_MILLIS;
+
+/// Weight of decoding a COSE key and checking its P-256 point is on the curve in wasm, about
+/// 500 µs.
+const P256_KEY_CHECK_WEIGHT: Weight = 500 * WEIGHT_PER_MICROS;
+
+/// Weight of hashing and copying one byte of a call argument.
+const BYTE_WEIGHT: Weight = 10 * WEIGHT_PER_NANOS;
+
 pub type UseridentityIndex = u32;
 
 //pub type DefaultAccountId = 
 
+/// A profile value too long for `Data::Raw`, stored under the Blake2 hash of its content and
+/// referenced from identity fields as `Data::BlakeTwo256`.
+#[derive(Encode, Decode, Clone, Eq, PartialEq, MaxEncodedLen, RuntimeDebug, TypeInfo)]
+pub struct Blob<AccountId, Balance, Bytes> {
+	/// The account that noted the blob and holds its deposit.
+	pub owner: AccountId,
+	/// Amount held on deposit for the blob.
+	pub deposit: Balance,
+	/// The number of fields of the owner's own identity referencing the blob. It cannot be
+	/// removed while any do; references from other identities do not hold it.
+	pub references: u32,
+	/// The content of the blob.
+	pub data: Bytes,
+}
+
+/// A WebAuthn credential (passkey) registered for a student.
+#[derive(Encode, Decode, Clone, Eq, PartialEq, MaxEncodedLen, RuntimeDebug, TypeInfo)]
+pub struct Passkey<CredentialId> {
+	/// The credential ID chosen by the authenticator.
+	pub credential_id: CredentialId,
+	/// The uncompressed SEC1 encoding of the credential's P-256 public key.
+	pub public_key: [u8; 65],
+	/// The signature counter reported by the last successful assertion.
+	pub sign_count: u32,
+}
+
 /// Token info
 #[derive(Encode, Decode, Clone, Eq, PartialEq, MaxEncodedLen, RuntimeDebug, TypeInfo)]
-pub struct TokenInfo<AccountId, Data, TokenMetadataOf> {
+pub struct TokenInfo<AccountId, Data, TokenMetadataOf, BlockNumber> {
 	/// Token metadata
 	pub metadata: TokenMetadataOf,
 	/// Token owner
 	pub owner: AccountId,
 	/// Token Properties
 	pub data: Data,
+	/// The block the token was issued in.
+	pub issued: BlockNumber,
+	/// The first block the token is no longer valid in.
+	pub expires: BlockNumber,
+}
+
+/// The state of an access token, as returned by token introspection.
+#[derive(Encode, Decode, Clone, Eq, PartialEq, RuntimeDebug, TypeInfo)]
+pub struct TokenStatus<AccountId, BlockNumber> {
+	/// Whether the token is allowed and has not expired.
+	pub active: bool,
+	/// The account the token was issued to.
+	pub owner: AccountId,
+	/// The service the token was issued for.
+	pub service: Vec<u8>,
+	/// The identity fields the owner discloses to the service.
+	pub scopes: IdentityFields,
+	/// The block the token was issued in.
+	pub issued: BlockNumber,
+	/// The first block the token is no longer valid in.
+	pub expires: BlockNumber,
+}
+
+/// A registrar's judgement of the current identity of an account, as returned by
+/// `judged_identity`.
+#[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebug, TypeInfo)]
+#[scale_info(skip_type_params(FieldLimit))]
+pub struct JudgedIdentity<AccountId, FieldLimit: Get<u32>> {
+	/// The account of the registrar.
+	pub registrar: AccountId,
+	/// The key the registrar signs attestations and credentials with, if set.
+	pub attestation_key: Option<[u8; 32]>,
+	/// The registrar's judgement.
+	pub judgement: Judgement,
+	/// The judged identity, see [`IdentityInfoSel::judged`].
+	pub info: IdentityInfoSel<FieldLimit>,
+}
+
+/// What a `did:selendra` DID document is derived from, as returned by `did_details`.
+#[derive(Encode, Decode, Clone, Eq, PartialEq, RuntimeDebug, TypeInfo)]
+pub struct DidDetails<AccountId> {
+	/// The account the registration is linked to, which controls the DID.
+	pub controller: AccountId,
+	/// The sub-accounts of the account, which did not need to consent to being added.
+	pub sub_accounts: Vec<AccountId>,
+	/// The Ethereum address linked to the account.
+	pub eth_account: Option<H160>,
+	/// The services the account granted access to.
+	pub services: Vec<Vec<u8>>,
 }
 
 #[frame_support::pallet]
@@ -110,11 +228,71 @@ pub mod pallet {
 		#[pallet::constant]
 		type MaxRegistrars: Get<u32>;
 
+		/// The identifier the sponsor pot accounts of institutions are derived from.
+		#[pallet::constant]
+		type SponsorPalletId: Get<PalletId>;
+
+		/// The length in blocks of the periods feeless onboarding calls are counted in.
+		#[pallet::constant]
+		type FeelessPeriod: Get<Self::BlockNumber>;
+
+		/// The number of feeless onboarding calls an account can make per `FeelessPeriod`.
+		#[pallet::constant]
+		type MaxFeelessCallsPerPeriod: Get<u32>;
+
+		/// The maximum number of students a single `renew_students` call renews.
+		#[pallet::constant]
+		type MaxRenewalsPerCall: Get<u32>;
+
+		/// The maximum number of email domains an institution can own.
+		#[pallet::constant]
+		type MaxDomainsPerInstitution: Get<u32>;
+
 		type MaxEmailsize: Get<u32>;
 
 		type MaxTokenid: Get<u32>;
 
+		/// The number of blocks an access token is valid for.
+		#[pallet::constant]
+		type TokenLifetime: Get<Self::BlockNumber>;
+
 		type MaxUseridentities: Get<u32>;
+
+		/// The maximum number of tokens and service grants removed by a single `clear_identity`
+		/// call. Students holding more than this need to call it repeatedly.
+		#[pallet::constant]
+		type MaxClearedPerCall: Get<u32>;
+
+		/// The maximum size in bytes of a blob held in the blob store.
+		#[pallet::constant]
+		type MaxBlobSize: Get<u32>;
+
+		/// The base amount held on deposit for a blob.
+		#[pallet::constant]
+		type BlobDepositBase: Get<BalanceOf<Self>>;
+
+		/// The amount held on deposit per byte of a blob.
+		#[pallet::constant]
+		type BlobDepositPerByte: Get<BalanceOf<Self>>;
+
+		/// The maximum number of passkeys a student can register.
+		#[pallet::constant]
+		type MaxPasskeys: Get<u32>;
+
+		/// The maximum length of a WebAuthn credential ID.
+		#[pallet::constant]
+		type MaxCredentialIdLen: Get<u32>;
+
+		/// The WebAuthn relying party ID passkeys are registered for, e.g. `id.selendra.org`.
+		type RelyingPartyId: Get<Vec<u8>>;
+
+		/// The origin of the web application passkey assertions are made from, e.g.
+		/// `https://id.selendra.org`.
+		type RelyingPartyOrigin: Get<Vec<u8>>;
+
+		/// Time used to check the validity period of Sign-In with Substrate messages.
+		type UnixTime: UnixTime;
+
 		/// What to do with slashed funds.
 		type Slashed: OnUnbalanced<NegativeImbalanceOf<Self>>;
 
@@ -136,15 +314,48 @@ pub mod pallet {
     pub type Email<T> =  BoundedVec<u8, <T as  Config>::MaxEmailsize>;
 
 	pub type TokenMetadataOf<T> = BoundedVec<u8, <T as Config>::MaxAccessTokenMetadata>;
-	pub type TokenInfoOf<T> =
-		TokenInfo<<T as frame_system::Config>::AccountId,  Data, TokenMetadataOf<T>>;
+	pub type TokenInfoOf<T> = TokenInfo<
+		<T as frame_system::Config>::AccountId,
+		Data,
+		TokenMetadataOf<T>,
+		<T as frame_system::Config>::BlockNumber,
+	>;
+	pub type BlobOf<T> = Blob<
+		<T as frame_system::Config>::AccountId,
+		BalanceOf<T>,
+		BoundedVec<u8, <T as Config>::MaxBlobSize>,
+	>;
+	pub type InstitutionOf<T> = Institution<
+		<T as frame_system::Config>::AccountId,
+		<T as Config>::MaxEmailsize,
+		<T as Config>::MaxDomainsPerInstitution,
+		<T as Config>::MaxRegistrars,
+	>;
+	pub type CredentialId<T> = BoundedVec<u8, <T as Config>::MaxCredentialIdLen>;
+	pub type PasskeyOf<T> = Passkey<CredentialId<T>>;
+	pub type RegistrationOf<T> = RegistrationSel<
+		BalanceOf<T>,
+		<T as frame_system::Config>::AccountId,
+		<T as Config>::MaxAdditionalFields,
+	>;
+
 
+	/// The current storage version.
+	const STORAGE_VERSION: StorageVersion = StorageVersion::new(2);
 
 	#[pallet::pallet]
     #[pallet::generate_store(pub(super) trait Store)]
     #[pallet::without_storage_info]
+	#[pallet::storage_version(STORAGE_VERSION)]
 	pub struct Pallet<T>(_);
 
+	#[pallet::hooks]
+	impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
+		fn on_runtime_upgrade() -> Weight {
+			migrations::v1::migrate::<T>().saturating_add(migrations::v2::migrate::<T>())
+		}
+	}
+
 	/// testing
 	/// Access tokens if needed, access for which services
 	/// Returns `None` if token info not set or removed.
@@ -153,6 +364,205 @@ pub mod pallet {
 	pub type Tokens<T: Config> =
 		StorageMap<_, Twox64Concat,  TokenId<T>, TokenInfoOf<T>>;
 
+	/// Index of the tokens held by each account, so they can be found without walking `Tokens`.
+	#[pallet::storage]
+	pub type TokensOf<T: Config> =
+		StorageDoubleMap<_, Twox64Concat, T::AccountId, Twox64Concat, TokenId<T>, ()>;
+
+	/// The services an account has been granted access to.
+	#[pallet::storage]
+	pub type ServiceGrants<T: Config> =
+		StorageDoubleMap<_, Twox64Concat, T::AccountId, Blake2_128Concat, Data, ()>;
+
+	/// The identity fields each account discloses to the services it granted access to.
+	#[pallet::storage]
+	pub type DisclosureOf<T: Config> = StorageDoubleMap<
+		_,
+		Twox64Concat,
+		T::AccountId,
+		Blake2_128Concat,
+		Data,
+		IdentityFields,
+		ValueQuery,
+	>;
+
+	/// Profile values too long to be stored inline, keyed by the Blake2 hash of their content.
+	///
+	/// TWOX-NOTE: Not needed ― the key is a hash of the content.
+	#[pallet::storage]
+	pub type Blobs<T: Config> = StorageMap<_, Identity, [u8; 32], BlobOf<T>>;
+
+	/// The Ethereum address linked to each account.
+	#[pallet::storage]
+	#[pallet::getter(fn eth_account_of)]
+	pub type EthAccountOf<T: Config> = StorageMap<_, Twox64Concat, T::AccountId, H160>;
+
+	/// Reverse lookup from a linked Ethereum address to the account it is linked to.
+	#[pallet::storage]
+	#[pallet::getter(fn student_of_eth)]
+	pub type StudentOfEth<T: Config> = StorageMap<_, Twox64Concat, H160, T::AccountId>;
+
+	/// The passkeys registered for each student, keyed by email.
+	#[pallet::storage]
+	#[pallet::getter(fn passkeys)]
+	pub type Passkeys<T: Config> =
+		StorageMap<_, Twox64Concat, Email<T>, BoundedVec<PasskeyOf<T>, T::MaxPasskeys>, ValueQuery>;
+
+	/// The Sign-In with Substrate nonces each account has used, by Blake2 hash, with the
+	/// expiration time of the message they were used in.
+	#[pallet::storage]
+	pub type SiwsNonces<T: Config> =
+		StorageDoubleMap<_, Twox64Concat, T::AccountId, Identity, [u8; 32], u64>;
+
+	/// Incremented on every passkey login so each challenge is only used once.
+	#[pallet::storage]
+	pub type PasskeyNonce<T: Config> = StorageMap<_, Twox64Concat, Email<T>, u32, ValueQuery>;
+
+	/// The super-identity of an alternative "sub" identity together with its name, within that
+	/// context. If the account is not some other account's sub-identity, then just `None`.
+	#[pallet::storage]
+	#[pallet::getter(fn super_of)]
+	pub type SuperOf<T: Config> =
+		StorageMap<_, Blake2_128Concat, T::AccountId, (T::AccountId, Data), OptionQuery>;
+
+	/// Alternative "sub" identities of this account, with the deposit held for them.
+	///
+	/// TWOX-NOTE: OK ― `AccountId` is a secure hash.
+	#[pallet::storage]
+	#[pallet::getter(fn subs_of)]
+	pub type SubsOf<T: Config> = StorageMap<
+		_,
+		Twox64Concat,
+		T::AccountId,
+		(BalanceOf<T>, BoundedVec<T::AccountId, T::MaxSubAccounts>),
+		ValueQuery,
+	>;
+
+	/// The registrars. The index into this can be cast to `RegistrarIndex` to get a valid value.
+	#[pallet::storage]
+	#[pallet::getter(fn registrars)]
+	pub type Registrars<T: Config> = StorageValue<
+		_,
+		BoundedVec<Option<RegistrarInfo<T::AccountId>>, T::MaxRegistrars>,
+		ValueQuery,
+	>;
+
+	/// The judgements registrars gave on identities, with the Blake2 hash of the judged
+	/// identity. A judgement only applies while the identity is unchanged.
+	#[pallet::storage]
+	pub type JudgementOf<T: Config> = StorageDoubleMap<
+		_,
+		Twox64Concat,
+		T::AccountId,
+		Twox64Concat,
+		RegistrarIndex,
+		(Judgement, [u8; 32]),
+	>;
+
+	/// Revoked ID card attestations, by attestation ID, with the registrar that revoked them
+	/// and the time the attestation expires at.
+	///
+	/// TWOX-NOTE: Not needed ― the key is a hash of the attestation.
+	#[pallet::storage]
+	pub type RevokedAttestations<T: Config> =
+		StorageMap<_, Identity, [u8; 32], (RegistrarIndex, u64)>;
+
+	/// The institutions, by index.
+	#[pallet::storage]
+	#[pallet::getter(fn institution)]
+	pub type Institutions<T: Config> = StorageMap<_, Twox64Concat, InstitutionIndex, InstitutionOf<T>>;
+
+	/// The number of institutions added, which is the index of the next one.
+	#[pallet::storage]
+	pub type InstitutionCount<T: Config> = StorageValue<_, InstitutionIndex, ValueQuery>;
+
+	/// The institution owning each normalized email domain. Students registering with an email
+	/// of a domain nobody owns are unverified and belong to no institution.
+	#[pallet::storage]
+	pub type DomainOwner<T: Config> = StorageMap<_, Blake2_128Concat, Email<T>, InstitutionIndex>;
+
+	/// The institution each registrar acts for. Registrars without one act for every student.
+	#[pallet::storage]
+	pub type InstitutionOfRegistrar<T: Config> =
+		StorageMap<_, Twox64Concat, RegistrarIndex, InstitutionIndex>;
+
+	/// The status of each student, by email. Students without one are `Unverified`: they may
+	/// still log in, as every student could before statuses existed, so existing students need
+	/// no migration, but they are not active students to other pallets or for sponsorship. It
+	/// outlives `clear_identity`, so a suspended student cannot shed the suspension by
+	/// registering again.
+	#[pallet::storage]
+	pub type StatusOf<T: Config> =
+		StorageMap<_, Blake2_128Concat, Email<T>, StudentState<T::BlockNumber>>;
+
+	/// The credentials registrars issued, by index.
+	#[pallet::storage]
+	#[pallet::getter(fn credential)]
+	pub type Credentials<T: Config> =
+		StorageMap<_, Twox64Concat, CredentialIndex, IssuedCredential<T::AccountId>>;
+
+	/// The number of credentials issued, which is the index of the next one.
+	#[pallet::storage]
+	pub type CredentialCount<T: Config> = StorageValue<_, CredentialIndex, ValueQuery>;
+
+	/// The credentials of each account.
+	#[pallet::storage]
+	pub type CredentialsOf<T: Config> =
+		StorageDoubleMap<_, Twox64Concat, T::AccountId, Twox64Concat, CredentialIndex, ()>;
+
+	/// The period an account last made feeless onboarding calls in, and how many it made.
+	#[pallet::storage]
+	pub type FeelessCallsOf<T: Config> =
+		StorageMap<_, Twox64Concat, T::AccountId, (T::BlockNumber, u32)>;
+
+	/// The terms under which institutions pay the transaction fees of their students.
+	#[pallet::storage]
+	pub type Sponsorships<T: Config> = StorageMap<
+		_,
+		Twox64Concat,
+		InstitutionIndex,
+		SponsorshipTerms<BalanceOf<T>, T::BlockNumber>,
+	>;
+
+	/// The period an institution last paid fees in, and how much it paid in it.
+	#[pallet::storage]
+	pub type SponsoredFees<T: Config> =
+		StorageMap<_, Twox64Concat, InstitutionIndex, (T::BlockNumber, BalanceOf<T>)>;
+
+	/// The period an institution last paid fees of a student in, and how much it paid in it.
+	#[pallet::storage]
+	pub type SponsoredFeesOf<T: Config> = StorageDoubleMap<
+		_,
+		Twox64Concat,
+		InstitutionIndex,
+		Twox64Concat,
+		T::AccountId,
+		(T::BlockNumber, BalanceOf<T>),
+	>;
+
+	/// The enrollment rosters registrars published, by registrar and term.
+	#[pallet::storage]
+	pub type Rosters<T: Config> = StorageDoubleMap<
+		_,
+		Twox64Concat,
+		RegistrarIndex,
+		Twox64Concat,
+		roster::Term,
+		Roster<T::BlockNumber>,
+	>;
+
+	/// The roster leaf each student claimed, by registrar. A later claim replaces the earlier
+	/// one, so this is the student's most recent term with the registrar.
+	#[pallet::storage]
+	pub type EnrollmentOf<T: Config> = StorageDoubleMap<
+		_,
+		Twox64Concat,
+		T::AccountId,
+		Twox64Concat,
+		RegistrarIndex,
+		roster::Leaf,
+	>;
+
 	#[pallet::storage]
 	#[pallet::getter(fn emailid)]
 	pub type EmailId<T: Config> =
@@ -175,7 +585,7 @@ pub mod pallet {
 
 	#[pallet::storage]
 	#[pallet::getter(fn studentidof)]
-	pub(super) type StudentidOf<T: Config> = StorageMap<
+	pub type StudentidOf<T: Config> = StorageMap<
 		_,
 		Twox64Concat,
 		BoundedVec<u8, T::MaxEmailsize> ,
@@ -225,10 +635,102 @@ pub mod pallet {
 		TooManyFields,
 		/// Maximum amount of registrars reached. Cannot add any more.
 		AlreadyClaimed,
+		/// The identity has no additional field with the given key.
+		NoSuchField,
+		/// The blob is larger than `MaxBlobSize`.
+		BlobTooLarge,
+		/// A blob with the same content is already stored.
+		BlobAlreadyNoted,
+		/// No blob is stored under the given hash.
+		NoSuchBlob,
+		/// The blob was noted by another account.
+		NotBlobOwner,
+		/// The signature does not recover to an Ethereum address.
+		InvalidEthSignature,
+		/// The Ethereum address is already linked to another account.
+		EthAccountAlreadyLinked,
+		/// No Ethereum address is linked to the account.
+		NoEthAccount,
+		/// The key is not a COSE encoded P-256 public key for ES256.
+		InvalidPasskey,
+		/// The credential ID is longer than `MaxCredentialIdLen`.
+		CredentialIdTooLong,
+		/// The student already has `MaxPasskeys` passkeys.
+		TooManyPasskeys,
+		/// A passkey with the same credential ID is already registered.
+		PasskeyAlreadyRegistered,
+		/// No passkey with the given credential ID is registered.
+		NoSuchPasskey,
+		/// The WebAuthn assertion did not verify.
+		PasskeyVerificationFailed,
+		/// The Sign-In with Substrate message is malformed or meant for another domain or chain.
+		InvalidSiwsMessage,
+		/// The Sign-In with Substrate message is not valid yet or has expired.
+		SiwsMessageExpired,
+		/// The Sign-In with Substrate signature does not match the message.
+		InvalidSiwsSignature,
+		/// The Sign-In with Substrate nonce was used before.
+		SiwsNonceUsed,
+		/// Maximum amount of registrars reached. Cannot add any more.
+		TooManyRegistrars,
+		/// The attestation was signed with another key than the registrar's attestation key.
+		NotAttestationKey,
+		/// The attestation was revoked before.
+		AttestationAlreadyRevoked,
+		/// The identity changed since the registrar looked at it.
+		JudgementForDifferentIdentity,
+		/// The registrar has not judged the identity.
+		JudgementNotFound,
+		/// The account is not a sub-account.
+		NotSub,
+		/// The sub-account is owned by another account.
+		NotOwned,
+		/// The registrar has not published a roster for the term.
+		RosterNotFound,
+		/// The proof does not show the leaf is on the roster.
+		InvalidRosterProof,
+		/// There is no institution with that index.
+		InvalidInstitution,
+		/// The email domain is empty or too long.
+		InvalidDomain,
+		/// The email domain is owned by an institution already.
+		DomainAlreadyOwned,
+		/// The email domain is not owned by the institution.
+		DomainNotOwned,
+		/// The institution owns the maximum number of email domains.
+		TooManyDomains,
+		/// The registrar acts for another institution.
+		RegistrarOfOtherInstitution,
+		/// The registrar does not act for the student's institution.
+		NotInstitutionStudent,
+		/// The student's status does not allow logging in or using services.
+		StudentInactive,
+		/// The student cannot move from their current status to the new one.
+		InvalidStatusTransition,
+		/// More students than `MaxRenewalsPerCall` were given.
+		TooManyRenewals,
+		/// The sender is not the admin of the institution.
+		NotInstitutionAdmin,
+		/// The institution does not sponsor transaction fees.
+		NoSponsorship,
+		/// There is no credential with that index.
+		InvalidCredential,
+		/// The credential was issued by another registrar.
+		NotCredentialIssuer,
+		/// The credential was revoked before.
+		CredentialAlreadyRevoked,
+		/// The registration is linked to an account and must be cleared with `clear_identity`.
+		IdentityLinked,
+		/// The identity holds a deposit, so it cannot be moved to another account.
+		DepositHeld,
+		/// The blob is referenced by a field of its owner's identity.
+		BlobInUse,
+		/// The status would already have lapsed: `valid_until` is not in the future.
+		StatusAlreadyLapsed,
 	}
 
 	#[pallet::event]
-	// #[pallet::generate_deposit(pub(super) fn deposit_event)]
+	#[pallet::generate_deposit(pub(super) fn deposit_event)]
 	pub enum Event<T: Config> {
 		/// A name was set or reset (which will remove all judgements).
 		UserRegistered { who: Vec<u8>      },
@@ -250,6 +752,23 @@ pub mod pallet {
 		IdentitySet { who: T::AccountId },
 		/// A name was cleared, and the given balance returned.
 		IdentityCleared { who: T::AccountId, deposit: BalanceOf<T> },
+		/// Part of the tokens and service grants of an identity were removed. `clear_identity`
+		/// must be called again to finish clearing it.
+		IdentityClearPending { who: T::AccountId },
+		/// The fields disclosed to a service were changed.
+		DisclosureSet { who: T::AccountId, service: Data },
+		/// A blob was added to the blob store.
+		BlobNoted { who: T::AccountId, hash: [u8; 32] },
+		/// A blob was removed from the blob store and its deposit returned.
+		BlobUnnoted { who: T::AccountId, hash: [u8; 32] },
+		/// An Ethereum address was linked to an account.
+		EthAccountLinked { who: T::AccountId, address: H160 },
+		/// The Ethereum address linked to an account was removed.
+		EthAccountUnlinked { who: T::AccountId, address: H160 },
+		/// A passkey was registered for a student.
+		PasskeyRegistered { who: Vec<u8>, credential_id: Vec<u8> },
+		/// A passkey was removed from a student.
+		PasskeyRemoved { who: Vec<u8>, credential_id: Vec<u8> },
 		/// A name was removed and the given balance slashed.
 		IdentityKilled { who: T::AccountId, deposit: BalanceOf<T> },
         
@@ -258,6 +777,75 @@ pub mod pallet {
 
 		/// A registrar was added.
 		RegistrarAdded { registrar_index: RegistrarIndex },
+		/// A registrar set the key it signs ID card attestations with.
+		AttestationKeySet { registrar_index: RegistrarIndex, key: [u8; 32] },
+		/// A registrar revoked an ID card attestation.
+		AttestationRevoked { registrar_index: RegistrarIndex, id: [u8; 32] },
+		/// A registrar judged an identity.
+		JudgementGiven { target: T::AccountId, registrar_index: RegistrarIndex },
+		/// A registrar revoked its judgement of an identity.
+		JudgementRevoked { target: T::AccountId, registrar_index: RegistrarIndex },
+		/// A sub-identity was added to an identity and the deposit paid.
+		SubIdentityAdded { sub: T::AccountId, main: T::AccountId, deposit: BalanceOf<T> },
+		/// A sub-identity was removed from an identity and the deposit freed.
+		SubIdentityRemoved { sub: T::AccountId, main: T::AccountId, deposit: BalanceOf<T> },
+		/// A sub-identity was cleared, and the given deposit repatriated from the
+		/// main identity account to the sub-identity account.
+		SubIdentityRevoked { sub: T::AccountId, main: T::AccountId, deposit: BalanceOf<T> },
+		/// An institution was added.
+		InstitutionAdded { institution: InstitutionIndex },
+		/// The name, admin or metadata of an institution changed.
+		InstitutionUpdated { institution: InstitutionIndex },
+		/// An institution took ownership of an email domain.
+		DomainAdded { institution: InstitutionIndex, domain: Vec<u8> },
+		/// An institution gave up an email domain.
+		DomainRemoved { institution: InstitutionIndex, domain: Vec<u8> },
+		/// A registrar started acting for an institution.
+		InstitutionRegistrarAdded { institution: InstitutionIndex, registrar_index: RegistrarIndex },
+		/// A registrar stopped acting for an institution.
+		InstitutionRegistrarRemoved { institution: InstitutionIndex, registrar_index: RegistrarIndex },
+		/// A student registered with an email of a domain owned by an institution.
+		StudentRouted { who: Vec<u8>, institution: InstitutionIndex },
+		/// A registrar set the status of a student, or the student claimed it from a roster.
+		StudentStatusChanged {
+			who: T::AccountId,
+			status: StudentStatus,
+			term: roster::Term,
+			valid_until: T::BlockNumber,
+		},
+		/// A student was left out of a bulk renewal because of their status or institution.
+		RenewalSkipped { who: T::AccountId },
+		/// Funds were added to the sponsor pot of an institution.
+		SponsorPotFunded { institution: InstitutionIndex, who: T::AccountId, amount: BalanceOf<T> },
+		/// Funds were taken out of the sponsor pot of an institution.
+		SponsorPotWithdrawn { institution: InstitutionIndex, amount: BalanceOf<T> },
+		/// An institution started paying the transaction fees of its students, or changed how much.
+		SponsorshipSet { institution: InstitutionIndex },
+		/// An institution stopped paying the transaction fees of its students.
+		SponsorshipRevoked { institution: InstitutionIndex },
+		/// A registrar issued a credential to a student.
+		CredentialIssued {
+			owner: T::AccountId,
+			index: CredentialIndex,
+			kind: CredentialKind,
+			registrar_index: RegistrarIndex,
+		},
+		/// A registrar revoked a credential.
+		CredentialRevoked { owner: T::AccountId, index: CredentialIndex },
+		/// A registrar published the roster of a term.
+		RosterPublished {
+			registrar_index: RegistrarIndex,
+			term: roster::Term,
+			root: [u8; 32],
+			valid_until: T::BlockNumber,
+		},
+		/// A student proved they are on the roster of a term.
+		EnrollmentClaimed {
+			who: T::AccountId,
+			registrar_index: RegistrarIndex,
+			term: roster::Term,
+			status: attestation::Status,
+		},
 	}
 
 	#[pallet::call]
@@ -272,6 +860,8 @@ pub mod pallet {
 			password: Vec<u8>,
 		) -> DispatchResult {
 			let sender = ensure_signed(origin)?;
+			// Keyed like rosters list it, so the student can claim their roster leaf.
+			let email = normalize_email(&email);
 
             let emailx : BoundedVec<_, T::MaxEmailsize> = email.clone().try_into().unwrap();
         
@@ -279,18 +869,8 @@ pub mod pallet {
 
 
 
-            let add: BoundedVec<_, T::MaxAdditionalFields> = vec![
-                    (
-                        Data::Raw(b"number".to_vec().try_into().unwrap()),
-                        Data::Raw(10u32.encode().try_into().unwrap())
-                    ),
-                    (
-                        Data::Raw(b"text".to_vec().try_into().unwrap()),
-                        Data::Raw(b"10".to_vec().try_into().unwrap())
-                    ),
-                ]
-                .try_into()
-                .unwrap();
+            // Profile fields are added by the student afterwards through `set_fields`.
+            let add: BoundedVec<_, T::MaxAdditionalFields> = Default::default();
 
 
         let info =  IdentityInfoSel {
@@ -316,6 +896,10 @@ pub mod pallet {
             
 			<StudentidOf<T>>::insert(emailx, reg);
 
+			if let Some(institution) = Self::institution_of_email(&email) {
+				Self::deposit_event(Event::StudentRouted { who: email, institution });
+			}
+
 			Ok(())
 		}
 
@@ -329,9 +913,10 @@ pub mod pallet {
 		) -> DispatchResult {
 			let _sender = ensure_signed(origin)?;
 
-            let emailx : BoundedVec<_, T::MaxEmailsize> = email.clone().try_into().unwrap();
+            let emailx : BoundedVec<_, T::MaxEmailsize> = normalize_email(&email).try_into().unwrap();
 
 			let id = <StudentidOf<T>>::get(&emailx).ok_or(Error::<T>::NoIdentity)?;
+			Self::ensure_may_log_in(&emailx)?;
 
             let info = id.info;
         
@@ -352,9 +937,10 @@ pub mod pallet {
 
 			let sender = ensure_signed(origin)?;
 
-            let emailx : BoundedVec<_, T::MaxEmailsize> = email.clone().try_into().unwrap();
+            let emailx : BoundedVec<_, T::MaxEmailsize> = normalize_email(&email).try_into().unwrap();
 
 			let id = <StudentidOf<T>>::get(&emailx).ok_or(Error::<T>::NoIdentity)?;
+			Self::ensure_relinkable(&id, &sender)?;
 
             let mut info = id.info;
 
@@ -366,7 +952,7 @@ pub mod pallet {
             let reg = RegistrationSel {
                     accountId: sender,
                     info: info,
-                    deposit: Zero::zero(),
+                    deposit: id.deposit,
 
             };
 
@@ -385,9 +971,10 @@ pub mod pallet {
             
 			let sender = ensure_signed(origin)?;
 
-            let emailx : BoundedVec<_, T::MaxEmailsize> = email.clone().try_into().unwrap();
+            let emailx : BoundedVec<_, T::MaxEmailsize> = normalize_email(&email).try_into().unwrap();
 
 			let id = <StudentidOf<T>>::get(&emailx).ok_or(Error::<T>::NoIdentity)?;
+			Self::ensure_relinkable(&id, &sender)?;
 
             let hashtoset = Data::Sha256(sha2_256(&referal.clone()));
 
@@ -399,7 +986,7 @@ pub mod pallet {
             let reg = RegistrationSel {
                     accountId: sender,
                     info: info,
-                    deposit: Zero::zero(),
+                    deposit: id.deposit,
             };
 
             
@@ -427,9 +1014,10 @@ pub mod pallet {
 
 //            ensure!(sender == idtolink , Error::<T>::SignerNotmatching);
 
-            let emailx : BoundedVec<_, T::MaxEmailsize> = email.clone().try_into().unwrap();
+            let emailx : BoundedVec<_, T::MaxEmailsize> = normalize_email(&email).try_into().unwrap();
 
 			let id = <StudentidOf<T>>::get(&emailx).ok_or(Error::<T>::NoIdentity)?;
+			Self::ensure_relinkable(&id, &idtolink)?;
 
             let hashtocheck = Data::Sha256(sha2_256(&referal.clone()));
 
@@ -448,7 +1036,7 @@ pub mod pallet {
             let reg = RegistrationSel {
                     accountId: idtolink.clone(),
                     info: info,
-                    deposit: Zero::zero(),
+                    deposit: id.deposit,
             };
 
             
@@ -478,9 +1066,10 @@ pub mod pallet {
 
 //            ensure!(sender == idtolink , Error::<T>::SignerNotmatching);
 
-            let emailx : BoundedVec<_, T::MaxEmailsize> = email.clone().try_into().unwrap();
+            let emailx : BoundedVec<_, T::MaxEmailsize> = normalize_email(&email).try_into().unwrap();
 
 			let id = <StudentidOf<T>>::get(&emailx).ok_or(Error::<T>::NoIdentity)?;
+			Self::ensure_relinkable(&id, &idtolink)?;
 
             let hashtocheck = Data::Sha256(sha2_256(&referal.clone()));
 
@@ -499,7 +1088,7 @@ pub mod pallet {
             let reg = RegistrationSel {
                     accountId: idtolink.clone(),
                     info: info,
-                    deposit: Zero::zero(),
+                    deposit: id.deposit,
             };
 
             
@@ -529,9 +1118,10 @@ pub mod pallet {
 
 //            ensure!(sender == idtolink , Error::<T>::SignerNotmatching);
 
-            let emailx : BoundedVec<_, T::MaxEmailsize> = email.clone().try_into().unwrap();
+            let emailx : BoundedVec<_, T::MaxEmailsize> = normalize_email(&email).try_into().unwrap();
 
 			let id = <StudentidOf<T>>::get(&emailx).ok_or(Error::<T>::NoIdentity)?;
+			Self::ensure_relinkable(&id, &idtolink)?;
 
             let hashtocheck = Data::Sha256(sha2_256(&referal.clone()));
 
@@ -550,7 +1140,7 @@ pub mod pallet {
             let reg = RegistrationSel {
                     accountId: idtolink.clone(),
                     info: info,
-                    deposit: Zero::zero(),
+                    deposit: id.deposit,
             };
 
             
@@ -576,6 +1166,7 @@ pub mod pallet {
 
 
 			let id = <StudentidOf<T>>::get(&emailx).ok_or(Error::<T>::NoIdentity)?;
+			Self::ensure_may_log_in(&emailx)?;
             // ensure!(sender == id.accountId , Error::<T>::LoginFailed);
 
 
@@ -589,7 +1180,7 @@ pub mod pallet {
             let reg = RegistrationSel {
                     accountId: id.accountId,
                     info: info,
-                    deposit: Zero::zero(),
+                    deposit: id.deposit,
 
             };
 
@@ -601,13 +1192,17 @@ pub mod pallet {
 	        let tokenmetadata: BoundedVec<u8, T::MaxAccessTokenMetadata> = BoundedVec::try_from(t1).unwrap() ;
 
 
+            let now = frame_system::Pallet::<T>::block_number();
             let tokeninfo = TokenInfo {
 	            metadata: tokenmetadata, 
 	            owner: sender,
-                data: Data::Raw(b"allowed".to_vec().try_into().unwrap())
+                data: Data::Raw(b"allowed".to_vec().try_into().unwrap()),
+                issued: now,
+                expires: now + T::TokenLifetime::get(),
             };
 
 	/// Token Properties
+            <TokensOf<T>>::insert(&tokeninfo.owner, &tokenid, ());
             <Tokens<T>>::insert(&tokenid, tokeninfo);
 
 
@@ -626,12 +1221,14 @@ pub mod pallet {
 
             let email = <EmailId<T>>::get(idtoaccess.clone()).ok_or(Error::<T>::NoIdentity)?;
 
-            let emailx : BoundedVec<_, T::MaxEmailsize> = email.clone().try_into().unwrap();
+            let emailx : BoundedVec<_, T::MaxEmailsize> = normalize_email(&email).try_into().unwrap();
 
             let id = <StudentidOf<T>>::get(&emailx).ok_or(Error::<T>::NoIdentity)?;
 
             let web = Data::Raw(service.try_into().unwrap());
 
+            <ServiceGrants<T>>::insert(&idtoaccess, &web, ());
+
             let mut info = id.info;
 
             info.web  =  web;
@@ -640,7 +1237,7 @@ pub mod pallet {
             let reg = RegistrationSel {
                     accountId: id.accountId,
                     info: info,
-                    deposit: Zero::zero(),
+                    deposit: id.deposit,
             };
 
 
@@ -660,21 +1257,25 @@ pub mod pallet {
 
             let email = <EmailId<T>>::get(sender.clone()).ok_or(Error::<T>::NoIdentity)?;
 
-            let emailx : BoundedVec<_, T::MaxEmailsize> = email.clone().try_into().unwrap();
+            let emailx : BoundedVec<_, T::MaxEmailsize> = normalize_email(&email).try_into().unwrap();
 
             let id = <StudentidOf<T>>::get(&emailx).ok_or(Error::<T>::NoIdentity)?;
+			Self::ensure_may_log_in(&emailx)?;
           
             let servicetocheck = Data::Raw(service.try_into().unwrap());
 
             let info = id.info;
             
-            ensure!(servicetocheck == info.web , Error::<T>::ServiceAccessFailed);
+            ensure!(
+                servicetocheck == info.web || <ServiceGrants<T>>::contains_key(&sender, &servicetocheck),
+                Error::<T>::ServiceAccessFailed
+            );
 
             Ok(())
         }
 
 
-        #[pallet::weight(5_000)]
+        #[pallet::weight(5_000 + T::DbWeight::get().reads_writes(3, 3))]
 		pub fn logout_web3_sel19(
 			origin: OriginFor<T>,
 		) -> DispatchResult{
@@ -690,41 +1291,1902 @@ pub mod pallet {
 
             let mut info = id.info;
 
-            let accesstoken  = info.accesstoken.clone();
+            // `login_web3_sel16` keeps the raw token ID as the access token.
+            let tokenid: Option<TokenId<T>> = match &info.accesstoken {
+                Data::Raw(x) => x.to_vec().try_into().ok(),
+                _ => None,
+            };
 
-            let challenge = accesstoken.encode();
+            if let Some(tokenid) = tokenid {
+                <TokensOf<T>>::remove(&sender, &tokenid);
+                // Token IDs are chosen by the client, so the ID may have been reused by another
+                // account since.
+                if <Tokens<T>>::get(&tokenid).map_or(false, |token| token.owner == sender) {
+                    <Tokens<T>>::remove(&tokenid);
+                }
+            }
 
+            let noneaccesstoken = Data::Raw(b"".to_vec().try_into().unwrap());
 
-            let tokenid : TokenId<T> =   challenge.clone().try_into().unwrap();
-            
+            info.accesstoken  = noneaccesstoken;
 
-//            let mut tokeninfo : TokenInfo = <Tokens<T>>::get(tokenid.clone()).try_into().unwrap();
+            let reg = RegistrationSel {
+                    accountId: sender.clone(),
+                    info: info,
+                    deposit: id.deposit,
 
-//            tokeninfo.data = Data::Raw(b"disabled".to_vec().try_into().unwrap());
+            };
 
-//            <Tokens<T>>::insert(tokenid, tokeninfo);
+            <StudentidOf<T>>::insert(emailx, reg);
 
 
+			Ok(())
+		}
 
-            <Tokens<T>>::remove(tokenid);
+		/// Clear the caller's identity.
+		///
+		/// Removes the caller's tokens, service grants and used Sign-In with Substrate nonces, at
+		/// most `MaxClearedPerCall` of them per call. Once none are left the `StudentidOf` record,
+		/// the `EmailId` link and the sub-accounts are removed and the deposits are returned.
+		///
+		/// `FeelessCallsOf` and `SponsoredFeesOf` are kept: they only count calls and fees within
+		/// the current period, and clearing them would let an account reset its limits by
+		/// registering again. A registration that was never linked to an account is removed with
+		/// `cancel_registration` instead.
+		///
+		/// Emits `IdentityClearPending` while entries remain and `IdentityCleared` when done.
+		#[pallet::weight(
+			5_000 + T::DbWeight::get().reads_writes(6, 6) +
+				T::DbWeight::get().reads_writes(2, 3).saturating_mul(T::MaxClearedPerCall::get().into()) +
+				T::DbWeight::get().reads_writes(1, 1).saturating_mul(T::MaxAdditionalFields::get().into()) +
+				T::DbWeight::get().writes(T::MaxSubAccounts::get().into()) +
+				T::DbWeight::get().writes(2 * T::MaxRegistrars::get() as Weight)
+		)]
+		pub fn clear_identity(origin: OriginFor<T>) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
 
-            let noneaccesstoken = Data::Raw(b"".to_vec().try_into().unwrap());
+			let emailx = <EmailId<T>>::get(&sender).ok_or(Error::<T>::NoIdentity)?;
+			let id = <StudentidOf<T>>::get(&emailx).ok_or(Error::<T>::NoIdentity)?;
 
-            info.accesstoken  = noneaccesstoken;
+			let mut budget = T::MaxClearedPerCall::get() as usize;
+
+			let tokens: Vec<TokenId<T>> = <TokensOf<T>>::iter_key_prefix(&sender).take(budget).collect();
+			budget = budget.saturating_sub(tokens.len());
+			for tokenid in tokens {
+				<TokensOf<T>>::remove(&sender, &tokenid);
+				// Token ids are chosen by the client, so the id may have been reused by another
+				// account since.
+				if <Tokens<T>>::get(&tokenid).map_or(false, |token| token.owner == sender) {
+					<Tokens<T>>::remove(&tokenid);
+				}
+			}
+
+			let grants: Vec<Data> = <ServiceGrants<T>>::iter_key_prefix(&sender).take(budget).collect();
+			budget = budget.saturating_sub(grants.len());
+			for service in grants {
+				<ServiceGrants<T>>::remove(&sender, &service);
+				<DisclosureOf<T>>::remove(&sender, &service);
+			}
+
+			let nonces: Vec<[u8; 32]> = <SiwsNonces<T>>::iter_key_prefix(&sender).take(budget).collect();
+			for nonce in nonces {
+				<SiwsNonces<T>>::remove(&sender, &nonce);
+			}
+
+			if <TokensOf<T>>::iter_key_prefix(&sender).next().is_some() ||
+				<ServiceGrants<T>>::iter_key_prefix(&sender).next().is_some() ||
+				<SiwsNonces<T>>::iter_key_prefix(&sender).next().is_some()
+			{
+				Self::deposit_event(Event::IdentityClearPending { who: sender });
+				return Ok(())
+			}
+
+			Self::update_blob_references(&id.accountId, &id.info.additional, &[]);
+			<StudentidOf<T>>::remove(&emailx);
+			<EmailId<T>>::remove(&sender);
+			<Passkeys<T>>::remove(&emailx);
+			<PasskeyNonce<T>>::remove(&emailx);
+			// There is at most one judgement and one enrollment per registrar.
+			let _ = <JudgementOf<T>>::remove_prefix(&sender, Some(T::MaxRegistrars::get()));
+			let _ = <EnrollmentOf<T>>::remove_prefix(&sender, Some(T::MaxRegistrars::get()));
+			if let Some(address) = <EthAccountOf<T>>::take(&sender) {
+				<StudentOfEth<T>>::remove(&address);
+			}
+
+			let (subs_deposit, sub_ids) = <SubsOf<T>>::take(&sender);
+			for sub in sub_ids.iter() {
+				<SuperOf<T>>::remove(sub);
+			}
+
+			// Field deposits are held by the account the record belongs to, which cannot change
+			// while it holds any; see `ensure_relinkable`.
+			T::Currency::unreserve(&id.accountId, id.total_deposit());
+			T::Currency::unreserve(&sender, subs_deposit);
+			let deposit = id.total_deposit().saturating_add(subs_deposit);
+
+			Self::deposit_event(Event::IdentityCleared { who: sender, deposit });
 
-            let reg = RegistrationSel {
-                    accountId: sender.clone(),
-                    info: info,
-                    deposit: Zero::zero(),
+			Ok(())
+		}
 
-            };
+		/// Withdraw the caller's registration of `email` before it was linked to an account.
+		///
+		/// The caller must be the account that registered it or last updated it, and know its
+		/// password. Unlinked registrations hold no deposit.
+		///
+		/// Emits `IdentityCleared`.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(1, 3))]
+		pub fn cancel_registration(
+			origin: OriginFor<T>,
+			email: Vec<u8>,
+			password: Vec<u8>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
 
-            <StudentidOf<T>>::insert(emailx, reg);
+			let emailx = Self::check_password(&email, &password)?;
+			let id = <StudentidOf<T>>::get(&emailx).ok_or(Error::<T>::NoIdentity)?;
+			ensure!(id.accountId == sender, Error::<T>::NotOwned);
+			ensure!(!Self::is_linked(&id), Error::<T>::IdentityLinked);
+
+			<StudentidOf<T>>::remove(&emailx);
+			<Passkeys<T>>::remove(&emailx);
+			<PasskeyNonce<T>>::remove(&emailx);
 
+			Self::deposit_event(Event::IdentityCleared { who: sender, deposit: Zero::zero() });
 
 			Ok(())
 		}
 
-    
-    }
+		/// Remove the registration of `email` before it was linked to an account, such as one
+		/// squatting the email of a student.
+		///
+		/// The dispatch origin for this call must be _Signed_ by the account of the registrar
+		/// `reg_index`, which must act for the institution owning the domain of `email`.
+		///
+		/// Emits `IdentityCleared` for the account that registered it.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(4, 3))]
+		pub fn discard_registration(
+			origin: OriginFor<T>,
+			#[pallet::compact] reg_index: RegistrarIndex,
+			email: Vec<u8>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_registrar(&sender, reg_index)?;
+			ensure!(Self::acts_for_email(reg_index, &email), Error::<T>::NotInstitutionStudent);
+
+			let emailx: Email<T> =
+				normalize_email(&email).try_into().map_err(|_| Error::<T>::NoIdentity)?;
+			let id = <StudentidOf<T>>::get(&emailx).ok_or(Error::<T>::NoIdentity)?;
+			ensure!(!Self::is_linked(&id), Error::<T>::IdentityLinked);
+
+			<StudentidOf<T>>::remove(&emailx);
+			<Passkeys<T>>::remove(&emailx);
+			<PasskeyNonce<T>>::remove(&emailx);
+
+			let who = id.accountId;
+			Self::deposit_event(Event::IdentityCleared { who, deposit: Zero::zero() });
+
+			Ok(())
+		}
+
+		/// Set additional profile fields on the caller's identity.
+		///
+		/// Each pair overwrites the field with the same key or is appended as a new field, up to
+		/// `MaxAdditionalFields`. `FieldDeposit` is reserved for every field held. The keys in
+		/// [`profile_keys`] should be used for the standard student fields. A value given as
+		/// `Data::BlakeTwo256` must refer to a noted blob, see `note_blob`.
+		///
+		/// Emits `IdentitySet`.
+		#[pallet::weight(
+			5_000 + 1_000 * T::MaxAdditionalFields::get() as Weight +
+				T::DbWeight::get().reads_writes(2, 2).saturating_mul(T::MaxAdditionalFields::get().into())
+		)]
+		pub fn set_fields(origin: OriginFor<T>, fields: Vec<(Data, Data)>) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			ensure!(
+				fields.len() <= T::MaxAdditionalFields::get() as usize,
+				Error::<T>::TooManyFields
+			);
+
+			let emailx = <EmailId<T>>::get(&sender).ok_or(Error::<T>::NoIdentity)?;
+			let mut id = <StudentidOf<T>>::get(&emailx).ok_or(Error::<T>::NoIdentity)?;
+			ensure!(id.accountId == sender, Error::<T>::NotOwned);
+			let old_fields = id.info.additional.clone();
+
+			for (key, value) in fields {
+				if let Data::BlakeTwo256(hash) = value {
+					ensure!(<Blobs<T>>::contains_key(&hash), Error::<T>::NoSuchBlob);
+				}
+				match id.info.additional.iter_mut().find(|(k, _)| *k == key) {
+					Some(field) => field.1 = value,
+					None => id
+						.info
+						.additional
+						.try_push((key, value))
+						.map_err(|_| Error::<T>::TooManyFields)?,
+				}
+			}
+
+			Self::update_field_deposit(&mut id)?;
+			Self::update_blob_references(&sender, &old_fields, &id.info.additional);
+			<StudentidOf<T>>::insert(&emailx, id);
+
+			Self::deposit_event(Event::IdentitySet { who: sender });
+
+			Ok(())
+		}
+
+		/// Remove the additional profile field stored under `key` from the caller's identity and
+		/// return its `FieldDeposit`.
+		///
+		/// Emits `IdentitySet`.
+		#[pallet::weight(
+			5_000 + 1_000 * T::MaxAdditionalFields::get() as Weight +
+				T::DbWeight::get().reads_writes(2, 2).saturating_mul(T::MaxAdditionalFields::get().into())
+		)]
+		pub fn remove_field(origin: OriginFor<T>, key: Data) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+
+			let emailx = <EmailId<T>>::get(&sender).ok_or(Error::<T>::NoIdentity)?;
+			let mut id = <StudentidOf<T>>::get(&emailx).ok_or(Error::<T>::NoIdentity)?;
+			ensure!(id.accountId == sender, Error::<T>::NotOwned);
+			let old_fields = id.info.additional.clone();
+
+			let index = id
+				.info
+				.additional
+				.iter()
+				.position(|(k, _)| *k == key)
+				.ok_or(Error::<T>::NoSuchField)?;
+			id.info.additional.remove(index);
+
+			Self::update_field_deposit(&mut id)?;
+			Self::update_blob_references(&sender, &old_fields, &id.info.additional);
+			<StudentidOf<T>>::insert(&emailx, id);
+
+			Self::deposit_event(Event::IdentitySet { who: sender });
+
+			Ok(())
+		}
+
+		/// Choose which identity fields `service` may see. The service must have been granted
+		/// access to the caller; fields outside `fields` are left out of what it sees.
+		///
+		/// Emits `DisclosureSet`.
+		#[pallet::weight(5_000)]
+		pub fn set_disclosure(
+			origin: OriginFor<T>,
+			service: Vec<u8>,
+			fields: IdentityFields,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+
+			let service = Data::Raw(service.try_into().map_err(|_| Error::<T>::InvalidTarget)?);
+			ensure!(
+				<ServiceGrants<T>>::contains_key(&sender, &service),
+				Error::<T>::ServiceAccessFailed
+			);
+
+			<DisclosureOf<T>>::insert(&sender, &service, fields);
+
+			Self::deposit_event(Event::DisclosureSet { who: sender, service });
+
+			Ok(())
+		}
+
+		/// Store a profile value too long for `Data::Raw`. It can then be referenced from identity
+		/// fields as `Data::BlakeTwo256` of its content.
+		///
+		/// A deposit of `BlobDepositBase` plus `BlobDepositPerByte` per byte is reserved until
+		/// the blob is removed with `unnote_blob`.
+		///
+		/// Emits `BlobNoted`.
+		#[pallet::weight(
+			5_000 + T::DbWeight::get().reads_writes(1, 1) + 10 * T::MaxBlobSize::get() as Weight
+		)]
+		pub fn note_blob(origin: OriginFor<T>, data: Vec<u8>) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+
+			let data: BoundedVec<u8, T::MaxBlobSize> =
+				data.try_into().map_err(|_| Error::<T>::BlobTooLarge)?;
+			let hash = blake2_256(&data);
+			ensure!(!<Blobs<T>>::contains_key(&hash), Error::<T>::BlobAlreadyNoted);
+
+			let deposit = T::BlobDepositBase::get() +
+				T::BlobDepositPerByte::get() * (data.len() as u32).into();
+			T::Currency::reserve(&sender, deposit)?;
+
+			<Blobs<T>>::insert(&hash, Blob { owner: sender.clone(), deposit, references: 0, data });
+
+			Self::deposit_event(Event::BlobNoted { who: sender, hash });
+
+			Ok(())
+		}
+
+		/// Remove a blob noted by the caller and return its deposit. The blob must not be
+		/// referenced by a field of the caller's own identity. Fields of other identities
+		/// referencing it no longer resolve once it is removed.
+		///
+		/// Emits `BlobUnnoted`.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(1, 1))]
+		pub fn unnote_blob(origin: OriginFor<T>, hash: [u8; 32]) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+
+			let blob = <Blobs<T>>::get(&hash).ok_or(Error::<T>::NoSuchBlob)?;
+			ensure!(blob.owner == sender, Error::<T>::NotBlobOwner);
+			ensure!(blob.references == 0, Error::<T>::BlobInUse);
+
+			<Blobs<T>>::remove(&hash);
+			T::Currency::unreserve(&sender, blob.deposit);
+
+			Self::deposit_event(Event::BlobUnnoted { who: sender, hash });
+
+			Ok(())
+		}
+
+		/// Link the Ethereum address that produced `signature` to the caller's identity.
+		///
+		/// `signature` is an EIP-191 `personal_sign` signature over
+		/// [`Pallet::eth_link_message`] for the caller, as produced by MetaMask and other
+		/// Ethereum wallets. A previously linked address is replaced.
+		///
+		/// Emits `EthAccountLinked`.
+		#[pallet::weight(ECRECOVER_WEIGHT + T::DbWeight::get().reads_writes(4, 3))]
+		pub fn link_eth_account(origin: OriginFor<T>, signature: [u8; 65]) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			ensure!(<EmailId<T>>::contains_key(&sender), Error::<T>::NoIdentity);
+
+			let address = Self::eth_recover(&signature, &Self::eth_link_message(&sender))
+				.ok_or(Error::<T>::InvalidEthSignature)?;
+
+			if let Some(owner) = <StudentOfEth<T>>::get(&address) {
+				ensure!(owner == sender, Error::<T>::EthAccountAlreadyLinked);
+			}
+
+			if let Some(old) = <EthAccountOf<T>>::get(&sender) {
+				<StudentOfEth<T>>::remove(&old);
+			}
+			<EthAccountOf<T>>::insert(&sender, &address);
+			<StudentOfEth<T>>::insert(&address, &sender);
+
+			Self::deposit_event(Event::EthAccountLinked { who: sender, address });
+
+			Ok(())
+		}
+
+		/// Remove the Ethereum address linked to the caller.
+		///
+		/// Emits `EthAccountUnlinked`.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(1, 2))]
+		pub fn unlink_eth_account(origin: OriginFor<T>) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+
+			let address = <EthAccountOf<T>>::take(&sender).ok_or(Error::<T>::NoEthAccount)?;
+			<StudentOfEth<T>>::remove(&address);
+
+			Self::deposit_event(Event::EthAccountUnlinked { who: sender, address });
+
+			Ok(())
+		}
+
+		/// Log in with a signed Sign-In with Substrate message.
+		///
+		/// `message` must be for the `RelyingPartyId` domain and this chain, and be signed by the
+		/// account it names, which must have an identity. Signing in gives every requested
+		/// service access to the account and issues an access token for it. Each nonce can only
+		/// be used once; nonces of expired messages are forgotten.
+		///
+		/// Emits `UserLoginsuccess` with the token ID for every service.
+		// The text of the message is checked against the signature as is and wrapped in
+		// `<Bytes>` tags. Decoding caps the message at a few kilobytes, see `siws`.
+		#[pallet::weight(
+			SR25519_VERIFY_WEIGHT.saturating_mul(2) +
+				BYTE_WEIGHT.saturating_mul(2 * message.encoded_size() as Weight) +
+				T::DbWeight::get().reads_writes(3, 1) +
+				T::DbWeight::get().reads_writes(1, 1).saturating_mul(MAX_SIWS_NONCES_PRUNED) +
+				T::DbWeight::get().writes(3).saturating_mul(siws::MAX_SERVICES as Weight)
+		)]
+		pub fn login_siws(
+			origin: OriginFor<T>,
+			message: siws::Message,
+			signature: [u8; 64],
+		) -> DispatchResult {
+			let _sender = ensure_signed(origin)?;
+
+			let genesis = Self::genesis_hash();
+			let domain = T::RelyingPartyId::get();
+			let expected = siws::Expected {
+				domain: &domain,
+				genesis_hash: &genesis,
+				ss58_prefix: T::SS58Prefix::get().into(),
+				now: T::UnixTime::now().as_secs(),
+			};
+			message
+				.verify(&sp_core::sr25519::Signature::from_raw(signature), &expected)
+				.map_err(|e| match e {
+					siws::Error::NotYetValid | siws::Error::Expired => Error::<T>::SiwsMessageExpired,
+					siws::Error::BadSignature => Error::<T>::InvalidSiwsSignature,
+					_ => Error::<T>::InvalidSiwsMessage,
+				})?;
+
+			// The account is the sr25519 public key, as with `AccountId32`.
+			let who = T::AccountId::decode(&mut &message.account[..])
+				.map_err(|_| Error::<T>::InvalidSiwsMessage)?;
+			let emailx = <EmailId<T>>::get(&who).ok_or(Error::<T>::NoIdentity)?;
+			Self::ensure_may_log_in(&emailx)?;
+
+			// Nonces only need to be remembered until their message expires. Messages are valid
+			// for at most `siws::MAX_VALIDITY`, so few are left at any time.
+			let expired: Vec<[u8; 32]> = <SiwsNonces<T>>::iter_prefix(&who)
+				.take(MAX_SIWS_NONCES_PRUNED as usize)
+				.filter(|(_, expiration_time)| *expiration_time <= expected.now)
+				.map(|(nonce, _)| nonce)
+				.collect();
+			for nonce in expired {
+				<SiwsNonces<T>>::remove(&who, &nonce);
+			}
+
+			let nonce = blake2_256(&message.nonce);
+			ensure!(!<SiwsNonces<T>>::contains_key(&who, &nonce), Error::<T>::SiwsNonceUsed);
+			<SiwsNonces<T>>::insert(&who, &nonce, message.expiration_time);
+
+			let message_hash = blake2_256(&message.encode());
+			let now = frame_system::Pallet::<T>::block_number();
+			for service in &message.services {
+				let web = Data::Raw(service.clone().try_into().map_err(|_| Error::<T>::InvalidSiwsMessage)?);
+				let tokenid: TokenId<T> = blake2_128(&(message_hash, service).encode())
+					.to_vec()
+					.try_into()
+					.map_err(|_| Error::<T>::MaxMetadataExceeded)?;
+				let tokenmetadata: TokenMetadataOf<T> =
+					service.clone().try_into().map_err(|_| Error::<T>::MaxMetadataExceeded)?;
+				let tokeninfo = TokenInfo {
+					metadata: tokenmetadata,
+					owner: who.clone(),
+					data: Data::Raw(b"allowed".to_vec().try_into().unwrap()),
+					issued: now,
+					expires: now + T::TokenLifetime::get(),
+				};
+
+				<ServiceGrants<T>>::insert(&who, &web, ());
+				<TokensOf<T>>::insert(&who, &tokenid, ());
+				<Tokens<T>>::insert(&tokenid, tokeninfo);
+
+				Self::deposit_event(Event::UserLoginsuccess {
+					who: emailx.to_vec(),
+					blocksession: tokenid.into_inner(),
+				});
+			}
+
+			Ok(())
+		}
+
+		/// Register a passkey for the student with `email`, authenticated with their password.
+		///
+		/// `cose_key` is the COSE encoded credential public key from the attestation returned by
+		/// `navigator.credentials.create()`. Only P-256 keys for ES256 are accepted. The student
+		/// must be active.
+		///
+		/// Emits `PasskeyRegistered`.
+		#[pallet::weight(
+			P256_KEY_CHECK_WEIGHT +
+				BYTE_WEIGHT.saturating_mul((cose_key.len() + credential_id.len()) as Weight) +
+				T::DbWeight::get().reads_writes(3, 1)
+		)]
+		pub fn register_passkey(
+			origin: OriginFor<T>,
+			email: Vec<u8>,
+			password: Vec<u8>,
+			credential_id: Vec<u8>,
+			cose_key: Vec<u8>,
+		) -> DispatchResult {
+			let _sender = ensure_signed(origin)?;
+			let emailx = Self::check_password(&email, &password)?;
+			Self::ensure_may_log_in(&emailx)?;
+
+			let public_key = webauthn::parse_cose_p256(&cose_key).ok_or(Error::<T>::InvalidPasskey)?;
+			let credential_id: CredentialId<T> =
+				credential_id.try_into().map_err(|_| Error::<T>::CredentialIdTooLong)?;
+
+			<Passkeys<T>>::try_mutate(&emailx, |passkeys| -> DispatchResult {
+				ensure!(
+					!passkeys.iter().any(|p| p.credential_id == credential_id),
+					Error::<T>::PasskeyAlreadyRegistered
+				);
+				passkeys
+					.try_push(Passkey { credential_id: credential_id.clone(), public_key, sign_count: 0 })
+					.map_err(|_| Error::<T>::TooManyPasskeys)?;
+				Ok(())
+			})?;
+
+			Self::deposit_event(Event::PasskeyRegistered {
+				who: email,
+				credential_id: credential_id.into_inner(),
+			});
+
+			Ok(())
+		}
+
+		/// Remove a passkey from the student with `email`, authenticated with their password.
+		///
+		/// Emits `PasskeyRemoved`.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(2, 1))]
+		pub fn remove_passkey(
+			origin: OriginFor<T>,
+			email: Vec<u8>,
+			password: Vec<u8>,
+			credential_id: Vec<u8>,
+		) -> DispatchResult {
+			let _sender = ensure_signed(origin)?;
+			let emailx = Self::check_password(&email, &password)?;
+
+			<Passkeys<T>>::try_mutate(&emailx, |passkeys| -> DispatchResult {
+				let index = passkeys
+					.iter()
+					.position(|p| p.credential_id[..] == credential_id[..])
+					.ok_or(Error::<T>::NoSuchPasskey)?;
+				passkeys.remove(index);
+				Ok(())
+			})?;
+
+			Self::deposit_event(Event::PasskeyRemoved { who: email, credential_id });
+
+			Ok(())
+		}
+
+		/// Log in the student with `email` with one of their passkeys.
+		///
+		/// The arguments are the credential ID and the `authenticatorData`, `clientDataJSON` and
+		/// DER signature of the assertion returned by `navigator.credentials.get()` for the
+		/// challenge [`Pallet::passkey_challenge`]. On success the challenge is consumed and an
+		/// access token is issued to the student's account.
+		///
+		/// Emits `UserLoginsuccess` with the access token ID.
+		#[pallet::weight(
+			P256_VERIFY_WEIGHT +
+				BYTE_WEIGHT.saturating_mul(
+					(authenticator_data.len() + client_data_json.len() + signature.len()) as Weight
+				) +
+				T::DbWeight::get().reads_writes(5, 4)
+		)]
+		pub fn login_passkey(
+			origin: OriginFor<T>,
+			email: Vec<u8>,
+			credential_id: Vec<u8>,
+			authenticator_data: Vec<u8>,
+			client_data_json: Vec<u8>,
+			signature: Vec<u8>,
+		) -> DispatchResult {
+			let _sender = ensure_signed(origin)?;
+
+			let emailx: Email<T> =
+				normalize_email(&email).try_into().map_err(|_| Error::<T>::NoIdentity)?;
+			let id = <StudentidOf<T>>::get(&emailx).ok_or(Error::<T>::NoIdentity)?;
+			Self::ensure_may_log_in(&emailx)?;
+
+			let challenge = Self::passkey_challenge(&emailx);
+			let mut passkeys = <Passkeys<T>>::get(&emailx);
+			let passkey = passkeys
+				.iter_mut()
+				.find(|p| p.credential_id[..] == credential_id[..])
+				.ok_or(Error::<T>::NoSuchPasskey)?;
+
+			passkey.sign_count = webauthn::verify_assertion(
+				&passkey.public_key,
+				&T::RelyingPartyId::get(),
+				&T::RelyingPartyOrigin::get(),
+				&challenge,
+				passkey.sign_count,
+				&authenticator_data,
+				&client_data_json,
+				&signature,
+			)
+			.map_err(|_| Error::<T>::PasskeyVerificationFailed)?;
+
+			<Passkeys<T>>::insert(&emailx, passkeys);
+			<PasskeyNonce<T>>::mutate(&emailx, |nonce| *nonce = nonce.wrapping_add(1));
+
+			let tokenid: TokenId<T> = blake2_128(&challenge)
+				.to_vec()
+				.try_into()
+				.map_err(|_| Error::<T>::MaxMetadataExceeded)?;
+			let tokenmetadata: TokenMetadataOf<T> = b"passkey"
+				.to_vec()
+				.try_into()
+				.map_err(|_| Error::<T>::MaxMetadataExceeded)?;
+			let now = frame_system::Pallet::<T>::block_number();
+			let tokeninfo = TokenInfo {
+				metadata: tokenmetadata,
+				owner: id.accountId,
+				data: Data::Raw(b"allowed".to_vec().try_into().unwrap()),
+				issued: now,
+				expires: now + T::TokenLifetime::get(),
+			};
+
+			<TokensOf<T>>::insert(&tokeninfo.owner, &tokenid, ());
+			<Tokens<T>>::insert(&tokenid, tokeninfo);
+
+			Self::deposit_event(Event::UserLoginsuccess { who: email, blocksession: tokenid.into_inner() });
+
+			Ok(())
+		}
+
+		/// Add a registrar to the system.
+		///
+		/// The dispatch origin for this call must be `T::RegistrarOrigin`.
+		///
+		/// - `account`: the account of the registrar.
+		///
+		/// Emits `RegistrarAdded` if successful.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(1, 1))]
+		pub fn add_registrar(origin: OriginFor<T>, account: T::AccountId) -> DispatchResult {
+			T::RegistrarOrigin::ensure_origin(origin)?;
+
+			let registrar_index = <Registrars<T>>::try_mutate(
+				|registrars| -> Result<RegistrarIndex, DispatchError> {
+					registrars
+						.try_push(Some(RegistrarInfo { account, attestation_key: None }))
+						.map_err(|_| Error::<T>::TooManyRegistrars)?;
+					Ok((registrars.len() - 1) as RegistrarIndex)
+				},
+			)?;
+
+			Self::deposit_event(Event::RegistrarAdded { registrar_index });
+			Ok(())
+		}
+
+		/// Set the sr25519 key the registrar `index` signs ID card attestations with.
+		///
+		/// The dispatch origin for this call must be _Signed_ by the account of the registrar.
+		/// Attestations signed with the previous key stop verifying.
+		///
+		/// Emits `AttestationKeySet` if successful.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(1, 1))]
+		pub fn set_attestation_key(
+			origin: OriginFor<T>,
+			#[pallet::compact] index: RegistrarIndex,
+			key: [u8; 32],
+		) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+
+			<Registrars<T>>::try_mutate(|registrars| -> DispatchResult {
+				let registrar = registrars
+					.get_mut(index as usize)
+					.and_then(|r| r.as_mut())
+					.filter(|r| r.account == who)
+					.ok_or(Error::<T>::InvalidIndex)?;
+				registrar.attestation_key = Some(key);
+				Ok(())
+			})?;
+
+			Self::deposit_event(Event::AttestationKeySet { registrar_index: index, key });
+			Ok(())
+		}
+
+		/// Revoke an ID card attestation signed by the registrar `index`, so verifiers reject it
+		/// once they fetched a newer snapshot.
+		///
+		/// The dispatch origin for this call must be _Signed_ by the account of the registrar.
+		///
+		/// Emits `AttestationRevoked` if successful.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(2, 1))]
+		pub fn revoke_attestation(
+			origin: OriginFor<T>,
+			#[pallet::compact] index: RegistrarIndex,
+			attestation: attestation::Attestation,
+		) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+
+			let registrar = Self::ensure_registrar(&who, index)?;
+			ensure!(
+				registrar.attestation_key == Some(attestation.registrar),
+				Error::<T>::NotAttestationKey
+			);
+
+			let id = attestation.id();
+			ensure!(!<RevokedAttestations<T>>::contains_key(&id), Error::<T>::AttestationAlreadyRevoked);
+			<RevokedAttestations<T>>::insert(&id, (index, attestation.valid_until));
+
+			Self::deposit_event(Event::AttestationRevoked { registrar_index: index, id });
+			Ok(())
+		}
+
+		/// Add the given account to the sender's subs.
+		///
+		/// Payment: `SubAccountDeposit` is reserved from the sender.
+		///
+		/// The dispatch origin for this call must be _Signed_ and the sender must have a registered
+		/// identity.
+		///
+		/// Emits `SubIdentityAdded` if successful.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(3, 2))]
+		pub fn add_sub(origin: OriginFor<T>, sub: T::AccountId, data: Data) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			ensure!(<EmailId<T>>::contains_key(&sender), Error::<T>::NoIdentity);
+
+			// Check if it's already claimed as sub-identity.
+			ensure!(!<SuperOf<T>>::contains_key(&sub), Error::<T>::AlreadyClaimed);
+
+			<SubsOf<T>>::try_mutate(&sender, |(ref mut subs_deposit, ref mut sub_ids)| {
+				// Ensure there is space and that the deposit is paid.
+				ensure!(
+					sub_ids.len() < T::MaxSubAccounts::get() as usize,
+					Error::<T>::TooManySubAccounts
+				);
+				let deposit = T::SubAccountDeposit::get();
+				T::Currency::reserve(&sender, deposit)?;
+
+				<SuperOf<T>>::insert(&sub, (sender.clone(), data));
+				sub_ids.try_push(sub.clone()).expect("sub ids length checked above; qed");
+				*subs_deposit = subs_deposit.saturating_add(deposit);
+
+				Self::deposit_event(Event::SubIdentityAdded { sub, main: sender.clone(), deposit });
+				Ok(())
+			})
+		}
+
+		/// Remove the given account from the sender's subs.
+		///
+		/// Payment: Balance reserved for the sub is returned to the sender.
+		///
+		/// The dispatch origin for this call must be _Signed_ and the sender must have a registered
+		/// sub identity of `sub`.
+		///
+		/// Emits `SubIdentityRemoved` if successful.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(3, 2))]
+		pub fn remove_sub(origin: OriginFor<T>, sub: T::AccountId) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			ensure!(<EmailId<T>>::contains_key(&sender), Error::<T>::NoIdentity);
+			let (sup, _) = <SuperOf<T>>::get(&sub).ok_or(Error::<T>::NotSub)?;
+			ensure!(sup == sender, Error::<T>::NotOwned);
+
+			<SuperOf<T>>::remove(&sub);
+			<SubsOf<T>>::mutate(&sup, |(ref mut subs_deposit, ref mut sub_ids)| {
+				sub_ids.retain(|x| x != &sub);
+				let deposit = T::SubAccountDeposit::get().min(*subs_deposit);
+				*subs_deposit -= deposit;
+				let err_amount = T::Currency::unreserve(&sender, deposit);
+				debug_assert!(err_amount.is_zero());
+				Self::deposit_event(Event::SubIdentityRemoved { sub, main: sender, deposit });
+			});
+			Ok(())
+		}
+
+		/// Remove the sender as a sub-account.
+		///
+		/// Payment: Balance reserved by a previous `add_sub` call for one sub will be repatriated
+		/// to the sender (*not* the original depositor).
+		///
+		/// The dispatch origin for this call must be _Signed_ and the sender must have a registered
+		/// super-identity.
+		///
+		/// Emits `SubIdentityRevoked` if successful.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(2, 2))]
+		pub fn quit_sub(origin: OriginFor<T>) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			let (sup, _) = <SuperOf<T>>::take(&sender).ok_or(Error::<T>::NotSub)?;
+
+			<SubsOf<T>>::mutate(&sup, |(ref mut subs_deposit, ref mut sub_ids)| {
+				sub_ids.retain(|x| x != &sender);
+				let deposit = T::SubAccountDeposit::get().min(*subs_deposit);
+				*subs_deposit -= deposit;
+				let _ = T::Currency::repatriate_reserved(&sup, &sender, deposit, BalanceStatus::Free);
+				Self::deposit_event(Event::SubIdentityRevoked { sub: sender, main: sup.clone(), deposit });
+			});
+			Ok(())
+		}
+
+		/// Judge the identity of `target`.
+		///
+		/// The dispatch origin for this call must be _Signed_ by the account of the registrar
+		/// `reg_index`.
+		///
+		/// - `judgement`: the judgement of the registrar.
+		/// - `identity`: the [`Self::identity_hash`] of the identity the registrar judged. The
+		///   judgement only applies while the identity is unchanged.
+		///
+		/// Emits `JudgementGiven` if successful.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(3, 1))]
+		pub fn provide_judgement(
+			origin: OriginFor<T>,
+			#[pallet::compact] reg_index: RegistrarIndex,
+			target: T::AccountId,
+			judgement: Judgement,
+			identity: [u8; 32],
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_registrar(&sender, reg_index)?;
+			ensure!(Self::acts_for(reg_index, &target), Error::<T>::NotInstitutionStudent);
+
+			let hash = Self::identity_hash(&target).ok_or(Error::<T>::InvalidTarget)?;
+			ensure!(hash == identity, Error::<T>::JudgementForDifferentIdentity);
+			<JudgementOf<T>>::insert(&target, reg_index, (judgement, hash));
+
+			Self::deposit_event(Event::JudgementGiven { target, registrar_index: reg_index });
+			Ok(())
+		}
+
+		/// Revoke the judgement the registrar `reg_index` gave on the identity of `target`.
+		///
+		/// The dispatch origin for this call must be _Signed_ by the account of the registrar.
+		///
+		/// Emits `JudgementRevoked` if successful.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(2, 1))]
+		pub fn revoke_judgement(
+			origin: OriginFor<T>,
+			#[pallet::compact] reg_index: RegistrarIndex,
+			target: T::AccountId,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_registrar(&sender, reg_index)?;
+
+			ensure!(
+				<JudgementOf<T>>::contains_key(&target, reg_index),
+				Error::<T>::JudgementNotFound
+			);
+			<JudgementOf<T>>::remove(&target, reg_index);
+
+			Self::deposit_event(Event::JudgementRevoked { target, registrar_index: reg_index });
+			Ok(())
+		}
+
+		/// Add an institution.
+		///
+		/// The dispatch origin for this call must be `T::RegistrarOrigin`.
+		///
+		/// Emits `InstitutionAdded` if successful.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(1, 2))]
+		pub fn add_institution(
+			origin: OriginFor<T>,
+			name: Data,
+			admin: T::AccountId,
+			metadata: Data,
+		) -> DispatchResult {
+			T::RegistrarOrigin::ensure_origin(origin)?;
+
+			let institution = <InstitutionCount<T>>::get();
+			<Institutions<T>>::insert(
+				institution,
+				Institution {
+					name,
+					admin,
+					domains: Default::default(),
+					registrars: Default::default(),
+					metadata,
+				},
+			);
+			<InstitutionCount<T>>::put(institution.saturating_add(1));
+
+			Self::deposit_event(Event::InstitutionAdded { institution });
+			Ok(())
+		}
+
+		/// Change the name, admin and metadata of an institution.
+		///
+		/// The dispatch origin for this call must be `T::RegistrarOrigin`.
+		///
+		/// Emits `InstitutionUpdated` if successful.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(1, 1))]
+		pub fn set_institution(
+			origin: OriginFor<T>,
+			#[pallet::compact] institution: InstitutionIndex,
+			name: Data,
+			admin: T::AccountId,
+			metadata: Data,
+		) -> DispatchResult {
+			T::RegistrarOrigin::ensure_origin(origin)?;
+
+			<Institutions<T>>::try_mutate(institution, |info| -> DispatchResult {
+				let info = info.as_mut().ok_or(Error::<T>::InvalidInstitution)?;
+				info.name = name;
+				info.admin = admin;
+				info.metadata = metadata;
+				Ok(())
+			})?;
+
+			Self::deposit_event(Event::InstitutionUpdated { institution });
+			Ok(())
+		}
+
+		/// Give an institution ownership of an email domain. Students registering with an email
+		/// of the domain belong to the institution.
+		///
+		/// The dispatch origin for this call must be `T::RegistrarOrigin`. The domain is
+		/// normalized to ASCII lowercase without a trailing dot.
+		///
+		/// Emits `DomainAdded` if successful.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(2, 2))]
+		pub fn add_domain(
+			origin: OriginFor<T>,
+			#[pallet::compact] institution: InstitutionIndex,
+			domain: Vec<u8>,
+		) -> DispatchResult {
+			T::RegistrarOrigin::ensure_origin(origin)?;
+
+			let domain = normalize_domain(&domain);
+			ensure!(!domain.is_empty(), Error::<T>::InvalidDomain);
+			let key: Email<T> = domain.clone().try_into().map_err(|_| Error::<T>::InvalidDomain)?;
+			ensure!(!<DomainOwner<T>>::contains_key(&key), Error::<T>::DomainAlreadyOwned);
+
+			<Institutions<T>>::try_mutate(institution, |info| -> DispatchResult {
+				let info = info.as_mut().ok_or(Error::<T>::InvalidInstitution)?;
+				info.domains.try_push(key.clone()).map_err(|_| Error::<T>::TooManyDomains)?;
+				Ok(())
+			})?;
+			<DomainOwner<T>>::insert(&key, institution);
+
+			Self::deposit_event(Event::DomainAdded { institution, domain });
+			Ok(())
+		}
+
+		/// Take an email domain away from an institution. Students with emails of the domain
+		/// become unverified.
+		///
+		/// The dispatch origin for this call must be `T::RegistrarOrigin`.
+		///
+		/// Emits `DomainRemoved` if successful.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(2, 2))]
+		pub fn remove_domain(
+			origin: OriginFor<T>,
+			#[pallet::compact] institution: InstitutionIndex,
+			domain: Vec<u8>,
+		) -> DispatchResult {
+			T::RegistrarOrigin::ensure_origin(origin)?;
+
+			let domain = normalize_domain(&domain);
+			let key: Email<T> = domain.clone().try_into().map_err(|_| Error::<T>::DomainNotOwned)?;
+			ensure!(<DomainOwner<T>>::get(&key) == Some(institution), Error::<T>::DomainNotOwned);
+
+			<Institutions<T>>::mutate(institution, |info| {
+				if let Some(info) = info {
+					info.domains.retain(|d| d != &key);
+				}
+			});
+			<DomainOwner<T>>::remove(&key);
+
+			Self::deposit_event(Event::DomainRemoved { institution, domain });
+			Ok(())
+		}
+
+		/// Make the registrar `reg_index` act for an institution. It can then only judge and
+		/// enroll the institution's students.
+		///
+		/// The dispatch origin for this call must be `T::RegistrarOrigin`.
+		///
+		/// Emits `InstitutionRegistrarAdded` if successful.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(3, 2))]
+		pub fn add_institution_registrar(
+			origin: OriginFor<T>,
+			#[pallet::compact] institution: InstitutionIndex,
+			#[pallet::compact] reg_index: RegistrarIndex,
+		) -> DispatchResult {
+			T::RegistrarOrigin::ensure_origin(origin)?;
+
+			ensure!(Self::registrar(reg_index).is_some(), Error::<T>::InvalidIndex);
+			ensure!(
+				!<InstitutionOfRegistrar<T>>::contains_key(reg_index),
+				Error::<T>::RegistrarOfOtherInstitution
+			);
+			<Institutions<T>>::try_mutate(institution, |info| -> DispatchResult {
+				let info = info.as_mut().ok_or(Error::<T>::InvalidInstitution)?;
+				info.registrars.try_push(reg_index).map_err(|_| Error::<T>::TooManyRegistrars)?;
+				Ok(())
+			})?;
+			<InstitutionOfRegistrar<T>>::insert(reg_index, institution);
+
+			Self::deposit_event(Event::InstitutionRegistrarAdded { institution, registrar_index: reg_index });
+			Ok(())
+		}
+
+		/// Stop the registrar `reg_index` acting for an institution.
+		///
+		/// The dispatch origin for this call must be `T::RegistrarOrigin`.
+		///
+		/// Emits `InstitutionRegistrarRemoved` if successful.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(2, 2))]
+		pub fn remove_institution_registrar(
+			origin: OriginFor<T>,
+			#[pallet::compact] institution: InstitutionIndex,
+			#[pallet::compact] reg_index: RegistrarIndex,
+		) -> DispatchResult {
+			T::RegistrarOrigin::ensure_origin(origin)?;
+
+			ensure!(
+				<InstitutionOfRegistrar<T>>::get(reg_index) == Some(institution),
+				Error::<T>::RegistrarOfOtherInstitution
+			);
+			<Institutions<T>>::mutate(institution, |info| {
+				if let Some(info) = info {
+					info.registrars.retain(|r| *r != reg_index);
+				}
+			});
+			<InstitutionOfRegistrar<T>>::remove(reg_index);
+
+			Self::deposit_event(Event::InstitutionRegistrarRemoved {
+				institution,
+				registrar_index: reg_index,
+			});
+			Ok(())
+		}
+
+		/// Set the status of the student `target`.
+		///
+		/// The dispatch origin for this call must be _Signed_ by the account of the registrar
+		/// `reg_index`, which must act for the student. The move from the student's current
+		/// status must be allowed, see [`StudentStatus::can_become`].
+		///
+		/// Emits `StudentStatusChanged` if successful.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(5, 1))]
+		pub fn set_student_status(
+			origin: OriginFor<T>,
+			#[pallet::compact] reg_index: RegistrarIndex,
+			target: T::AccountId,
+			status: StudentStatus,
+			term: roster::Term,
+			valid_until: T::BlockNumber,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_registrar(&sender, reg_index)?;
+			ensure!(Self::acts_for(reg_index, &target), Error::<T>::NotInstitutionStudent);
+			Self::ensure_future(valid_until)?;
+
+			let emailx = <EmailId<T>>::get(&target).ok_or(Error::<T>::InvalidTarget)?;
+			ensure!(
+				Self::student_status_of(&emailx).can_become(status),
+				Error::<T>::InvalidStatusTransition
+			);
+			let registrar = reg_index;
+			<StatusOf<T>>::insert(&emailx, StudentState { status, term, valid_until, registrar });
+
+			Self::deposit_event(Event::StudentStatusChanged { who: target, status, term, valid_until });
+			Ok(())
+		}
+
+		/// Enroll the students `targets` for `term` until `valid_until`, at the start of a term.
+		///
+		/// The dispatch origin for this call must be _Signed_ by the account of the registrar
+		/// `reg_index`. Students who are enrolled, expired or have no status yet are renewed;
+		/// students on leave, suspended, graduated, without an identity or of another institution
+		/// are skipped.
+		///
+		/// Emits `StudentStatusChanged` for every renewed student and `RenewalSkipped` for every
+		/// skipped one.
+		#[pallet::weight(
+			5_000 + T::DbWeight::get().reads_writes(4, 1).saturating_mul(targets.len() as Weight)
+		)]
+		pub fn renew_students(
+			origin: OriginFor<T>,
+			#[pallet::compact] reg_index: RegistrarIndex,
+			targets: Vec<T::AccountId>,
+			term: roster::Term,
+			valid_until: T::BlockNumber,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_registrar(&sender, reg_index)?;
+			ensure!(
+				targets.len() <= T::MaxRenewalsPerCall::get() as usize,
+				Error::<T>::TooManyRenewals
+			);
+			Self::ensure_future(valid_until)?;
+
+			let status = StudentStatus::Enrolled;
+			let registrar = reg_index;
+			for who in targets {
+				let renewable = |emailx: &Email<T>| {
+					matches!(
+						Self::student_status_of(emailx),
+						StudentStatus::Unverified | StudentStatus::Enrolled | StudentStatus::Expired
+					)
+				};
+				match <EmailId<T>>::get(&who) {
+					Some(emailx) if Self::acts_for(reg_index, &who) && renewable(&emailx) => {
+						let state = StudentState { status, term, valid_until, registrar };
+						<StatusOf<T>>::insert(&emailx, state);
+						Self::deposit_event(Event::StudentStatusChanged { who, status, term, valid_until });
+					},
+					_ => Self::deposit_event(Event::RenewalSkipped { who }),
+				}
+			}
+			Ok(())
+		}
+
+		/// Move `amount` from the sender into the sponsor pot of an institution, which pays the
+		/// transaction fees of its students.
+		///
+		/// The dispatch origin for this call must be _Signed_.
+		///
+		/// Emits `SponsorPotFunded` if successful.
+		#[pallet::weight(50_000 + T::DbWeight::get().reads_writes(3, 2))]
+		pub fn fund_sponsor_pot(
+			origin: OriginFor<T>,
+			#[pallet::compact] institution: InstitutionIndex,
+			#[pallet::compact] amount: BalanceOf<T>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			ensure!(<Institutions<T>>::contains_key(institution), Error::<T>::InvalidInstitution);
+
+			T::Currency::transfer(
+				&sender,
+				&Self::sponsor_pot(institution),
+				amount,
+				ExistenceRequirement::KeepAlive,
+			)?;
+
+			Self::deposit_event(Event::SponsorPotFunded { institution, who: sender, amount });
+			Ok(())
+		}
+
+		/// Move `amount` out of the sponsor pot of an institution to its admin.
+		///
+		/// The dispatch origin for this call must be _Signed_ by the admin of the institution.
+		///
+		/// Emits `SponsorPotWithdrawn` if successful.
+		#[pallet::weight(50_000 + T::DbWeight::get().reads_writes(3, 2))]
+		pub fn withdraw_sponsor_pot(
+			origin: OriginFor<T>,
+			#[pallet::compact] institution: InstitutionIndex,
+			#[pallet::compact] amount: BalanceOf<T>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_institution_admin(&sender, institution)?;
+
+			T::Currency::transfer(
+				&Self::sponsor_pot(institution),
+				&sender,
+				amount,
+				ExistenceRequirement::AllowDeath,
+			)?;
+
+			Self::deposit_event(Event::SponsorPotWithdrawn { institution, amount });
+			Ok(())
+		}
+
+		/// Pay the transaction fees of the institution's active students for calls to this pallet
+		/// from its sponsor pot, up to `per_student` for each student and `per_period` for all of
+		/// them every `period` blocks. Calls with a tip are not sponsored.
+		///
+		/// The dispatch origin for this call must be _Signed_ by the admin of the institution.
+		///
+		/// Emits `SponsorshipSet` if successful.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(1, 1))]
+		pub fn set_sponsorship(
+			origin: OriginFor<T>,
+			#[pallet::compact] institution: InstitutionIndex,
+			per_student: BalanceOf<T>,
+			per_period: BalanceOf<T>,
+			period: T::BlockNumber,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_institution_admin(&sender, institution)?;
+
+			<Sponsorships<T>>::insert(institution, SponsorshipTerms { per_student, per_period, period });
+
+			Self::deposit_event(Event::SponsorshipSet { institution });
+			Ok(())
+		}
+
+		/// Stop paying the transaction fees of the institution's students. The sponsor pot keeps
+		/// its funds.
+		///
+		/// The dispatch origin for this call must be _Signed_ by the admin of the institution.
+		///
+		/// Emits `SponsorshipRevoked` if successful.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(2, 1))]
+		pub fn revoke_sponsorship(
+			origin: OriginFor<T>,
+			#[pallet::compact] institution: InstitutionIndex,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_institution_admin(&sender, institution)?;
+			ensure!(<Sponsorships<T>>::contains_key(institution), Error::<T>::NoSponsorship);
+
+			<Sponsorships<T>>::remove(institution);
+
+			Self::deposit_event(Event::SponsorshipRevoked { institution });
+			Ok(())
+		}
+
+		/// Issue a diploma, certificate or badge to the student `target`. The credential stays
+		/// with the account; there is no way to transfer it.
+		///
+		/// The dispatch origin for this call must be _Signed_ by the account of the registrar
+		/// `reg_index`, which must act for the student.
+		///
+		/// Emits `CredentialIssued` if successful.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(5, 3))]
+		pub fn issue_credential(
+			origin: OriginFor<T>,
+			#[pallet::compact] reg_index: RegistrarIndex,
+			target: T::AccountId,
+			kind: CredentialKind,
+			metadata: Data,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_registrar(&sender, reg_index)?;
+			ensure!(<EmailId<T>>::contains_key(&target), Error::<T>::InvalidTarget);
+			ensure!(Self::acts_for(reg_index, &target), Error::<T>::NotInstitutionStudent);
+
+			let index = <CredentialCount<T>>::get();
+			<Credentials<T>>::insert(
+				index,
+				IssuedCredential {
+					owner: target.clone(),
+					kind,
+					issuer: reg_index,
+					metadata,
+					issued: T::UnixTime::now().as_secs(),
+					revoked: None,
+				},
+			);
+			<CredentialsOf<T>>::insert(&target, index, ());
+			<CredentialCount<T>>::put(index.saturating_add(1));
+
+			Self::deposit_event(Event::CredentialIssued {
+				owner: target,
+				index,
+				kind,
+				registrar_index: reg_index,
+			});
+			Ok(())
+		}
+
+		/// Revoke a credential. It stays with its owner, marked as revoked.
+		///
+		/// The dispatch origin for this call must be _Signed_ by the account of the registrar
+		/// `reg_index` that issued the credential.
+		///
+		/// Emits `CredentialRevoked` if successful.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(2, 1))]
+		pub fn revoke_credential(
+			origin: OriginFor<T>,
+			#[pallet::compact] reg_index: RegistrarIndex,
+			#[pallet::compact] index: CredentialIndex,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_registrar(&sender, reg_index)?;
+
+			let owner = <Credentials<T>>::try_mutate(index, |credential| {
+				let credential = credential.as_mut().ok_or(Error::<T>::InvalidCredential)?;
+				ensure!(credential.issuer == reg_index, Error::<T>::NotCredentialIssuer);
+				ensure!(credential.revoked.is_none(), Error::<T>::CredentialAlreadyRevoked);
+				credential.revoked = Some(T::UnixTime::now().as_secs());
+				Ok::<_, DispatchError>(credential.owner.clone())
+			})?;
+
+			Self::deposit_event(Event::CredentialRevoked { owner, index });
+			Ok(())
+		}
+
+		/// Publish the Merkle root of the enrollment roster of `term`, see [`roster`]. The
+		/// statuses students claim from it hold until `valid_until`. Publishing again for the
+		/// same term replaces the roster; claims made against the old one stand.
+		///
+		/// The dispatch origin for this call must be _Signed_ by the account of the registrar
+		/// `reg_index`.
+		///
+		/// Emits `RosterPublished` if successful.
+		#[pallet::weight(5_000 + T::DbWeight::get().reads_writes(1, 1))]
+		pub fn publish_roster(
+			origin: OriginFor<T>,
+			#[pallet::compact] reg_index: RegistrarIndex,
+			term: roster::Term,
+			root: [u8; 32],
+			valid_until: T::BlockNumber,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_registrar(&sender, reg_index)?;
+			Self::ensure_future(valid_until)?;
+
+			<Rosters<T>>::insert(reg_index, term, Roster { root, valid_until });
+
+			Self::deposit_event(Event::RosterPublished {
+				registrar_index: reg_index,
+				term,
+				root,
+				valid_until,
+			});
+			Ok(())
+		}
+
+		/// Prove the sender is on the roster the registrar `reg_index` published for `term`.
+		///
+		/// The dispatch origin for this call must be _Signed_ and the sender must have linked
+		/// their account to an email. The roster leaf is built from the hash of that email,
+		/// normalized like the registrar's import normalizes it, `status` and `term`, and
+		/// checked against the roster root with `proof`.
+		///
+		/// The claimed status becomes the student's status until the roster's `valid_until`,
+		/// as if the registrar had set it, unless a registrar already set one for `term` or a
+		/// later term. Staff leaves are recorded without setting a status.
+		///
+		/// Emits `EnrollmentClaimed`, and `StudentStatusChanged` if the status was set.
+		#[pallet::weight(
+			20_000 + 1_000 * proof.len().min(roster::MAX_DEPTH) as Weight +
+				T::DbWeight::get().reads_writes(3, 2)
+		)]
+		pub fn claim_enrollment(
+			origin: OriginFor<T>,
+			#[pallet::compact] reg_index: RegistrarIndex,
+			term: roster::Term,
+			status: attestation::Status,
+			proof: Vec<[u8; 32]>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			let emailx = <EmailId<T>>::get(&sender).ok_or(Error::<T>::NoIdentity)?;
+			let roster = <Rosters<T>>::get(reg_index, term).ok_or(Error::<T>::RosterNotFound)?;
+			ensure!(Self::acts_for(reg_index, &sender), Error::<T>::NotInstitutionStudent);
+			Self::ensure_future(roster.valid_until)?;
+
+			let email_hash = blake2_256(&normalize_email(&emailx));
+			let leaf = roster::Leaf { email_hash, status, term };
+			ensure!(roster::verify(&roster.root, &leaf, &proof), Error::<T>::InvalidRosterProof);
+
+			let current = <StatusOf<T>>::get(&emailx);
+			let claimed = StudentStatus::from_roster(status)
+				.filter(|_| current.as_ref().map_or(true, |state| state.term < term));
+			if let Some(claimed) = claimed {
+				ensure!(
+					Self::student_status_of(&emailx).can_become(claimed),
+					Error::<T>::InvalidStatusTransition
+				);
+				let valid_until = roster.valid_until;
+				let registrar = reg_index;
+				let state = StudentState { status: claimed, term, valid_until, registrar };
+				<StatusOf<T>>::insert(&emailx, state);
+				Self::deposit_event(Event::StudentStatusChanged {
+					who: sender.clone(),
+					status: claimed,
+					term,
+					valid_until,
+				});
+			}
+			<EnrollmentOf<T>>::insert(&sender, reg_index, leaf);
+
+			Self::deposit_event(Event::EnrollmentClaimed {
+				who: sender,
+				registrar_index: reg_index,
+				term,
+				status,
+			});
+			Ok(())
+		}
+    }
+}
+
+impl<T: Config> Pallet<T> {
+	/// The identity of `who` as seen by `service`, restricted to the fields `who` disclosed to
+	/// it. Returns `None` if `service` has not been granted access to `who`.
+	pub fn disclosed_identity(
+		who: &T::AccountId,
+		service: &[u8],
+	) -> Option<IdentityInfoSel<T::MaxAdditionalFields>> {
+		let service = Data::Raw(service.to_vec().try_into().ok()?);
+		if !<ServiceGrants<T>>::contains_key(who, &service) {
+			return None
+		}
+
+		let emailx = <EmailId<T>>::get(who)?;
+		let id = <StudentidOf<T>>::get(&emailx)?;
+
+		Some(id.info.disclose(<DisclosureOf<T>>::get(who, &service)))
+	}
+
+	/// The full value behind `data`: the bytes of `Data::Raw`, or the content of the blob a
+	/// `Data::BlakeTwo256` refers to. `None` for anything else.
+	pub fn resolve(data: &Data) -> Option<Vec<u8>> {
+		match data {
+			Data::Raw(x) => Some(x.to_vec()),
+			Data::BlakeTwo256(hash) => <Blobs<T>>::get(hash).map(|blob| blob.data.into_inner()),
+			_ => None,
+		}
+	}
+
+	/// The hash of the genesis block of this chain.
+	pub fn genesis_hash() -> [u8; 32] {
+		let hash = <frame_system::Pallet<T>>::block_hash(T::BlockNumber::zero());
+		let mut genesis = [0u8; 32];
+		genesis.copy_from_slice(hash.as_ref());
+		genesis
+	}
+
+	/// The message an Ethereum wallet signs to link its address to `who`.
+	///
+	/// It names the `RelyingPartyId` domain and the genesis hash of this chain, so a signature
+	/// cannot be replayed on another chain or presented by another site.
+	pub fn eth_link_message(who: &T::AccountId) -> Vec<u8> {
+		let mut message = T::RelyingPartyId::get();
+		message.extend_from_slice(b" wants you to link your Ethereum account to studentid account:");
+		message.extend_from_slice(b"\n0x");
+		message.extend(to_hex(&who.encode()));
+		message.extend_from_slice(b"\n\nChain: 0x");
+		message.extend(to_hex(&Self::genesis_hash()));
+		message
+	}
+
+	/// The EIP-191 `personal_sign` form of `what`: the Ethereum prefix, the decimal length of
+	/// `what` and `what` itself.
+	pub fn ethereum_signable_message(what: &[u8]) -> Vec<u8> {
+		let mut l = what.len();
+		let mut rev = Vec::new();
+		while l > 0 {
+			rev.push(b'0' + (l % 10) as u8);
+			l /= 10;
+		}
+		let mut v = b"\x19Ethereum Signed Message:\n".to_vec();
+		v.extend(rev.into_iter().rev());
+		v.extend_from_slice(what);
+		v
+	}
+
+	/// The Ethereum address that produced `signature` over the `personal_sign` form of `what`.
+	pub fn eth_recover(signature: &[u8; 65], what: &[u8]) -> Option<H160> {
+		let msg = keccak_256(&Self::ethereum_signable_message(what));
+		let pubkey = sp_io::crypto::secp256k1_ecdsa_recover(signature, &msg).ok()?;
+		Some(H160::from_slice(&keccak_256(&pubkey[..])[12..]))
+	}
+
+	/// The owner of the access token `token_id`, if it exists and is still active.
+	pub fn token_owner(token_id: &[u8]) -> Option<T::AccountId> {
+		Self::introspect_token(token_id).filter(|status| status.active).map(|status| status.owner)
+	}
+
+	/// The state of the access token `token_id`, or `None` if there is no such token.
+	///
+	/// A token is active while it is allowed and has not expired.
+	pub fn introspect_token(token_id: &[u8]) -> Option<TokenStatus<T::AccountId, T::BlockNumber>> {
+		let tokenid: TokenId<T> = token_id.to_vec().try_into().ok()?;
+		let token = <Tokens<T>>::get(&tokenid)?;
+
+		let allowed = token.data == Data::Raw(b"allowed".to_vec().try_into().ok()?);
+		let student_active =
+			<EmailId<T>>::get(&token.owner).map_or(true, |emailx| Self::may_log_in(&emailx));
+		let active = allowed &&
+			student_active && frame_system::Pallet::<T>::block_number() < token.expires;
+		let service = token.metadata.into_inner();
+		let scopes = match service.clone().try_into() {
+			Ok(service) => <DisclosureOf<T>>::get(&token.owner, Data::Raw(service)),
+			Err(_) => Default::default(),
+		};
+
+		Some(TokenStatus {
+			active,
+			owner: token.owner,
+			service,
+			scopes,
+			issued: token.issued,
+			expires: token.expires,
+		})
+	}
+
+	/// Whether `who` has given `service` access, as checked by `check_web3access_sel18`.
+	pub fn has_access(who: &T::AccountId, service: &[u8]) -> bool {
+		let service = match service.to_vec().try_into() {
+			Ok(service) => Data::Raw(service),
+			Err(_) => return false,
+		};
+		if !<EmailId<T>>::get(who).map_or(false, |emailx| Self::may_log_in(&emailx)) {
+			return false
+		}
+		if <ServiceGrants<T>>::contains_key(who, &service) {
+			return true
+		}
+		<EmailId<T>>::get(who)
+			.and_then(|emailx| <StudentidOf<T>>::get(&emailx))
+			.map_or(false, |id| id.info.web == service)
+	}
+
+	/// The challenge the next passkey assertion for the student with `email` must sign.
+	///
+	/// It commits to the genesis hash of this chain, so an assertion cannot be replayed on
+	/// another chain the student registered the same passkey on.
+	pub fn passkey_challenge(email: &[u8]) -> [u8; 32] {
+		let email = normalize_email(email);
+		let emailx: Option<Email<T>> = email.clone().try_into().ok();
+		let nonce = emailx.map(|e| <PasskeyNonce<T>>::get(&e)).unwrap_or_default();
+
+		let mut preimage = b"studentid/webauthn".to_vec();
+		preimage.extend_from_slice(&Self::genesis_hash());
+		preimage.extend_from_slice(&email);
+		preimage.extend_from_slice(&nonce.to_le_bytes());
+		blake2_256(&preimage)
+	}
+
+	/// The registrar attestation keys and the revoked attestations that have not expired yet,
+	/// for verifying ID card attestations offline.
+	pub fn attestation_snapshot() -> attestation::Snapshot {
+		let now = T::UnixTime::now().as_secs();
+		let registrars = <Registrars<T>>::get()
+			.into_iter()
+			.filter_map(|r| r.and_then(|r| r.attestation_key))
+			.collect();
+		let revoked = <RevokedAttestations<T>>::iter()
+			.filter(|(_, (_, valid_until))| *valid_until > now)
+			.map(|(id, _)| id)
+			.collect();
+
+		attestation::Snapshot::new(registrars, revoked, now)
+	}
+
+	/// What the `did:selendra` DID document of `who` is derived from. `None` if `who` has no
+	/// identity.
+	pub fn did_details(who: &T::AccountId) -> Option<DidDetails<T::AccountId>> {
+		let emailx = <EmailId<T>>::get(who)?;
+		let id = <StudentidOf<T>>::get(&emailx)?;
+		let services = <ServiceGrants<T>>::iter_key_prefix(who)
+			.filter_map(|service| Self::resolve(&service))
+			.collect();
+
+		Some(DidDetails {
+			controller: id.accountId,
+			sub_accounts: <SubsOf<T>>::get(who).1.into_inner(),
+			eth_account: <EthAccountOf<T>>::get(who),
+			services,
+		})
+	}
+
+	/// The SS58 prefix accounts of this chain are rendered with, as in `did:selendra` DIDs.
+	pub fn ss58_prefix() -> u16 {
+		T::SS58Prefix::get()
+	}
+
+	/// The registrar `index`, if it is registered.
+	pub fn registrar(index: RegistrarIndex) -> Option<RegistrarInfo<T::AccountId>> {
+		<Registrars<T>>::get().get(index as usize).cloned().flatten()
+	}
+
+	/// The index of the registrar whose account is `who`.
+	pub fn registrar_index_of(who: &T::AccountId) -> Option<RegistrarIndex> {
+		<Registrars<T>>::get()
+			.iter()
+			.position(|r| r.as_ref().map_or(false, |r| &r.account == who))
+			.map(|index| index as RegistrarIndex)
+	}
+
+	/// The Blake2 hash of the judged part of the identity of `who`, see
+	/// [`IdentityInfoSel::judged`].
+	pub fn identity_hash(who: &T::AccountId) -> Option<[u8; 32]> {
+		Self::judged_info(who).map(|info| blake2_256(&info.encode()))
+	}
+
+	/// The identity of `who` with the judgement of the registrar `index`. `None` if the
+	/// registrar is not registered, has not judged the identity, or the identity changed since.
+	pub fn judged_identity(
+		who: &T::AccountId,
+		index: RegistrarIndex,
+	) -> Option<JudgedIdentity<T::AccountId, T::MaxAdditionalFields>> {
+		let registrar = Self::registrar(index)?;
+		let (judgement, hash) = <JudgementOf<T>>::get(who, index)?;
+		let info = Self::judged_info(who)?;
+		if blake2_256(&info.encode()) != hash {
+			return None
+		}
+
+		Some(JudgedIdentity {
+			registrar: registrar.account,
+			attestation_key: registrar.attestation_key,
+			judgement,
+			info,
+		})
+	}
+
+	/// The institution owning the domain of `email`, or `None` if the email is unverified.
+	pub fn institution_of_email(email: &[u8]) -> Option<InstitutionIndex> {
+		let domain: Email<T> = email_domain(email)?.try_into().ok()?;
+		<DomainOwner<T>>::get(&domain)
+	}
+
+	/// The institution the student `who` belongs to through their email, or `None` if `who` has
+	/// no identity or an unverified email.
+	pub fn institution_of(who: &T::AccountId) -> Option<InstitutionIndex> {
+		Self::institution_of_email(&<EmailId<T>>::get(who)?)
+	}
+
+	/// Whether the registrar `index` acts for the student `who`: it acts for no institution in
+	/// particular, or for the one `who` belongs to.
+	pub fn acts_for(index: RegistrarIndex, who: &T::AccountId) -> bool {
+		match <InstitutionOfRegistrar<T>>::get(index) {
+			Some(institution) => Self::institution_of(who) == Some(institution),
+			None => true,
+		}
+	}
+
+	/// Whether the registrar `index` acts for the students with `email`, see [`Self::acts_for`].
+	pub fn acts_for_email(index: RegistrarIndex, email: &[u8]) -> bool {
+		match <InstitutionOfRegistrar<T>>::get(index) {
+			Some(institution) => Self::institution_of_email(email) == Some(institution),
+			None => true,
+		}
+	}
+
+	/// The state of the student `who`, with the status `Expired` once it ran past its validity.
+	/// `None` if no registrar set a status for `who`.
+	pub fn student_state(who: &T::AccountId) -> Option<StudentState<T::BlockNumber>> {
+		let emailx = <EmailId<T>>::get(who)?;
+		let mut state = <StatusOf<T>>::get(&emailx)?;
+		state.status = state.status_at(&frame_system::Pallet::<T>::block_number());
+		Some(state)
+	}
+
+	/// The status of the student `who`, `Unverified` if no status was set. `None` if `who` has
+	/// no identity.
+	pub fn student_status(who: &T::AccountId) -> Option<StudentStatus> {
+		Some(Self::student_status_of(&<EmailId<T>>::get(who)?))
+	}
+
+	/// The current status of the student with `emailx`, `Unverified` if no status was set.
+	fn student_status_of(emailx: &Email<T>) -> StudentStatus {
+		<StatusOf<T>>::get(emailx).map_or(StudentStatus::Unverified, |state| {
+			state.status_at(&frame_system::Pallet::<T>::block_number())
+		})
+	}
+
+	/// The institution vouching for the student `who`: a registrar acting for it set an active
+	/// status for `who`, or `who` claimed one from its roster, and it owns the domain of their
+	/// email. `None` for students no institution vouches for, whatever their email says.
+	pub fn vouching_institution_of(who: &T::AccountId) -> Option<InstitutionIndex> {
+		let emailx = <EmailId<T>>::get(who)?;
+		let state = <StatusOf<T>>::get(&emailx)?;
+		if !state.status_at(&frame_system::Pallet::<T>::block_number()).grants_access() {
+			return None
+		}
+		let institution = <InstitutionOfRegistrar<T>>::get(state.registrar)?;
+		(Self::institution_of_email(&emailx) == Some(institution)).then(|| institution)
+	}
+
+	/// Whether the student with `emailx` may log in and use services, see
+	/// [`StudentStatus::may_log_in`].
+	fn may_log_in(emailx: &Email<T>) -> bool {
+		Self::student_status_of(emailx).may_log_in()
+	}
+
+	/// Ensure a status valid until `valid_until` would not already have lapsed.
+	fn ensure_future(valid_until: T::BlockNumber) -> DispatchResult {
+		ensure!(
+			valid_until > frame_system::Pallet::<T>::block_number(),
+			Error::<T>::StatusAlreadyLapsed
+		);
+		Ok(())
+	}
+
+	fn ensure_may_log_in(emailx: &Email<T>) -> DispatchResult {
+		ensure!(Self::may_log_in(emailx), Error::<T>::StudentInactive);
+		Ok(())
+	}
+
+	/// The credentials issued to `who`, revoked ones included.
+	pub fn credentials_of(who: &T::AccountId) -> Vec<(CredentialIndex, IssuedCredential<T::AccountId>)> {
+		<CredentialsOf<T>>::iter_key_prefix(who)
+			.filter_map(|index| Some((index, <Credentials<T>>::get(index)?)))
+			.collect()
+	}
+
+	/// The account holding the funds an institution pays the fees of its students with.
+	pub fn sponsor_pot(institution: InstitutionIndex) -> T::AccountId {
+		T::SponsorPalletId::get().into_sub_account(institution)
+	}
+
+	/// The institution paying a `fee` of `who`, with its sponsor pot. `None` if `who` is not an
+	/// active student the institution vouches for, see [`Self::vouching_institution_of`], it
+	/// does not sponsor fees, or the fee exceeds what is left of its limits. The pot may still
+	/// lack the funds.
+	pub fn sponsor_of(
+		who: &T::AccountId,
+		fee: BalanceOf<T>,
+	) -> Option<(InstitutionIndex, T::AccountId)> {
+		let institution = Self::vouching_institution_of(who)?;
+		let terms = <Sponsorships<T>>::get(institution)?;
+		let period = Self::sponsor_period(&terms);
+
+		let spent = |paid: Option<(T::BlockNumber, BalanceOf<T>)>| match paid {
+			Some((p, spent)) if p == period => spent,
+			_ => Zero::zero(),
+		};
+		let institution_spent = spent(<SponsoredFees<T>>::get(institution)).saturating_add(fee);
+		let student_spent = spent(<SponsoredFeesOf<T>>::get(institution, who)).saturating_add(fee);
+		if institution_spent > terms.per_period || student_spent > terms.per_student {
+			return None
+		}
+		Some((institution, Self::sponsor_pot(institution)))
+	}
+
+	/// Count a `fee` of `who` the institution paid against its limits.
+	pub fn note_sponsored_fee(institution: InstitutionIndex, who: &T::AccountId, fee: BalanceOf<T>) {
+		let period = match <Sponsorships<T>>::get(institution) {
+			Some(terms) => Self::sponsor_period(&terms),
+			None => return,
+		};
+		let add = |paid: &mut Option<(T::BlockNumber, BalanceOf<T>)>| match paid {
+			Some((p, spent)) if *p == period => *spent = spent.saturating_add(fee),
+			_ => *paid = Some((period, fee)),
+		};
+		<SponsoredFees<T>>::mutate(institution, add);
+		<SponsoredFeesOf<T>>::mutate(institution, who, add);
+	}
+
+	fn sponsor_period(terms: &SponsorshipTerms<BalanceOf<T>, T::BlockNumber>) -> T::BlockNumber {
+		frame_system::Pallet::<T>::block_number() / terms.period.max(1u32.into())
+	}
+
+	fn ensure_institution_admin(who: &T::AccountId, institution: InstitutionIndex) -> DispatchResult {
+		let info = <Institutions<T>>::get(institution).ok_or(Error::<T>::InvalidInstitution)?;
+		ensure!(&info.admin == who, Error::<T>::NotInstitutionAdmin);
+		Ok(())
+	}
+
+	/// Whether `who` may make `call` without paying fees: the call is one of the onboarding calls
+	/// and `who` has not used up its feeless calls for the current period.
+	///
+	/// New students can register an unclaimed email of an institution's domain without funds.
+	/// As every fresh account gets its own allowance, a registrar acting for the institution
+	/// removes registrations squatting its students' emails with `discard_registration`.
+	/// Setting the referal and linking need a pending registration made by `who`; the other calls
+	/// a linked identity. Calls reserving deposits are not feeless.
+	pub fn is_feeless(who: &T::AccountId, call: &Call<T>) -> bool {
+		let registration = |email: &[u8]| {
+			let emailx: Option<Email<T>> = normalize_email(email).try_into().ok();
+			emailx.and_then(|e| <StudentidOf<T>>::get(&e))
+		};
+		let pending = |email: &[u8]| registration(email).map_or(false, |id| &id.accountId == who);
+		let eligible = match call {
+			Call::request_registration_sel11 { email, .. } =>
+				Self::institution_of_email(email).is_some() && registration(email).is_none(),
+			Call::set_referal_sel12 { email, .. } | Call::create_web3link_sel15 { email, .. } =>
+				pending(email),
+			Call::register_passkey { .. } |
+			Call::link_eth_account { .. } |
+			Call::claim_enrollment { .. } => <EmailId<T>>::contains_key(who),
+			_ => false,
+		};
+		eligible && Self::feeless_calls_left(who) > 0
+	}
+
+	/// Whether an institution may pay the fee of `call` for a student: only the calls managing
+	/// the student's own identity and its access to services are sponsored, not registrations,
+	/// deposits, sub-accounts or the calls of registrars and institutions.
+	pub fn is_sponsored(call: &Call<T>) -> bool {
+		matches!(
+			call,
+			Call::login_access_sel12 { .. } |
+				Call::change_password_sel13 { .. } |
+				Call::set_referal_sel12 { .. } |
+				Call::create_web3link_sel15 { .. } |
+				Call::create_web3link_sel { .. } |
+				Call::create_weblink_sel { .. } |
+				Call::login_web3_sel16 { .. } |
+				Call::set_accessservice_sel17 { .. } |
+				Call::check_web3access_sel18 { .. } |
+				Call::logout_web3_sel19 { .. } |
+				Call::set_fields { .. } |
+				Call::remove_field { .. } |
+				Call::set_disclosure { .. } |
+				Call::link_eth_account { .. } |
+				Call::unlink_eth_account { .. } |
+				Call::login_siws { .. } |
+				Call::register_passkey { .. } |
+				Call::remove_passkey { .. } |
+				Call::login_passkey { .. } |
+				Call::claim_enrollment { .. }
+		)
+	}
+
+	/// The number of feeless onboarding calls `who` can still make in the current period.
+	pub fn feeless_calls_left(who: &T::AccountId) -> u32 {
+		T::MaxFeelessCallsPerPeriod::get().saturating_sub(Self::feeless_calls_made(who).1)
+	}
+
+	/// The current feeless period and the number of feeless onboarding calls `who` made in it.
+	pub fn feeless_calls_made(who: &T::AccountId) -> (T::BlockNumber, u32) {
+		let current = Self::feeless_period();
+		match <FeelessCallsOf<T>>::get(who) {
+			Some((period, made)) if period == current => (current, made),
+			_ => (current, 0),
+		}
+	}
+
+	/// The number of blocks until the current feeless period ends, at least one.
+	pub fn feeless_period_left() -> T::BlockNumber {
+		let length = T::FeelessPeriod::get().max(1u32.into());
+		length - frame_system::Pallet::<T>::block_number() % length
+	}
+
+	/// Count a feeless onboarding call made by `who`.
+	pub fn note_feeless_call(who: &T::AccountId) {
+		let period = Self::feeless_period();
+		<FeelessCallsOf<T>>::mutate(who, |calls| match calls {
+			Some((p, made)) if *p == period => *made = made.saturating_add(1),
+			_ => *calls = Some((period, 1)),
+		});
+	}
+
+	fn feeless_period() -> T::BlockNumber {
+		let length = T::FeelessPeriod::get().max(1u32.into());
+		frame_system::Pallet::<T>::block_number() / length
+	}
+
+	/// The roster leaf `who` last claimed with the registrar `index`.
+	pub fn enrollment(who: &T::AccountId, index: RegistrarIndex) -> Option<roster::Leaf> {
+		<EnrollmentOf<T>>::get(who, index)
+	}
+
+	fn judged_info(who: &T::AccountId) -> Option<IdentityInfoSel<T::MaxAdditionalFields>> {
+		let emailx = <EmailId<T>>::get(who)?;
+		<StudentidOf<T>>::get(&emailx).map(|id| id.info.judged())
+	}
+
+	/// The registrar `index`, if `who` is its account.
+	fn ensure_registrar(
+		who: &T::AccountId,
+		index: RegistrarIndex,
+	) -> Result<RegistrarInfo<T::AccountId>, DispatchError> {
+		Ok(Self::registrar(index).filter(|r| &r.account == who).ok_or(Error::<T>::InvalidIndex)?)
+	}
+
+	/// Whether the registration `id` was linked to an account with its referal code, which
+	/// replaces the code with `null`.
+	fn is_linked(id: &RegistrationOf<T>) -> bool {
+		let linked: BoundedVec<u8, _> = b"null".to_vec().try_into().expect("4 bytes fit in Data; qed");
+		id.info.referalhash == Data::Raw(linked)
+	}
+
+	/// Check `password` against the one of the student with `email`.
+	fn check_password(email: &[u8], password: &[u8]) -> Result<Email<T>, DispatchError> {
+		let emailx: Email<T> =
+			normalize_email(email).try_into().map_err(|_| Error::<T>::NoIdentity)?;
+		let id = <StudentidOf<T>>::get(&emailx).ok_or(Error::<T>::NoIdentity)?;
+		ensure!(id.info.passwordhash == Data::Sha256(sha2_256(password)), Error::<T>::LoginFailed);
+		Ok(emailx)
+	}
+
+	/// Move the references held by the additional fields `old` of the identity of `owner` over
+	/// to the fields `new`, so that `Blobs` count the fields referencing them. Only blobs noted
+	/// by `owner` are counted, so another account cannot keep a blob from being removed.
+	fn update_blob_references(owner: &T::AccountId, old: &[(Data, Data)], new: &[(Data, Data)]) {
+		let blob = |(_, value): &(Data, Data)| match value {
+			Data::BlakeTwo256(hash) => Some(*hash),
+			_ => None,
+		};
+		for hash in old.iter().filter_map(blob) {
+			<Blobs<T>>::mutate(&hash, |blob| match blob {
+				Some(blob) if &blob.owner == owner =>
+					blob.references = blob.references.saturating_sub(1),
+				_ => (),
+			});
+		}
+		for hash in new.iter().filter_map(blob) {
+			<Blobs<T>>::mutate(&hash, |blob| match blob {
+				Some(blob) if &blob.owner == owner =>
+					blob.references = blob.references.saturating_add(1),
+				_ => (),
+			});
+		}
+	}
+
+	/// Ensure the record `id` may be moved to `account`. Records holding a deposit stay with the
+	/// account that holds it.
+	fn ensure_relinkable(id: &RegistrationOf<T>, account: &T::AccountId) -> DispatchResult {
+		ensure!(id.deposit.is_zero() || id.accountId == *account, Error::<T>::DepositHeld);
+		Ok(())
+	}
+
+	/// Bring the deposit held for `id` in line with `FieldDeposit` per additional field,
+	/// reserving or unreserving the difference from the account it belongs to.
+	fn update_field_deposit(id: &mut RegistrationOf<T>) -> DispatchResult {
+		let new_deposit = T::FieldDeposit::get() * (id.info.additional.len() as u32).into();
+		let old_deposit = id.deposit;
+
+		if new_deposit > old_deposit {
+			T::Currency::reserve(&id.accountId, new_deposit - old_deposit)?;
+		} else if old_deposit > new_deposit {
+			T::Currency::unreserve(&id.accountId, old_deposit - new_deposit);
+		}
+
+		id.deposit = new_deposit;
+		Ok(())
+	}
+}
+
+/// Lowercase hexadecimal encoding of `data`.
+fn to_hex(data: &[u8]) -> Vec<u8> {
+	const ALPHABET: &[u8; 16] = b"0123456789abcdef";
+	data.iter().flat_map(|b| [ALPHABET[(b >> 4) as usize], ALPHABET[(b & 0xf) as usize]]).collect()
 }
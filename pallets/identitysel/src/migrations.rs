@@ -0,0 +1,112 @@
+//! Storage migrations of the studentid pallet.
+
+use super::*;
+use frame_support::{
+	log,
+	traits::{GetStorageVersion, StorageVersion},
+	weights::Weight,
+};
+
+/// Version 1: access tokens record the block they were issued in and expire.
+pub mod v1 {
+	use super::*;
+
+	/// `TokenInfo` before version 1.
+	#[derive(Decode)]
+	struct OldTokenInfo<AccountId, Data, TokenMetadataOf> {
+		metadata: TokenMetadataOf,
+		owner: AccountId,
+		data: Data,
+	}
+
+	/// Give the existing tokens a full `TokenLifetime` from now, as when they are issued, and
+	/// index them in `TokensOf` so `clear_identity` finds them.
+	pub fn migrate<T: Config>() -> Weight {
+		let on_chain = Pallet::<T>::on_chain_storage_version();
+		if on_chain >= 1 {
+			log::info!(
+				target: "runtime::studentid",
+				"Storage is at {:?}, skipping v1 migration",
+				on_chain
+			);
+			return T::DbWeight::get().reads(1)
+		}
+
+		let now = frame_system::Pallet::<T>::block_number();
+		let expires = now.saturating_add(T::TokenLifetime::get());
+		let mut translated = 0u64;
+		Tokens::<T>::translate::<OldTokenInfo<T::AccountId, Data, TokenMetadataOf<T>>, _>(
+			|tokenid, old| {
+				translated += 1;
+				TokensOf::<T>::insert(&old.owner, &tokenid, ());
+				Some(TokenInfo {
+					metadata: old.metadata,
+					owner: old.owner,
+					data: old.data,
+					issued: now,
+					expires,
+				})
+			},
+		);
+		StorageVersion::new(1).put::<Pallet<T>>();
+
+		log::info!(target: "runtime::studentid", "Migrated {} tokens to v1", translated);
+		T::DbWeight::get().reads_writes(translated + 1, 2 * translated + 1)
+	}
+}
+
+/// Version 2: records no longer carry the placeholder fields registration used to add.
+pub mod v2 {
+	use super::*;
+
+	/// Whether `field` is one of the placeholders registration added before `set_fields`
+	/// existed: `("number", 10)` or `("text", "10")`.
+	fn is_legacy((key, value): &(Data, Data)) -> bool {
+		match (key, value) {
+			(Data::Raw(key), Data::Raw(value)) =>
+				(&key[..] == b"number" && value[..] == 10u32.encode()[..]) ||
+					(&key[..] == b"text" && &value[..] == b"10"),
+			_ => false,
+		}
+	}
+
+	/// Strip the placeholder fields from the existing records, so they are not charged a
+	/// `FieldDeposit` the next time the student sets a field. Any deposit held beyond what the
+	/// remaining fields need is returned.
+	pub fn migrate<T: Config>() -> Weight {
+		let on_chain = Pallet::<T>::on_chain_storage_version();
+		if on_chain >= 2 {
+			log::info!(
+				target: "runtime::studentid",
+				"Storage is at {:?}, skipping v2 migration",
+				on_chain
+			);
+			return T::DbWeight::get().reads(1)
+		}
+
+		let mut translated = 0u64;
+		let mut stripped = 0u64;
+		StudentidOf::<T>::translate::<RegistrationOf<T>, _>(|_, mut id| {
+			translated += 1;
+			let before = id.info.additional.len();
+			id.info.additional.retain(|field| !is_legacy(field));
+			stripped += (before - id.info.additional.len()) as u64;
+
+			let needed = T::FieldDeposit::get() * (id.info.additional.len() as u32).into();
+			if id.deposit > needed {
+				T::Currency::unreserve(&id.accountId, id.deposit - needed);
+				id.deposit = needed;
+			}
+			Some(id)
+		});
+		StorageVersion::new(2).put::<Pallet<T>>();
+
+		log::info!(
+			target: "runtime::studentid",
+			"Stripped {} placeholder fields from {} records in v2",
+			stripped,
+			translated
+		);
+		T::DbWeight::get().reads_writes(2 * translated + 1, 2 * translated + 1)
+	}
+}
@@ -25,7 +25,7 @@ use frame_support::{
 use sp_core::H256;
 use sp_runtime::{
 	testing::Header,
-	traits::{BlakeTwo256, IdentityLookup},
+	traits::{BadOrigin, BlakeTwo256, IdentityLookup},
 };
 
 type UncheckedExtrinsic = frame_system::mocking::MockUncheckedExtrinsic<Test>;
@@ -99,9 +99,30 @@ parameter_types! {
 	pub const MaxUseridentities: u32 = 2;
 	pub const MaxAdditionalFields: u32 = 2;
 	pub const MaxRegistrars: u32 = 20;
+	pub const MaxDomainsPerInstitution: u32 = 2;
+	pub const MaxRenewalsPerCall: u32 = 3;
+	pub const SponsorPalletId: PalletId = PalletId(*b"py/stdsp");
+	pub const FeelessPeriod: u64 = 10;
+	pub const MaxFeelessCallsPerPeriod: u32 = 2;
 	pub const MaxEmailsize: u32 = 30;
     pub const MaxTokenid: u32 = 30;
+	pub const TokenLifetime: u64 = 100;
+	pub const MaxClearedPerCall: u32 = 2;
+	pub const MaxBlobSize: u32 = 64;
+	pub const BlobDepositBase: u64 = 5;
+	pub const BlobDepositPerByte: u64 = 1;
+	pub const MaxPasskeys: u32 = 2;
+	pub const MaxCredentialIdLen: u32 = 64;
+	pub RelyingPartyId: Vec<u8> = crate::webauthn::tests::RP_ID.to_vec();
+	pub RelyingPartyOrigin: Vec<u8> = crate::webauthn::tests::ORIGIN.to_vec();
+	pub static Now: u64 = 0;
+}
 
+pub struct MockTime;
+impl frame_support::traits::UnixTime for MockTime {
+	fn now() -> core::time::Duration {
+		core::time::Duration::from_secs(Now::get())
+	}
 }
 ord_parameter_types! {
 	pub const One: u64 = 1;
@@ -122,9 +143,24 @@ impl pallet_studentid::Config for Test {
 	type MaxUseridentities = MaxUseridentities;
 	type MaxAdditionalFields = MaxAdditionalFields;
 	type MaxRegistrars = MaxRegistrars;
+	type MaxDomainsPerInstitution = MaxDomainsPerInstitution;
+	type MaxRenewalsPerCall = MaxRenewalsPerCall;
+	type SponsorPalletId = SponsorPalletId;
+	type FeelessPeriod = FeelessPeriod;
+	type MaxFeelessCallsPerPeriod = MaxFeelessCallsPerPeriod;
 	type MaxEmailsize = MaxEmailsize;
     type MaxAccessTokenMetadata = MaxAccessTokenMetadata;
     type MaxTokenid = MaxTokenid;
+	type TokenLifetime = TokenLifetime;
+	type MaxClearedPerCall = MaxClearedPerCall;
+	type MaxBlobSize = MaxBlobSize;
+	type BlobDepositBase = BlobDepositBase;
+	type BlobDepositPerByte = BlobDepositPerByte;
+	type MaxPasskeys = MaxPasskeys;
+	type MaxCredentialIdLen = MaxCredentialIdLen;
+	type RelyingPartyId = RelyingPartyId;
+	type RelyingPartyOrigin = RelyingPartyOrigin;
+	type UnixTime = MockTime;
 
 	type RegistrarOrigin = frame_system::EnsureRoot<Self::AccountId> ;
 	type ForceOrigin = frame_system::EnsureRoot<Self::AccountId>  ;
@@ -273,13 +309,1492 @@ fn logout_web3_method_sela() {
 
 	   assert_ok!(Identity::logout_web3_sel19(Origin::signed(staff) ));
 
-        let tokeninfo2 = <Tokens<Test>>::get(BoundedVec::try_from(challenge).unwrap()).unwrap();
+        assert_eq!(<Tokens<Test>>::get(BoundedVec::try_from(challenge).unwrap()), None);
+
+
+
+	});
+}
+
+
+#[test]
+fn clear_identity_removes_everything() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		let user: Vec<u8> = b"a@b.com".to_vec();
+		let pass: Vec<u8> = b"hello123".to_vec();
+		let referal: Vec<u8> = b"referABCD".to_vec();
+		let challenge: Vec<u8> = b"random27363".to_vec();
+		let emailx: BoundedVec<u8, MaxEmailsize> = user.clone().try_into().unwrap();
+		let tokenid: BoundedVec<u8, MaxTokenid> = challenge.clone().try_into().unwrap();
+		let service = Data::Raw(b"docsystem".to_vec().try_into().unwrap());
+
+		assert_noop!(Identity::clear_identity(Origin::signed(10)), Error::<Test>::NoIdentity);
+
+		assert_ok!(Identity::request_registration_sel11(Origin::signed(10), user.clone(), pass));
+		assert_ok!(Identity::set_referal_sel12(Origin::signed(10), user.clone(), referal.clone()));
+		assert_ok!(Identity::create_web3link_sel15(Origin::signed(10), user, 10, referal));
+		assert_ok!(Identity::login_web3_sel16(Origin::signed(10), challenge));
+		assert_ok!(Identity::set_accessservice_sel17(Origin::signed(10), 10, b"docsystem".to_vec()));
+
+		assert_ok!(Identity::clear_identity(Origin::signed(10)));
+
+		assert!(<StudentidOf<Test>>::get(&emailx).is_none());
+		assert!(<EmailId<Test>>::get(10).is_none());
+		assert!(<Tokens<Test>>::get(&tokenid).is_none());
+		assert!(!<TokensOf<Test>>::contains_key(10, &tokenid));
+		assert!(!<ServiceGrants<Test>>::contains_key(10, &service));
+		System::assert_last_event(Event::Identity(crate::Event::IdentityCleared {
+			who: 10,
+			deposit: 0,
+		}));
+
+		assert_noop!(Identity::clear_identity(Origin::signed(10)), Error::<Test>::NoIdentity);
+	});
+}
+
+#[test]
+fn clear_identity_is_paged() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		let user: Vec<u8> = b"a@b.com".to_vec();
+		let referal: Vec<u8> = b"referABCD".to_vec();
+		let emailx: BoundedVec<u8, MaxEmailsize> = user.clone().try_into().unwrap();
+
+		assert_ok!(Identity::request_registration_sel11(Origin::signed(10), user.clone(), b"hello123".to_vec()));
+		assert_ok!(Identity::set_referal_sel12(Origin::signed(10), user.clone(), referal.clone()));
+		assert_ok!(Identity::create_web3link_sel15(Origin::signed(10), user, 10, referal));
+		assert_ok!(Identity::login_web3_sel16(Origin::signed(10), b"session1".to_vec()));
+		assert_ok!(Identity::login_web3_sel16(Origin::signed(10), b"session2".to_vec()));
+		assert_ok!(Identity::login_web3_sel16(Origin::signed(10), b"session3".to_vec()));
+		assert_ok!(Identity::set_accessservice_sel17(Origin::signed(10), 10, b"docsystem".to_vec()));
+
+		// Three tokens and one grant, two removed per call.
+		assert_ok!(Identity::clear_identity(Origin::signed(10)));
+		System::assert_last_event(Event::Identity(crate::Event::IdentityClearPending { who: 10 }));
+		assert!(<StudentidOf<Test>>::get(&emailx).is_some());
+		assert_eq!(<TokensOf<Test>>::iter_key_prefix(10).count(), 1);
+
+		assert_ok!(Identity::clear_identity(Origin::signed(10)));
+		System::assert_last_event(Event::Identity(crate::Event::IdentityCleared {
+			who: 10,
+			deposit: 0,
+		}));
+		assert!(<StudentidOf<Test>>::get(&emailx).is_none());
+		assert_eq!(<Tokens<Test>>::iter().count(), 0);
+		assert_eq!(<ServiceGrants<Test>>::iter_key_prefix(10).count(), 0);
+	});
+}
+
+#[test]
+fn clear_identity_keeps_tokens_reused_by_others() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		let tokenid: BoundedVec<u8, MaxTokenid> = b"session1".to_vec().try_into().unwrap();
+		register_and_link(10, b"a@b.com");
+		register_and_link(20, b"c@d.com");
+		assert_ok!(Identity::login_web3_sel16(Origin::signed(10), b"session1".to_vec()));
+		assert_ok!(Identity::login_web3_sel16(Origin::signed(20), b"session1".to_vec()));
+		<SiwsNonces<Test>>::insert(10, [1u8; 32], 0);
+
+		assert_ok!(Identity::clear_identity(Origin::signed(10)));
+
+		assert_eq!(<Tokens<Test>>::get(&tokenid).unwrap().owner, 20);
+		assert!(<TokensOf<Test>>::contains_key(20, &tokenid));
+		assert!(!<TokensOf<Test>>::contains_key(10, &tokenid));
+		assert_eq!(<SiwsNonces<Test>>::iter_key_prefix(10).count(), 0);
+	});
+}
+
+#[test]
+fn cancel_registration_removes_unlinked_registrations() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		let emailx: BoundedVec<u8, MaxEmailsize> = b"a@b.com".to_vec().try_into().unwrap();
+		let pass: Vec<u8> = b"hello123".to_vec();
+		assert_ok!(Identity::request_registration_sel11(Origin::signed(10), b"a@b.com".to_vec(), pass.clone()));
+		assert_noop!(Identity::clear_identity(Origin::signed(10)), Error::<Test>::NoIdentity);
+
+		assert_noop!(
+			Identity::cancel_registration(Origin::signed(10), b"a@b.com".to_vec(), b"wrong".to_vec()),
+			Error::<Test>::LoginFailed
+		);
+		assert_noop!(
+			Identity::cancel_registration(Origin::signed(20), b"a@b.com".to_vec(), pass.clone()),
+			Error::<Test>::NotOwned
+		);
+		assert_ok!(Identity::cancel_registration(Origin::signed(10), b"a@b.com".to_vec(), pass.clone()));
+		assert!(<StudentidOf<Test>>::get(&emailx).is_none());
+		System::assert_last_event(Event::Identity(crate::Event::IdentityCleared {
+			who: 10,
+			deposit: 0,
+		}));
+
+		register_and_link(10, b"a@b.com");
+		assert_noop!(
+			Identity::cancel_registration(Origin::signed(10), b"a@b.com".to_vec(), pass),
+			Error::<Test>::IdentityLinked
+		);
+	});
+}
+
+fn register_and_link(who: u64, email: &[u8]) {
+	let referal: Vec<u8> = b"referABCD".to_vec();
+	assert_ok!(Identity::request_registration_sel11(Origin::signed(who), email.to_vec(), b"hello123".to_vec()));
+	assert_ok!(Identity::set_referal_sel12(Origin::signed(who), email.to_vec(), referal.clone()));
+	assert_ok!(Identity::create_web3link_sel15(Origin::signed(who), email.to_vec(), who, referal));
+}
+
+fn raw(bytes: &[u8]) -> Data {
+	Data::Raw(bytes.to_vec().try_into().unwrap())
+}
+
+#[test]
+fn identities_holding_deposits_cannot_be_relinked() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		register_and_link(10, b"a@b.com");
+		assert_ok!(Identity::set_fields(Origin::signed(10), vec![(raw(profile_keys::FACULTY), raw(b"Arts"))]));
+
+		assert_noop!(
+			Identity::set_referal_sel12(Origin::signed(20), b"a@b.com".to_vec(), b"referXYZ".to_vec()),
+			Error::<Test>::DepositHeld
+		);
+		assert_noop!(
+			Identity::change_password_sel13(Origin::signed(20), b"a@b.com".to_vec(), b"pass".to_vec()),
+			Error::<Test>::DepositHeld
+		);
+		assert_ok!(Identity::set_referal_sel12(Origin::signed(10), b"a@b.com".to_vec(), b"referXYZ".to_vec()));
+		assert_noop!(
+			Identity::create_web3link_sel15(Origin::signed(10), b"a@b.com".to_vec(), 20, b"referXYZ".to_vec()),
+			Error::<Test>::DepositHeld
+		);
+
+		assert_ok!(Identity::clear_identity(Origin::signed(10)));
+		assert_eq!(Balances::reserved_balance(10), 0);
+	});
+}
+
+#[test]
+fn set_fields_reserves_field_deposits() {
+	new_test_ext().execute_with(|| {
+		let emailx: BoundedVec<u8, MaxEmailsize> = b"a@b.com".to_vec().try_into().unwrap();
+		register_and_link(10, b"a@b.com");
+		assert!(<StudentidOf<Test>>::get(&emailx).unwrap().info.additional.is_empty());
+
+		assert_ok!(Identity::set_fields(
+			Origin::signed(10),
+			vec![(raw(profile_keys::STUDENT_NUMBER), raw(b"S1234567")), (raw(profile_keys::FACULTY), raw(b"Science"))],
+		));
+		assert_eq!(Balances::reserved_balance(10), 20);
+
+		// Overwriting an existing key does not take a new deposit.
+		assert_ok!(Identity::set_fields(Origin::signed(10), vec![(raw(profile_keys::FACULTY), raw(b"Arts"))]));
+		assert_eq!(Balances::reserved_balance(10), 20);
+
+		let info = <StudentidOf<Test>>::get(&emailx).unwrap().info;
+		assert_eq!(info.field(profile_keys::STUDENT_NUMBER), Some(&raw(b"S1234567")));
+		assert_eq!(info.field(profile_keys::FACULTY), Some(&raw(b"Arts")));
+		assert_eq!(info.field(profile_keys::PROGRAM), None);
+
+		assert_noop!(
+			Identity::set_fields(Origin::signed(10), vec![(raw(profile_keys::PROGRAM), raw(b"Physics"))]),
+			Error::<Test>::TooManyFields
+		);
+		assert_noop!(
+			Identity::set_fields(Origin::signed(11), vec![(raw(profile_keys::PROGRAM), raw(b"Physics"))]),
+			Error::<Test>::NoIdentity
+		);
+	});
+}
+
+#[test]
+fn remove_field_returns_deposit() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		register_and_link(10, b"a@b.com");
+		assert_ok!(Identity::set_fields(
+			Origin::signed(10),
+			vec![(raw(profile_keys::PROGRAM), raw(b"Physics")), (raw(profile_keys::ENROLLMENT_YEAR), raw(b"2022"))],
+		));
+
+		assert_ok!(Identity::remove_field(Origin::signed(10), raw(profile_keys::PROGRAM)));
+		assert_eq!(Balances::reserved_balance(10), 10);
+		assert_noop!(
+			Identity::remove_field(Origin::signed(10), raw(profile_keys::PROGRAM)),
+			Error::<Test>::NoSuchField
+		);
+
+		assert_ok!(Identity::clear_identity(Origin::signed(10)));
+		assert_eq!(Balances::reserved_balance(10), 0);
+		System::assert_last_event(Event::Identity(crate::Event::IdentityCleared {
+			who: 10,
+			deposit: 10,
+		}));
+	});
+}
+
+
+#[test]
+fn disclosure_limits_what_a_service_sees() {
+	new_test_ext().execute_with(|| {
+		register_and_link(10, b"a@b.com");
+		assert_ok!(Identity::set_fields(Origin::signed(10), vec![(raw(profile_keys::FACULTY), raw(b"Arts"))]));
+
+		assert_noop!(
+			Identity::set_disclosure(Origin::signed(10), b"wordpress".to_vec(), IdentityFields::default()),
+			Error::<Test>::ServiceAccessFailed
+		);
+		assert_eq!(Identity::disclosed_identity(&10, b"wordpress"), None);
+
+		assert_ok!(Identity::set_accessservice_sel17(Origin::signed(10), 10, b"wordpress".to_vec()));
+
+		// Nothing disclosed yet: the service sees none of the fields, not even their hashes.
+		let info = Identity::disclosed_identity(&10, b"wordpress").unwrap();
+		assert_eq!(info.display, Data::None);
+		assert_eq!(info.email, Data::None);
+		assert_eq!(info.field(profile_keys::FACULTY), None);
+		assert!(info.additional.is_empty());
+		assert_eq!(info.passwordhash, Data::None);
+
+		assert_ok!(Identity::set_disclosure(
+			Origin::signed(10),
+			b"wordpress".to_vec(),
+			IdentityFields::new(IdentityField::Display | IdentityField::Twitter),
+		));
+
+		let info = Identity::disclosed_identity(&10, b"wordpress").unwrap();
+		assert_eq!(info.display, raw(b"ten"));
+		assert_eq!(info.email, Data::None);
+		assert_eq!(info.legal, Data::None);
+		assert_eq!(info.field(profile_keys::FACULTY), None);
+		assert_eq!(info.passwordhash, Data::None);
+		assert_eq!(info.accesstoken, Data::None);
+
+		assert_ok!(Identity::set_disclosure(
+			Origin::signed(10),
+			b"wordpress".to_vec(),
+			IdentityFields::new(IdentityField::Profile.into()),
+		));
+
+		let info = Identity::disclosed_identity(&10, b"wordpress").unwrap();
+		assert_eq!(info.display, Data::None);
+		assert_eq!(info.field(profile_keys::FACULTY), Some(&raw(b"Arts")));
+	});
+}
+
+#[test]
+fn blobs_hold_long_profile_values() {
+	new_test_ext().execute_with(|| {
+		register_and_link(10, b"a@b.com");
+		let cid = b"bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi".to_vec();
+		let hash = blake2_256(&cid);
+
+		assert_noop!(Identity::note_blob(Origin::signed(10), vec![0u8; 65]), Error::<Test>::BlobTooLarge);
+		assert_ok!(Identity::note_blob(Origin::signed(10), cid.clone()));
+		assert_eq!(Balances::reserved_balance(10), 5 + cid.len() as u64);
+		assert_noop!(Identity::note_blob(Origin::signed(20), cid.clone()), Error::<Test>::BlobAlreadyNoted);
+
+		assert_ok!(Identity::set_fields(Origin::signed(10), vec![(raw(b"avatar"), Data::BlakeTwo256(hash))]));
+		let emailx: BoundedVec<u8, MaxEmailsize> = b"a@b.com".to_vec().try_into().unwrap();
+		let info = <StudentidOf<Test>>::get(&emailx).unwrap().info;
+		assert_eq!(Identity::resolve(info.field(b"avatar").unwrap()), Some(cid.clone()));
+		assert_eq!(Identity::resolve(&raw(b"avatar")), Some(b"avatar".to_vec()));
+
+		// A service the value is withheld from doesn't see the reference.
+		assert_ok!(Identity::set_accessservice_sel17(Origin::signed(10), 10, b"wordpress".to_vec()));
+		let info = Identity::disclosed_identity(&10, b"wordpress").unwrap();
+		assert_eq!(info.field(b"avatar"), None);
+
+		// Values must refer to a noted blob.
+		assert_noop!(
+			Identity::set_fields(Origin::signed(10), vec![(raw(b"cover"), Data::BlakeTwo256([7; 32]))]),
+			Error::<Test>::NoSuchBlob
+		);
+
+		// Another account referencing the blob does not keep the owner from removing it.
+		register_and_link(20, b"c@d.com");
+		assert_ok!(Identity::set_fields(Origin::signed(20), vec![(raw(b"avatar"), Data::BlakeTwo256(hash))]));
+		assert_eq!(<Blobs<Test>>::get(&hash).unwrap().references, 1);
+
+		assert_noop!(Identity::unnote_blob(Origin::signed(20), hash), Error::<Test>::NotBlobOwner);
+		assert_noop!(Identity::unnote_blob(Origin::signed(10), hash), Error::<Test>::BlobInUse);
+		assert_ok!(Identity::remove_field(Origin::signed(10), raw(b"avatar")));
+		assert_ok!(Identity::unnote_blob(Origin::signed(10), hash));
+		assert_eq!(Balances::reserved_balance(10), 0);
+		assert_eq!(Identity::resolve(&Data::BlakeTwo256(hash)), None);
+		assert_noop!(Identity::unnote_blob(Origin::signed(10), hash), Error::<Test>::NoSuchBlob);
+	});
+}
+
+fn eth_secret(seed: u8) -> libsecp256k1::SecretKey {
+	libsecp256k1::SecretKey::parse(&keccak_256(&[seed])).unwrap()
+}
+
+fn eth_address(secret: &libsecp256k1::SecretKey) -> H160 {
+	let public = libsecp256k1::PublicKey::from_secret_key(secret);
+	H160::from_slice(&keccak_256(&public.serialize()[1..65])[12..])
+}
+
+fn eth_sign(secret: &libsecp256k1::SecretKey, what: &[u8]) -> [u8; 65] {
+	let msg = keccak_256(&Identity::ethereum_signable_message(what));
+	let (sig, recovery_id) = libsecp256k1::sign(&libsecp256k1::Message::parse(&msg), secret);
+	let mut r = [0u8; 65];
+	r[0..64].copy_from_slice(&sig.serialize()[..]);
+	// Wallets report the recovery id as 27 or 28.
+	r[64] = recovery_id.serialize() + 27;
+	r
+}
+
+#[test]
+fn eth_signable_message_follows_eip191() {
+	assert_eq!(Identity::ethereum_signable_message(b"hello"), b"\x19Ethereum Signed Message:\n5hello".to_vec());
+	new_test_ext().execute_with(|| {
+		let mut expected = crate::webauthn::tests::RP_ID.to_vec();
+		expected.extend_from_slice(b" wants you to link your Ethereum account to studentid account:\n");
+		expected.extend_from_slice(b"0x0a00000000000000\n\nChain: 0x");
+		expected.extend_from_slice(&[b'4', b'5'].repeat(32));
+		assert_eq!(Identity::eth_link_message(&10), expected);
+	});
+}
+
+#[test]
+fn link_eth_account_works() {
+	new_test_ext().execute_with(|| {
+		let alice = eth_secret(1);
+		let address = eth_address(&alice);
+
+		let signature = eth_sign(&alice, &Identity::eth_link_message(&10));
+		assert_noop!(Identity::link_eth_account(Origin::signed(10), signature), Error::<Test>::NoIdentity);
+
+		register_and_link(10, b"a@b.com");
+		register_and_link(20, b"c@d.com");
+
+		// A signature made for another account recovers to a different address.
+		assert_ok!(Identity::link_eth_account(Origin::signed(20), signature));
+		assert_ne!(Identity::eth_account_of(20), Some(address));
+
+		assert_ok!(Identity::link_eth_account(Origin::signed(10), signature));
+		assert_eq!(Identity::eth_account_of(10), Some(address));
+		assert_eq!(Identity::student_of_eth(address), Some(10));
+
+		let signature = eth_sign(&alice, &Identity::eth_link_message(&20));
+		assert_noop!(
+			Identity::link_eth_account(Origin::signed(20), signature),
+			Error::<Test>::EthAccountAlreadyLinked
+		);
+
+		assert_ok!(Identity::unlink_eth_account(Origin::signed(10)));
+		assert_eq!(Identity::student_of_eth(address), None);
+		assert_noop!(Identity::unlink_eth_account(Origin::signed(10)), Error::<Test>::NoEthAccount);
+
+		assert_noop!(
+			Identity::link_eth_account(Origin::signed(10), [0u8; 65]),
+			Error::<Test>::InvalidEthSignature
+		);
+	});
+}
+
+#[test]
+fn passkey_login_works() {
+	use hex_literal::hex;
+
+	// A software P-256 authenticator's key and its assertion over the first challenge for
+	// `a@b.com` on the mock chain.
+	const COSE_KEY: [u8; 77] = hex!("a50102032620012158200efd865b424289c70c3cb386a21abf001977d5b7732a0a356b3945fc27e7ed112258209fb848f618cd75f37cefb382e36082e2e10a13b82c411e758aa44117ce387cf7");
+	const PUBLIC_KEY: [u8; 65] = hex!("040efd865b424289c70c3cb386a21abf001977d5b7732a0a356b3945fc27e7ed119fb848f618cd75f37cefb382e36082e2e10a13b82c411e758aa44117ce387cf7");
+	let authenticator_data = hex!("f2d8223b71e9754e444a4314a4d920698221505195be3d225b7a5bf4a3576ed90500000001");
+	let client_data_json = br#"{"type":"webauthn.get","challenge":"v793-BF4Gr2vWKdnP0XwG-6ClAyssRDg2iUB_kbSiD4","origin":"https://id.selendra.org","crossOrigin":false}"#;
+	let signature = hex!("304402203bf3db94d64ba15c0e0906834bf7c1682f2416f48ed2da519cfd70532a73c37102205f23c1c768e600824d38efc897f4bf376d0c1fb54a0ef21c3d1c72e10ad6e1ad");
+
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		let emailx: BoundedVec<u8, MaxEmailsize> = b"a@b.com".to_vec().try_into().unwrap();
+		register_and_link(10, b"a@b.com");
+		assert_eq!(
+			Identity::passkey_challenge(b"a@b.com"),
+			hex!("bfbf77f811781abdaf58a7673f45f01bee82940cacb110e0da2501fe46d2883e")
+		);
+
+		assert_noop!(
+			Identity::register_passkey(Origin::signed(1), b"a@b.com".to_vec(), b"wrong".to_vec(), b"cred".to_vec(), COSE_KEY.to_vec()),
+			Error::<Test>::LoginFailed
+		);
+		assert_noop!(
+			Identity::register_passkey(Origin::signed(1), b"a@b.com".to_vec(), b"hello123".to_vec(), b"cred".to_vec(), COSE_KEY[..70].to_vec()),
+			Error::<Test>::InvalidPasskey
+		);
+		assert_ok!(Identity::register_passkey(Origin::signed(1), b"a@b.com".to_vec(), b"hello123".to_vec(), b"cred".to_vec(), COSE_KEY.to_vec()));
+		assert_noop!(
+			Identity::register_passkey(Origin::signed(1), b"a@b.com".to_vec(), b"hello123".to_vec(), b"cred".to_vec(), COSE_KEY.to_vec()),
+			Error::<Test>::PasskeyAlreadyRegistered
+		);
+		assert_eq!(Identity::passkeys(&emailx)[0].public_key, PUBLIC_KEY);
+
+		assert_noop!(
+			Identity::login_passkey(Origin::signed(1), b"a@b.com".to_vec(), b"other".to_vec(), authenticator_data.to_vec(), client_data_json.to_vec(), signature.to_vec()),
+			Error::<Test>::NoSuchPasskey
+		);
+		assert_ok!(Identity::login_passkey(Origin::signed(1), b"a@b.com".to_vec(), b"cred".to_vec(), authenticator_data.to_vec(), client_data_json.to_vec(), signature.to_vec()));
+		assert_eq!(Identity::passkeys(&emailx)[0].sign_count, 1);
+
+		let tokenid: BoundedVec<u8, MaxTokenid> =
+			sp_io::hashing::blake2_128(&hex!("bfbf77f811781abdaf58a7673f45f01bee82940cacb110e0da2501fe46d2883e")).to_vec().try_into().unwrap();
+		assert_eq!(Identity::tokens(&tokenid).unwrap().owner, 10);
+		System::assert_last_event(Event::Identity(crate::Event::UserLoginsuccess { who: b"a@b.com".to_vec(), blocksession: tokenid.into_inner() }));
+
+		// The challenge has been consumed, so the same assertion can't be replayed.
+		assert_noop!(
+			Identity::login_passkey(Origin::signed(1), b"a@b.com".to_vec(), b"cred".to_vec(), authenticator_data.to_vec(), client_data_json.to_vec(), signature.to_vec()),
+			Error::<Test>::PasskeyVerificationFailed
+		);
+
+		assert_ok!(Identity::remove_passkey(Origin::signed(1), b"a@b.com".to_vec(), b"hello123".to_vec(), b"cred".to_vec()));
+		assert!(Identity::passkeys(&emailx).is_empty());
+	});
+}
+
+#[test]
+fn token_owner_and_access_follow_chain_state() {
+	new_test_ext().execute_with(|| {
+		register_and_link(10, b"a@b.com");
+		assert_eq!(Identity::token_owner(b"session1"), None);
+
+		assert_ok!(Identity::login_web3_sel16(Origin::signed(10), b"session1".to_vec()));
+		assert_eq!(Identity::token_owner(b"session1"), Some(10));
+
+		assert!(!Identity::has_access(&10, b"docsystem"));
+		assert_ok!(Identity::set_accessservice_sel17(Origin::signed(10), 10, b"docsystem".to_vec()));
+		assert!(Identity::has_access(&10, b"docsystem"));
+		assert!(!Identity::has_access(&20, b"docsystem"));
+	});
+}
+
+#[test]
+fn logout_revokes_the_login_token() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		register_and_link(10, b"a@b.com");
+		assert_ok!(Identity::login_web3_sel16(Origin::signed(10), b"session1".to_vec()));
+		assert!(Identity::introspect_token(b"session1").unwrap().active);
+
+		assert_ok!(Identity::logout_web3_sel19(Origin::signed(10)));
+		assert_eq!(Identity::introspect_token(b"session1"), None);
+		let tokenid: BoundedVec<u8, MaxTokenid> = b"session1".to_vec().try_into().unwrap();
+		assert!(!<TokensOf<Test>>::contains_key(10, &tokenid));
+
+		// Logging out again is harmless.
+		assert_ok!(Identity::logout_web3_sel19(Origin::signed(10)));
+	});
+}
+
+#[test]
+fn introspect_token_reports_validity() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(5);
+		register_and_link(10, b"a@b.com");
+		assert_eq!(Identity::introspect_token(b"session1"), None);
+
+		assert_ok!(Identity::login_web3_sel16(Origin::signed(10), b"session1".to_vec()));
+		assert_ok!(Identity::set_accessservice_sel17(Origin::signed(10), 10, b"wordpress".to_vec()));
+		assert_ok!(Identity::set_disclosure(
+			Origin::signed(10),
+			b"wordpress".to_vec(),
+			IdentityFields::new(IdentityField::Display | IdentityField::Email),
+		));
+
+		let status = Identity::introspect_token(b"session1").unwrap();
+		assert!(status.active);
+		assert_eq!(status.owner, 10);
+		assert_eq!(status.service, b"wordpress".to_vec());
+		assert!(status.scopes.contains(IdentityField::Email));
+		assert!(!status.scopes.contains(IdentityField::Legal));
+		assert_eq!((status.issued, status.expires), (5, 105));
+
+		System::set_block_number(105);
+		assert!(!Identity::introspect_token(b"session1").unwrap().active);
+		assert_eq!(Identity::token_owner(b"session1"), None);
+	});
+}
+
+#[test]
+fn siws_login_works() {
+	use hex_literal::hex;
+
+	// Signed with the sr25519 key of the mini secret `[7; 32]`, for the mock chain's genesis
+	// hash and SS58 prefix.
+	let message = siws::Message {
+		domain: b"id.selendra.org".to_vec(),
+		account: hex!("7c0f469d3bd340bae718203fa30ca071a5e37c751e891dbded837b213d45d91d"),
+		nonce: b"a1b2c3d4e5".to_vec(),
+		issued_at: 1_646_136_000,
+		expiration_time: 1_646_136_600,
+		genesis_hash: [69; 32],
+		services: vec![b"wordpress".to_vec()],
+	};
+	let signature = hex!("9cc1dbf61b9c0c146f830e14b984dc797a68edb7c240993a0df064563d6d626f5760777dfafc9185794b4dc332633dc8dce68ff401bf27ecb041973dc237048c");
+	// The first eight bytes of the public key, as the mock uses `u64` accounts.
+	let who = 13_420_959_142_559_223_676;
+
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		Now::set(1_646_136_100);
+		assert_noop!(
+			Identity::login_siws(Origin::signed(1), message.clone(), signature),
+			Error::<Test>::NoIdentity
+		);
+		register_and_link(who, b"a@b.com");
+
+		let mut tampered = signature;
+		tampered[0] ^= 1;
+		assert_noop!(
+			Identity::login_siws(Origin::signed(1), message.clone(), tampered),
+			Error::<Test>::InvalidSiwsSignature
+		);
+		assert_noop!(
+			Identity::login_siws(Origin::signed(1), siws::Message { genesis_hash: [1; 32], ..message.clone() }, signature),
+			Error::<Test>::InvalidSiwsMessage
+		);
+
+		assert!(!Identity::has_access(&who, b"wordpress"));
+		<SiwsNonces<Test>>::insert(who, [1; 32], 1);
+		<SiwsNonces<Test>>::insert(who, [2; 32], u64::MAX);
+		assert_ok!(Identity::login_siws(Origin::signed(1), message.clone(), signature));
+		assert!(Identity::has_access(&who, b"wordpress"));
+		// Nonces of expired messages are forgotten.
+		assert!(!<SiwsNonces<Test>>::contains_key(who, [1; 32]));
+		assert!(<SiwsNonces<Test>>::contains_key(who, [2; 32]));
+		let tokenid = sp_io::hashing::blake2_128(&(sp_io::hashing::blake2_256(&message.encode()), b"wordpress".to_vec()).encode());
+		assert_eq!(Identity::token_owner(&tokenid), Some(who));
+
+		// Nonces can't be reused.
+		assert_noop!(
+			Identity::login_siws(Origin::signed(1), message.clone(), signature),
+			Error::<Test>::SiwsNonceUsed
+		);
+
+		Now::set(1_646_136_600);
+		<SiwsNonces<Test>>::remove(who, sp_io::hashing::blake2_256(b"a1b2c3d4e5"));
+		assert_noop!(
+			Identity::login_siws(Origin::signed(1), message, signature),
+			Error::<Test>::SiwsMessageExpired
+		);
+	});
+}
+
+fn card(registrar: [u8; 32], valid_until: u64) -> attestation::Attestation {
+	attestation::Attestation {
+		student: [1; 32],
+		email_hash: sp_io::hashing::blake2_256(b"a@b.com"),
+		status: attestation::Status::Enrolled,
+		valid_until,
+		registrar,
+	}
+}
+
+#[test]
+fn registrars_set_attestation_keys() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_noop!(Identity::add_registrar(Origin::signed(1), 3), BadOrigin);
+		assert_ok!(Identity::add_registrar(Origin::root(), 3));
+		System::assert_last_event(Event::Identity(crate::Event::RegistrarAdded { registrar_index: 0 }));
+
+		assert_noop!(Identity::set_attestation_key(Origin::signed(2), 0, [7; 32]), Error::<Test>::InvalidIndex);
+		assert_noop!(Identity::set_attestation_key(Origin::signed(3), 1, [7; 32]), Error::<Test>::InvalidIndex);
+		assert_ok!(Identity::set_attestation_key(Origin::signed(3), 0, [7; 32]));
+		assert_eq!(Identity::registrars()[0].as_ref().unwrap().attestation_key, Some([7; 32]));
+
+		Now::set(1_000);
+		assert_eq!(Identity::attestation_snapshot(), attestation::Snapshot::new(vec![[7; 32]], vec![], 1_000));
+	});
+}
+
+#[test]
+fn registrars_revoke_attestations() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(Identity::add_registrar(Origin::root(), 3));
+		assert_ok!(Identity::add_registrar(Origin::root(), 4));
+		assert_ok!(Identity::set_attestation_key(Origin::signed(3), 0, [7; 32]));
+		let expiring = card([7; 32], 2_000);
+		let valid = card([7; 32], 3_000);
+
+		assert_noop!(Identity::revoke_attestation(Origin::signed(4), 0, valid.clone()), Error::<Test>::InvalidIndex);
+		assert_noop!(
+			Identity::revoke_attestation(Origin::signed(4), 1, valid.clone()),
+			Error::<Test>::NotAttestationKey
+		);
+		assert_noop!(
+			Identity::revoke_attestation(Origin::signed(3), 0, card([8; 32], 3_000)),
+			Error::<Test>::NotAttestationKey
+		);
+
+		assert_ok!(Identity::revoke_attestation(Origin::signed(3), 0, valid.clone()));
+		System::assert_last_event(Event::Identity(crate::Event::AttestationRevoked {
+			registrar_index: 0,
+			id: valid.id(),
+		}));
+		assert_noop!(
+			Identity::revoke_attestation(Origin::signed(3), 0, valid.clone()),
+			Error::<Test>::AttestationAlreadyRevoked
+		);
+		assert_ok!(Identity::revoke_attestation(Origin::signed(3), 0, expiring.clone()));
+
+		Now::set(1_000);
+		assert_eq!(Identity::attestation_snapshot().revoked.len(), 2);
+		Now::set(2_000);
+		assert_eq!(Identity::attestation_snapshot().revoked, vec![valid.id()]);
+	});
+}
+
+#[test]
+fn registrars_judge_identities() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(Identity::add_registrar(Origin::root(), 3));
+		assert_ok!(Identity::set_attestation_key(Origin::signed(3), 0, [7; 32]));
+		register_and_link(10, b"a@b.com");
+		let hash = Identity::identity_hash(&10).unwrap();
+
+		assert_noop!(
+			Identity::provide_judgement(Origin::signed(2), 0, 10, Judgement::KnownGood, hash),
+			Error::<Test>::InvalidIndex
+		);
+		assert_noop!(
+			Identity::provide_judgement(Origin::signed(3), 0, 20, Judgement::KnownGood, hash),
+			Error::<Test>::InvalidTarget
+		);
+		assert_noop!(
+			Identity::provide_judgement(Origin::signed(3), 0, 10, Judgement::KnownGood, [0; 32]),
+			Error::<Test>::JudgementForDifferentIdentity
+		);
+
+		assert_ok!(Identity::provide_judgement(Origin::signed(3), 0, 10, Judgement::KnownGood, hash));
+		System::assert_last_event(Event::Identity(crate::Event::JudgementGiven {
+			target: 10,
+			registrar_index: 0,
+		}));
+		let judged = Identity::judged_identity(&10, 0).unwrap();
+		assert_eq!(judged.registrar, 3);
+		assert_eq!(judged.attestation_key, Some([7; 32]));
+		assert_eq!(judged.judgement, Judgement::KnownGood);
+		assert_eq!(judged.info.passwordhash, Data::None);
+
+		// Changing the identity voids the judgement.
+		assert_ok!(Identity::set_fields(Origin::signed(10), vec![(raw(profile_keys::PROGRAM), raw(b"Physics"))]));
+		assert!(Identity::judged_identity(&10, 0).is_none());
+		let hash = Identity::identity_hash(&10).unwrap();
+		assert_ok!(Identity::provide_judgement(Origin::signed(3), 0, 10, Judgement::Reasonable, hash));
+		assert_eq!(Identity::judged_identity(&10, 0).unwrap().judgement, Judgement::Reasonable);
+
+		assert_noop!(Identity::revoke_judgement(Origin::signed(3), 0, 20), Error::<Test>::JudgementNotFound);
+		assert_ok!(Identity::revoke_judgement(Origin::signed(3), 0, 10));
+		System::assert_last_event(Event::Identity(crate::Event::JudgementRevoked {
+			target: 10,
+			registrar_index: 0,
+		}));
+		assert!(Identity::judged_identity(&10, 0).is_none());
+	});
+}
+
+#[test]
+fn sub_accounts_work() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_noop!(Identity::add_sub(Origin::signed(10), 1, raw(b"phone")), Error::<Test>::NoIdentity);
+		register_and_link(10, b"a@b.com");
+		register_and_link(20, b"c@d.com");
+
+		assert_ok!(Identity::add_sub(Origin::signed(10), 1, raw(b"phone")));
+		assert_ok!(Identity::add_sub(Origin::signed(10), 2, raw(b"laptop")));
+		assert_eq!(Balances::reserved_balance(10), 20);
+		assert_eq!(Identity::super_of(1), Some((10, raw(b"phone"))));
+		assert_noop!(Identity::add_sub(Origin::signed(20), 1, raw(b"phone")), Error::<Test>::AlreadyClaimed);
+		assert_noop!(Identity::add_sub(Origin::signed(10), 3, raw(b"tablet")), Error::<Test>::TooManySubAccounts);
+
+		assert_noop!(Identity::remove_sub(Origin::signed(20), 1), Error::<Test>::NotOwned);
+		assert_noop!(Identity::remove_sub(Origin::signed(10), 3), Error::<Test>::NotSub);
+		assert_ok!(Identity::remove_sub(Origin::signed(10), 1));
+		assert_eq!(Balances::reserved_balance(10), 10);
+		assert_eq!(Identity::super_of(1), None);
+
+		assert_ok!(Identity::quit_sub(Origin::signed(2)));
+		System::assert_last_event(Event::Identity(crate::Event::SubIdentityRevoked {
+			sub: 2,
+			main: 10,
+			deposit: 10,
+		}));
+		assert_eq!(Balances::reserved_balance(10), 0);
+		assert_eq!(Balances::free_balance(2), 20);
+		assert!(Identity::subs_of(10).1.is_empty());
+	});
+}
+
+#[test]
+fn did_details_follow_chain_state() {
+	new_test_ext().execute_with(|| {
+		assert_eq!(Identity::did_details(&10), None);
+		register_and_link(10, b"a@b.com");
+		assert_ok!(Identity::add_sub(Origin::signed(10), 1, raw(b"phone")));
+		assert_ok!(Identity::set_accessservice_sel17(Origin::signed(10), 10, b"wordpress".to_vec()));
+
+		assert_eq!(
+			Identity::did_details(&10),
+			Some(DidDetails {
+				controller: 10,
+				sub_accounts: vec![1],
+				eth_account: None,
+				services: vec![b"wordpress".to_vec()],
+			})
+		);
+
+		assert_ok!(Identity::clear_identity(Origin::signed(10)));
+		assert_eq!(Identity::did_details(&10), None);
+		assert_eq!(Identity::super_of(1), None);
+		assert_eq!(Balances::reserved_balance(10), 0);
+	});
+}
+
+#[test]
+fn students_claim_enrollment_with_roster_proofs() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(Identity::add_registrar(Origin::root(), 3));
+		let leaf = |email: &[u8], status| roster::Leaf {
+			email_hash: sp_io::hashing::blake2_256(email),
+			status,
+			term: 20221,
+		};
+		let leaves = vec![
+			leaf(b"a@b.com", attestation::Status::Enrolled),
+			leaf(b"c@d.com", attestation::Status::Enrolled),
+			leaf(b"e@f.com", attestation::Status::Suspended),
+		];
+		let root = roster::root(&leaves);
+		let proof = roster::proof(&leaves, 0).unwrap();
+
+		assert_noop!(
+			Identity::publish_roster(Origin::signed(10), 0, 20221, root, 100),
+			Error::<Test>::InvalidIndex
+		);
+		assert_noop!(
+			Identity::publish_roster(Origin::signed(3), 0, 20221, root, 1),
+			Error::<Test>::StatusAlreadyLapsed
+		);
+		// The roster lists the normalized email.
+		register_and_link(10, b"a@B.com.");
+		assert_noop!(
+			Identity::claim_enrollment(Origin::signed(10), 0, 20221, attestation::Status::Enrolled, proof.clone()),
+			Error::<Test>::RosterNotFound
+		);
+
+		assert_ok!(Identity::publish_roster(Origin::signed(3), 0, 20221, root, 100));
+		System::assert_last_event(Event::Identity(crate::Event::RosterPublished {
+			registrar_index: 0,
+			term: 20221,
+			root,
+			valid_until: 100,
+		}));
+
+		// The leaf is built from the sender's own email, so another student's proof or a
+		// different status is rejected.
+		assert_noop!(
+			Identity::claim_enrollment(Origin::signed(10), 0, 20221, attestation::Status::Staff, proof.clone()),
+			Error::<Test>::InvalidRosterProof
+		);
+		assert_noop!(
+			Identity::claim_enrollment(
+				Origin::signed(10),
+				0,
+				20221,
+				attestation::Status::Enrolled,
+				roster::proof(&leaves, 1).unwrap()
+			),
+			Error::<Test>::InvalidRosterProof
+		);
+		assert_noop!(
+			Identity::claim_enrollment(Origin::signed(20), 0, 20221, attestation::Status::Enrolled, proof.clone()),
+			Error::<Test>::NoIdentity
+		);
+
+		assert_ok!(Identity::claim_enrollment(
+			Origin::signed(10),
+			0,
+			20221,
+			attestation::Status::Enrolled,
+			proof
+		));
+		System::assert_last_event(Event::Identity(crate::Event::EnrollmentClaimed {
+			who: 10,
+			registrar_index: 0,
+			term: 20221,
+			status: attestation::Status::Enrolled,
+		}));
+		assert_eq!(Identity::enrollment(&10, 0), Some(leaves[0].clone()));
+		assert_eq!(
+			Identity::student_state(&10),
+			Some(StudentState {
+				status: StudentStatus::Enrolled,
+				term: 20221,
+				valid_until: 100,
+				registrar: 0,
+			})
+		);
+
+		assert_ok!(Identity::clear_identity(Origin::signed(10)));
+		assert_eq!(Identity::enrollment(&10, 0), None);
 
-        // This is not correct. Need to fix it
-		assert_eq!(tokeninfo2.data,   Data::Raw(b"allowed".to_vec().try_into().unwrap()) );
+		System::set_block_number(100);
+		register_and_link(10, b"a@b.com");
+		assert_noop!(
+			Identity::claim_enrollment(Origin::signed(10), 0, 20221, attestation::Status::Enrolled, proof),
+			Error::<Test>::StatusAlreadyLapsed
+		);
+	});
+}
+
+#[test]
+fn roster_claims_do_not_override_newer_statuses() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(Identity::add_registrar(Origin::root(), 3));
+		let leaves = vec![roster::Leaf {
+			email_hash: sp_io::hashing::blake2_256(b"a@b.com"),
+			status: attestation::Status::Enrolled,
+			term: 20221,
+		}];
+		let proof = roster::proof(&leaves, 0).unwrap();
+		assert_ok!(Identity::publish_roster(Origin::signed(3), 0, 20221, roster::root(&leaves), 100));
+		register_and_link(10, b"a@b.com");
+
+		// A suspension for the same term stands.
+		assert_ok!(Identity::set_student_status(Origin::signed(3), 0, 10, StudentStatus::Suspended, 20221, 50));
+		assert_ok!(Identity::claim_enrollment(
+			Origin::signed(10),
+			0,
+			20221,
+			attestation::Status::Enrolled,
+			proof.clone()
+		));
+		assert_eq!(Identity::student_state(&10).unwrap().status, StudentStatus::Suspended);
+		assert_eq!(Identity::enrollment(&10, 0), Some(leaves[0].clone()));
+
+		// A status of an earlier term is replaced.
+		assert_ok!(Identity::set_student_status(Origin::signed(3), 0, 10, StudentStatus::Suspended, 20212, 50));
+		assert_ok!(Identity::claim_enrollment(Origin::signed(10), 0, 20221, attestation::Status::Enrolled, proof));
+		assert_eq!(
+			Identity::student_state(&10),
+			Some(StudentState {
+				status: StudentStatus::Enrolled,
+				term: 20221,
+				valid_until: 100,
+				registrar: 0,
+			})
+		);
+	});
+}
+
+#[test]
+fn email_domains_are_normalized() {
+	assert_eq!(email_domain(b"Alice@RUPP.edu.KH."), Some(b"rupp.edu.kh".to_vec()));
+	assert_eq!(email_domain(b"\"a@b\"@c.com"), Some(b"c.com".to_vec()));
+	assert_eq!(email_domain(b"nobody"), None);
+}
+
+#[test]
+fn registrations_are_keyed_by_normalized_emails() {
+	new_test_ext().execute_with(|| {
+		let emailx: BoundedVec<u8, MaxEmailsize> = b"Dara@rupp.edu.kh".to_vec().try_into().unwrap();
+		register_and_link(10, b"Dara@RUPP.edu.KH.");
+		assert_eq!(<StudentidOf<Test>>::get(&emailx).map(|id| id.accountId), Some(10));
+		assert_eq!(<EmailId<Test>>::get(10), Some(emailx));
+
+		// The same email spelled differently is already claimed.
+		assert_noop!(
+			Identity::request_registration_sel11(
+				Origin::signed(20),
+				b"Dara@rupp.edu.kh".to_vec(),
+				b"hello123".to_vec()
+			),
+			Error::<Test>::IdentityAlreadyClaimed
+		);
+		assert_ok!(Identity::login_access_sel12(
+			Origin::signed(10),
+			b"Dara@rupp.edu.kh.".to_vec(),
+			b"hello123".to_vec()
+		));
+	});
+}
+
+#[test]
+fn institutions_own_email_domains() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_noop!(Identity::add_institution(Origin::signed(1), raw(b"RUPP"), 10, Data::None), BadOrigin);
+		assert_ok!(Identity::add_institution(Origin::root(), raw(b"RUPP"), 10, Data::None));
+		System::assert_last_event(Event::Identity(crate::Event::InstitutionAdded { institution: 0 }));
+		assert_ok!(Identity::add_institution(Origin::root(), raw(b"ITC"), 20, Data::None));
 
+		assert_noop!(Identity::add_domain(Origin::root(), 2, b"rupp.edu.kh".to_vec()), Error::<Test>::InvalidInstitution);
+		assert_noop!(Identity::add_domain(Origin::root(), 0, b".".to_vec()), Error::<Test>::InvalidDomain);
+		assert_ok!(Identity::add_domain(Origin::root(), 0, b"RUPP.edu.kh".to_vec()));
+		System::assert_last_event(Event::Identity(crate::Event::DomainAdded {
+			institution: 0,
+			domain: b"rupp.edu.kh".to_vec(),
+		}));
+		assert_noop!(
+			Identity::add_domain(Origin::root(), 1, b"rupp.edu.kh".to_vec()),
+			Error::<Test>::DomainAlreadyOwned
+		);
+		assert_ok!(Identity::add_domain(Origin::root(), 0, b"a.kh".to_vec()));
+		assert_noop!(Identity::add_domain(Origin::root(), 0, b"b.kh".to_vec()), Error::<Test>::TooManyDomains);
 
+		// Registration is routed by domain; other domains are unverified.
+		assert_ok!(Identity::request_registration_sel11(
+			Origin::signed(1),
+			b"sok@rupp.edu.kh".to_vec(),
+			b"hello123".to_vec()
+		));
+		System::assert_last_event(Event::Identity(crate::Event::StudentRouted {
+			who: b"sok@rupp.edu.kh".to_vec(),
+			institution: 0,
+		}));
+		register_and_link(10, b"dara@rupp.edu.kh");
+		register_and_link(20, b"a@b.com");
+		assert_eq!(Identity::institution_of(&10), Some(0));
+		assert_eq!(Identity::institution_of(&20), None);
 
+		assert_ok!(Identity::set_institution(Origin::root(), 0, raw(b"RUPP"), 30, raw(b"meta")));
+		assert_eq!(Identity::institution(0).unwrap().admin, 30);
+
+		assert_noop!(
+			Identity::remove_domain(Origin::root(), 1, b"rupp.edu.kh".to_vec()),
+			Error::<Test>::DomainNotOwned
+		);
+		assert_ok!(Identity::remove_domain(Origin::root(), 0, b"rupp.edu.kh".to_vec()));
+		assert_eq!(Identity::institution_of(&10), None);
+		assert_eq!(Identity::institution(0).unwrap().domains.into_inner(), vec![b"a.kh".to_vec()]);
 	});
 }
 
+#[test]
+fn institution_registrars_only_act_for_its_students() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(Identity::add_registrar(Origin::root(), 3));
+		assert_ok!(Identity::add_institution(Origin::root(), raw(b"RUPP"), 10, Data::None));
+		assert_ok!(Identity::add_domain(Origin::root(), 0, b"rupp.edu.kh".to_vec()));
+		register_and_link(10, b"dara@rupp.edu.kh");
+		register_and_link(20, b"a@b.com");
+
+		assert_noop!(
+			Identity::add_institution_registrar(Origin::root(), 0, 1),
+			Error::<Test>::InvalidIndex
+		);
+		assert_ok!(Identity::add_institution_registrar(Origin::root(), 0, 0));
+		assert_eq!(Identity::institution(0).unwrap().registrars.into_inner(), vec![0]);
+		assert_eq!(Identity::registrar_index_of(&3), Some(0));
+		assert_eq!(Identity::registrar_index_of(&10), None);
+		assert_noop!(
+			Identity::add_institution_registrar(Origin::root(), 0, 0),
+			Error::<Test>::RegistrarOfOtherInstitution
+		);
+
+		let hash = Identity::identity_hash(&20).unwrap();
+		assert_noop!(
+			Identity::provide_judgement(Origin::signed(3), 0, 20, Judgement::KnownGood, hash),
+			Error::<Test>::NotInstitutionStudent
+		);
+		let hash = Identity::identity_hash(&10).unwrap();
+		assert_ok!(Identity::provide_judgement(Origin::signed(3), 0, 10, Judgement::KnownGood, hash));
+
+		assert_ok!(Identity::remove_institution_registrar(Origin::root(), 0, 0));
+		assert!(Identity::acts_for(0, &20));
+	});
+}
+
+#[test]
+fn student_status_transitions() {
+	use StudentStatus::*;
+	assert!(Enrolled.can_become(OnLeave));
+	assert!(OnLeave.can_become(Enrolled));
+	assert!(Suspended.can_become(Suspended));
+	assert!(Graduated.can_become(Enrolled));
+	assert!(!Graduated.can_become(Suspended));
+	assert!(!Expired.can_become(OnLeave));
+	assert!(!Suspended.can_become(Graduated));
+	assert!(Unverified.can_become(Enrolled));
+	assert!(!Unverified.can_become(Expired));
+	assert!(!Enrolled.can_become(Unverified));
+	assert!(!Unverified.can_become(Unverified));
+	assert!(OnLeave.grants_access());
+	assert!(!Graduated.grants_access());
+	assert!(!Unverified.grants_access());
+	assert!(Unverified.may_log_in());
+	assert!(!Suspended.may_log_in());
+}
+
+#[test]
+fn student_status_gates_access() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(Identity::add_registrar(Origin::root(), 3));
+		register_and_link(10, b"a@b.com");
+		assert_ok!(Identity::set_accessservice_sel17(Origin::signed(10), 10, b"svc".to_vec()));
+		assert_eq!(Identity::student_state(&10), None);
+
+		assert_noop!(
+			Identity::set_student_status(Origin::signed(3), 0, 20, StudentStatus::Enrolled, 20221, 10),
+			Error::<Test>::InvalidTarget
+		);
+		assert_ok!(Identity::set_student_status(Origin::signed(3), 0, 10, StudentStatus::Suspended, 20221, 10));
+		System::assert_last_event(Event::Identity(crate::Event::StudentStatusChanged {
+			who: 10,
+			status: StudentStatus::Suspended,
+			term: 20221,
+			valid_until: 10,
+		}));
+
+		assert_noop!(
+			Identity::check_web3access_sel18(Origin::signed(10), b"svc".to_vec()),
+			Error::<Test>::StudentInactive
+		);
+		assert_noop!(
+			Identity::login_access_sel12(Origin::signed(10), b"a@b.com".to_vec(), b"hello123".to_vec()),
+			Error::<Test>::StudentInactive
+		);
+		assert_noop!(
+			Identity::login_web3_sel16(Origin::signed(10), b"challenge".to_vec()),
+			Error::<Test>::StudentInactive
+		);
+		assert_noop!(
+			Identity::register_passkey(
+				Origin::signed(10),
+				b"a@b.com".to_vec(),
+				b"hello123".to_vec(),
+				b"cred".to_vec(),
+				crate::webauthn::tests::COSE_KEY.to_vec(),
+			),
+			Error::<Test>::StudentInactive
+		);
+		assert!(!Identity::has_access(&10, b"svc"));
+		assert_noop!(
+			Identity::set_student_status(Origin::signed(3), 0, 10, StudentStatus::Graduated, 20221, 10),
+			Error::<Test>::InvalidStatusTransition
+		);
+
+		assert_ok!(Identity::set_student_status(Origin::signed(3), 0, 10, StudentStatus::Enrolled, 20221, 10));
+		assert_ok!(Identity::check_web3access_sel18(Origin::signed(10), b"svc".to_vec()));
+		assert_ok!(Identity::login_web3_sel16(Origin::signed(10), b"challenge".to_vec()));
+		assert!(Identity::introspect_token(b"challenge").unwrap().active);
+
+		// The status lapses at `valid_until`.
+		System::set_block_number(10);
+		assert_eq!(Identity::student_state(&10).unwrap().status, StudentStatus::Expired);
+		assert!(!Identity::has_access(&10, b"svc"));
+		assert!(!Identity::introspect_token(b"challenge").unwrap().active);
+
+		// Clearing the identity keeps the status with the email.
+		assert_ok!(Identity::clear_identity(Origin::signed(10)));
+		register_and_link(10, b"a@b.com");
+		assert_eq!(Identity::student_state(&10).unwrap().status, StudentStatus::Expired);
+	});
+}
+
+#[test]
+fn students_without_status_stay_active() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(Identity::add_registrar(Origin::root(), 3));
+		register_and_link(10, b"a@b.com");
+		assert_ok!(Identity::set_accessservice_sel17(Origin::signed(10), 10, b"svc".to_vec()));
+
+		// Statuses are opt-in: no registrar manages the student, however long they stay.
+		System::set_block_number(1_000);
+		assert_eq!(Identity::student_state(&10), None);
+		assert!(Identity::has_access(&10, b"svc"));
+		assert_ok!(Identity::login_web3_sel16(Origin::signed(10), b"challenge".to_vec()));
+		assert!(Identity::introspect_token(b"challenge").unwrap().active);
+
+		// A registrar cannot set a status that already lapsed.
+		assert_noop!(
+			Identity::set_student_status(Origin::signed(3), 0, 10, StudentStatus::Enrolled, 20221, 1_000),
+			Error::<Test>::StatusAlreadyLapsed
+		);
+		assert_noop!(
+			Identity::renew_students(Origin::signed(3), 0, vec![10], 20221, 999),
+			Error::<Test>::StatusAlreadyLapsed
+		);
+		assert_ok!(Identity::renew_students(Origin::signed(3), 0, vec![10], 20221, 1_001));
+		assert_eq!(Identity::student_state(&10).unwrap().status, StudentStatus::Enrolled);
+	});
+}
+
+#[test]
+fn registrars_renew_students_in_bulk() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(Identity::add_registrar(Origin::root(), 3));
+		register_and_link(10, b"a@b.com");
+		register_and_link(20, b"c@d.com");
+		register_and_link(30, b"e@f.com");
+		assert_ok!(Identity::set_student_status(Origin::signed(3), 0, 10, StudentStatus::Enrolled, 20221, 5));
+		assert_ok!(Identity::set_student_status(Origin::signed(3), 0, 20, StudentStatus::Suspended, 20221, 50));
+
+		assert_noop!(
+			Identity::renew_students(Origin::signed(3), 0, vec![10, 20, 30, 1], 20222, 100),
+			Error::<Test>::TooManyRenewals
+		);
+		assert_noop!(
+			Identity::renew_students(Origin::signed(2), 0, vec![10], 20222, 100),
+			Error::<Test>::InvalidIndex
+		);
+
+		System::set_block_number(5);
+		assert_ok!(Identity::renew_students(Origin::signed(3), 0, vec![10, 20, 1], 20222, 100));
+		System::assert_has_event(Event::Identity(crate::Event::StudentStatusChanged {
+			who: 10,
+			status: StudentStatus::Enrolled,
+			term: 20222,
+			valid_until: 100,
+		}));
+		System::assert_has_event(Event::Identity(crate::Event::RenewalSkipped { who: 20 }));
+		System::assert_last_event(Event::Identity(crate::Event::RenewalSkipped { who: 1 }));
+
+		assert_eq!(
+			Identity::student_state(&10),
+			Some(StudentState {
+				status: StudentStatus::Enrolled,
+				term: 20222,
+				valid_until: 100,
+				registrar: 0,
+			})
+		);
+		assert_eq!(Identity::student_state(&20).unwrap().status, StudentStatus::Suspended);
+		assert_eq!(Identity::student_state(&30), None);
+	});
+}
+
+#[test]
+fn registrars_issue_soulbound_credentials() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(Identity::add_registrar(Origin::root(), 3));
+		assert_ok!(Identity::add_registrar(Origin::root(), 4));
+		register_and_link(10, b"a@b.com");
+		Now::set(1_000);
+
+		assert_noop!(
+			Identity::issue_credential(Origin::signed(3), 0, 20, CredentialKind::Diploma, raw(b"BSc CS")),
+			Error::<Test>::InvalidTarget
+		);
+		assert_ok!(Identity::issue_credential(Origin::signed(3), 0, 10, CredentialKind::Diploma, raw(b"BSc CS")));
+		System::assert_last_event(Event::Identity(crate::Event::CredentialIssued {
+			owner: 10,
+			index: 0,
+			kind: CredentialKind::Diploma,
+			registrar_index: 0,
+		}));
+		assert_ok!(Identity::issue_credential(Origin::signed(4), 1, 10, CredentialKind::Badge, raw(b"Hackathon")));
+
+		let diploma = IssuedCredential {
+			owner: 10,
+			kind: CredentialKind::Diploma,
+			issuer: 0,
+			metadata: raw(b"BSc CS"),
+			issued: 1_000,
+			revoked: None,
+		};
+		assert_eq!(Identity::credentials_of(&10).len(), 2);
+		assert_eq!(Identity::credential(0), Some(diploma.clone()));
+		assert!(Identity::credentials_of(&20).is_empty());
+
+		assert_noop!(Identity::revoke_credential(Origin::signed(4), 1, 0), Error::<Test>::NotCredentialIssuer);
+		assert_noop!(Identity::revoke_credential(Origin::signed(3), 0, 2), Error::<Test>::InvalidCredential);
+		Now::set(2_000);
+		assert_ok!(Identity::revoke_credential(Origin::signed(3), 0, 0));
+		System::assert_last_event(Event::Identity(crate::Event::CredentialRevoked { owner: 10, index: 0 }));
+		assert_noop!(Identity::revoke_credential(Origin::signed(3), 0, 0), Error::<Test>::CredentialAlreadyRevoked);
+		assert_eq!(Identity::credential(0), Some(IssuedCredential { revoked: Some(2_000), ..diploma }));
+
+		// Credentials stay with the account even when the identity is cleared.
+		assert_ok!(Identity::clear_identity(Origin::signed(10)));
+		assert_eq!(Identity::credentials_of(&10).len(), 2);
+	});
+}
+
+#[test]
+fn other_pallets_see_active_students() {
+	use frame_support::traits::EnsureOrigin;
+	type Provider = Pallet<Test>;
+
+	new_test_ext().execute_with(|| {
+		assert_ok!(Identity::add_registrar(Origin::root(), 3));
+		assert_ok!(Identity::add_institution(Origin::root(), raw(b"RUPP"), 10, Data::None));
+		assert_ok!(Identity::add_domain(Origin::root(), 0, b"rupp.edu.kh".to_vec()));
+		register_and_link(10, b"dara@rupp.edu.kh");
+		register_and_link(20, b"a@b.com");
+
+		// Students no registrar vouched for are not active.
+		assert_eq!(
+			<Provider as StudentIdentityProvider<u64>>::status_of(&10),
+			Some(StudentStatus::Unverified)
+		);
+		assert_eq!(<Provider as StudentIdentityProvider<u64>>::status_of(&30), None);
+		assert!(!<Provider as StudentIdentityProvider<u64>>::is_active_student(&10));
+		assert!(EnsureStudent::<Test>::ensure_origin(Origin::signed(10)).is_err());
+
+		// Nor are students of unowned domains, or those vouched for by registrars that do not
+		// act for their institution.
+		assert_ok!(Identity::set_student_status(Origin::signed(3), 0, 10, StudentStatus::Enrolled, 20221, 10));
+		assert_ok!(Identity::set_student_status(Origin::signed(3), 0, 20, StudentStatus::Enrolled, 20221, 10));
+		assert!(!<Provider as StudentIdentityProvider<u64>>::is_active_student(&10));
+		assert!(!<Provider as StudentIdentityProvider<u64>>::is_active_student(&20));
+
+		assert_ok!(Identity::add_institution_registrar(Origin::root(), 0, 0));
+		assert_ok!(Identity::set_student_status(Origin::signed(3), 0, 10, StudentStatus::Enrolled, 20221, 10));
+		assert!(<Provider as StudentIdentityProvider<u64>>::is_active_student(&10));
+		assert!(!<Provider as StudentIdentityProvider<u64>>::is_active_student(&20));
+		assert_eq!(<Provider as StudentIdentityProvider<u64>>::institution_of(&10), Some(0));
+		assert_eq!(EnsureStudent::<Test>::ensure_origin(Origin::signed(10)), Ok(10));
+		assert!(EnsureStudent::<Test>::ensure_origin(Origin::signed(20)).is_err());
+		assert!(EnsureStudent::<Test>::ensure_origin(Origin::root()).is_err());
+
+		assert_ok!(Identity::set_student_status(Origin::signed(3), 0, 10, StudentStatus::Suspended, 20221, 10));
+		assert_eq!(
+			<Provider as StudentIdentityProvider<u64>>::status_of(&10),
+			Some(StudentStatus::Suspended)
+		);
+		assert!(!<Provider as StudentIdentityProvider<u64>>::is_active_student(&10));
+		assert!(EnsureStudent::<Test>::ensure_origin(Origin::signed(10)).is_err());
+
+		assert_eq!(EnsureRegistrar::<Test>::ensure_origin(Origin::signed(3)), Ok(0));
+		assert!(EnsureRegistrar::<Test>::ensure_origin(Origin::signed(10)).is_err());
+	});
+}
+
+#[test]
+fn onboarding_calls_are_feeless_within_limits() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(Identity::add_institution(Origin::root(), raw(b"RUPP"), 10, Data::None));
+		assert_ok!(Identity::add_domain(Origin::root(), 0, b"rupp.edu.kh".to_vec()));
+
+		let register = |email: &[u8]| crate::Call::<Test>::request_registration_sel11 {
+			email: email.to_vec(),
+			password: b"hello123".to_vec(),
+		};
+		let referal = crate::Call::<Test>::set_referal_sel12 {
+			email: b"dara@rupp.edu.kh".to_vec(),
+			referal: b"referABCD".to_vec(),
+		};
+
+		// Only registrations of unclaimed emails of an institution's domain are free.
+		assert!(Identity::is_feeless(&5, &register(b"dara@rupp.edu.kh")));
+		assert!(!Identity::is_feeless(&5, &register(b"a@b.com")));
+		assert!(!Identity::is_feeless(&5, &referal));
+		assert!(!Identity::is_feeless(&5, &crate::Call::<Test>::clear_identity {}));
+
+		assert_ok!(Identity::request_registration_sel11(
+			Origin::signed(5),
+			b"dara@rupp.edu.kh".to_vec(),
+			b"hello123".to_vec()
+		));
+		assert!(!Identity::is_feeless(&6, &register(b"dara@rupp.edu.kh")));
+		assert!(Identity::is_feeless(&5, &referal));
+		assert!(!Identity::is_feeless(&6, &referal));
+		assert_eq!(Identity::feeless_calls_made(&5), (0, 0));
+
+		Identity::note_feeless_call(&5);
+		assert_eq!(Identity::feeless_calls_made(&5), (0, 1));
+		assert!(Identity::is_feeless(&5, &referal));
+		Identity::note_feeless_call(&5);
+		assert_eq!(Identity::feeless_calls_left(&5), 0);
+		assert!(!Identity::is_feeless(&5, &referal));
+		assert_eq!(Identity::feeless_period_left(), 9);
+
+		// The allowance comes back in the next period.
+		System::set_block_number(10);
+		assert_eq!(Identity::feeless_calls_made(&5), (1, 0));
+		assert_eq!(Identity::feeless_calls_left(&5), 2);
+		assert_eq!(Identity::feeless_period_left(), 10);
+		assert!(Identity::is_feeless(&5, &referal));
+
+		// Calls reserving deposits are not free.
+		register_and_link(10, b"sok@rupp.edu.kh");
+		let fields = crate::Call::<Test>::set_fields { fields: vec![] };
+		assert!(!Identity::is_feeless(&10, &fields));
+	});
+}
+
+#[test]
+fn registrars_discard_unlinked_registrations() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		let emailx: BoundedVec<u8, MaxEmailsize> = b"dara@rupp.edu.kh".to_vec().try_into().unwrap();
+		assert_ok!(Identity::add_registrar(Origin::root(), 3));
+		assert_ok!(Identity::add_registrar(Origin::root(), 4));
+		assert_ok!(Identity::add_institution(Origin::root(), raw(b"RUPP"), 10, Data::None));
+		assert_ok!(Identity::add_institution(Origin::root(), raw(b"ITC"), 10, Data::None));
+		assert_ok!(Identity::add_domain(Origin::root(), 0, b"rupp.edu.kh".to_vec()));
+		assert_ok!(Identity::add_institution_registrar(Origin::root(), 0, 0));
+		assert_ok!(Identity::add_institution_registrar(Origin::root(), 1, 1));
+		assert_ok!(Identity::request_registration_sel11(
+			Origin::signed(5),
+			b"dara@rupp.edu.kh".to_vec(),
+			b"hello123".to_vec()
+		));
+		register_and_link(20, b"sok@rupp.edu.kh");
+
+		assert_noop!(
+			Identity::discard_registration(Origin::signed(5), 0, b"dara@rupp.edu.kh".to_vec()),
+			Error::<Test>::InvalidIndex
+		);
+		assert_noop!(
+			Identity::discard_registration(Origin::signed(4), 1, b"dara@rupp.edu.kh".to_vec()),
+			Error::<Test>::NotInstitutionStudent
+		);
+		assert_noop!(
+			Identity::discard_registration(Origin::signed(3), 0, b"sok@rupp.edu.kh".to_vec()),
+			Error::<Test>::IdentityLinked
+		);
+
+		assert_ok!(Identity::discard_registration(Origin::signed(3), 0, b"dara@rupp.edu.kh".to_vec()));
+		assert!(<StudentidOf<Test>>::get(&emailx).is_none());
+		System::assert_last_event(Event::Identity(crate::Event::IdentityCleared {
+			who: 5,
+			deposit: 0,
+		}));
+		assert_ok!(Identity::request_registration_sel11(
+			Origin::signed(6),
+			b"dara@rupp.edu.kh".to_vec(),
+			b"hello123".to_vec()
+		));
+	});
+}
+
+#[test]
+fn institutions_sponsor_student_fees() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(Identity::add_institution(Origin::root(), raw(b"RUPP"), 20, Data::None));
+		assert_ok!(Identity::add_domain(Origin::root(), 0, b"rupp.edu.kh".to_vec()));
+		register_and_link(10, b"dara@rupp.edu.kh");
+		register_and_link(30, b"a@b.com");
+		let pot = Identity::sponsor_pot(0);
+		assert_ok!(Identity::add_registrar(Origin::root(), 3));
+		assert_ok!(Identity::set_student_status(Origin::signed(3), 0, 30, StudentStatus::Enrolled, 20221, 100));
+
+		assert_noop!(Identity::fund_sponsor_pot(Origin::signed(20), 1, 50), Error::<Test>::InvalidInstitution);
+		assert_ok!(Identity::fund_sponsor_pot(Origin::signed(20), 0, 50));
+		System::assert_last_event(Event::Identity(crate::Event::SponsorPotFunded {
+			institution: 0,
+			who: 20,
+			amount: 50,
+		}));
+		assert_eq!(Balances::free_balance(pot), 50);
+		assert_eq!(Identity::sponsor_of(&10, 1), None);
+
+		assert_noop!(
+			Identity::set_sponsorship(Origin::signed(10), 0, 5, 8, 10),
+			Error::<Test>::NotInstitutionAdmin
+		);
+		assert_ok!(Identity::set_sponsorship(Origin::signed(20), 0, 5, 8, 10));
+		// Students no registrar of the institution vouched for are not sponsored.
+		assert_eq!(Identity::sponsor_of(&10, 5), None);
+		assert_ok!(Identity::set_student_status(Origin::signed(3), 0, 10, StudentStatus::Enrolled, 20221, 100));
+		assert_eq!(Identity::sponsor_of(&10, 5), None);
+		assert_ok!(Identity::add_institution_registrar(Origin::root(), 0, 0));
+		assert_ok!(Identity::set_student_status(Origin::signed(3), 0, 10, StudentStatus::Enrolled, 20221, 100));
+		assert_eq!(Identity::sponsor_of(&10, 5), Some((0, pot)));
+		assert_eq!(Identity::sponsor_of(&10, 6), None);
+		// Students of unowned domains are not sponsored.
+		assert_eq!(Identity::sponsor_of(&30, 1), None);
+
+		// Only the calls managing a student's identity and access are sponsored.
+		assert!(Identity::is_sponsored(&crate::Call::<Test>::logout_web3_sel19 {}));
+		assert!(Identity::is_sponsored(&crate::Call::<Test>::remove_field { key: raw(b"key") }));
+		assert!(!Identity::is_sponsored(&crate::Call::<Test>::clear_identity {}));
+		assert!(!Identity::is_sponsored(&crate::Call::<Test>::fund_sponsor_pot {
+			institution: 0,
+			amount: 1
+		}));
+		assert!(!Identity::is_sponsored(&crate::Call::<Test>::note_blob { data: vec![1] }));
+
+		Identity::note_sponsored_fee(0, &10, 4);
+		assert_eq!(Identity::sponsor_of(&10, 1), Some((0, pot)));
+		assert_eq!(Identity::sponsor_of(&10, 2), None);
+
+		// The limits start over every period.
+		System::set_block_number(10);
+		assert_eq!(Identity::sponsor_of(&10, 5), Some((0, pot)));
+
+		assert_ok!(Identity::revoke_sponsorship(Origin::signed(20), 0));
+		assert_eq!(Identity::sponsor_of(&10, 1), None);
+		assert_noop!(Identity::revoke_sponsorship(Origin::signed(20), 0), Error::<Test>::NoSponsorship);
+
+		assert_ok!(Identity::withdraw_sponsor_pot(Origin::signed(20), 0, 50));
+		assert_eq!(Balances::free_balance(pot), 0);
+		assert_eq!(Balances::free_balance(20), 100);
+	});
+}
+
+#[test]
+fn v1_migration_gives_tokens_an_expiry() {
+	use frame_support::traits::{GetStorageVersion, StorageVersion};
+
+	new_test_ext().execute_with(|| {
+		System::set_block_number(50);
+		let tokenid: BoundedVec<u8, MaxTokenid> = b"session1".to_vec().try_into().unwrap();
+		let metadata: BoundedVec<u8, MaxAccessTokenMetadata> = b"wordpress".to_vec().try_into().unwrap();
+		let old = (metadata.clone(), 10u64, raw(b"allowed"));
+		frame_support::storage::unhashed::put_raw(&<Tokens<Test>>::hashed_key_for(&tokenid), &old.encode());
+		StorageVersion::new(0).put::<Identity>();
+
+		crate::migrations::v1::migrate::<Test>();
+
+		assert_eq!(Identity::on_chain_storage_version(), 1);
+		let token = <Tokens<Test>>::get(&tokenid).unwrap();
+		assert_eq!((token.metadata, token.owner, token.data), (metadata, 10, raw(b"allowed")));
+		assert_eq!((token.issued, token.expires), (50, 150));
+		assert!(<TokensOf<Test>>::contains_key(10, &tokenid));
+
+		// Running it again does nothing.
+		System::set_block_number(60);
+		crate::migrations::v1::migrate::<Test>();
+		assert_eq!(<Tokens<Test>>::get(&tokenid).unwrap().expires, 150);
+	});
+}
+
+#[test]
+fn v2_migration_strips_placeholder_fields() {
+	use frame_support::traits::{GetStorageVersion, StorageVersion};
+
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		let emailx: BoundedVec<u8, MaxEmailsize> = b"a@b.com".to_vec().try_into().unwrap();
+		register_and_link(10, b"a@b.com");
+		<StudentidOf<Test>>::mutate(&emailx, |id| {
+			let id = id.as_mut().unwrap();
+			id.info.additional = vec![
+				(raw(b"number"), Data::Raw(10u32.encode().try_into().unwrap())),
+				(raw(b"text"), raw(b"10")),
+			]
+			.try_into()
+			.unwrap();
+		});
+		StorageVersion::new(1).put::<Identity>();
+
+		crate::migrations::v2::migrate::<Test>();
+
+		assert_eq!(Identity::on_chain_storage_version(), 2);
+		assert!(<StudentidOf<Test>>::get(&emailx).unwrap().info.additional.is_empty());
+
+		// Both fields can now be set for their deposit.
+		assert_ok!(Identity::set_fields(
+			Origin::signed(10),
+			vec![(raw(profile_keys::FACULTY), raw(b"Arts")), (raw(profile_keys::PROGRAM), raw(b"Physics"))],
+		));
+		assert_eq!(Balances::reserved_balance(10), 20);
+	});
+}
@@ -0,0 +1,173 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2019-2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! What other pallets can ask about students, and origins gating calls to students and
+//! registrars.
+
+use super::*;
+use frame_support::traits::EnsureOrigin;
+use sp_std::marker::PhantomData;
+
+/// Answers whether an account is a student, and where they study.
+pub trait StudentIdentityProvider<AccountId> {
+	/// Whether `who` is an active student of an institution that vouches for them: a registrar
+	/// acting for the institution set their status, or they claimed it from its roster. The
+	/// domain of their email alone does not make them one.
+	fn is_active_student(who: &AccountId) -> bool;
+
+	/// The institution `who` belongs to through their email.
+	fn institution_of(who: &AccountId) -> Option<InstitutionIndex>;
+
+	/// The current status of `who`, `Expired` once it ran past its validity and `Unverified` if
+	/// no status was set. `None` if `who` has no identity.
+	fn status_of(who: &AccountId) -> Option<StudentStatus>;
+
+	/// The index of the registrar whose account is `who`.
+	fn registrar_index_of(who: &AccountId) -> Option<RegistrarIndex>;
+}
+
+/// Nobody is a student or a registrar.
+impl<AccountId> StudentIdentityProvider<AccountId> for () {
+	fn is_active_student(_: &AccountId) -> bool {
+		false
+	}
+
+	fn institution_of(_: &AccountId) -> Option<InstitutionIndex> {
+		None
+	}
+
+	fn status_of(_: &AccountId) -> Option<StudentStatus> {
+		None
+	}
+
+	fn registrar_index_of(_: &AccountId) -> Option<RegistrarIndex> {
+		None
+	}
+}
+
+impl<T: Config> StudentIdentityProvider<T::AccountId> for Pallet<T> {
+	fn is_active_student(who: &T::AccountId) -> bool {
+		Self::vouching_institution_of(who).is_some()
+	}
+
+	fn institution_of(who: &T::AccountId) -> Option<InstitutionIndex> {
+		Self::institution_of(who)
+	}
+
+	fn status_of(who: &T::AccountId) -> Option<StudentStatus> {
+		Self::student_status(who)
+	}
+
+	fn registrar_index_of(who: &T::AccountId) -> Option<RegistrarIndex> {
+		Self::registrar_index_of(who)
+	}
+}
+
+/// Ensures the origin is signed by an active student, see
+/// [`StudentIdentityProvider::is_active_student`]. Succeeds with the student's account.
+pub struct EnsureStudent<T>(PhantomData<T>);
+
+impl<T: Config> EnsureOrigin<T::Origin> for EnsureStudent<T> {
+	type Success = T::AccountId;
+
+	fn try_origin(o: T::Origin) -> Result<Self::Success, T::Origin> {
+		o.into().and_then(|o| match o {
+			frame_system::RawOrigin::Signed(who)
+				if <Pallet<T> as StudentIdentityProvider<_>>::is_active_student(&who) =>
+				Ok(who),
+			r => Err(T::Origin::from(r)),
+		})
+	}
+
+	#[cfg(feature = "runtime-benchmarks")]
+	fn successful_origin() -> T::Origin {
+		let who: T::AccountId = frame_benchmarking::account("student", 0, 0);
+		make_active_student::<T>(&who);
+		T::Origin::from(frame_system::RawOrigin::Signed(who))
+	}
+}
+
+/// Ensures the origin is signed by the account of a registrar. Succeeds with the registrar's
+/// index.
+pub struct EnsureRegistrar<T>(PhantomData<T>);
+
+impl<T: Config> EnsureOrigin<T::Origin> for EnsureRegistrar<T> {
+	type Success = RegistrarIndex;
+
+	fn try_origin(o: T::Origin) -> Result<Self::Success, T::Origin> {
+		o.into().and_then(|o| match o {
+			frame_system::RawOrigin::Signed(who) => match Pallet::<T>::registrar_index_of(&who) {
+				Some(index) => Ok(index),
+				None => Err(T::Origin::from(frame_system::RawOrigin::Signed(who))),
+			},
+			r => Err(T::Origin::from(r)),
+		})
+	}
+
+	#[cfg(feature = "runtime-benchmarks")]
+	fn successful_origin() -> T::Origin {
+		let who: T::AccountId = frame_benchmarking::account("registrar", 0, 0);
+		make_registrar::<T>(&who);
+		T::Origin::from(frame_system::RawOrigin::Signed(who))
+	}
+}
+
+/// Register `who` as a registrar, unless it is one, and return its index.
+#[cfg(feature = "runtime-benchmarks")]
+fn make_registrar<T: Config>(who: &T::AccountId) -> RegistrarIndex {
+	if let Some(index) = Pallet::<T>::registrar_index_of(who) {
+		return index
+	}
+	<Registrars<T>>::mutate(|registrars| {
+		registrars
+			.try_push(Some(RegistrarInfo { account: who.clone(), attestation_key: None }))
+			.expect("benchmarks leave room for another registrar; qed");
+		(registrars.len() - 1) as RegistrarIndex
+	})
+}
+
+/// Make `who` an enrolled student of a new institution, vouched for by its registrar.
+#[cfg(feature = "runtime-benchmarks")]
+fn make_active_student<T: Config>(who: &T::AccountId) {
+	use sp_runtime::traits::Bounded;
+
+	if Pallet::<T>::vouching_institution_of(who).is_some() {
+		return
+	}
+	let registrar = make_registrar::<T>(&frame_benchmarking::account("registrar", 0, 0));
+	let institution = <InstitutionCount<T>>::mutate(|count| {
+		*count += 1;
+		*count - 1
+	});
+	let domain: Email<T> =
+		b"benchmark.edu".to_vec().try_into().expect("the domain fits an email; qed");
+	let email: Email<T> =
+		b"student@benchmark.edu".to_vec().try_into().expect("emails hold 21 bytes; qed");
+
+	<DomainOwner<T>>::insert(&domain, institution);
+	<InstitutionOfRegistrar<T>>::insert(registrar, institution);
+	<EmailId<T>>::insert(who, &email);
+	<StatusOf<T>>::insert(
+		&email,
+		StudentState {
+			status: StudentStatus::Enrolled,
+			term: 0,
+			valid_until: T::BlockNumber::max_value(),
+			registrar,
+		},
+	);
+}
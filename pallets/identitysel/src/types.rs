@@ -19,6 +19,8 @@ use sp_std::{fmt::Debug, iter::once, ops::Add, prelude::*};
 /// Either underlying data blob if it is at most 32 bytes, or a hash of it. If the data is greater
 /// than 32-bytes then it will be truncated when encoding.
 ///
+/// Longer values can be kept in the pallet's blob store and referenced as `BlakeTwo256`.
+///
 /// Can also be `None`.
 #[derive(Clone, Eq, PartialEq, RuntimeDebug, MaxEncodedLen)]
 pub enum Data {
@@ -167,6 +169,229 @@ impl Default for Data {
 /// An identifier for a single name registrar/identity verification service.
 pub type RegistrarIndex = u32;
 
+/// An attestation of a registrar over how accurate some `IdentityInfoSel` is in describing an
+/// account.
+#[derive(Copy, Clone, Encode, Decode, Eq, MaxEncodedLen, PartialEq, RuntimeDebug, TypeInfo)]
+pub enum Judgement {
+	/// The registrar checked the data against the institution's records and found it correct.
+	KnownGood,
+	/// The data appears reasonable, but no in-depth checks were performed.
+	Reasonable,
+	/// The data was once good but is currently out of date.
+	OutOfDate,
+	/// The data is imprecise or of sufficiently low quality to be problematic.
+	LowQuality,
+	/// The data is erroneous.
+	Erroneous,
+}
+
+impl Judgement {
+	/// Returns `true` if this judgement is a positive statement about the identity.
+	pub fn is_positive(&self) -> bool {
+		matches!(self, Judgement::KnownGood | Judgement::Reasonable)
+	}
+}
+
+/// Information concerning a registrar.
+#[derive(Clone, Encode, Decode, Eq, MaxEncodedLen, PartialEq, RuntimeDebug, TypeInfo)]
+pub struct RegistrarInfo<AccountId: Encode + Decode + Clone + Debug + Eq + PartialEq> {
+	/// The account of the registrar.
+	pub account: AccountId,
+	/// The sr25519 key the registrar signs ID card attestations with. Attestations signed with
+	/// a previous key stop verifying when it is changed.
+	pub attestation_key: Option<[u8; 32]>,
+}
+
+/// Where a student is in their studies, as recorded by a registrar.
+#[derive(Copy, Clone, Encode, Decode, Eq, MaxEncodedLen, PartialEq, RuntimeDebug, TypeInfo)]
+pub enum StudentStatus {
+	/// The student is enrolled.
+	Enrolled,
+	/// The student interrupted their studies and is expected back.
+	OnLeave,
+	/// The institution suspended the student.
+	Suspended,
+	/// The student graduated.
+	Graduated,
+	/// The status ran past its validity without being renewed.
+	Expired,
+	/// No registrar vouched for the student: they registered themselves and have no status.
+	/// Never stored; it is the status of every student without one.
+	Unverified,
+}
+
+impl StudentStatus {
+	/// Whether a registrar may move a student from this status to `next`. A status can always
+	/// be kept, to change its validity, but no student can be made `Unverified`.
+	pub fn can_become(&self, next: StudentStatus) -> bool {
+		use StudentStatus::*;
+		next != Unverified &&
+			(*self == next ||
+				matches!(
+					(self, next),
+					(Enrolled, OnLeave | Suspended | Graduated | Expired) |
+						(OnLeave, Enrolled | Suspended | Expired) |
+						(Suspended, Enrolled | Expired) |
+						(Graduated, Enrolled) |
+						(Expired, Enrolled) |
+						(Unverified, Enrolled | OnLeave | Suspended | Graduated)
+				))
+	}
+
+	/// Whether a student with this status is an active student: a registrar vouched for them
+	/// and they may log in and use services.
+	pub fn grants_access(&self) -> bool {
+		matches!(self, StudentStatus::Enrolled | StudentStatus::OnLeave)
+	}
+
+	/// Whether a student with this status may log in and use services. Unverified students
+	/// may, as every student could before statuses existed, but they are not active students.
+	pub fn may_log_in(&self) -> bool {
+		self.grants_access() || *self == StudentStatus::Unverified
+	}
+
+	/// The status a roster leaf with `status` stands for, `None` for staff, who are not
+	/// students.
+	pub fn from_roster(status: roster::Status) -> Option<Self> {
+		match status {
+			roster::Status::Enrolled => Some(StudentStatus::Enrolled),
+			roster::Status::Suspended => Some(StudentStatus::Suspended),
+			roster::Status::Alumni => Some(StudentStatus::Graduated),
+			roster::Status::Staff => None,
+		}
+	}
+}
+
+/// The status of a student and how long it holds.
+#[derive(Clone, Encode, Decode, Eq, MaxEncodedLen, PartialEq, RuntimeDebug, TypeInfo)]
+pub struct StudentState<BlockNumber> {
+	/// The status the registrar set.
+	pub status: StudentStatus,
+	/// The academic term the status was set for.
+	pub term: roster::Term,
+	/// The first block the status no longer holds at; the student is `Expired` from then on.
+	pub valid_until: BlockNumber,
+	/// The registrar that set the status, or whose roster the student claimed it from.
+	pub registrar: RegistrarIndex,
+}
+
+impl<BlockNumber: PartialOrd> StudentState<BlockNumber> {
+	/// The status at block `now`.
+	pub fn status_at(&self, now: &BlockNumber) -> StudentStatus {
+		if *now < self.valid_until {
+			self.status
+		} else {
+			StudentStatus::Expired
+		}
+	}
+}
+
+/// An enrollment roster a registrar published for a term.
+#[derive(Clone, Encode, Decode, Eq, MaxEncodedLen, PartialEq, RuntimeDebug, TypeInfo)]
+pub struct Roster<BlockNumber> {
+	/// The Merkle root over the leaves of the roster, see [`roster::root`].
+	pub root: [u8; 32],
+	/// The first block the statuses claimed from the roster no longer hold at.
+	pub valid_until: BlockNumber,
+}
+
+/// An identifier for a credential issued to a student.
+pub type CredentialIndex = u64;
+
+/// What a credential attests.
+#[derive(Copy, Clone, Encode, Decode, Eq, MaxEncodedLen, PartialEq, RuntimeDebug, TypeInfo)]
+pub enum CredentialKind {
+	/// A degree the student was awarded.
+	Diploma,
+	/// The completion of a course.
+	Certificate,
+	/// Participation in an event.
+	Badge,
+}
+
+/// A credential a registrar issued to a student. It is bound to the student's account and
+/// cannot be transferred.
+#[derive(Clone, Encode, Decode, Eq, MaxEncodedLen, PartialEq, RuntimeDebug, TypeInfo)]
+pub struct IssuedCredential<AccountId> {
+	/// The account the credential was issued to.
+	pub owner: AccountId,
+	/// What the credential attests.
+	pub kind: CredentialKind,
+	/// The registrar that issued the credential.
+	pub issuer: RegistrarIndex,
+	/// What the credential is for, e.g. the degree or course, usually a `BlakeTwo256` blob.
+	pub metadata: Data,
+	/// When the credential was issued, in seconds since the Unix epoch.
+	pub issued: u64,
+	/// When the issuer revoked the credential, in seconds since the Unix epoch.
+	pub revoked: Option<u64>,
+}
+
+/// How much of the transaction fees of its students an institution pays.
+#[derive(Clone, Encode, Decode, Eq, MaxEncodedLen, PartialEq, RuntimeDebug, TypeInfo)]
+pub struct SponsorshipTerms<Balance, BlockNumber> {
+	/// The most fees paid for a single student per period.
+	pub per_student: Balance,
+	/// The most fees paid for all students together per period.
+	pub per_period: Balance,
+	/// The length of a period in blocks.
+	pub period: BlockNumber,
+}
+
+/// An identifier for an institution.
+pub type InstitutionIndex = u32;
+
+/// A university or other institution, owning the email domains of its students.
+#[derive(
+	CloneNoBound, Encode, Decode, Eq, MaxEncodedLen, PartialEqNoBound, RuntimeDebugNoBound, TypeInfo,
+)]
+#[codec(mel_bound(
+	AccountId: Encode + Decode + MaxEncodedLen + Clone + Debug + Eq + PartialEq,
+	DomainLimit: Get<u32>,
+	MaxDomains: Get<u32>,
+	MaxRegistrars: Get<u32>,
+))]
+#[scale_info(skip_type_params(DomainLimit, MaxDomains, MaxRegistrars))]
+pub struct Institution<
+	AccountId: Encode + Decode + Clone + Debug + Eq + PartialEq,
+	DomainLimit: Get<u32>,
+	MaxDomains: Get<u32>,
+	MaxRegistrars: Get<u32>,
+> {
+	/// The name of the institution.
+	pub name: Data,
+	/// The account administering the institution.
+	pub admin: AccountId,
+	/// The normalized email domains the institution owns, see [`normalize_domain`].
+	pub domains: BoundedVec<BoundedVec<u8, DomainLimit>, MaxDomains>,
+	/// The registrars acting for the institution.
+	pub registrars: BoundedVec<RegistrarIndex, MaxRegistrars>,
+	/// Further information on the institution, usually a `BlakeTwo256` blob.
+	pub metadata: Data,
+}
+
+/// The domain part of `email` in ASCII lowercase and without a trailing dot, or `None` if
+/// `email` has no `@`.
+pub fn email_domain(email: &[u8]) -> Option<Vec<u8>> {
+	let at = email.iter().rposition(|b| *b == b'@')?;
+	Some(normalize_domain(&email[at + 1..]))
+}
+
+/// `email` with its domain normalized, see [`normalize_domain`]. The local part is kept as it
+/// is; `email` without an `@` is returned unchanged.
+pub fn normalize_email(email: &[u8]) -> Vec<u8> {
+	match email.iter().rposition(|b| *b == b'@') {
+		Some(at) => [&email[..=at], &normalize_domain(&email[at + 1..])[..]].concat(),
+		None => email.to_vec(),
+	}
+}
+
+/// `domain` in ASCII lowercase and without a trailing dot.
+pub fn normalize_domain(domain: &[u8]) -> Vec<u8> {
+	let domain = domain.strip_suffix(b".").unwrap_or(domain);
+	domain.to_ascii_lowercase()
+}
+
 
 /// The fields that we use to identify the owner of an account with. Each corresponds to a field
 /// in the `IdentityInfo` struct.
@@ -181,6 +406,8 @@ pub enum IdentityField {
 	PgpFingerprint = 0b0000000000000000000000000000000000000000000000000000000000100000,
 	Image = 0b0000000000000000000000000000000000000000000000000000000001000000,
 	Twitter = 0b0000000000000000000000000000000000000000000000000000000010000000,
+	Profile = 0b0000000000000000000000000000000000000000000000000000000100000000,
+	Account = 0b0000000000000000000000000000000000000000000000000000001000000000,
 }
 
 /// Wrapper type for `BitFlags<IdentityField>` that implements `Codec`.
@@ -193,6 +420,18 @@ impl MaxEncodedLen for IdentityFields {
 	}
 }
 
+impl IdentityFields {
+	/// Wrap a set of identity fields.
+	pub fn new(fields: BitFlags<IdentityField>) -> Self {
+		Self(fields)
+	}
+
+	/// Whether `field` is part of the set.
+	pub fn contains(&self, field: IdentityField) -> bool {
+		self.0.contains(field)
+	}
+}
+
 impl Eq for IdentityFields {}
 impl Encode for IdentityFields {
 	fn using_encoded<R, F: FnOnce(&[u8]) -> R>(&self, f: F) -> R {
@@ -274,6 +513,76 @@ pub struct IdentityInfoSel<FieldLimit: Get<u32>> {
 
 
 
+impl<FieldLimit: Get<u32>> IdentityInfoSel<FieldLimit> {
+	/// The value of the additional field stored under the raw `key`, if any.
+	pub fn field(&self, key: &[u8]) -> Option<&Data> {
+		self.additional
+			.iter()
+			.find(|(k, _)| matches!(k, Data::Raw(raw) if &raw[..] == key))
+			.map(|(_, value)| value)
+	}
+
+	/// The identity without credentials and session state (password hash, referal hash and
+	/// access token). This is what registrars judge.
+	pub fn judged(&self) -> Self {
+		Self {
+			referalhash: Data::None,
+			accesstoken: Data::None,
+			passwordhash: Data::None,
+			..self.clone()
+		}
+	}
+
+	/// The identity restricted to `fields`.
+	///
+	/// Fields outside of `fields` are omitted: display name, legal name, web, email and the
+	/// account become `Data::None`, the additional profile fields and the PGP fingerprint are
+	/// dropped. Nothing derived from a withheld value is shown, as a hash of a short value such
+	/// as an email can be reversed by guessing. Credentials and session state (password hash,
+	/// referal hash and access token) are never disclosed, whatever `fields` says.
+	pub fn disclose(&self, fields: IdentityFields) -> Self {
+		let pick =
+			|field, data: &Data| if fields.contains(field) { data.clone() } else { Data::None };
+
+		let additional = if fields.contains(IdentityField::Profile) {
+			self.additional.clone()
+		} else {
+			Default::default()
+		};
+
+		Self {
+			additional,
+			display: pick(IdentityField::Display, &self.display),
+			legal: pick(IdentityField::Legal, &self.legal),
+			web: pick(IdentityField::Web, &self.web),
+			referalhash: Data::None,
+			email: pick(IdentityField::Email, &self.email),
+			pgp_fingerprint: if fields.contains(IdentityField::PgpFingerprint) {
+				self.pgp_fingerprint
+			} else {
+				None
+			},
+			account: pick(IdentityField::Account, &self.account),
+			accesstoken: Data::None,
+			passwordhash: Data::None,
+		}
+	}
+}
+
+/// Keys of the standard student profile fields kept in `IdentityInfoSel::additional`, stored as
+/// `Data::Raw`. Apps should read and write profile data under these keys so that it is
+/// interpreted the same way everywhere.
+pub mod profile_keys {
+	/// The student number assigned by the institution. Stored as UTF-8.
+	pub const STUDENT_NUMBER: &[u8] = b"student_number";
+	/// The faculty or school the student belongs to. Stored as UTF-8.
+	pub const FACULTY: &[u8] = b"faculty";
+	/// The study program the student is enrolled in. Stored as UTF-8.
+	pub const PROGRAM: &[u8] = b"program";
+	/// The year of first enrollment, e.g. `2022`. Stored as UTF-8 digits.
+	pub const ENROLLMENT_YEAR: &[u8] = b"enrollment_year";
+}
+
 /// NOTE: This is stored separately primarily to facilitate the addition of extra fields in a
 /// backwards compatible way through a specialized `Decode` impl.
 #[derive(
@@ -0,0 +1,367 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2019-2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Verification of WebAuthn (passkey) assertions made with P-256 credentials.
+//!
+//! The client data is checked with the limited verification algorithm of the WebAuthn
+//! specification, which only needs a prefix comparison instead of a JSON parser.
+
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use sp_io::hashing::sha2_256;
+use sp_std::prelude::*;
+
+/// Set in the authenticator data flags when the user was present.
+const FLAG_USER_PRESENT: u8 = 0x01;
+
+/// Length of the fixed part of the authenticator data: RP ID hash, flags and signature counter.
+const AUTHENTICATOR_DATA_MIN_LEN: usize = 37;
+
+/// Why an assertion was rejected.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AssertionError {
+	/// The client data is not a `webauthn.get` for the expected challenge and origin.
+	ClientData,
+	/// The authenticator data is malformed, for another relying party, or the user was not
+	/// present.
+	AuthenticatorData,
+	/// The signature counter did not increase since the last assertion.
+	Replayed,
+	/// The public key or signature is malformed, or the signature does not verify.
+	Signature,
+}
+
+/// The uncompressed SEC1 encoding of the P-256 public key in a COSE_Key, as returned in the
+/// attested credential data when a passkey is created. Only EC2 keys on P-256 for ES256 are
+/// accepted.
+pub fn parse_cose_p256(cose: &[u8]) -> Option<[u8; 65]> {
+	const KTY: i64 = 1;
+	const ALG: i64 = 3;
+	const CRV: i64 = -1;
+	const X: i64 = -2;
+	const Y: i64 = -3;
+
+	let mut cbor = Cbor(cose);
+	let (major, entries) = cbor.head()?;
+	if major != 5 {
+		return None
+	}
+
+	let (mut kty, mut alg, mut crv, mut x, mut y) = (None, None, None, None, None);
+	for _ in 0..entries {
+		match cbor.int()? {
+			KTY => kty = Some(cbor.int()?),
+			ALG => alg = Some(cbor.int()?),
+			CRV => crv = Some(cbor.int()?),
+			X => x = Some(cbor.bytes()?),
+			Y => y = Some(cbor.bytes()?),
+			_ => cbor.skip()?,
+		}
+	}
+
+	// EC2 key type, ES256 algorithm, P-256 curve.
+	if kty != Some(2) || alg != Some(-7) || crv != Some(1) || !cbor.0.is_empty() {
+		return None
+	}
+	let (x, y) = (x?, y?);
+	if x.len() != 32 || y.len() != 32 {
+		return None
+	}
+
+	let mut key = [0u8; 65];
+	key[0] = 0x04;
+	key[1..33].copy_from_slice(x);
+	key[33..].copy_from_slice(y);
+	VerifyingKey::from_sec1_bytes(&key).ok()?;
+	Some(key)
+}
+
+/// Verify a WebAuthn assertion and return the authenticator's new signature counter.
+///
+/// `public_key` is the uncompressed SEC1 P-256 key of the credential and `sign_count` the
+/// counter returned by the previous successful assertion. `signature` is the DER encoded ECDSA
+/// signature returned by the authenticator.
+pub fn verify_assertion(
+	public_key: &[u8; 65],
+	rp_id: &[u8],
+	origin: &[u8],
+	challenge: &[u8],
+	sign_count: u32,
+	authenticator_data: &[u8],
+	client_data_json: &[u8],
+	signature: &[u8],
+) -> Result<u32, AssertionError> {
+	check_client_data(client_data_json, challenge, origin)?;
+
+	if authenticator_data.len() < AUTHENTICATOR_DATA_MIN_LEN ||
+		authenticator_data[..32] != sha2_256(rp_id) ||
+		authenticator_data[32] & FLAG_USER_PRESENT == 0
+	{
+		return Err(AssertionError::AuthenticatorData)
+	}
+
+	let mut counter = [0u8; 4];
+	counter.copy_from_slice(&authenticator_data[33..37]);
+	let new_sign_count = u32::from_be_bytes(counter);
+	// Authenticators without a counter always report zero.
+	if (new_sign_count != 0 || sign_count != 0) && new_sign_count <= sign_count {
+		return Err(AssertionError::Replayed)
+	}
+
+	let key = VerifyingKey::from_sec1_bytes(public_key).map_err(|_| AssertionError::Signature)?;
+	let signature = Signature::from_der(signature).map_err(|_| AssertionError::Signature)?;
+
+	let mut message = authenticator_data.to_vec();
+	message.extend_from_slice(&sha2_256(client_data_json));
+	key.verify(&message, &signature).map_err(|_| AssertionError::Signature)?;
+
+	Ok(new_sign_count)
+}
+
+/// Check the client data with the limited verification algorithm: it must start with the
+/// `type`, `challenge` and `origin` members in that order, and not be a cross-origin request.
+fn check_client_data(
+	client_data_json: &[u8],
+	challenge: &[u8],
+	origin: &[u8],
+) -> Result<(), AssertionError> {
+	let mut expected = b"{\"type\":\"webauthn.get\",\"challenge\":\"".to_vec();
+	expected.extend(base64url(challenge));
+	expected.extend_from_slice(b"\",\"origin\":\"");
+	expected.extend_from_slice(origin);
+	expected.push(b'"');
+
+	let rest = client_data_json
+		.strip_prefix(&expected[..])
+		.ok_or(AssertionError::ClientData)?;
+	if rest.starts_with(b",\"crossOrigin\":true") || !(rest.starts_with(b",") || rest == b"}") {
+		return Err(AssertionError::ClientData)
+	}
+
+	Ok(())
+}
+
+/// Unpadded base64url encoding of `data`, as used for the challenge in the client data.
+pub fn base64url(data: &[u8]) -> Vec<u8> {
+	const ALPHABET: &[u8; 64] =
+		b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+	let mut out = Vec::with_capacity((data.len() * 4 + 2) / 3);
+	for chunk in data.chunks(3) {
+		let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+		let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+		for i in 0..=chunk.len() {
+			out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize]);
+		}
+	}
+	out
+}
+
+/// A reader for the small subset of CBOR used by COSE keys: integers and byte and text strings.
+struct Cbor<'a>(&'a [u8]);
+
+impl<'a> Cbor<'a> {
+	fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+		if self.0.len() < n {
+			return None
+		}
+		let (head, tail) = self.0.split_at(n);
+		self.0 = tail;
+		Some(head)
+	}
+
+	/// The major type and argument of the next data item.
+	fn head(&mut self) -> Option<(u8, u64)> {
+		let initial = self.take(1)?[0];
+		let argument = match initial & 0x1f {
+			n @ 0..=23 => n as u64,
+			24 => self.take(1)?[0] as u64,
+			25 => self.take(2)?.iter().fold(0, |acc, b| acc << 8 | *b as u64),
+			26 => self.take(4)?.iter().fold(0, |acc, b| acc << 8 | *b as u64),
+			27 => self.take(8)?.iter().fold(0, |acc, b| acc << 8 | *b as u64),
+			_ => return None,
+		};
+		Some((initial >> 5, argument))
+	}
+
+	fn int(&mut self) -> Option<i64> {
+		match self.head()? {
+			(0, n) if n <= i64::MAX as u64 => Some(n as i64),
+			(1, n) if n <= i64::MAX as u64 => Some(-1 - n as i64),
+			_ => None,
+		}
+	}
+
+	fn bytes(&mut self) -> Option<&'a [u8]> {
+		match self.head()? {
+			(2, len) => self.take(len as usize),
+			_ => None,
+		}
+	}
+
+	fn skip(&mut self) -> Option<()> {
+		match self.head()? {
+			(0, _) | (1, _) => Some(()),
+			(2, len) | (3, len) => self.take(len as usize).map(|_| ()),
+			_ => None,
+		}
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+	use hex_literal::hex;
+
+	// Vectors produced with a software P-256 authenticator for the relying party
+	// `id.selendra.org`.
+	pub const RP_ID: &[u8] = b"id.selendra.org";
+	pub const ORIGIN: &[u8] = b"https://id.selendra.org";
+	pub const COSE_KEY: [u8; 77] = hex!("a50102032620012158204ff290eee0998e472d9252281345f0d92cb96cc2f89c010968280f4f137deb7e225820c1a993ea29dc3867d91565acc0d292a57a2fed29e3c35f4335a0c9fdb762d0ee");
+	pub const PUBLIC_KEY: [u8; 65] = hex!("044ff290eee0998e472d9252281345f0d92cb96cc2f89c010968280f4f137deb7ec1a993ea29dc3867d91565acc0d292a57a2fed29e3c35f4335a0c9fdb762d0ee");
+
+	const CHALLENGE: [u8; 32] = hex!("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+	const AUTH_DATA: [u8; 37] = hex!("f2d8223b71e9754e444a4314a4d920698221505195be3d225b7a5bf4a3576ed90500000001");
+	const CLIENT_DATA: &[u8] = br#"{"type":"webauthn.get","challenge":"AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8","origin":"https://id.selendra.org","crossOrigin":false}"#;
+	const SIGNATURE: [u8; 70] = hex!("304402203114af1705f25c57d278657bb9a5b152ebd7ea3c31bd32573bca552c6a3f52150220343374156a6dd6cf2bd35ed996184344feca06b9bae534d269c8f7571a4556f5");
+
+	// The user presence flag is not set.
+	const NO_UP_AUTH_DATA: [u8; 37] = hex!("f2d8223b71e9754e444a4314a4d920698221505195be3d225b7a5bf4a3576ed90400000002");
+	const NO_UP_SIGNATURE: [u8; 71] = hex!("30450221009bee885e6fad128dc5539901d651d1a9e51f91acd5470b00db55de74ec3b351a022015ed583c4d7c2286d06572c5d65ba3d86eef7a097f9d8b3c566417e715749232");
+
+	// A cross-origin request.
+	const CROSS_ORIGIN_AUTH_DATA: [u8; 37] = hex!("f2d8223b71e9754e444a4314a4d920698221505195be3d225b7a5bf4a3576ed90500000003");
+	const CROSS_ORIGIN_CLIENT_DATA: &[u8] = br#"{"type":"webauthn.get","challenge":"AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8","origin":"https://id.selendra.org","crossOrigin":true}"#;
+	const CROSS_ORIGIN_SIGNATURE: [u8; 72] = hex!("30460221008c640190aff51ac813b3ac0702dfe963bea759856b2105df31c81214716e81d902210097da1483bc023f775b0aa2d2d36df3d4d3425e45f346605de5a7ca751c0d1b56");
+
+	fn verify(
+		challenge: &[u8],
+		sign_count: u32,
+		auth_data: &[u8],
+		client_data: &[u8],
+		signature: &[u8],
+	) -> Result<u32, AssertionError> {
+		verify_assertion(
+			&PUBLIC_KEY,
+			RP_ID,
+			ORIGIN,
+			challenge,
+			sign_count,
+			auth_data,
+			client_data,
+			signature,
+		)
+	}
+
+	#[test]
+	fn parses_cose_p256_keys() {
+		assert_eq!(parse_cose_p256(&COSE_KEY), Some(PUBLIC_KEY));
+
+		// Trailing data, truncated data and other algorithms are rejected.
+		let mut trailing = COSE_KEY.to_vec();
+		trailing.push(0);
+		assert_eq!(parse_cose_p256(&trailing), None);
+		assert_eq!(parse_cose_p256(&COSE_KEY[..70]), None);
+		let mut rs256 = COSE_KEY.to_vec();
+		rs256[4] = 0x39;
+		assert_eq!(parse_cose_p256(&rs256), None);
+
+		// A point that is not on the curve.
+		let mut off_curve = COSE_KEY.to_vec();
+		off_curve[76] ^= 1;
+		assert_eq!(parse_cose_p256(&off_curve), None);
+	}
+
+	#[test]
+	fn encodes_base64url() {
+		assert_eq!(base64url(b""), b"".to_vec());
+		assert_eq!(base64url(b"f"), b"Zg".to_vec());
+		assert_eq!(base64url(b"fo"), b"Zm8".to_vec());
+		assert_eq!(base64url(b"foo"), b"Zm9v".to_vec());
+		assert_eq!(base64url(b"foob"), b"Zm9vYg".to_vec());
+		assert_eq!(base64url(&[0xfb, 0xff]), b"-_8".to_vec());
+	}
+
+	#[test]
+	fn accepts_valid_assertion() {
+		assert_eq!(verify(&CHALLENGE, 0, &AUTH_DATA, CLIENT_DATA, &SIGNATURE), Ok(1));
+	}
+
+	#[test]
+	fn rejects_invalid_assertions() {
+		let mut other_challenge = CHALLENGE;
+		other_challenge[0] = 1;
+		assert_eq!(
+			verify(&other_challenge, 0, &AUTH_DATA, CLIENT_DATA, &SIGNATURE),
+			Err(AssertionError::ClientData)
+		);
+		assert_eq!(
+			verify_assertion(
+				&PUBLIC_KEY,
+				RP_ID,
+				b"https://evil.example",
+				&CHALLENGE,
+				0,
+				&AUTH_DATA,
+				CLIENT_DATA,
+				&SIGNATURE
+			),
+			Err(AssertionError::ClientData)
+		);
+		assert_eq!(
+			verify_assertion(
+				&PUBLIC_KEY,
+				b"evil.example",
+				ORIGIN,
+				&CHALLENGE,
+				0,
+				&AUTH_DATA,
+				CLIENT_DATA,
+				&SIGNATURE
+			),
+			Err(AssertionError::AuthenticatorData)
+		);
+		assert_eq!(
+			verify(&CHALLENGE, 1, &AUTH_DATA, CLIENT_DATA, &SIGNATURE),
+			Err(AssertionError::Replayed)
+		);
+		assert_eq!(
+			verify(&CHALLENGE, 0, &NO_UP_AUTH_DATA, CLIENT_DATA, &NO_UP_SIGNATURE),
+			Err(AssertionError::AuthenticatorData)
+		);
+		assert_eq!(
+			verify(
+				&CHALLENGE,
+				0,
+				&CROSS_ORIGIN_AUTH_DATA,
+				CROSS_ORIGIN_CLIENT_DATA,
+				&CROSS_ORIGIN_SIGNATURE
+			),
+			Err(AssertionError::ClientData)
+		);
+
+		let mut tampered = AUTH_DATA;
+		tampered[36] = 9;
+		assert_eq!(
+			verify(&CHALLENGE, 0, &tampered, CLIENT_DATA, &SIGNATURE),
+			Err(AssertionError::Signature)
+		);
+		assert_eq!(
+			verify(&CHALLENGE, 0, &AUTH_DATA, CLIENT_DATA, &SIGNATURE[..60]),
+			Err(AssertionError::Signature)
+		);
+	}
+}
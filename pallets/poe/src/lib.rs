@@ -4,8 +4,43 @@
 // Re-export pallet items so that they can be accessed from the crate namespace.
 pub use pallet::*;
 
+use frame_support::dispatch::Parameter;
+
+#[cfg(test)]
+mod mock;
+
+#[cfg(test)]
+mod tests;
+
+/// Who may anchor proofs on behalf of whom, so claims can be attributed to an issuer and a
+/// subject without this pallet depending on an identity pallet.
+pub trait Attribution<AccountId> {
+	/// The institution or registrar a claim is attributed to.
+	type Issuer: Parameter;
+
+	/// The issuer `who` anchors claims as, if `who` may attribute claims at all.
+	fn issuer_of(who: &AccountId) -> Option<Self::Issuer>;
+
+	/// Whether `issuer` may attribute claims to `subject`.
+	fn may_attribute(issuer: &Self::Issuer, subject: &AccountId) -> bool;
+}
+
+/// No attribution: nobody can create attributed claims.
+impl<AccountId> Attribution<AccountId> for () {
+	type Issuer = ();
+
+	fn issuer_of(_: &AccountId) -> Option<()> {
+		None
+	}
+
+	fn may_attribute(_: &(), _: &AccountId) -> bool {
+		false
+	}
+}
+
 #[frame_support::pallet]
 pub mod pallet {
+	use super::Attribution;
 	use frame_support::{pallet_prelude::*, storage::bounded_vec::BoundedVec};
 	use frame_system::pallet_prelude::*;
 
@@ -21,8 +56,14 @@ pub mod pallet {
 		type Event: From<Event<Self>> + IsType<<Self as frame_system::Config>::Event>;
 		/// For constraining the maximum bytes of a hash used for any proof
 		type MaxBytesInHash: Get<u32>;
+		/// Who may attribute claims to which subjects.
+		type Attribution: Attribution<Self::AccountId>;
 	}
 
+	/// The issuer claims are attributed to.
+	pub type IssuerOf<T> =
+		<<T as Config>::Attribution as Attribution<<T as frame_system::Config>::AccountId>>::Issuer;
+
 	// Pallets use events to inform users when important changes are made.
 	// Event documentation should end with an array that provides descriptive names for parameters.
 	// https://docs.substrate.io/v3/runtime/events-and-errors
@@ -33,6 +74,9 @@ pub mod pallet {
 		ClaimCreated(T::AccountId, BoundedVec<u8, T::MaxBytesInHash>),
 		/// Event emitted when a claim is revoked by the owner. [who, claim]
 		ClaimRevoked(T::AccountId, BoundedVec<u8, T::MaxBytesInHash>),
+		/// Event emitted when a proof has been claimed by an issuer for a subject.
+		/// [who, claim, issuer, subject]
+		AttributedClaimCreated(T::AccountId, BoundedVec<u8, T::MaxBytesInHash>, IssuerOf<T>, T::AccountId),
 	}
 
 	#[pallet::error]
@@ -43,6 +87,10 @@ pub mod pallet {
 		NoSuchProof,
 		/// The proof is claimed by another account, so caller can't revoke it.
 		NotProofOwner,
+		/// The caller may not attribute claims.
+		NotIssuer,
+		/// The caller may not attribute claims to the subject.
+		NotIssuerOfSubject,
 	}
 
 	#[pallet::storage]
@@ -55,6 +103,17 @@ pub mod pallet {
 		OptionQuery,
 	>;
 
+	#[pallet::storage]
+	#[pallet::getter(fn attribution)]
+	/// Maps attributed proofs to the issuer that anchored them and the subject they are about
+	pub(super) type Attributions<T: Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		BoundedVec<u8, T::MaxBytesInHash>,
+		(IssuerOf<T>, T::AccountId),
+		OptionQuery,
+	>;
+
 	// Dispatchable functions allow users to interact with the pallet and invoke state changes.
 	// These functions materialize as "extrinsics", which are often compared to transactions.
 	// Dispatchable functions must be annotated with a weight and must return a DispatchResult.
@@ -85,6 +144,33 @@ pub mod pallet {
 			Ok(())
 		}
 
+		/// Claim a proof as the caller's issuer, e.g. a university, attributing it to `subject`,
+		/// e.g. the student a diploma is for.
+		#[pallet::weight(2_000)]
+		pub fn create_attributed_claim(
+			origin: OriginFor<T>,
+			proof: BoundedVec<u8, T::MaxBytesInHash>,
+			subject: T::AccountId,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+
+			// Verify that the sender may attribute the claim to the subject.
+			let issuer = T::Attribution::issuer_of(&sender).ok_or(Error::<T>::NotIssuer)?;
+			ensure!(T::Attribution::may_attribute(&issuer, &subject), Error::<T>::NotIssuerOfSubject);
+
+			ensure!(!Proofs::<T>::contains_key(&proof), Error::<T>::ProofAlreadyClaimed);
+
+			let current_block = <frame_system::Pallet<T>>::block_number();
+
+			// Store the proof like an unattributed one, and the attribution next to it.
+			Proofs::<T>::insert(&proof, (&sender, current_block));
+			Attributions::<T>::insert(&proof, (&issuer, &subject));
+
+			Self::deposit_event(Event::AttributedClaimCreated(sender, proof, issuer, subject));
+
+			Ok(())
+		}
+
 		#[pallet::weight(10_000)]
 		pub fn revoke_claim(
 			origin: OriginFor<T>,
@@ -105,8 +191,9 @@ pub mod pallet {
 			// Verify that sender of the current call is the claim owner.
 			ensure!(sender == owner, Error::<T>::NotProofOwner);
 
-			// Remove claim from storage.
+			// Remove claim and its attribution from storage.
 			Proofs::<T>::remove(&proof);
+			Attributions::<T>::remove(&proof);
 
 			// Emit an event that the claim was erased.
 			Self::deposit_event(Event::ClaimRevoked(sender, proof));
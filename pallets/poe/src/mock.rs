@@ -0,0 +1,84 @@
+use crate as pallet_poe;
+use frame_support::traits::{ConstU16, ConstU32, ConstU64};
+use frame_system as system;
+use sp_core::H256;
+use sp_runtime::{
+	testing::Header,
+	traits::{BlakeTwo256, IdentityLookup},
+};
+
+type UncheckedExtrinsic = frame_system::mocking::MockUncheckedExtrinsic<Test>;
+type Block = frame_system::mocking::MockBlock<Test>;
+
+// Configure a mock runtime to test the pallet.
+frame_support::construct_runtime!(
+	pub enum Test where
+		Block = Block,
+		NodeBlock = Block,
+		UncheckedExtrinsic = UncheckedExtrinsic,
+	{
+		System: frame_system::{Pallet, Call, Config, Storage, Event<T>},
+		PoeModule: pallet_poe::{Pallet, Call, Storage, Event<T>},
+	}
+);
+
+impl system::Config for Test {
+	type BaseCallFilter = frame_support::traits::Everything;
+	type BlockWeights = ();
+	type BlockLength = ();
+	type DbWeight = ();
+	type Origin = Origin;
+	type Call = Call;
+	type Index = u64;
+	type BlockNumber = u64;
+	type Hash = H256;
+	type Hashing = BlakeTwo256;
+	type AccountId = u64;
+	type Lookup = IdentityLookup<Self::AccountId>;
+	type Header = Header;
+	type Event = Event;
+	type BlockHashCount = ConstU64<250>;
+	type Version = ();
+	type PalletInfo = PalletInfo;
+	type AccountData = ();
+	type OnNewAccount = ();
+	type OnKilledAccount = ();
+	type SystemWeightInfo = ();
+	type SS58Prefix = ConstU16<42>;
+	type OnSetCode = ();
+	type MaxConsumers = ConstU32<16>;
+}
+
+/// The issuer of the mock runtime.
+pub const ISSUER: u64 = 1;
+/// The only subject `ISSUER` may attribute claims to.
+pub const STUDENT: u64 = 2;
+
+/// `ISSUER` anchors claims as issuer 7, for `STUDENT` only.
+pub struct TestAttribution;
+
+impl pallet_poe::Attribution<u64> for TestAttribution {
+	type Issuer = u32;
+
+	fn issuer_of(who: &u64) -> Option<u32> {
+		(*who == ISSUER).then(|| 7)
+	}
+
+	fn may_attribute(issuer: &u32, subject: &u64) -> bool {
+		*issuer == 7 && *subject == STUDENT
+	}
+}
+
+impl pallet_poe::Config for Test {
+	type Event = Event;
+	type MaxBytesInHash = ConstU32<64>;
+	type Attribution = TestAttribution;
+}
+
+// Build genesis storage according to the mock runtime.
+pub fn new_test_ext() -> sp_io::TestExternalities {
+	let mut ext: sp_io::TestExternalities =
+		system::GenesisConfig::default().build_storage::<Test>().unwrap().into();
+	ext.execute_with(|| System::set_block_number(1));
+	ext
+}
@@ -0,0 +1,91 @@
+use crate::{mock::*, Error, Proofs};
+use frame_support::{assert_noop, assert_ok, storage::bounded_vec::BoundedVec, traits::ConstU32};
+
+fn proof(bytes: &[u8]) -> BoundedVec<u8, ConstU32<64>> {
+	bytes.to_vec().try_into().unwrap()
+}
+
+#[test]
+fn create_and_revoke_claim() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(PoeModule::create_claim(Origin::signed(3), proof(b"hash")));
+		assert_eq!(Proofs::<Test>::get(proof(b"hash")), Some((3, 1)));
+		assert_eq!(PoeModule::attribution(proof(b"hash")), None);
+		assert_noop!(
+			PoeModule::create_claim(Origin::signed(4), proof(b"hash")),
+			Error::<Test>::ProofAlreadyClaimed
+		);
+
+		assert_noop!(
+			PoeModule::revoke_claim(Origin::signed(4), proof(b"hash")),
+			Error::<Test>::NotProofOwner
+		);
+		assert_ok!(PoeModule::revoke_claim(Origin::signed(3), proof(b"hash")));
+		assert_eq!(Proofs::<Test>::get(proof(b"hash")), None);
+		assert_noop!(
+			PoeModule::revoke_claim(Origin::signed(3), proof(b"hash")),
+			Error::<Test>::NoSuchProof
+		);
+	});
+}
+
+#[test]
+fn issuers_attribute_claims_to_their_subjects() {
+	new_test_ext().execute_with(|| {
+		assert_noop!(
+			PoeModule::create_attributed_claim(Origin::signed(3), proof(b"diploma"), STUDENT),
+			Error::<Test>::NotIssuer
+		);
+		assert_noop!(
+			PoeModule::create_attributed_claim(Origin::signed(ISSUER), proof(b"diploma"), 3),
+			Error::<Test>::NotIssuerOfSubject
+		);
+
+		assert_ok!(PoeModule::create_attributed_claim(
+			Origin::signed(ISSUER),
+			proof(b"diploma"),
+			STUDENT
+		));
+		System::assert_last_event(Event::PoeModule(crate::Event::AttributedClaimCreated(
+			ISSUER,
+			proof(b"diploma"),
+			7,
+			STUDENT,
+		)));
+		assert_eq!(Proofs::<Test>::get(proof(b"diploma")), Some((ISSUER, 1)));
+		assert_eq!(PoeModule::attribution(proof(b"diploma")), Some((7, STUDENT)));
+
+		// Attributed and unattributed claims share the proofs.
+		assert_noop!(
+			PoeModule::create_claim(Origin::signed(3), proof(b"diploma")),
+			Error::<Test>::ProofAlreadyClaimed
+		);
+		assert_noop!(
+			PoeModule::create_attributed_claim(Origin::signed(ISSUER), proof(b"diploma"), STUDENT),
+			Error::<Test>::ProofAlreadyClaimed
+		);
+	});
+}
+
+#[test]
+fn revoking_a_claim_removes_its_attribution() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(PoeModule::create_attributed_claim(
+			Origin::signed(ISSUER),
+			proof(b"diploma"),
+			STUDENT
+		));
+		assert_noop!(
+			PoeModule::revoke_claim(Origin::signed(STUDENT), proof(b"diploma")),
+			Error::<Test>::NotProofOwner
+		);
+
+		assert_ok!(PoeModule::revoke_claim(Origin::signed(ISSUER), proof(b"diploma")));
+		assert_eq!(Proofs::<Test>::get(proof(b"diploma")), None);
+		assert_eq!(PoeModule::attribution(proof(b"diploma")), None);
+
+		// The proof can be claimed again, without the old attribution.
+		assert_ok!(PoeModule::create_claim(Origin::signed(3), proof(b"diploma")));
+		assert_eq!(PoeModule::attribution(proof(b"diploma")), None);
+	});
+}
@@ -0,0 +1,225 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2019-2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Student ID card attestations
+//!
+//! A registrar attests that an account belongs to a student by signing an [`Attestation`]
+//! with its attestation key. The SCALE encoded [`SignedAttestation`] is 169 bytes, small
+//! enough for a QR code on a student ID card.
+//!
+//! Door readers and other devices without a node connection check an attestation with
+//! [`verify`] against a [`Snapshot`] of the registrar keys and revoked attestations, which
+//! they fetch from a node (`studentid_attestationSnapshot`) whenever they are online.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::{Decode, Encode};
+use scale_info::TypeInfo;
+use sp_core::{
+	crypto::KeyTypeId,
+	sr25519::{Public, Signature},
+	RuntimeDebug,
+};
+use sp_std::prelude::*;
+
+/// Key type of registrar attestation keys in a node keystore.
+pub const KEY_TYPE: KeyTypeId = KeyTypeId(*b"attn");
+
+/// Prefixed to the encoded attestation to form the signed payload.
+const SIGNING_CONTEXT: &[u8] = b"studentid/attestation";
+
+/// What the holder of an attestation is to the institution.
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug, TypeInfo)]
+pub enum Status {
+	/// An enrolled student.
+	Enrolled,
+	/// A member of staff.
+	Staff,
+	/// A former student.
+	Alumni,
+	/// A student whose enrollment is suspended.
+	Suspended,
+}
+
+/// A registrar's statement about a student.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug, TypeInfo)]
+pub struct Attestation {
+	/// The student's account.
+	pub student: [u8; 32],
+	/// Blake2-256 hash of the student's email address.
+	pub email_hash: [u8; 32],
+	/// The student's status when the attestation was issued.
+	pub status: Status,
+	/// When the attestation stops being valid, in seconds since the Unix epoch.
+	pub valid_until: u64,
+	/// The sr25519 attestation key of the registrar that signed it.
+	pub registrar: [u8; 32],
+}
+
+impl Attestation {
+	/// The identifier under which the attestation is revoked.
+	pub fn id(&self) -> [u8; 32] {
+		sp_io::hashing::blake2_256(&self.encode())
+	}
+
+	/// The payload the registrar signs.
+	pub fn signing_payload(&self) -> Vec<u8> {
+		[SIGNING_CONTEXT, &self.encode()].concat()
+	}
+}
+
+/// An attestation together with the registrar's signature, as printed on an ID card.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug, TypeInfo)]
+pub struct SignedAttestation {
+	/// The attestation.
+	pub attestation: Attestation,
+	/// The registrar's sr25519 signature over [`Attestation::signing_payload`].
+	pub signature: [u8; 64],
+}
+
+/// The chain state needed to check attestations offline.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug, TypeInfo)]
+pub struct Snapshot {
+	/// The attestation keys of the registrars, sorted.
+	pub registrars: Vec<[u8; 32]>,
+	/// The identifiers of revoked attestations that have not expired yet, sorted.
+	pub revoked: Vec<[u8; 32]>,
+	/// When the snapshot was taken, in seconds since the Unix epoch.
+	pub taken_at: u64,
+}
+
+impl Snapshot {
+	/// A snapshot of `registrars` and `revoked`, taken at `taken_at`.
+	pub fn new(mut registrars: Vec<[u8; 32]>, mut revoked: Vec<[u8; 32]>, taken_at: u64) -> Self {
+		registrars.sort_unstable();
+		registrars.dedup();
+		revoked.sort_unstable();
+		revoked.dedup();
+		Snapshot { registrars, revoked, taken_at }
+	}
+}
+
+/// Why an attestation was rejected.
+#[derive(Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum Error {
+	/// The snapshot is older than the verifier accepts.
+	StaleSnapshot,
+	/// The attestation was not signed by a registrar key.
+	UnknownRegistrar,
+	/// The signature does not match the attestation.
+	BadSignature,
+	/// The attestation has expired.
+	Expired,
+	/// The attestation was revoked.
+	Revoked,
+}
+
+/// Check `signed` at time `now` against `snapshot`, which may be at most `max_snapshot_age`
+/// seconds old. Returns the attestation, whose `status` the caller still needs to check.
+pub fn verify<'a>(
+	signed: &'a SignedAttestation,
+	snapshot: &Snapshot,
+	now: u64,
+	max_snapshot_age: u64,
+) -> Result<&'a Attestation, Error> {
+	let attestation = &signed.attestation;
+
+	if now.saturating_sub(snapshot.taken_at) > max_snapshot_age {
+		return Err(Error::StaleSnapshot)
+	}
+	if snapshot.registrars.binary_search(&attestation.registrar).is_err() {
+		return Err(Error::UnknownRegistrar)
+	}
+	if !sp_io::crypto::sr25519_verify(
+		&Signature::from_raw(signed.signature),
+		&attestation.signing_payload(),
+		&Public::from_raw(attestation.registrar),
+	) {
+		return Err(Error::BadSignature)
+	}
+	if now >= attestation.valid_until {
+		return Err(Error::Expired)
+	}
+	if snapshot.revoked.binary_search(&attestation.id()).is_ok() {
+		return Err(Error::Revoked)
+	}
+
+	Ok(attestation)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use hex_literal::hex;
+
+	// Signed with the sr25519 key of the mini secret `[8; 32]`.
+	const REGISTRAR: [u8; 32] = hex!("ae4e00d549b0dcbd0123ea06f87cc9961a22de7bd57bc5d018a261e341224a7a");
+	const ENCODED: &[u8] = &hex!("0101010101010101010101010101010101010101010101010101010101010101156b62254aebd9a9a573c148961140ebef306a1b71e39a54a8ed97abd1df434d0000f60f6300000000ae4e00d549b0dcbd0123ea06f87cc9961a22de7bd57bc5d018a261e341224a7ac6ed4b2392b7e686911060f1c9a925c072f03b35d03eb657a5caa1cf0092705a6e19448f6c7669acc563b85f996f4b4b03f613dcf056214704b1e7b206c4578c");
+	const NOW: u64 = 1_646_136_000;
+
+	fn attestation() -> Attestation {
+		Attestation {
+			student: [1; 32],
+			email_hash: sp_io::hashing::blake2_256(b"a@b.com"),
+			status: Status::Enrolled,
+			valid_until: 1_661_990_400,
+			registrar: REGISTRAR,
+		}
+	}
+
+	fn signed() -> SignedAttestation {
+		SignedAttestation::decode(&mut &ENCODED[..]).unwrap()
+	}
+
+	fn snapshot(revoked: Vec<[u8; 32]>) -> Snapshot {
+		Snapshot::new(vec![[9; 32], REGISTRAR], revoked, NOW - 60)
+	}
+
+	#[test]
+	fn card_encoding_is_compact() {
+		assert_eq!(signed().attestation, attestation());
+		assert_eq!(signed().encode(), ENCODED);
+		assert_eq!(ENCODED.len(), 169);
+	}
+
+	#[test]
+	fn verifies_attestations() {
+		assert_eq!(verify(&signed(), &snapshot(vec![]), NOW, 3_600), Ok(&attestation()));
+	}
+
+	#[test]
+	fn rejects_invalid_attestations() {
+		let signed = signed();
+		assert_eq!(verify(&signed, &snapshot(vec![]), NOW + 3_600, 3_600), Err(Error::StaleSnapshot));
+		assert_eq!(
+			verify(&signed, &Snapshot::new(vec![[9; 32]], vec![], NOW), NOW, 3_600),
+			Err(Error::UnknownRegistrar)
+		);
+		assert_eq!(
+			verify(&signed, &snapshot(vec![[0; 32], attestation().id()]), NOW, 3_600),
+			Err(Error::Revoked)
+		);
+
+		let mut expired = snapshot(vec![]);
+		expired.taken_at = 1_661_990_400;
+		assert_eq!(verify(&signed, &expired, 1_661_990_400, 3_600), Err(Error::Expired));
+
+		let mut tampered = signed.clone();
+		tampered.attestation.status = Status::Staff;
+		assert_eq!(verify(&tampered, &snapshot(vec![]), NOW, 3_600), Err(Error::BadSignature));
+	}
+}
@@ -0,0 +1,165 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2019-2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Enrollment rosters
+//!
+//! A registrar commits to the roster of an academic term by publishing the [`root`] of a
+//! binary Merkle tree over its [`Leaf`]s. A student later shows they are on the roster with
+//! the [`proof`] for their leaf, which anyone holding the root checks with [`verify`].
+//!
+//! Leaves and inner nodes are hashed with Blake2-256 under distinct prefixes. The two
+//! children of an inner node are hashed in ascending order, so a proof is only the list of
+//! siblings on the path to the root. A node without a sibling is carried up unchanged.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::{Decode, Encode};
+use scale_info::TypeInfo;
+use sp_core::RuntimeDebug;
+use sp_std::prelude::*;
+
+pub use studentid_attestation::Status;
+
+/// An academic term, numbered by the registrar.
+pub type Term = u32;
+
+/// The deepest tree a proof is accepted for, enough for 2^32 leaves.
+pub const MAX_DEPTH: usize = 32;
+
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+/// One student on a roster.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug, TypeInfo)]
+pub struct Leaf {
+	/// Blake2-256 hash of the student's email address.
+	pub email_hash: [u8; 32],
+	/// The student's status in the term.
+	pub status: Status,
+	/// The term the roster is for.
+	pub term: Term,
+}
+
+impl Leaf {
+	/// The hash of the leaf in the tree.
+	pub fn hash(&self) -> [u8; 32] {
+		let mut data = vec![LEAF_PREFIX];
+		self.encode_to(&mut data);
+		sp_io::hashing::blake2_256(&data)
+	}
+}
+
+fn combine(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+	let (first, second) = if a <= b { (a, b) } else { (b, a) };
+	let mut data = [0u8; 65];
+	data[0] = NODE_PREFIX;
+	data[1..33].copy_from_slice(first);
+	data[33..].copy_from_slice(second);
+	sp_io::hashing::blake2_256(&data)
+}
+
+fn next_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+	level
+		.chunks(2)
+		.map(|pair| match pair {
+			[a, b] => combine(a, b),
+			[a] => *a,
+			_ => unreachable!("chunks are one or two nodes; qed"),
+		})
+		.collect()
+}
+
+/// The root of the tree over `leaves`, or all zeroes for an empty roster.
+pub fn root(leaves: &[Leaf]) -> [u8; 32] {
+	let mut level: Vec<_> = leaves.iter().map(Leaf::hash).collect();
+	if level.is_empty() {
+		return [0u8; 32]
+	}
+	while level.len() > 1 {
+		level = next_level(&level);
+	}
+	level[0]
+}
+
+/// The proof for the leaf at `index`, or `None` if there is no such leaf.
+pub fn proof(leaves: &[Leaf], mut index: usize) -> Option<Vec<[u8; 32]>> {
+	if index >= leaves.len() {
+		return None
+	}
+	let mut level: Vec<_> = leaves.iter().map(Leaf::hash).collect();
+	let mut proof = Vec::new();
+	while level.len() > 1 {
+		if let Some(sibling) = level.get(index ^ 1) {
+			proof.push(*sibling);
+		}
+		level = next_level(&level);
+		index /= 2;
+	}
+	Some(proof)
+}
+
+/// Whether `proof` shows that `leaf` is in the tree with the given `root`.
+pub fn verify(root: &[u8; 32], leaf: &Leaf, proof: &[[u8; 32]]) -> bool {
+	if proof.len() > MAX_DEPTH {
+		return false
+	}
+	let computed = proof.iter().fold(leaf.hash(), |node, sibling| combine(&node, sibling));
+	&computed == root
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn leaf(n: u8) -> Leaf {
+		Leaf { email_hash: [n; 32], status: Status::Enrolled, term: 20221 }
+	}
+
+	#[test]
+	fn every_leaf_has_a_valid_proof() {
+		for size in 1..=9 {
+			let leaves: Vec<_> = (0..size).map(leaf).collect();
+			let root = root(&leaves);
+			for (index, leaf) in leaves.iter().enumerate() {
+				let proof = proof(&leaves, index).unwrap();
+				assert!(verify(&root, leaf, &proof), "leaf {} of {}", index, size);
+			}
+			assert_eq!(proof(&leaves, size as usize), None);
+		}
+	}
+
+	#[test]
+	fn proofs_do_not_carry_over() {
+		let leaves: Vec<_> = (0..5).map(leaf).collect();
+		let root = root(&leaves);
+		let proof = proof(&leaves, 1).unwrap();
+		assert!(!verify(&root, &leaf(2), &proof));
+		assert!(!verify(&root, &Leaf { status: Status::Staff, ..leaf(1) }, &proof));
+		assert!(!verify(&root, &Leaf { term: 20222, ..leaf(1) }, &proof));
+		assert!(!verify(&[0u8; 32], &leaf(1), &proof));
+	}
+
+	#[test]
+	fn inner_nodes_are_not_leaves() {
+		let leaves: Vec<_> = (0..4).map(leaf).collect();
+		let root = root(&leaves);
+		// The root of a single leaf tree is the hash of the leaf, never an inner node.
+		assert_eq!(super::root(&leaves[..1]), leaves[0].hash());
+		assert_ne!(root, combine(&leaves[0].hash(), &leaves[1].hash()));
+		assert_eq!(super::root(&[]), [0u8; 32]);
+	}
+}
@@ -0,0 +1,457 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2019-2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Sign-In with Substrate
+//!
+//! A standard off-chain login message, modelled on EIP-4361. An application asks the wallet of
+//! a student to sign the canonical text of a [`Message`]:
+//!
+//! ```text
+//! id.selendra.org wants you to sign in with your Substrate account:
+//! 5EsNLFaGe9XK5LzWH3i6eC2Wqv6YqZS1442N1C4yeSdP6uxy
+//!
+//! Services: wordpress, library
+//! Nonce: 32891756
+//! Issued At: 2022-03-01T12:00:00Z
+//! Expiration Time: 2022-03-01T12:10:00Z
+//! Genesis Hash: 0x0101010101010101010101010101010101010101010101010101010101010101
+//! ```
+//!
+//! and sends the message, SCALE encoded, together with the sr25519 signature to whoever checks
+//! the login: the runtime or an ordinary backend. Both use [`Message::verify`]. Signatures over
+//! the text wrapped in `<Bytes>` tags, as produced by browser extension wallets, are accepted
+//! as well.
+//!
+//! Decoding a message fails as soon as a length prefix exceeds the limits [`Message::validate`]
+//! checks, so a message from an untrusted source never takes more than a few kilobytes.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::{Compact, Decode, Encode, Input};
+use scale_info::TypeInfo;
+use sp_core::{
+	sr25519::{Public, Signature},
+	RuntimeDebug,
+};
+use sp_std::{prelude::*, vec};
+
+/// The maximum length of a domain, as for DNS names.
+pub const MAX_DOMAIN_LEN: usize = 253;
+
+/// The maximum number of services a message can request.
+pub const MAX_SERVICES: usize = 8;
+
+/// The maximum length of a service name.
+pub const MAX_SERVICE_LEN: usize = 64;
+
+/// The minimum length of a nonce.
+pub const MIN_NONCE_LEN: usize = 8;
+
+/// The maximum length of a nonce.
+pub const MAX_NONCE_LEN: usize = 64;
+
+/// The longest a message can be valid for, in seconds.
+pub const MAX_VALIDITY: u64 = 10 * 60;
+
+/// A Sign-In with Substrate message.
+#[derive(Encode, Clone, PartialEq, Eq, RuntimeDebug, TypeInfo)]
+pub struct Message {
+	/// The domain of the application asking for the sign-in, e.g. `id.selendra.org`.
+	pub domain: Vec<u8>,
+	/// The sr25519 public key of the account signing in.
+	pub account: [u8; 32],
+	/// An alphanumeric nonce chosen by the application, [`MIN_NONCE_LEN`] to [`MAX_NONCE_LEN`]
+	/// long.
+	pub nonce: Vec<u8>,
+	/// When the message was created, in seconds since the Unix epoch.
+	pub issued_at: u64,
+	/// When the message stops being valid, in seconds since the Unix epoch.
+	pub expiration_time: u64,
+	/// The genesis hash of the chain the account signs in to.
+	pub genesis_hash: [u8; 32],
+	/// The services the account gives access to by signing in.
+	pub services: Vec<Vec<u8>>,
+}
+
+/// What the verifier expects of a message.
+#[derive(Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub struct Expected<'a> {
+	/// The domain of the verifier.
+	pub domain: &'a [u8],
+	/// The genesis hash of the verifier's chain.
+	pub genesis_hash: &'a [u8; 32],
+	/// The SS58 address format of the verifier's chain.
+	pub ss58_prefix: u16,
+	/// The current time, in seconds since the Unix epoch.
+	pub now: u64,
+}
+
+/// Why a message was rejected.
+#[derive(Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum Error {
+	/// A field of the message is malformed, see [`Message::validate`].
+	Malformed,
+	/// The message is for another domain.
+	WrongDomain,
+	/// The message is for another chain.
+	WrongChain,
+	/// The message is not valid yet.
+	NotYetValid,
+	/// The message has expired.
+	Expired,
+	/// The signature does not match the message and account.
+	BadSignature,
+}
+
+impl Message {
+	/// The canonical text of the message, which is what the account signs. The account is
+	/// rendered as an SS58 address with `ss58_prefix`.
+	pub fn to_text(&self, ss58_prefix: u16) -> Vec<u8> {
+		let mut text = self.domain.clone();
+		text.extend_from_slice(b" wants you to sign in with your Substrate account:\n");
+		text.extend(ss58(&self.account, ss58_prefix));
+		text.extend_from_slice(b"\n\n");
+		if !self.services.is_empty() {
+			text.extend_from_slice(b"Services: ");
+			text.extend(self.services.join(&b", "[..]));
+			text.push(b'\n');
+		}
+		text.extend_from_slice(b"Nonce: ");
+		text.extend_from_slice(&self.nonce);
+		text.extend_from_slice(b"\nIssued At: ");
+		push_datetime(&mut text, self.issued_at);
+		text.extend_from_slice(b"\nExpiration Time: ");
+		push_datetime(&mut text, self.expiration_time);
+		text.extend_from_slice(b"\nGenesis Hash: 0x");
+		push_hex(&mut text, &self.genesis_hash);
+		text
+	}
+
+	/// Check that the fields of the message can't be confused when it is rendered as text.
+	///
+	/// The domain must be at most [`MAX_DOMAIN_LEN`] characters of printable ASCII without
+	/// spaces, the nonce [`MIN_NONCE_LEN`] to [`MAX_NONCE_LEN`] alphanumeric characters, there
+	/// may be at most [`MAX_SERVICES`] services of at most [`MAX_SERVICE_LEN`] characters of
+	/// printable ASCII without spaces or commas, and the message must expire after it was
+	/// issued, at most [`MAX_VALIDITY`] later.
+	pub fn validate(&self) -> Result<(), Error> {
+		let token = |s: &[u8], max| {
+			!s.is_empty() && s.len() <= max && s.iter().all(|c| c.is_ascii_graphic())
+		};
+
+		let valid = token(&self.domain, MAX_DOMAIN_LEN) &&
+			(MIN_NONCE_LEN..=MAX_NONCE_LEN).contains(&self.nonce.len()) &&
+			self.nonce.iter().all(u8::is_ascii_alphanumeric) &&
+			self.services.len() <= MAX_SERVICES &&
+			self.services.iter().all(|s| token(s, MAX_SERVICE_LEN) && !s.contains(&b',')) &&
+			self.issued_at < self.expiration_time &&
+			self.expiration_time - self.issued_at <= MAX_VALIDITY;
+
+		if valid {
+			Ok(())
+		} else {
+			Err(Error::Malformed)
+		}
+	}
+
+	/// Verify that `signature` is the account's signature over the message, and that the
+	/// message is well formed and meant for `expected` at `expected.now`.
+	pub fn verify(&self, signature: &Signature, expected: &Expected) -> Result<(), Error> {
+		self.validate()?;
+
+		if self.domain != expected.domain {
+			return Err(Error::WrongDomain)
+		}
+		if &self.genesis_hash != expected.genesis_hash {
+			return Err(Error::WrongChain)
+		}
+		if expected.now < self.issued_at {
+			return Err(Error::NotYetValid)
+		}
+		if expected.now >= self.expiration_time {
+			return Err(Error::Expired)
+		}
+
+		let text = self.to_text(expected.ss58_prefix);
+		let public = Public::from_raw(self.account);
+		let wrapped = [&b"<Bytes>"[..], &text, &b"</Bytes>"[..]].concat();
+		if sp_io::crypto::sr25519_verify(signature, &text, &public) ||
+			sp_io::crypto::sr25519_verify(signature, &wrapped, &public)
+		{
+			Ok(())
+		} else {
+			Err(Error::BadSignature)
+		}
+	}
+}
+
+impl Decode for Message {
+	fn decode<I: Input>(input: &mut I) -> Result<Self, codec::Error> {
+		Ok(Message {
+			domain: decode_bytes(input, MAX_DOMAIN_LEN)?,
+			account: Decode::decode(input)?,
+			nonce: decode_bytes(input, MAX_NONCE_LEN)?,
+			issued_at: Decode::decode(input)?,
+			expiration_time: Decode::decode(input)?,
+			genesis_hash: Decode::decode(input)?,
+			services: (0..decode_len(input, MAX_SERVICES)?)
+				.map(|_| decode_bytes(input, MAX_SERVICE_LEN))
+				.collect::<Result<_, _>>()?,
+		})
+	}
+}
+
+/// Decode the length prefix of a vector, failing if it is longer than `max`.
+fn decode_len<I: Input>(input: &mut I, max: usize) -> Result<usize, codec::Error> {
+	let len = <Compact<u32>>::decode(input)?.0 as usize;
+	if len > max {
+		return Err("Sign-In with Substrate message field too long".into())
+	}
+	Ok(len)
+}
+
+/// Decode a byte vector of at most `max` bytes, without reading or allocating more.
+fn decode_bytes<I: Input>(input: &mut I, max: usize) -> Result<Vec<u8>, codec::Error> {
+	let mut bytes = vec![0; decode_len(input, max)?];
+	input.read(&mut bytes)?;
+	Ok(bytes)
+}
+
+/// The SS58 address of `public_key` with address format `prefix`.
+pub fn ss58(public_key: &[u8; 32], prefix: u16) -> Vec<u8> {
+	// Identifiers of 64 and above take two bytes, see the SS58 specification.
+	let ident = prefix & 0b0011_1111_1111_1111;
+	let mut data = match ident {
+		0..=63 => vec![ident as u8],
+		_ => vec![
+			((ident & 0b0000_0000_1111_1100) as u8) >> 2 | 0b0100_0000,
+			(ident >> 8) as u8 | ((ident & 0b0000_0000_0000_0011) as u8) << 6,
+		],
+	};
+	data.extend_from_slice(public_key);
+	let checksum = sp_io::hashing::blake2_512(&[&b"SS58PRE"[..], &data].concat());
+	data.extend_from_slice(&checksum[..2]);
+	base58(&data)
+}
+
+/// Bitcoin style base58 encoding of `data`.
+fn base58(data: &[u8]) -> Vec<u8> {
+	const ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+	// Little endian base58 digits.
+	let mut digits: Vec<u8> = Vec::with_capacity(data.len() * 138 / 100 + 1);
+	for byte in data {
+		let mut carry = *byte as u32;
+		for digit in digits.iter_mut() {
+			carry += (*digit as u32) << 8;
+			*digit = (carry % 58) as u8;
+			carry /= 58;
+		}
+		while carry > 0 {
+			digits.push((carry % 58) as u8);
+			carry /= 58;
+		}
+	}
+
+	let zeros = data.iter().take_while(|b| **b == 0).count();
+	let mut out = vec![ALPHABET[0]; zeros];
+	out.extend(digits.iter().rev().map(|d| ALPHABET[*d as usize]));
+	out
+}
+
+/// Append `secs` since the Unix epoch as an RFC 3339 UTC timestamp.
+fn push_datetime(out: &mut Vec<u8>, secs: u64) {
+	let (days, secs) = (secs / 86_400, secs % 86_400);
+
+	// Civil date from the number of days since 1970-01-01, see
+	// http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+	let z = days + 719_468;
+	let era = z / 146_097;
+	let doe = z - era * 146_097;
+	let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + (month <= 2) as u64;
+
+	push_decimal(out, year, 4);
+	out.push(b'-');
+	push_decimal(out, month, 2);
+	out.push(b'-');
+	push_decimal(out, day, 2);
+	out.push(b'T');
+	push_decimal(out, secs / 3_600, 2);
+	out.push(b':');
+	push_decimal(out, secs / 60 % 60, 2);
+	out.push(b':');
+	push_decimal(out, secs % 60, 2);
+	out.push(b'Z');
+}
+
+/// Append `n` in decimal, zero padded to `width` digits.
+fn push_decimal(out: &mut Vec<u8>, mut n: u64, width: usize) {
+	let mut digits = Vec::new();
+	while n > 0 || digits.len() < width {
+		digits.push(b'0' + (n % 10) as u8);
+		n /= 10;
+	}
+	out.extend(digits.iter().rev());
+}
+
+/// Append `data` in lowercase hexadecimal.
+fn push_hex(out: &mut Vec<u8>, data: &[u8]) {
+	const ALPHABET: &[u8; 16] = b"0123456789abcdef";
+	out.extend(data.iter().flat_map(|b| [ALPHABET[(b >> 4) as usize], ALPHABET[(b & 0xf) as usize]]));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use hex_literal::hex;
+
+	// Signed with the sr25519 key of the mini secret `[7; 32]`.
+	const ACCOUNT: [u8; 32] = hex!("7c0f469d3bd340bae718203fa30ca071a5e37c751e891dbded837b213d45d91d");
+	const GENESIS: [u8; 32] = [1; 32];
+	const TEXT: &str = "id.selendra.org wants you to sign in with your Substrate account:
+5EsNLFaGe9XK5LzWH3i6eC2Wqv6YqZS1442N1C4yeSdP6uxy
+
+Services: wordpress, library
+Nonce: 32891756
+Issued At: 2022-03-01T12:00:00Z
+Expiration Time: 2022-03-01T12:10:00Z
+Genesis Hash: 0x0101010101010101010101010101010101010101010101010101010101010101";
+	const ENCODED: &[u8] = &hex!("3c69642e73656c656e6472612e6f72677c0f469d3bd340bae718203fa30ca071a5e37c751e891dbded837b213d45d91d203332383931373536c00a1e6200000000180d1e620000000001010101010101010101010101010101010101010101010101010101010101010824776f726470726573731c6c696272617279");
+	const SIGNATURE: [u8; 64] = hex!("28bac0ec2ceee29ebc889b1cf617a7f09a3418e92d13f0bf2c1ea7441a0184429d28376e0abd2534aab1af9ef6b36574219cb3ad30a72c82a55ab60f7898bc83");
+	const WRAPPED_SIGNATURE: [u8; 64] = hex!("20c7f588806fae987b1f9028811c59fe63505bc6fa98330f7f7762ea7165394007013ecfaa402e07ebd6062b821ed5f90b27a19c5199233b4aac0300c2435589");
+
+	fn message() -> Message {
+		Message {
+			domain: b"id.selendra.org".to_vec(),
+			account: ACCOUNT,
+			nonce: b"32891756".to_vec(),
+			issued_at: 1_646_136_000,
+			expiration_time: 1_646_136_600,
+			genesis_hash: GENESIS,
+			services: vec![b"wordpress".to_vec(), b"library".to_vec()],
+		}
+	}
+
+	fn expected(now: u64) -> Expected<'static> {
+		Expected { domain: b"id.selendra.org", genesis_hash: &GENESIS, ss58_prefix: 42, now }
+	}
+
+	#[test]
+	fn canonical_encodings() {
+		assert_eq!(String::from_utf8(message().to_text(42)).unwrap(), TEXT);
+		assert_eq!(message().encode(), ENCODED);
+		assert_eq!(Message::decode(&mut &ENCODED[..]).unwrap(), message());
+	}
+
+	#[test]
+	fn renders_addresses_and_dates() {
+		// Alice on the generic substrate (42) and Polkadot (0) formats.
+		let alice = hex!("d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d");
+		assert_eq!(ss58(&alice, 42), b"5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY".to_vec());
+		assert_eq!(ss58(&alice, 0), b"15oF4uVJwmo4TdGW7VfQxNLavjCXviqxT9S1MgbjMNHr6Sp5".to_vec());
+		assert_eq!(ss58(&alice, 252).len(), 49);
+
+		let date = |secs| {
+			let mut out = Vec::new();
+			push_datetime(&mut out, secs);
+			String::from_utf8(out).unwrap()
+		};
+		assert_eq!(date(0), "1970-01-01T00:00:00Z");
+		assert_eq!(date(951_782_400), "2000-02-29T00:00:00Z");
+		assert_eq!(date(4_102_444_799), "2099-12-31T23:59:59Z");
+	}
+
+	#[test]
+	fn verifies_signatures() {
+		let signature = Signature::from_raw(SIGNATURE);
+		let wrapped = Signature::from_raw(WRAPPED_SIGNATURE);
+		assert_eq!(message().verify(&signature, &expected(1_646_136_000)), Ok(()));
+		assert_eq!(message().verify(&wrapped, &expected(1_646_136_599)), Ok(()));
+
+		let mut other = message();
+		other.services.pop();
+		assert_eq!(other.verify(&signature, &expected(1_646_136_000)), Err(Error::BadSignature));
+		// The address is part of the signed text.
+		assert_eq!(
+			message().verify(&signature, &Expected { ss58_prefix: 0, ..expected(1_646_136_000) }),
+			Err(Error::BadSignature)
+		);
+	}
+
+	#[test]
+	fn rejects_messages_for_someone_else() {
+		let signature = Signature::from_raw(SIGNATURE);
+		let now = 1_646_136_300;
+		assert_eq!(
+			message().verify(&signature, &Expected { domain: b"evil.example", ..expected(now) }),
+			Err(Error::WrongDomain)
+		);
+		assert_eq!(
+			message().verify(&signature, &Expected { genesis_hash: &[2; 32], ..expected(now) }),
+			Err(Error::WrongChain)
+		);
+		assert_eq!(message().verify(&signature, &expected(1_646_135_999)), Err(Error::NotYetValid));
+		assert_eq!(message().verify(&signature, &expected(1_646_136_600)), Err(Error::Expired));
+	}
+
+	#[test]
+	fn decoding_stops_at_overlong_fields() {
+		let overlong = |f: fn(&mut Message)| {
+			let mut message = message();
+			f(&mut message);
+			Message::decode(&mut &message.encode()[..]).is_err()
+		};
+		assert!(!overlong(|_| ()));
+		assert!(overlong(|m| m.domain = vec![b'a'; MAX_DOMAIN_LEN + 1]));
+		assert!(overlong(|m| m.nonce = vec![b'1'; MAX_NONCE_LEN + 1]));
+		assert!(overlong(|m| m.services = vec![b"s".to_vec(); MAX_SERVICES + 1]));
+		assert!(overlong(|m| m.services = vec![vec![b's'; MAX_SERVICE_LEN + 1]]));
+
+		// A length prefix claiming gigabytes fails without reading further.
+		let mut huge = Compact(u32::MAX).encode();
+		huge.extend_from_slice(&ENCODED[1..]);
+		assert!(Message::decode(&mut &huge[..]).is_err());
+	}
+
+	#[test]
+	fn rejects_malformed_messages() {
+		let malformed = |f: fn(&mut Message)| {
+			let mut message = message();
+			f(&mut message);
+			message.validate()
+		};
+		assert_eq!(malformed(|_| ()), Ok(()));
+		assert_eq!(malformed(|m| m.domain = b"id.selendra.org\nNonce: 1".to_vec()), Err(Error::Malformed));
+		assert_eq!(malformed(|m| m.nonce = b"1234567".to_vec()), Err(Error::Malformed));
+		assert_eq!(malformed(|m| m.nonce = b"1234-5678".to_vec()), Err(Error::Malformed));
+		assert_eq!(malformed(|m| m.nonce = vec![b'1'; MAX_NONCE_LEN + 1]), Err(Error::Malformed));
+		assert_eq!(malformed(|m| m.services.push(b"a,b".to_vec())), Err(Error::Malformed));
+		assert_eq!(malformed(|m| m.services = vec![b"s".to_vec(); MAX_SERVICES + 1]), Err(Error::Malformed));
+		assert_eq!(malformed(|m| m.services = vec![vec![b's'; MAX_SERVICE_LEN + 1]]), Err(Error::Malformed));
+		assert_eq!(malformed(|m| m.domain = vec![b'a'; MAX_DOMAIN_LEN + 1]), Err(Error::Malformed));
+		assert_eq!(malformed(|m| m.expiration_time = m.issued_at), Err(Error::Malformed));
+		assert_eq!(
+			malformed(|m| m.expiration_time = m.issued_at + MAX_VALIDITY + 1),
+			Err(Error::Malformed)
+		);
+	}
+}
@@ -0,0 +1,97 @@
+//! Fee payment that lets new students finish onboarding without funds.
+
+use codec::{Decode, Encode};
+use pallet_transaction_payment::ChargeTransactionPayment;
+use scale_info::TypeInfo;
+use sp_runtime::{
+	traits::{DispatchInfoOf, PostDispatchInfoOf, SignedExtension},
+	transaction_validity::{TransactionValidity, TransactionValidityError, ValidTransaction},
+	DispatchResult, RuntimeDebug,
+};
+
+use crate::{AccountId, Balance, Call, Identity, Runtime};
+
+/// `ChargeTransactionPayment`, except that the onboarding calls of `pallet_studentid` are free
+/// for eligible accounts, up to `MaxFeelessCallsPerPeriod` of them per `FeelessPeriod`; see
+/// `pallet_studentid::Pallet::is_feeless`.
+///
+/// It encodes like `ChargeTransactionPayment` and keeps its identifier, so clients sign
+/// transactions the same way. The tip of a feeless call is not charged.
+///
+/// As feeless calls are only counted once dispatched, a feeless call provides a tag of its
+/// sender, the period and the calls made in it, so the pool holds one at a time per account.
+/// Feeless calls have the lowest priority and do not outlive their period.
+#[derive(Encode, Decode, Clone, Eq, PartialEq, RuntimeDebug, TypeInfo)]
+pub struct ChargeTransactionPaymentOrFeeless(ChargeTransactionPayment<Runtime>);
+
+impl ChargeTransactionPaymentOrFeeless {
+	/// Pay `tip` on top of the fee of calls that are not feeless.
+	pub fn from(tip: Balance) -> Self {
+		Self(ChargeTransactionPayment::from(tip))
+	}
+
+	fn feeless_call(who: &AccountId, call: &Call) -> bool {
+		match call {
+			Call::Identity(call) => Identity::is_feeless(who, call),
+			_ => false,
+		}
+	}
+}
+
+impl SignedExtension for ChargeTransactionPaymentOrFeeless {
+	const IDENTIFIER: &'static str = <ChargeTransactionPayment<Runtime> as SignedExtension>::IDENTIFIER;
+	type AccountId = AccountId;
+	type Call = Call;
+	type AdditionalSigned = ();
+	/// `None` for feeless calls.
+	type Pre = Option<<ChargeTransactionPayment<Runtime> as SignedExtension>::Pre>;
+
+	fn additional_signed(&self) -> Result<(), TransactionValidityError> {
+		Ok(())
+	}
+
+	fn validate(
+		&self,
+		who: &Self::AccountId,
+		call: &Self::Call,
+		info: &DispatchInfoOf<Self::Call>,
+		len: usize,
+	) -> TransactionValidity {
+		if Self::feeless_call(who, call) {
+			let (period, made) = Identity::feeless_calls_made(who);
+			return ValidTransaction::with_tag_prefix("FeelessCall")
+				.and_provides((who, period, made))
+				.priority(0)
+				.longevity(Identity::feeless_period_left().into())
+				.build()
+		}
+		self.0.validate(who, call, info, len)
+	}
+
+	fn pre_dispatch(
+		self,
+		who: &Self::AccountId,
+		call: &Self::Call,
+		info: &DispatchInfoOf<Self::Call>,
+		len: usize,
+	) -> Result<Self::Pre, TransactionValidityError> {
+		if Self::feeless_call(who, call) {
+			Identity::note_feeless_call(who);
+			return Ok(None)
+		}
+		self.0.pre_dispatch(who, call, info, len).map(Some)
+	}
+
+	fn post_dispatch(
+		pre: Self::Pre,
+		info: &DispatchInfoOf<Self::Call>,
+		post_info: &PostDispatchInfoOf<Self::Call>,
+		len: usize,
+		result: &DispatchResult,
+	) -> Result<(), TransactionValidityError> {
+		match pre {
+			Some(pre) => ChargeTransactionPayment::<Runtime>::post_dispatch(pre, info, post_info, len, result),
+			None => Ok(()),
+		}
+	}
+}
@@ -10,6 +10,7 @@ use pallet_grandpa::{
 	fg_primitives, AuthorityId as GrandpaId, AuthorityList as GrandpaAuthorityList,
 };
 
+use frame_support::PalletId;
 use frame_system::{EnsureRoot, EnsureSignedBy };
 
 use sp_api::impl_runtime_apis;
@@ -38,7 +39,6 @@ pub use frame_support::{
 };
 pub use pallet_balances::Call as BalancesCall;
 pub use pallet_timestamp::Call as TimestampCall;
-use pallet_transaction_payment::CurrencyAdapter;
 #[cfg(any(feature = "std", test))]
 pub use sp_runtime::BuildStorage;
 pub use sp_runtime::{Perbill, Permill};
@@ -46,6 +46,11 @@ pub use sp_runtime::{Perbill, Permill};
 /// Import the template pallet.
 pub use pallet_template;
 
+mod feeless;
+mod sponsor;
+pub use feeless::ChargeTransactionPaymentOrFeeless;
+pub use sponsor::SponsoredCurrencyAdapter;
+
 /// An index to a block.
 pub type BlockNumber = u32;
 
@@ -101,10 +106,10 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
 	//   `spec_version`, and `authoring_version` are the same between Wasm and native.
 	// This value is set to 100 to notify Polkadot-JS App (https://polkadot.js.org/apps) to use
 	//   the compatible custom types.
-	spec_version: 100,
+	spec_version: 101,
 	impl_version: 1,
 	apis: RUNTIME_API_VERSIONS,
-	transaction_version: 1,
+	transaction_version: 2,
 	state_version: 1,
 };
 
@@ -257,7 +262,7 @@ parameter_types! {
 }
 
 impl pallet_transaction_payment::Config for Runtime {
-	type OnChargeTransaction = CurrencyAdapter<Balances, ()>;
+	type OnChargeTransaction = SponsoredCurrencyAdapter;
 	type TransactionByteFee = TransactionByteFee;
 	type OperationalFeeMultiplier = ConstU8<5>;
 	type WeightToFee = IdentityFee<Balance>;
@@ -281,16 +286,47 @@ parameter_types! {
     pub const MaxSubAccounts: u32 = 2;
     pub const MaxAdditionalFields: u32 = 5;
     pub const MaxRegistrars: u32 = 20;
+    pub const MaxDomainsPerInstitution: u32 = 10;
+    pub const MaxRenewalsPerCall: u32 = 100;
+    pub const SponsorPalletId: PalletId = PalletId(*b"py/stdsp");
+    pub const FeelessPeriod: BlockNumber = DAYS;
+    pub const MaxFeelessCallsPerPeriod: u32 = 10;
     pub const MaxEmailsize: u32 = 30;
     pub const MaxTokenid: u32 = 30;
+    pub const TokenLifetime: BlockNumber = DAYS;
     pub const MaxUseridentities: u32 = 20;
     pub const MaxAccessTokenMetadata: u32 = 15;
+    pub const MaxClearedPerCall: u32 = 50;
+    pub const MaxBlobSize: u32 = 1024;
+    pub const BlobDepositBase: u64 = 10;
+    pub const BlobDepositPerByte: u64 = 1;
+    pub const MaxPasskeys: u32 = 5;
+    pub const MaxCredentialIdLen: u32 = 255;
+    pub RelyingPartyId: Vec<u8> = b"id.selendra.org".to_vec();
+    pub RelyingPartyOrigin: Vec<u8> = b"https://id.selendra.org".to_vec();
+}
+
+/// Lets registrars anchor proofs for the students they act for.
+pub struct RegistrarAttribution;
+
+impl pallet_poe::Attribution<AccountId> for RegistrarAttribution {
+    /// The registrar and the institution it acts for.
+    type Issuer = (pallet_studentid::RegistrarIndex, Option<pallet_studentid::InstitutionIndex>);
+
+    fn issuer_of(who: &AccountId) -> Option<Self::Issuer> {
+        let index = Identity::registrar_index_of(who)?;
+        Some((index, pallet_studentid::InstitutionOfRegistrar::<Runtime>::get(index)))
+    }
+
+    fn may_attribute((index, _): &Self::Issuer, subject: &AccountId) -> bool {
+        Identity::emailid(subject).is_some() && Identity::acts_for(*index, subject)
+    }
 }
 
-/// Configure the pallet-template in pallets/template.
 impl pallet_poe::Config for Runtime {
     type Event = Event;
     type MaxBytesInHash = frame_support::traits::ConstU32<64>;
+    type Attribution = RegistrarAttribution;
 }
 
 impl pallet_studentid::Config for Runtime {
@@ -304,9 +340,24 @@ impl pallet_studentid::Config for Runtime {
     type MaxAdditionalFields = MaxAdditionalFields;
     type MaxUseridentities = MaxUseridentities;
     type MaxRegistrars = MaxRegistrars;
+    type MaxDomainsPerInstitution = MaxDomainsPerInstitution;
+    type MaxRenewalsPerCall = MaxRenewalsPerCall;
+    type SponsorPalletId = SponsorPalletId;
+    type FeelessPeriod = FeelessPeriod;
+    type MaxFeelessCallsPerPeriod = MaxFeelessCallsPerPeriod;
     type MaxEmailsize = MaxEmailsize;
     type MaxAccessTokenMetadata = MaxAccessTokenMetadata;
     type MaxTokenid = MaxTokenid;
+    type TokenLifetime = TokenLifetime;
+    type MaxClearedPerCall = MaxClearedPerCall;
+    type MaxBlobSize = MaxBlobSize;
+    type BlobDepositBase = BlobDepositBase;
+    type BlobDepositPerByte = BlobDepositPerByte;
+    type MaxPasskeys = MaxPasskeys;
+    type MaxCredentialIdLen = MaxCredentialIdLen;
+    type RelyingPartyId = RelyingPartyId;
+    type RelyingPartyOrigin = RelyingPartyOrigin;
+    type UnixTime = Timestamp;
 
     type RegistrarOrigin = frame_system::EnsureRoot<AccountId>; //EnsureOneOrRoot;
     type ForceOrigin = frame_system::EnsureRoot<AccountId>; //EnsureTwoOrRoot;
@@ -349,7 +400,7 @@ pub type SignedExtra = (
 	frame_system::CheckEra<Runtime>,
 	frame_system::CheckNonce<Runtime>,
 	frame_system::CheckWeight<Runtime>,
-	pallet_transaction_payment::ChargeTransactionPayment<Runtime>,
+	ChargeTransactionPaymentOrFeeless,
 );
 /// Unchecked extrinsic type as expected by this runtime.
 pub type UncheckedExtrinsic = generic::UncheckedExtrinsic<Address, Call, Signature, SignedExtra>;
@@ -508,6 +559,76 @@ impl_runtime_apis! {
 		}
 	}
 
+	impl pallet_studentid_rpc_runtime_api::StudentidApi<Block, AccountId, MaxAdditionalFields> for Runtime {
+		fn disclosed_identity(
+			who: AccountId,
+			service: Vec<u8>,
+		) -> Option<pallet_studentid::IdentityInfoSel<MaxAdditionalFields>> {
+			Identity::disclosed_identity(&who, &service)
+		}
+
+		fn resolve_data(data: pallet_studentid::Data) -> Option<Vec<u8>> {
+			Identity::resolve(&data)
+		}
+
+		fn token_owner(token_id: Vec<u8>) -> Option<AccountId> {
+			Identity::token_owner(&token_id)
+		}
+
+		fn has_access(who: AccountId, service: Vec<u8>) -> bool {
+			Identity::has_access(&who, &service)
+		}
+
+		fn introspect_token(token_id: Vec<u8>) -> Option<pallet_studentid::TokenStatus<AccountId, BlockNumber>> {
+			Identity::introspect_token(&token_id)
+		}
+
+		fn passkey_challenge(email: Vec<u8>) -> [u8; 32] {
+			Identity::passkey_challenge(&email)
+		}
+
+		fn attestation_snapshot() -> pallet_studentid::attestation::Snapshot {
+			Identity::attestation_snapshot()
+		}
+
+		fn registrar(index: pallet_studentid::RegistrarIndex) -> Option<pallet_studentid::RegistrarInfo<AccountId>> {
+			Identity::registrar(index)
+		}
+
+		fn identity_hash(who: AccountId) -> Option<[u8; 32]> {
+			Identity::identity_hash(&who)
+		}
+
+		fn judged_identity(
+			who: AccountId,
+			index: pallet_studentid::RegistrarIndex,
+		) -> Option<pallet_studentid::JudgedIdentity<AccountId, MaxAdditionalFields>> {
+			Identity::judged_identity(&who, index)
+		}
+
+		fn did_details(who: AccountId) -> Option<pallet_studentid::DidDetails<AccountId>> {
+			Identity::did_details(&who)
+		}
+
+		fn ss58_prefix() -> u16 {
+			Identity::ss58_prefix()
+		}
+
+		fn student_state(who: AccountId) -> Option<pallet_studentid::StudentState<BlockNumber>> {
+			Identity::student_state(&who)
+		}
+
+		fn student_status(who: AccountId) -> Option<pallet_studentid::StudentStatus> {
+			Identity::student_status(&who)
+		}
+
+		fn credentials_of(
+			who: AccountId,
+		) -> Vec<(pallet_studentid::CredentialIndex, pallet_studentid::IssuedCredential<AccountId>)> {
+			Identity::credentials_of(&who)
+		}
+	}
+
 	#[cfg(feature = "runtime-benchmarks")]
 	impl frame_benchmarking::Benchmark<Block> for Runtime {
 		fn benchmark_metadata(extra: bool) -> (
@@ -0,0 +1,61 @@
+//! Fee payment by the institutions sponsoring their students.
+
+use pallet_transaction_payment::{CurrencyAdapter, OnChargeTransaction};
+use sp_runtime::{
+	traits::{DispatchInfoOf, PostDispatchInfoOf, Zero},
+	transaction_validity::TransactionValidityError,
+};
+
+use crate::{AccountId, Balance, Balances, Call, Identity, Runtime};
+
+type Adapter = CurrencyAdapter<Balances, ()>;
+
+/// `CurrencyAdapter`, except that the fees of active students for the `pallet_studentid` calls
+/// `Identity::is_sponsored` allows are taken from the sponsor pot of their institution, as long
+/// as it sponsors them, the fee fits its limits and the pot has the funds. Otherwise the student
+/// pays, as do calls with a tip.
+pub struct SponsoredCurrencyAdapter;
+
+impl OnChargeTransaction<Runtime> for SponsoredCurrencyAdapter {
+	type Balance = Balance;
+	/// What `CurrencyAdapter` withdrew, with the sponsoring institution and its pot.
+	type LiquidityInfo = (
+		<Adapter as OnChargeTransaction<Runtime>>::LiquidityInfo,
+		Option<(pallet_studentid::InstitutionIndex, AccountId)>,
+	);
+
+	fn withdraw_fee(
+		who: &AccountId,
+		call: &Call,
+		info: &DispatchInfoOf<Call>,
+		fee: Self::Balance,
+		tip: Self::Balance,
+	) -> Result<Self::LiquidityInfo, TransactionValidityError> {
+		if tip.is_zero() && matches!(call, Call::Identity(call) if Identity::is_sponsored(call)) {
+			if let Some((institution, pot)) = Identity::sponsor_of(who, fee) {
+				if let Ok(paid) = Adapter::withdraw_fee(&pot, call, info, fee, tip) {
+					return Ok((paid, Some((institution, pot))))
+				}
+			}
+		}
+		Adapter::withdraw_fee(who, call, info, fee, tip).map(|paid| (paid, None))
+	}
+
+	fn correct_and_deposit_fee(
+		who: &AccountId,
+		dispatch_info: &DispatchInfoOf<Call>,
+		post_info: &PostDispatchInfoOf<Call>,
+		corrected_fee: Self::Balance,
+		tip: Self::Balance,
+		(paid, sponsor): Self::LiquidityInfo,
+	) -> Result<(), TransactionValidityError> {
+		let payer = match sponsor {
+			Some((institution, pot)) => {
+				Identity::note_sponsored_fee(institution, who, corrected_fee);
+				pot
+			},
+			None => who.clone(),
+		};
+		Adapter::correct_and_deposit_fee(&payer, dispatch_info, post_info, corrected_fee, tip, paid)
+	}
+}